@@ -0,0 +1,96 @@
+use std::sync::atomic::Ordering;
+use tauri::Manager;
+use tracker_core::{app_lock, settings};
+
+use crate::error::Error;
+use crate::AppState;
+
+/// Whether the OS can confirm it's the user without the app's own passcode.
+pub const SYSTEM_AUTHENTICATION: bool = cfg!(target_os = "macos");
+
+/// Whether the app lock setting is on.
+pub fn enabled(app_handle: &tauri::AppHandle) -> Result<bool, Error> {
+    let state = app_handle.state::<AppState>();
+    let db = state.readers.get()?;
+    Ok(settings::get_bool(&db, settings::APP_LOCK_ENABLED, false)?)
+}
+
+/// Whether the window's contents and exports need unlocking right now.
+pub fn is_locked(app_handle: &tauri::AppHandle) -> Result<bool, Error> {
+    let unlocked = app_handle
+        .state::<AppState>()
+        .app_unlocked
+        .load(Ordering::SeqCst);
+    Ok(!unlocked && enabled(app_handle)?)
+}
+
+/// Fails if the app is locked, for commands that give away history.
+pub fn require_unlocked(app_handle: &tauri::AppHandle) -> Result<(), Error> {
+    if is_locked(app_handle)? {
+        return Err("The app is locked".into());
+    }
+    Ok(())
+}
+
+/// Locks again, e.g. once the window is hidden.
+pub fn lock(app_handle: &tauri::AppHandle) {
+    app_handle
+        .state::<AppState>()
+        .app_unlocked
+        .store(false, Ordering::SeqCst);
+}
+
+/// Unlocks with `passcode`, or by asking the OS to confirm it's the user if
+/// there isn't one. Blocks until they respond, so mustn't be called on the
+/// main thread.
+pub fn unlock(app_handle: &tauri::AppHandle, passcode: Option<&str>) -> Result<(), Error> {
+    match passcode {
+        Some(passcode) if !app_lock::verify_passcode(passcode)? => {
+            return Err("Incorrect passcode".into());
+        }
+        Some(_) => {}
+        None => platform::authenticate("unlock your time tracking history")?,
+    }
+    app_handle
+        .state::<AppState>()
+        .app_unlocked
+        .store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use block2::RcBlock;
+    use objc2::runtime::Bool;
+    use objc2_foundation::{NSError, NSString};
+    use objc2_local_authentication::{LAContext, LAPolicy};
+    use std::sync::mpsc;
+
+    /// Asks for Touch ID, falling back to the account password on Macs
+    /// without it or after failed attempts.
+    pub fn authenticate(reason: &str) -> Result<(), String> {
+        let (tx, rx) = mpsc::channel();
+        unsafe {
+            let context = LAContext::new();
+            let reply = RcBlock::new(move |success: Bool, _: *mut NSError| {
+                let _ = tx.send(success.as_bool());
+            });
+            context.evaluatePolicy_localizedReason_reply(
+                LAPolicy::DeviceOwnerAuthentication,
+                &NSString::from_str(reason),
+                &reply,
+            );
+        }
+        match rx.recv() {
+            Ok(true) => Ok(()),
+            _ => Err("Couldn't confirm it's you".to_string()),
+        }
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+mod platform {
+    pub fn authenticate(_reason: &str) -> Result<(), String> {
+        Err("Enter the passcode to unlock".to_string())
+    }
+}
@@ -0,0 +1,169 @@
+use tracing::info;
+use tracker_core::error::Result;
+
+/// Matches the bundle identifier in tauri.conf.json.
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+const APP_ID: &str = "dev.britannio.foundership-time-tracker";
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+const APP_NAME: &str = "Foundership Time Tracker";
+
+/// Registers the app to launch when the user logs in, pointing at the
+/// currently running executable. Safe to call again to refresh the path
+/// after the app has moved.
+pub fn enable() -> Result<()> {
+    let exe = std::env::current_exe()?;
+    platform::enable(&exe)?;
+    info!("Enabled launch at login for {:?}", exe);
+    Ok(())
+}
+
+/// Removes the login item, if there is one.
+pub fn disable() -> Result<()> {
+    platform::disable()?;
+    info!("Disabled launch at login");
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// A per-user launchd agent that runs the app once at login.
+    pub fn enable(exe: &Path) -> Result<()> {
+        let path = plist_path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(
+            path,
+            format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{}</string>
+    </array>
+    <key>RunAtLoad</key>
+    <true/>
+</dict>
+</plist>
+"#,
+                APP_ID,
+                xml_escape(&exe.to_string_lossy())
+            ),
+        )?;
+        Ok(())
+    }
+
+    pub fn disable() -> Result<()> {
+        let path = plist_path()?;
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn plist_path() -> Result<PathBuf> {
+        let home = tauri::api::path::home_dir().ok_or("Failed to get home dir")?;
+        Ok(home
+            .join("Library/LaunchAgents")
+            .join(format!("{}.plist", APP_ID)))
+    }
+
+    fn xml_escape(value: &str) -> String {
+        value
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::*;
+    use std::path::Path;
+    use std::process::Command;
+    use tracker_core::error::Error;
+
+    const RUN_KEY: &str = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Run";
+
+    /// An entry under the current user's `Run` key.
+    pub fn enable(exe: &Path) -> Result<()> {
+        let output = Command::new("reg")
+            .args(&["add", RUN_KEY, "/v", APP_NAME, "/t", "REG_SZ", "/f", "/d"])
+            .arg(format!("\"{}\"", exe.display()))
+            .output()
+            .map_err(Error::command("reg"))?;
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).trim().into());
+        }
+        Ok(())
+    }
+
+    pub fn disable() -> Result<()> {
+        // Fails when the value doesn't exist, which is what we want anyway
+        Command::new("reg")
+            .args(&["delete", RUN_KEY, "/v", APP_NAME, "/f"])
+            .output()
+            .map_err(Error::command("reg"))?;
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::*;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    /// An XDG autostart entry, honoured by GNOME, KDE and most other
+    /// desktops.
+    pub fn enable(exe: &Path) -> Result<()> {
+        let path = desktop_entry_path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(
+            path,
+            format!(
+                "[Desktop Entry]\nType=Application\nName={}\nExec=\"{}\"\nX-GNOME-Autostart-enabled=true\n",
+                APP_NAME,
+                exe.display()
+            ),
+        )?;
+        Ok(())
+    }
+
+    pub fn disable() -> Result<()> {
+        let path = desktop_entry_path()?;
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn desktop_entry_path() -> Result<PathBuf> {
+        let config = tauri::api::path::config_dir().ok_or("Failed to get config dir")?;
+        Ok(config.join("autostart").join(format!("{}.desktop", APP_ID)))
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
+mod platform {
+    use super::*;
+    use std::path::Path;
+
+    pub fn enable(_exe: &Path) -> Result<()> {
+        Err("Launch at login is not supported on this platform".into())
+    }
+
+    pub fn disable() -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use tracing::warn;
+
+/// Shows how much of today's goal is left on the dock icon on macOS, or as a
+/// taskbar overlay on Windows, so progress can be seen while the menu bar
+/// item is hidden, e.g. behind the notch. Cleared once the goal is met or on
+/// days without one. Does nothing on other platforms.
+pub fn update(app_handle: &tauri::AppHandle, worked_minutes: i64, goal_minutes: i64) {
+    let remaining = (goal_minutes - worked_minutes).max(0);
+    if let Err(e) = platform::update(app_handle, remaining, goal_minutes) {
+        warn!("Error updating the badge: {}", e);
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use objc2_app_kit::NSApplication;
+    use objc2_foundation::{MainThreadMarker, NSString};
+
+    /// Badges the dock icon with e.g. "2:40", which fits where
+    /// "2h 40m" wouldn't.
+    pub fn update(app_handle: &tauri::AppHandle, remaining: i64, _goal: i64) -> Result<(), String> {
+        let label = (remaining > 0).then(|| format!("{}:{:02}", remaining / 60, remaining % 60));
+        app_handle
+            .run_on_main_thread(move || {
+                let Some(mtm) = MainThreadMarker::new() else {
+                    return;
+                };
+                let label = label.as_deref().map(NSString::from_str);
+                unsafe {
+                    NSApplication::sharedApplication(mtm)
+                        .dockTile()
+                        .setBadgeLabel(label.as_deref());
+                }
+            })
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use std::f64::consts::PI;
+    use std::ffi::c_void;
+    use std::{mem, ptr};
+    use tauri::Manager;
+    use tracing::warn;
+    use tracker_core::reports::format_minutes;
+    use windows_sys::core::{GUID, HRESULT, PCWSTR};
+    use windows_sys::Win32::Foundation::HWND;
+    use windows_sys::Win32::Graphics::Gdi::{CreateBitmap, DeleteObject};
+    use windows_sys::Win32::System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER};
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        CreateIconIndirect, DestroyIcon, HICON, ICONINFO,
+    };
+
+    const CLSID_TASKBAR_LIST: GUID = GUID::from_u128(0x56fdf344_fd6d_11d0_958a_006097c9a090);
+    const IID_ITASKBAR_LIST3: GUID = GUID::from_u128(0xea1afb91_9e28_4b86_90e9_9e9f8a5eefaf);
+    /// Positions in ITaskbarList3's vtable, which starts with IUnknown's
+    /// methods and then those of ITaskbarList and ITaskbarList2.
+    const RELEASE: usize = 2;
+    const HR_INIT: usize = 3;
+    const SET_OVERLAY_ICON: usize = 18;
+    /// Side of the overlay icon, in pixels.
+    const ICON_SIZE: i32 = 16;
+
+    /// Overlays the taskbar button with a pie of the day's progress, with
+    /// e.g. "2h 40m left today" for screen readers.
+    pub fn update(app_handle: &tauri::AppHandle, remaining: i64, goal: i64) -> Result<(), String> {
+        let window = app_handle
+            .get_window("main")
+            .ok_or("Main window not found")?;
+        let hwnd: HWND = window.hwnd().map_err(|e| e.to_string())?.0;
+        let description =
+            (remaining > 0).then(|| format!("{} left today", format_minutes(remaining)));
+        let done = if goal > 0 {
+            1.0 - remaining as f64 / goal as f64
+        } else {
+            1.0
+        };
+        app_handle
+            .run_on_main_thread(move || {
+                if let Err(e) = unsafe { set_overlay(hwnd, description.as_deref(), done) } {
+                    warn!("Error updating the taskbar overlay: {}", e);
+                }
+            })
+            .map_err(|e| e.to_string())
+    }
+
+    /// Sets the overlay to a pie `done` full, or clears it without a
+    /// `description`. windows-sys has no COM interfaces, so the taskbar's
+    /// methods are called through its vtable.
+    unsafe fn set_overlay(hwnd: HWND, description: Option<&str>, done: f64) -> Result<(), String> {
+        let mut taskbar: *mut c_void = ptr::null_mut();
+        let result = CoCreateInstance(
+            &CLSID_TASKBAR_LIST,
+            ptr::null_mut(),
+            CLSCTX_INPROC_SERVER,
+            &IID_ITASKBAR_LIST3,
+            &mut taskbar,
+        );
+        if result < 0 {
+            return Err(format!("CoCreateInstance failed with {:#x}", result));
+        }
+        let vtable = *(taskbar as *const *const usize);
+        let release: unsafe extern "system" fn(*mut c_void) -> u32 =
+            mem::transmute(*vtable.add(RELEASE));
+        let hr_init: unsafe extern "system" fn(*mut c_void) -> HRESULT =
+            mem::transmute(*vtable.add(HR_INIT));
+        let set_overlay_icon: unsafe extern "system" fn(
+            *mut c_void,
+            HWND,
+            HICON,
+            PCWSTR,
+        ) -> HRESULT = mem::transmute(*vtable.add(SET_OVERLAY_ICON));
+
+        let icon = match description {
+            Some(_) => render_icon(done),
+            None => 0,
+        };
+        let description: Vec<u16> = description
+            .unwrap_or_default()
+            .encode_utf16()
+            .chain([0])
+            .collect();
+        let mut result = hr_init(taskbar);
+        if result >= 0 {
+            result = set_overlay_icon(taskbar, hwnd, icon, description.as_ptr());
+        }
+        // The taskbar keeps its own copy
+        if icon != 0 {
+            DestroyIcon(icon);
+        }
+        release(taskbar);
+        if result < 0 {
+            return Err(format!("SetOverlayIcon failed with {:#x}", result));
+        }
+        Ok(())
+    }
+
+    /// Draws a pie filled clockwise from 12 o'clock, green for the part of
+    /// the goal that's `done` and grey for the rest. Returns 0 if the icon
+    /// couldn't be created, which just clears the overlay.
+    unsafe fn render_icon(done: f64) -> HICON {
+        let centre = ICON_SIZE as f64 / 2.0;
+        let radius = centre - 0.5;
+        let mut bgra = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+        for y in 0..ICON_SIZE {
+            for x in 0..ICON_SIZE {
+                let (dx, dy) = (x as f64 + 0.5 - centre, y as f64 + 0.5 - centre);
+                // How far round from 12 o'clock, as a fraction of a turn
+                let turn = (dx.atan2(-dy) / (2.0 * PI)).rem_euclid(1.0);
+                let [r, g, b] = if turn < done {
+                    [0x34, 0xc7, 0x59]
+                } else {
+                    [0x8e, 0x8e, 0x93]
+                };
+                let alpha = (radius - dx.hypot(dy) + 0.5).clamp(0.0, 1.0);
+                bgra.extend_from_slice(&[b, g, r, (alpha * 255.0).round() as u8]);
+            }
+        }
+        // The alpha channel does the masking, so the mask is left empty
+        let mask_bits = vec![0u8; (ICON_SIZE * ICON_SIZE / 8) as usize];
+        let colour = CreateBitmap(ICON_SIZE, ICON_SIZE, 1, 32, bgra.as_ptr() as *const c_void);
+        let mask = CreateBitmap(
+            ICON_SIZE,
+            ICON_SIZE,
+            1,
+            1,
+            mask_bits.as_ptr() as *const c_void,
+        );
+        let info = ICONINFO {
+            fIcon: 1,
+            xHotspot: 0,
+            yHotspot: 0,
+            hbmMask: mask,
+            hbmColor: colour,
+        };
+        let icon = CreateIconIndirect(&info);
+        DeleteObject(colour);
+        DeleteObject(mask);
+        icon
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+mod platform {
+    pub fn update(
+        _app_handle: &tauri::AppHandle,
+        _remaining: i64,
+        _goal: i64,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+}
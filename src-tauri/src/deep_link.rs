@@ -0,0 +1,95 @@
+use tauri::Manager;
+use tracing::{error, info};
+use tracker_core::lunch;
+
+use crate::{notifications, set_paused, today_summary, AppState, LoopSignal};
+
+/// Matches the bundle identifier in tauri.conf.json.
+const APP_ID: &str = "dev.britannio.foundership-time-tracker";
+/// Also listed under `CFBundleURLTypes` in Info.plist for macOS.
+pub const SCHEME: &str = "timetracker";
+
+/// What a `timetracker://` link asks for.
+#[derive(Debug)]
+enum Action {
+    CheckNow,
+    Pause,
+    Resume,
+    ReportToday,
+}
+
+/// Hands links opened while the app is already running to that instance,
+/// on Windows and Linux where they start a new process. Call first thing in
+/// `main`.
+pub fn prepare() {
+    tauri_plugin_deep_link::prepare(APP_ID);
+}
+
+/// Handles links like `timetracker://check-now` from other tools, e.g.
+/// Raycast, Alfred or Shortcuts, including one the app was launched with.
+pub fn register(app_handle: &tauri::AppHandle) {
+    let handle = app_handle.clone();
+    if let Err(e) = tauri_plugin_deep_link::register(SCHEME, move |url| open(&handle, &url)) {
+        error!("Error registering {}:// links: {}", SCHEME, e);
+    }
+
+    // macOS delivers the launch link to the handler, elsewhere it's an
+    // argument
+    #[cfg(not(target_os = "macos"))]
+    if let Some(url) = std::env::args().nth(1) {
+        if url.starts_with(&format!("{}://", SCHEME)) {
+            open(app_handle, &url);
+        }
+    }
+}
+
+fn open(app_handle: &tauri::AppHandle, url: &str) {
+    info!("Opening {}", url);
+    let result = parse(url).and_then(|action| run(app_handle, action));
+    if let Err(e) = result {
+        error!("Error opening {}: {}", url, e);
+    }
+}
+
+/// e.g. "timetracker://report/today", ignoring any query or trailing slash.
+fn parse(url: &str) -> Result<Action, String> {
+    let path = url
+        .strip_prefix(SCHEME)
+        .and_then(|rest| rest.strip_prefix("://"))
+        .ok_or_else(|| format!("Not a {}:// link", SCHEME))?;
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    match path.trim_end_matches('/') {
+        "check-now" => Ok(Action::CheckNow),
+        "pause" => Ok(Action::Pause),
+        "resume" => Ok(Action::Resume),
+        "report/today" => Ok(Action::ReportToday),
+        other => Err(format!("Unknown action '{}'", other)),
+    }
+}
+
+fn run(app_handle: &tauri::AppHandle, action: Action) -> Result<(), String> {
+    match action {
+        Action::CheckNow => app_handle
+            .state::<AppState>()
+            .signals
+            .send(LoopSignal::CheckNow(None))
+            .map_err(|e| e.to_string()),
+        Action::Pause => Ok(set_paused(app_handle, true)?),
+        Action::Resume => Ok(set_paused(app_handle, false)?),
+        // Shown even with notifications off, since it was asked for
+        Action::ReportToday => {
+            let today = today_summary(app_handle, None)?;
+            let body = match (&today.earliest, &today.latest) {
+                (Some(earliest), Some(latest)) => format!(
+                    "{} to {}, {} in total",
+                    earliest,
+                    latest,
+                    lunch::format_with_lunch(today.total_minutes, today.lunch_minutes)
+                ),
+                _ => "Not in yet".to_string(),
+            };
+            notifications::show(app_handle, "Today", &body);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use serde::{Serialize, Serializer};
+
+pub use tracker_core::error::LockExt;
+
+/// Failures in the app layer: anything from the tracker core, plus the
+/// window and tray.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Core(#[from] tracker_core::Error),
+    #[error(transparent)]
+    Tauri(#[from] tauri::Error),
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Self {
+        Error::Core(error.into())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Core(error.into())
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Core(message.into())
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Core(message.into())
+    }
+}
+
+/// Lets commands that return plain string errors use `?` on app errors.
+impl From<Error> for String {
+    fn from(error: Error) -> Self {
+        error.to_string()
+    }
+}
+
+/// Sent to the frontend as just the message.
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
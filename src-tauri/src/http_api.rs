@@ -0,0 +1,139 @@
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use serde::Serialize;
+use std::sync::Arc;
+use std::thread;
+use tauri::Manager;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{error, info};
+use tracker_core::reports;
+use tracker_core::sessions::{self, DayBoundary};
+use tracker_core::settings;
+use url::Url;
+
+use crate::{parse_date, status, today_summary, AppState};
+
+/// A read-only JSON API on localhost, so scripts, Stream Deck buttons and
+/// desktop widgets can show what the tracker knows. Stops when dropped.
+pub struct HttpApi {
+    server: Arc<Server>,
+}
+
+impl Drop for HttpApi {
+    fn drop(&mut self) {
+        self.server.unblock();
+    }
+}
+
+/// A fresh random token for `Authorization: Bearer <token>`.
+pub fn new_token() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+/// Starts serving on `127.0.0.1:port`. Requests are checked against the
+/// token in settings as they come in, so changing it needs no restart.
+pub fn start(app_handle: &tauri::AppHandle, port: u16) -> Result<HttpApi, String> {
+    let server = Server::http(("127.0.0.1", port))
+        .map_err(|e| format!("Couldn't serve the HTTP API on port {}: {}", port, e))?;
+    let server = Arc::new(server);
+    info!("Serving the HTTP API on http://127.0.0.1:{}", port);
+
+    let incoming = server.clone();
+    let app_handle = app_handle.clone();
+    thread::spawn(move || {
+        // Ends once the server is unblocked
+        for request in incoming.incoming_requests() {
+            let (status, body) = match handle(&app_handle, &request) {
+                Ok(body) => (200, body),
+                Err((status, message)) => {
+                    (status, serde_json::json!({ "error": message }).to_string())
+                }
+            };
+            let content_type =
+                Header::from_bytes("Content-Type", "application/json").expect("header is valid");
+            let response = Response::from_string(body)
+                .with_status_code(status)
+                .with_header(content_type);
+            if let Err(e) = request.respond(response) {
+                error!("Error responding to HTTP API request: {}", e);
+            }
+        }
+    });
+    Ok(HttpApi { server })
+}
+
+type ApiResult = Result<String, (u16, String)>;
+
+fn handle(app_handle: &tauri::AppHandle, request: &Request) -> ApiResult {
+    let state = app_handle.state::<AppState>();
+    let token = {
+        let db = state.readers.get().map_err(internal)?;
+        settings::get(&db, settings::HTTP_API_TOKEN)
+            .map_err(internal)?
+            .unwrap_or_default()
+    };
+    let authorized = request.headers().iter().any(|header| {
+        header.field.equiv("Authorization")
+            && header.value.as_str().strip_prefix("Bearer ") == Some(token.as_str())
+    });
+    if token.is_empty() || !authorized {
+        return Err((401, "Missing or wrong bearer token".to_string()));
+    }
+    if *request.method() != Method::Get {
+        return Err((405, "Only GET is supported".to_string()));
+    }
+
+    let url = Url::parse(&format!("http://localhost{}", request.url()))
+        .map_err(|e| (400, e.to_string()))?;
+    let query = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    let location = query("location");
+
+    match url.path().trim_end_matches('/') {
+        // Whether the tracker sees the office right now
+        "/status" => json(&status(&state)),
+        // Today's times so far, counting any open session
+        "/today" => json(&today_summary(app_handle, location.as_deref()).map_err(internal)?),
+        // The week starting `start` (YYYY-MM-DD), this week by default
+        "/week" => {
+            let db = state.readers.get().map_err(internal)?;
+            let boundary = DayBoundary::load(&db).map_err(internal)?;
+            let today = boundary.date(Local::now());
+            let week_start = match query("start") {
+                Some(start) => parse_date(&start).map_err(|e| (400, e))?,
+                None => monday_of(today),
+            };
+            json(
+                &reports::weekly_summary(&db, week_start, today, boundary, location.as_deref())
+                    .map_err(internal)?,
+            )
+        }
+        // A day's sessions, every day's if no `date` is given
+        "/sessions" => {
+            let date = query("date");
+            if let Some(date) = &date {
+                parse_date(date).map_err(|e| (400, e))?;
+            }
+            let db = state.readers.get().map_err(internal)?;
+            json(
+                &sessions::get_sessions(&db, date.as_deref(), location.as_deref())
+                    .map_err(internal)?,
+            )
+        }
+        path => Err((404, format!("No endpoint at {}", path))),
+    }
+}
+
+fn monday_of(today: NaiveDate) -> NaiveDate {
+    today - Duration::days(today.weekday().num_days_from_monday() as i64)
+}
+
+fn json<T: Serialize>(value: &T) -> ApiResult {
+    serde_json::to_string(value).map_err(internal)
+}
+
+fn internal(error: impl ToString) -> (u16, String) {
+    (500, error.to_string())
+}
@@ -0,0 +1,74 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use tracing::level_filters::LevelFilter;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::prelude::*;
+
+use crate::error::LockExt;
+
+/// Log files kept, one a day, before the oldest are deleted.
+const MAX_LOG_FILES: usize = 14;
+
+/// Flushes buffered lines to the log file when dropped.
+static GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
+
+pub fn logs_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("logs")
+}
+
+/// Logs this app and tracker-core to a file a day in `logs_dir`, at debug
+/// level so every presence check is there when something goes wrong in the
+/// field, and to the console too in debug builds. Other crates only log
+/// warnings and errors.
+pub fn init(data_dir: &Path) -> Result<(), String> {
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix("tracker")
+        .filename_suffix("log")
+        .max_log_files(MAX_LOG_FILES)
+        .build(logs_dir(data_dir))
+        .map_err(|e| format!("Couldn't create the log file: {}", e))?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+    let targets = Targets::new()
+        .with_target("foundership_time_tracker", LevelFilter::DEBUG)
+        .with_target("tracker_core", LevelFilter::DEBUG)
+        .with_default(LevelFilter::WARN);
+    let console = cfg!(debug_assertions)
+        .then(|| tracing_subscriber::fmt::layer().with_filter(targets.clone()));
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(writer)
+                .with_ansi(false)
+                .with_filter(targets),
+        )
+        .with(console)
+        .try_init()
+        .map_err(|e| e.to_string())?;
+    *GUARD.lock_unpoisoned() = Some(guard);
+    Ok(())
+}
+
+/// Writes out anything still buffered. Nothing's logged to the file after.
+pub fn flush() {
+    GUARD.lock_unpoisoned().take();
+}
+
+/// Shows the logs folder in Finder, Explorer or the default file manager.
+pub fn open_logs_dir(data_dir: &Path) -> Result<(), String> {
+    let dir = logs_dir(data_dir);
+    #[cfg(target_os = "macos")]
+    let program = "open";
+    #[cfg(target_os = "windows")]
+    let program = "explorer";
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    let program = "xdg-open";
+    Command::new(program)
+        .arg(&dir)
+        .spawn()
+        .map_err(|e| format!("Couldn't open {:?}: {}", dir, e))?;
+    Ok(())
+}
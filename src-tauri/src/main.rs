@@ -1,69 +1,388 @@
-use chrono::Local;
+use chrono::{DateTime, Datelike, Local, NaiveDate};
 use rusqlite::{Connection, Result};
-use serde::{Deserialize, Serialize};
-use std::process::Command;
-use std::sync::{Mutex, MutexGuard};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
-use tauri::Manager;
+use std::time::{Duration, Instant};
+use tauri::api::dialog::FileDialogBuilder;
 use tauri::api::path::app_data_dir;
-use std::fs;
+use tauri::async_runtime::JoinHandle;
+use tauri::{GlobalShortcutManager, Manager};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{oneshot, Mutex as AsyncMutex};
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{debug, error, info, warn};
+use tracker_core::clock::{Clock, SystemClock};
+use tracker_core::connections::{
+    self, insert_connection, ConnectionLog, LogQuery, MANUAL_PRESENCE,
+};
+use tracker_core::detector::PresenceDetector;
+use tracker_core::pool::ReadPool;
+use tracker_core::sessions::{DayBoundary, ManualTimer, Session, SessionTracker, Transition};
+use tracker_core::settings::Settings;
+use tracker_core::webhooks::WebhookEvent;
+use tracker_core::{
+    anomalies, audit, backup, daily_notes, database, day_types, demo, diagnostics, digest, edits,
+    export, flexitime, folder_sync, google_calendar, heartbeat, holidays, invoices, keychain,
+    location, pattern, publishing, reports, retention, server_sync, sessions, settings, slack,
+    team, time_sync, timesheet, tracking, webhooks, wifi,
+};
 
-#[derive(Serialize, Deserialize)]
-struct ConnectionLog {
-    date: String,
-    earliest: String,
-    latest: String,
+mod app_lock;
+mod autostart;
+mod badge;
+mod deep_link;
+mod error;
+mod http_api;
+mod logging;
+mod network_events;
+mod notifications;
+mod power_events;
+mod screen_events;
+mod tray;
+
+use error::{Error, LockExt};
+use notifications::GoHomeReminder;
+
+/// How often to check when nothing has changed, to keep the current session's
+/// end time moving.
+const FALLBACK_POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const MIN_POLL_INTERVAL: Duration = Duration::from_secs(settings::MIN_POLL_INTERVAL_SECONDS);
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(settings::MAX_POLL_INTERVAL_SECONDS);
+/// How much to slow polling down on battery, and again once the office
+/// hasn't been seen for `ABSENT_BACKOFF_AFTER`.
+const BACKOFF_FACTOR: u32 = 4;
+const ABSENT_BACKOFF_AFTER: Duration = Duration::from_secs(2 * 60 * 60);
+const NETWORK_SETTLE_DELAY: Duration = Duration::from_secs(3);
+const CHECK_NOW_TIMEOUT: Duration = Duration::from_secs(30);
+const BACKUP_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const DEFAULT_TREND_WINDOW: u32 = 7;
+const MAX_TREND_WINDOW: u32 = 365;
+/// How often the watchdog looks in on the tracking loop.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(60);
+/// How long the tracking loop can go without a heartbeat before it's
+/// reported as stalled. Comfortably longer than the slowest poll.
+const STALL_THRESHOLD: Duration = Duration::from_secs(15 * 60);
+/// How long quitting waits for the tracking loop to finish a check in
+/// progress.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reasons for the tracking loop to wake up before its next scheduled poll.
+#[derive(Debug)]
+enum LoopSignal {
+    NetworkChanged,
+    NetworkWatchStopped,
+    Sleep,
+    Wake,
+    ScreenLocked,
+    ScreenUnlocked,
+    /// Check immediately, replying with whether presence was detected.
+    CheckNow(Option<oneshot::Sender<Result<bool, String>>>),
+    /// Stop the loop for good, replying once it has. Waited on from
+    /// outside the async runtime, e.g. while quitting.
+    Shutdown(mpsc::Sender<()>),
+}
+
+/// Snapshot of what the tracker is doing, for the UI.
+#[derive(Serialize)]
+struct TrackingStatus {
+    current_ssid: Option<String>,
+    /// Whether the last successful check detected presence.
+    present: bool,
+    /// When the last successful check ran, as "YYYY-MM-DD HH:MM:SS".
+    last_check: Option<String>,
+    paused: bool,
+    /// Start time of the open session, e.g. "09:12".
+    on_site_since: Option<String>,
+    /// Whether the tracking loop has stopped responding.
+    stalled: bool,
+    /// Why the last check failed, e.g. a detector's command timing out.
+    check_error: Option<String>,
+}
+
+struct LastCheck {
+    at: DateTime<Local>,
+    present: bool,
 }
 
 struct AppState {
+    clock: Box<dyn Clock>,
+    /// The only connection that writes; readers use `readers`.
     db: Mutex<Connection>,
+    readers: ReadPool,
+    /// Rebuilt when the office networks change.
+    detector: Mutex<Box<dyn PresenceDetector>>,
+    sessions: Mutex<SessionTracker>,
+    manual: Mutex<ManualTimer>,
+    go_home: Mutex<GoHomeReminder>,
+    paused: AtomicBool,
+    /// Whether the screen is locked and the `pause_when_locked` setting is
+    /// on, so checks are skipped until it's unlocked.
+    locked: AtomicBool,
+    /// Why the last presence check failed, e.g. the detector erroring, or
+    /// `None` if it didn't.
+    check_error: Mutex<Option<String>>,
+    signals: UnboundedSender<LoopSignal>,
+    last_check: Mutex<Option<LastCheck>>,
+    /// Unix time the tracking loop last came round, for the watchdog.
+    heartbeat: AtomicI64,
+    stalled: AtomicBool,
+    /// Held while pushing sessions to Toggl or Clockify, so two pushes
+    /// can't send the same session.
+    time_sync: Mutex<()>,
+    /// Held while syncing through the sync server, so two syncs can't send
+    /// the same changes.
+    server_sync: Mutex<()>,
+    /// Running while the `http_api_enabled` setting is on.
+    http_api: Mutex<Option<http_api::HttpApi>>,
+    /// Sends Slack status changes in order, off the tracking loop.
+    slack: slack::StatusUpdater,
+    /// Set while quitting, so the tracking loop isn't restarted.
+    shutting_down: AtomicBool,
+    data_dir: PathBuf,
+    /// Where the database is: in `data_dir` unless it's been moved.
+    db_path: PathBuf,
+    /// The SQLCipher key, if the database is encrypted.
+    db_key: Option<String>,
+    /// Whether the app lock has been unlocked since the window was last
+    /// hidden.
+    app_unlocked: AtomicBool,
 }
 
 fn main() {
+    deep_link::prepare();
+
     // Set up system tray
-    let tray_menu = tauri::SystemTrayMenu::new()
-        .add_item(tauri::CustomMenuItem::new("toggle", "Show/Hide"))
-        .add_item(tauri::CustomMenuItem::new("quit", "Quit"));
+    let tray_menu = tray::menu(&[], false, false, false);
     let system_tray = tauri::SystemTray::new().with_menu(tray_menu);
 
     tauri::Builder::default()
         .system_tray(system_tray)
         .setup(|app| {
             let app_handle = app.handle();
-            let db = create_db_connection(&app_handle)?;
-            app.manage(AppState { db: Mutex::new(db) });
+            // `--data-dir <path>` keeps everything in `path` instead, e.g.
+            // next to the app on a USB stick
+            let args: Vec<String> = std::env::args().collect();
+            let mut data_dir = match args.iter().position(|arg| arg == "--data-dir") {
+                Some(i) => PathBuf::from(args.get(i + 1).ok_or("--data-dir needs a path")?),
+                None => app_data_dir(&app.config()).ok_or("Failed to get app data dir")?,
+            };
+            // `--demo` runs on made-up history, kept apart from the real
+            // database and its backups
+            let demo = args.iter().any(|arg| arg == "--demo");
+            if demo {
+                data_dir = data_dir.join("demo");
+            }
+            logging::init(&data_dir)?;
+            wifi::init();
+            let db_path = database::path_in(&data_dir);
+            let db_key = if demo { None } else { database::key() };
+            let backups_dir = backup::backups_dir(&data_dir);
+            if let Some(key) = &db_key {
+                backup::encrypt_backups(&backups_dir, key)?;
+            }
+            let (db, recovery) =
+                backup::open_or_recover(&db_path, &backups_dir, db_key.as_deref())?;
+            if demo && !demo::has_history(&db)? {
+                let today = DayBoundary::load(&db)?.date(Local::now());
+                let seed = Local::now().timestamp() as u64;
+                demo::seed(&db, today, demo::DEFAULT_MONTHS, seed)?;
+            }
+            // Sessions left running by a crash or a quit that didn't finish
+            heartbeat::close_dangling(&db)?;
+            let settings = Settings::load(&db)?;
+            if !settings.geofences.is_empty() {
+                location::start();
+            }
+            let (signal_tx, signal_rx) = unbounded_channel();
+            app.manage(AppState {
+                clock: Box::new(SystemClock),
+                db: Mutex::new(db),
+                readers: ReadPool::new(db_path.clone(), database::BUSY_TIMEOUT, db_key.clone()),
+                detector: Mutex::new(build_detector(&settings)?),
+                sessions: Mutex::new(SessionTracker::default()),
+                manual: Mutex::new(ManualTimer::default()),
+                go_home: Mutex::new(GoHomeReminder::default()),
+                paused: AtomicBool::new(false),
+                locked: AtomicBool::new(false),
+                check_error: Mutex::new(None),
+                signals: signal_tx.clone(),
+                last_check: Mutex::new(None),
+                heartbeat: AtomicI64::new(Local::now().timestamp()),
+                stalled: AtomicBool::new(false),
+                time_sync: Mutex::new(()),
+                server_sync: Mutex::new(()),
+                http_api: Mutex::new(None),
+                slack: slack::StatusUpdater::spawn(ReadPool::new(
+                    db_path.clone(),
+                    database::BUSY_TIMEOUT,
+                    db_key.clone(),
+                )),
+                shutting_down: AtomicBool::new(false),
+                data_dir: data_dir.clone(),
+                db_path,
+                db_key,
+                app_unlocked: AtomicBool::new(false),
+            });
+            if let Some(recovery) = recovery {
+                notify_recovery(&app_handle, &recovery);
+            }
+            // Start hidden rather than show history before unlocking
+            if app_lock::enabled(&app_handle)? {
+                if let Some(window) = app.get_window("main") {
+                    window.hide()?;
+                }
+            }
+
+            deep_link::register(&app_handle);
+            if settings.http_api_enabled {
+                match http_api::start(&app_handle, settings.http_api_port) {
+                    Ok(server) => {
+                        *app.state::<AppState>().http_api.lock_unpoisoned() = Some(server)
+                    }
+                    Err(e) => error!("Error starting the HTTP API: {}", e),
+                }
+            }
+            if let Err(e) = register_toggle_shortcut(&app_handle, &settings.toggle_shortcut) {
+                error!("Error registering window shortcut: {}", e);
+            }
 
-            // Start background task
+            // Re-register the login item so it follows the app if it moved
+            if settings.autostart_enabled {
+                if let Err(e) = autostart::enable() {
+                    error!("Error enabling launch at login: {}", e);
+                }
+            }
+            let db = app.state::<AppState>().readers.get()?;
+            tray::update_menu(&app_handle, &db)?;
+
+            // Take a backup once a day, fetch public holidays for each new
+            // year, point out unusual days, send the weekly digest, prune old
+            // history and catch up with other devices, checking hourly in
+            // case the app isn't running at any particular time
+            let backup_handle = app.handle();
             std::thread::spawn(move || loop {
-                let now = Local::now();
-                println!(
-                    "Checking WiFi connection at {}",
-                    now.format("%Y-%m-%d %H:%M")
-                );
-                if let Err(e) = check_wifi_connection(app_handle.state()) {
-                    eprintln!("Error checking WiFi connection: {}", e);
-                }
-                thread::sleep(Duration::from_secs(30));
+                let state = backup_handle.state::<AppState>();
+                let dir = backup::backups_dir(&state.data_dir);
+                let result = state
+                    .readers
+                    .get()
+                    .map_err(tracker_core::Error::from)
+                    .and_then(|db| {
+                        backup::backup_if_due(
+                            &db,
+                            &dir,
+                            backup::BACKUPS_TO_KEEP,
+                            state.db_key.as_deref(),
+                        )
+                    });
+                if let Err(e) = result {
+                    error!("Error backing up database: {}", e);
+                }
+                if let Err(e) = fetch_public_holidays(&backup_handle) {
+                    error!("Error fetching public holidays: {}", e);
+                }
+                if let Err(e) = notify_anomalies(&backup_handle) {
+                    error!("Error checking for unusual days: {}", e);
+                }
+                if let Err(e) = send_digest_if_due(&backup_handle) {
+                    error!("Error sending weekly digest: {}", e);
+                }
+                if let Err(e) = prune_history(&backup_handle) {
+                    error!("Error pruning old history: {}", e);
+                }
+                if let Err(e) = sync_with_devices(&backup_handle) {
+                    error!("Error syncing with other devices: {}", e);
+                }
+                if let Err(e) = publish_team_totals(&backup_handle) {
+                    error!("Error publishing totals to the team: {}", e);
+                }
+                thread::sleep(BACKUP_CHECK_INTERVAL);
             });
 
+            // Start background task, woken by network changes, sleep/wake
+            // and screen locking with a slow poll as a fallback
+            network_events::spawn(signal_tx.clone());
+            power_events::register(signal_tx.clone());
+            screen_events::register(signal_tx);
+            let signal_rx = Arc::new(AsyncMutex::new(signal_rx));
+            tauri::async_runtime::spawn(supervise_tracking_loop(app_handle, signal_rx));
+
             Ok(())
         })
         .on_system_tray_event(|app, event| match event {
             tauri::SystemTrayEvent::MenuItemClick { id, .. } => match id.as_str() {
                 "toggle" => {
-                    let window = app.get_window("main").unwrap();
-                    if window.is_visible().unwrap() {
-                        window.hide().unwrap();
+                    if let Err(e) = toggle_window(app) {
+                        error!("Error toggling window: {}", e);
+                    }
+                }
+                "check_now" => {
+                    let state = app.state::<AppState>();
+                    let _ = state.signals.send(LoopSignal::CheckNow(None));
+                }
+                "pause" => {
+                    let paused = app.state::<AppState>().paused.load(Ordering::SeqCst);
+                    if let Err(e) = set_paused(app, !paused) {
+                        error!("Error toggling pause: {}", e);
+                    }
+                }
+                "manual" => {
+                    let running = app
+                        .state::<AppState>()
+                        .manual
+                        .lock_unpoisoned()
+                        .is_running();
+                    let result = if running {
+                        stop_manual_session(app.clone()).map(|_| ())
                     } else {
-                        window.show().unwrap();
-                        window.set_focus().unwrap();
-                        #[cfg(target_os = "macos")]
-                        window.set_skip_taskbar(false).unwrap();
+                        start_manual_session(app.clone()).map(|_| ())
+                    };
+                    if let Err(e) = result {
+                        error!("Error toggling manual timer: {}", e);
+                    }
+                }
+                "autostart" => {
+                    let result = update_setting(app, |settings| {
+                        settings.autostart_enabled = !settings.autostart_enabled
+                    });
+                    if let Err(e) = result {
+                        error!("Error toggling launch at login: {}", e);
+                    }
+                }
+                "export_csv" => {
+                    if let Err(e) = app_lock::require_unlocked(app) {
+                        error!("Not exporting CSV: {}", e);
+                        return;
+                    }
+                    let app_handle = app.clone();
+                    FileDialogBuilder::new()
+                        .set_file_name("time-tracker.csv")
+                        .add_filter("CSV", &["csv"])
+                        .save_file(move |path| {
+                            let Some(path) = path else { return };
+                            let state = app_handle.state::<AppState>();
+                            let db = state.db.lock_unpoisoned();
+                            match export::export_csv(&db, &path, "connections", None, None, None) {
+                                Ok(count) => info!("Exported {} rows to {:?}", count, path),
+                                Err(e) => error!("Error exporting CSV: {}", e),
+                            }
+                        });
+                }
+                "open_logs" => {
+                    let state = app.state::<AppState>();
+                    if let Err(e) = logging::open_logs_dir(&state.data_dir) {
+                        error!("Error opening logs: {}", e);
                     }
                 }
                 "quit" => {
-                    std::process::exit(0);
+                    // Off the main thread, which has to stay free to run
+                    // the tray and window calls made while shutting down
+                    let app = app.clone();
+                    thread::spawn(move || shutdown(&app));
                 }
                 _ => {}
             },
@@ -72,13 +391,93 @@ fn main() {
         .on_window_event(|event| {
             if let tauri::WindowEvent::CloseRequested { api, .. } = event.event() {
                 let window = event.window();
-                window.hide().unwrap();
+                if let Err(e) = window.hide() {
+                    error!("Error hiding window: {}", e);
+                }
                 #[cfg(target_os = "macos")]
-                window.set_skip_taskbar(true).unwrap();
+                if let Err(e) = window.set_skip_taskbar(true) {
+                    error!("Error hiding window from the dock: {}", e);
+                }
                 api.prevent_close();
             }
         })
-        .invoke_handler(tauri::generate_handler![get_connections])
+        .invoke_handler(tauri::generate_handler![
+            check_now,
+            collect_diagnostics,
+            connect_google_calendar,
+            create_backup,
+            delete_day,
+            disconnect_google_calendar,
+            encrypt_database,
+            get_database_location,
+            move_database,
+            export_csv,
+            export_daily_note,
+            export_ical,
+            export_json,
+            generate_invoice,
+            generate_timesheet,
+            get_anomalies,
+            get_arrival_histogram,
+            get_balance,
+            get_connections,
+            get_day_types,
+            get_public_holidays,
+            get_goal_progress,
+            get_heatmap,
+            get_location_breakdown,
+            get_sessions,
+            get_audit_log,
+            get_settings,
+            get_status,
+            get_streaks,
+            get_today,
+            get_trends,
+            get_monthly_summary,
+            get_weekly_summary,
+            import_json,
+            is_app_locked,
+            list_backups,
+            pause_tracking,
+            prune_now,
+            redo_edit,
+            restore_backup,
+            send_weekly_digest,
+            set_app_passcode,
+            set_autostart,
+            set_pause_when_locked,
+            set_slack_token,
+            set_smtp_password,
+            set_toggle_shortcut,
+            set_day_boundary_hour,
+            set_day_note,
+            set_day_type,
+            set_go_home_reminder,
+            set_grace_period,
+            set_min_session_minutes,
+            set_notifications_enabled,
+            set_poll_interval,
+            set_weekly_target,
+            snooze_go_home_reminder,
+            start_manual_session,
+            stop_manual_session,
+            sync_devices,
+            get_sync_pairing_code,
+            pair_sync_device,
+            get_team_summary,
+            set_team_token,
+            publish_report,
+            set_report_bucket_secret,
+            sync_time_entries,
+            resume_tracking,
+            update_settings,
+            undo_last_edit,
+            unlock_app,
+            upsert_day,
+            validate_ssid_pattern,
+            list_networks,
+            test_network
+        ])
         .build(tauri::generate_context!())
         .expect("error while building tauri application")
         .run(|_app_handle, event| match event {
@@ -86,98 +485,2047 @@ fn main() {
         });
 }
 
+/// Quits once the tracking loop has stopped, the open session and any manual
+/// timer have been ended now, and the database is fully written out.
+fn shutdown(app_handle: &tauri::AppHandle) {
+    if wind_down(app_handle) {
+        app_handle.exit(0);
+    }
+}
 
+/// Everything `shutdown` does before exiting. Returns false if another
+/// caller is already shutting down.
+fn wind_down(app_handle: &tauri::AppHandle) -> bool {
+    let state = app_handle.state::<AppState>();
+    if state.shutting_down.swap(true, Ordering::SeqCst) {
+        return false;
+    }
+    info!("Shutting down");
 
-fn create_db_connection(
-    app_handle: &tauri::AppHandle,
-) -> Result<Connection, Box<dyn std::error::Error>> {
-    let app_data_dir = app_data_dir(&app_handle.config()).expect("Failed to get app data dir");
-    fs::create_dir_all(&app_data_dir)?;
-    let db_path = app_data_dir.join("connections.db");
-
-    let db = Connection::open(db_path)?;
-    db.execute(
-        "CREATE TABLE IF NOT EXISTS connections (
-            date TEXT PRIMARY KEY,
-            earliest TEXT NOT NULL,
-            latest TEXT NOT NULL
-        )",
-        [],
-    )?;
-    Ok(db)
+    let (reply_tx, reply_rx) = mpsc::channel();
+    let sent = state.signals.send(LoopSignal::Shutdown(reply_tx));
+    if sent.is_err() || reply_rx.recv_timeout(SHUTDOWN_TIMEOUT).is_err() {
+        warn!("Tracking loop didn't stop, shutting down anyway");
+    }
+
+    if let Err(e) = end_session(app_handle) {
+        error!("Error ending session: {}", e);
+    }
+    {
+        let db = state.db.lock_unpoisoned();
+        let now = state.clock.now();
+        let stopped = DayBoundary::load(&db)
+            .map_err(Error::from)
+            .and_then(|boundary| {
+                let id = state.manual.lock_unpoisoned().stop(&db, now, boundary)?;
+                if id.is_some() {
+                    insert_connection(&db, &MANUAL_PRESENCE, now, boundary)?;
+                }
+                Ok(id)
+            });
+        match stopped {
+            Ok(Some(id)) => info!("Manual session {} ended", id),
+            Ok(None) => {}
+            Err(e) => error!("Error stopping manual timer: {}", e),
+        }
+        // Nothing's running any more
+        if let Err(e) = heartbeat::record(&db, now, None, None) {
+            error!("Error recording heartbeat: {}", e);
+        }
+        if let Err(e) = database::checkpoint(&db) {
+            error!("Error checkpointing database: {}", e);
+        }
+    }
+    logging::flush();
+    true
+}
+
+/// Tells the user the database was found damaged at startup, and what was
+/// done about it.
+fn notify_recovery(app_handle: &tauri::AppHandle, recovery: &backup::Recovery) {
+    let body = match (&recovery.restored, &recovery.moved_to) {
+        (Some(name), Some(moved_to)) => format!(
+            "It was restored from the backup {}, so anything recorded since may be \
+             missing. The damaged copy is at {}.",
+            name,
+            moved_to.display()
+        ),
+        _ => "There's no backup to restore, so export what you can and report a bug.".to_string(),
+    };
+    notifications::show(app_handle, "The database was damaged", &body);
 }
 
-fn get_connection_log(db: MutexGuard<Connection>) -> Result<Vec<ConnectionLog>> {
-    let mut stmt =
-        db.prepare("SELECT date, earliest, latest FROM connections ORDER BY date DESC")?;
-    let logs = stmt.query_map([], |row| {
-        Ok(ConnectionLog {
-            date: row.get(0)?,
-            earliest: row.get(1)?,
-            latest: row.get(2)?,
+fn toggle_window(app_handle: &tauri::AppHandle) -> Result<(), Error> {
+    let window = app_handle
+        .get_window("main")
+        .ok_or("Main window not found")?;
+    if window.is_visible()? {
+        window.hide()?;
+        app_lock::lock(app_handle);
+    } else if app_lock::SYSTEM_AUTHENTICATION && app_lock::is_locked(app_handle)? {
+        // Wait for the prompt off the main thread, which has to keep running
+        // to show it, and only show the window once it's passed. A passcode
+        // is asked for by the window itself instead.
+        let app_handle = app_handle.clone();
+        thread::spawn(move || {
+            let shown = app_lock::unlock(&app_handle, None).and_then(|()| show_window(&window));
+            if let Err(e) = shown {
+                error!("Error unlocking: {}", e);
+            }
+        });
+    } else {
+        show_window(&window)?;
+    }
+    Ok(())
+}
+
+fn show_window(window: &tauri::Window) -> Result<(), Error> {
+    window.show()?;
+    window.set_focus()?;
+    #[cfg(target_os = "macos")]
+    window.set_skip_taskbar(false)?;
+    Ok(())
+}
+
+/// Makes `shortcut` (e.g. "CmdOrCtrl+Shift+T") show or hide the window from
+/// anywhere, like the tray's "Show/Hide". Does nothing if it's empty.
+fn register_toggle_shortcut(app_handle: &tauri::AppHandle, shortcut: &str) -> Result<(), Error> {
+    let shortcut = shortcut.trim();
+    if shortcut.is_empty() {
+        return Ok(());
+    }
+    let handle = app_handle.clone();
+    app_handle
+        .global_shortcut_manager()
+        .register(shortcut, move || {
+            if let Err(e) = toggle_window(&handle) {
+                error!("Error toggling window: {}", e);
+            }
         })
-    })?;
+        .map_err(|e| format!("Couldn't use shortcut '{}': {}", shortcut, e))?;
+    Ok(())
+}
 
-    logs.collect()
+fn unregister_shortcut(app_handle: &tauri::AppHandle, shortcut: &str) -> Result<(), Error> {
+    let shortcut = shortcut.trim();
+    if shortcut.is_empty() {
+        return Ok(());
+    }
+    Ok(app_handle.global_shortcut_manager().unregister(shortcut)?)
 }
 
+/// Sets the global shortcut that shows or hides the window; empty turns it
+/// off.
 #[tauri::command]
-fn get_connections(state: tauri::State<AppState>) -> Result<Vec<ConnectionLog>, String> {
-    // return Ok(vec![]);
-    let db = state.db.lock().unwrap();
-    get_connection_log(db).map_err(|e| e.to_string())
+fn set_toggle_shortcut(app_handle: tauri::AppHandle, shortcut: String) -> Result<(), Error> {
+    update_setting(&app_handle, |settings| settings.toggle_shortcut = shortcut)
+}
+
+/// Runs the tracking loop as a task of its own, restarting it if it dies and
+/// flagging it as stalled if it stops coming round.
+async fn supervise_tracking_loop(
+    app_handle: tauri::AppHandle,
+    signals: Arc<AsyncMutex<UnboundedReceiver<LoopSignal>>>,
+) {
+    let mut worker = spawn_tracking_loop(&app_handle, &signals);
+    let mut watchdog = time::interval(WATCHDOG_INTERVAL);
+    watchdog.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    watchdog.tick().await;
+    let mut last_tick = Local::now().timestamp();
+    loop {
+        let died = tokio::select! {
+            _ = &mut worker => true,
+            _ = watchdog.tick() => false,
+        };
+        if app_handle
+            .state::<AppState>()
+            .shutting_down
+            .load(Ordering::SeqCst)
+        {
+            return;
+        }
+        if died {
+            warn!("Tracking loop died, restarting it");
+            worker = spawn_tracking_loop(&app_handle, &signals);
+            continue;
+        }
+        let now = Local::now().timestamp();
+        // A long gap between our own ticks means the machine was asleep, so
+        // the loop hasn't had a chance to run either
+        let slept = now - last_tick > 2 * WATCHDOG_INTERVAL.as_secs() as i64;
+        last_tick = now;
+        if slept {
+            continue;
+        }
+
+        let state = app_handle.state::<AppState>();
+        let heartbeat = state.heartbeat.load(Ordering::SeqCst);
+        let stalled = now - heartbeat > STALL_THRESHOLD.as_secs() as i64;
+        if stalled == state.stalled.swap(stalled, Ordering::SeqCst) {
+            continue;
+        }
+        let result = if stalled {
+            warn!("Tracking loop hasn't run since {}", heartbeat);
+            tray::show_stalled(&app_handle)
+        } else {
+            info!("Tracking loop recovered");
+            tray::update_summary(&app_handle)
+        }
+        .and_then(|()| tray::update_icon(&app_handle));
+        if let Err(e) = result {
+            error!("Error updating tray: {}", e);
+        }
+        let _ = app_handle.emit_all("tracking-stalled", stalled);
+    }
+}
+
+fn spawn_tracking_loop(
+    app_handle: &tauri::AppHandle,
+    signals: &Arc<AsyncMutex<UnboundedReceiver<LoopSignal>>>,
+) -> JoinHandle<()> {
+    let app_handle = app_handle.clone();
+    let signals = Arc::clone(signals);
+    tauri::async_runtime::spawn(async move {
+        run_tracking_loop(app_handle, &mut *signals.lock().await).await
+    })
+}
+
+/// Checks presence, then waits for the next poll or a signal, whichever
+/// comes first. Checks and anything else that blocks, such as detectors
+/// running commands or probing hosts and the database, run on the blocking
+/// pool so signals are still taken in the meantime.
+async fn run_tracking_loop(
+    app_handle: tauri::AppHandle,
+    signals: &mut UnboundedReceiver<LoopSignal>,
+) {
+    let state = app_handle.state::<AppState>();
+    let mut watching_network = true;
+    let mut last_present = Instant::now();
+    let mut reply = None;
+    loop {
+        state
+            .heartbeat
+            .store(Local::now().timestamp(), Ordering::SeqCst);
+        let check_started = Instant::now();
+        // A panic during one check (e.g. in a platform API) mustn't stop
+        // tracking for good
+        let result = blocking(&app_handle, run_check).await.unwrap_or_else(|| {
+            warn!("Presence check panicked");
+            Err("Presence check panicked".into())
+        });
+        if let Ok(true) = result {
+            last_present = Instant::now();
+        }
+        if let Some(reply) = reply.take() {
+            let _ = reply.send(result.map_err(String::from));
+        }
+
+        // Wait until something warrants another check, counting time spent
+        // on the check itself (e.g. waiting on a probe) towards the interval
+        let poll_interval = blocking(&app_handle, move |app_handle| {
+            poll_interval(app_handle, watching_network, last_present)
+        })
+        .await
+        .unwrap_or(FALLBACK_POLL_INTERVAL)
+        .saturating_sub(check_started.elapsed());
+        let next_check = time::sleep(poll_interval);
+        tokio::pin!(next_check);
+        loop {
+            let signal = tokio::select! {
+                _ = &mut next_check => break,
+                signal = signals.recv() => signal,
+            };
+            match signal {
+                Some(LoopSignal::NetworkChanged) | Some(LoopSignal::Wake) => {
+                    // Give the network a moment to finish associating
+                    time::sleep(NETWORK_SETTLE_DELAY).await;
+                    break;
+                }
+                Some(LoopSignal::CheckNow(reply_tx)) => {
+                    reply = reply_tx;
+                    break;
+                }
+                Some(LoopSignal::Sleep) => {
+                    // Whatever happens while asleep isn't presence, so close
+                    // the session now rather than at the first poll after wake
+                    if let Some(Err(e)) = blocking(&app_handle, end_session).await {
+                        error!("Error ending session before sleep: {}", e);
+                    }
+                }
+                Some(LoopSignal::ScreenLocked) => {
+                    if let Some(Err(e)) = blocking(&app_handle, screen_locked).await {
+                        error!("Error pausing for screen lock: {}", e);
+                    }
+                }
+                Some(LoopSignal::ScreenUnlocked) => {
+                    if state.locked.swap(false, Ordering::SeqCst) {
+                        info!("Screen unlocked, resuming checks");
+                        break;
+                    }
+                }
+                Some(LoopSignal::Shutdown(reply_tx)) => {
+                    info!("Tracking loop stopping");
+                    let _ = reply_tx.send(());
+                    return;
+                }
+                Some(LoopSignal::NetworkWatchStopped) => {
+                    info!("Network notifications stopped, falling back to polling");
+                    watching_network = false;
+                    break;
+                }
+                None => {
+                    (&mut next_check).await;
+                    break;
+                }
+            }
+        }
+    }
 }
 
-fn get_current_wifi_ssid() -> Option<String> {
-    // TODO support Windows
-    let output = Command::new("networksetup")
-        .args(&["-getairportnetwork", "en0"])
-        .output()
-        .expect("Failed to execute networksetup command");
+/// Runs `f` on the blocking pool, or returns `None` if it panicked.
+async fn blocking<T: Send + 'static>(
+    app_handle: &tauri::AppHandle,
+    f: impl FnOnce(&tauri::AppHandle) -> T + Send + 'static,
+) -> Option<T> {
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn_blocking(move || f(&app_handle))
+        .await
+        .ok()
+}
 
-    if output.status.success() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        // The output format is typically: "Current Wi-Fi Network: SSID_NAME"
-        stdout.split(": ").nth(1).map(|s| s.trim().to_string())
+/// How long to wait for a signal before checking anyway: the configured
+/// interval when polling, backed off on battery and when the office hasn't
+/// been seen for a while.
+fn poll_interval(
+    app_handle: &tauri::AppHandle,
+    watching_network: bool,
+    last_present: Instant,
+) -> Duration {
+    let mut interval = if watching_network {
+        FALLBACK_POLL_INTERVAL
     } else {
-        println!(
-            "Error executing networksetup command: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-        None
-    }
-}
-
-fn insert_connection(state: &tauri::State<AppState>) -> Result<(), String> {
-    let now = Local::now();
-    let date = now.format("%Y-%m-%d").to_string();
-    let time = now.format("%H:%M").to_string();
-
-    let db = state.db.lock().unwrap();
-    return db
-        .execute(
-            "INSERT INTO connections (date, earliest, latest) 
-         VALUES (?1, ?2, ?2) 
-         ON CONFLICT(date) DO UPDATE SET 
-         earliest = MIN(earliest, ?2),
-         latest = MAX(latest, ?2)",
-            &[&date, &time],
+        let state = app_handle.state::<AppState>();
+        let db = state.db.lock_unpoisoned();
+        let seconds = settings::get_f64(
+            &db,
+            settings::POLL_INTERVAL_SECONDS,
+            settings::DEFAULT_POLL_INTERVAL_SECONDS,
         )
-        .map(|_| ())
-        .map_err(|e| e.to_string());
+        .unwrap_or(settings::DEFAULT_POLL_INTERVAL_SECONDS);
+        Duration::from_secs_f64(seconds).clamp(MIN_POLL_INTERVAL, MAX_POLL_INTERVAL)
+    };
+    if power_events::on_battery() {
+        interval *= BACKOFF_FACTOR;
+    }
+    if last_present.elapsed() > ABSENT_BACKOFF_AFTER {
+        interval *= BACKOFF_FACTOR;
+    }
+    interval.min(MAX_POLL_INTERVAL.max(FALLBACK_POLL_INTERVAL))
 }
 
-fn check_wifi_connection(state: tauri::State<AppState>) -> Result<(), String> {
-    let target_ssid = "eduroam";
-    if let Some(current_ssid) = get_current_wifi_ssid() {
-        println!("Current WiFi SSID: {}", current_ssid);
-        if current_ssid == target_ssid {
-            println!("SSID matched, inserting connection");
-            return insert_connection(&state);
+/// Runs one presence check unless tracking is paused, returning whether
+/// presence was detected.
+fn run_check(app_handle: &tauri::AppHandle) -> Result<bool, Error> {
+    let state = app_handle.state::<AppState>();
+    if state.paused.load(Ordering::SeqCst) {
+        debug!("Tracking paused, skipping check");
+        return Err("Tracking is paused".into());
+    }
+    if state.locked.load(Ordering::SeqCst) {
+        debug!("Screen locked, skipping check");
+        return Err("The screen is locked".into());
+    }
+
+    let now = state.clock.now();
+    let result = check_presence(app_handle);
+    *state.check_error.lock_unpoisoned() = result.as_ref().err().map(ToString::to_string);
+    match &result {
+        Ok(present) => {
+            *state.last_check.lock_unpoisoned() = Some(LastCheck {
+                at: now,
+                present: *present,
+            });
+            if let Err(e) = tray::update_summary(app_handle) {
+                error!("Error updating tray: {}", e);
+            }
         }
-    } else {
-        println!("No WiFi connection detected");
+        Err(e) => error!("Error checking presence: {}", e),
+    }
+    if let Err(e) = tray::update_icon(app_handle) {
+        error!("Error updating tray icon: {}", e);
+    }
+    result
+}
+
+#[tauri::command]
+fn get_connections(
+    state: tauri::State<AppState>,
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    location: Option<String>,
+) -> Result<Vec<ConnectionLog>, Error> {
+    for date in [&from, &to].into_iter().flatten() {
+        validate_date(date)?;
+    }
+    if limit.is_some_and(|limit| limit < 0) || offset.is_some_and(|offset| offset < 0) {
+        return Err("limit and offset must not be negative".into());
+    }
+
+    let db = state.readers.get()?;
+    let query = LogQuery {
+        from: from.as_deref(),
+        to: to.as_deref(),
+        limit,
+        offset,
+        location: location.as_deref(),
+    };
+    Ok(connections::get_connection_log(&db, &query)?)
+}
+
+/// Summarises the seven days starting at `week_start` (YYYY-MM-DD).
+#[tauri::command]
+fn get_weekly_summary(
+    state: tauri::State<AppState>,
+    week_start: String,
+    location: Option<String>,
+) -> Result<reports::WeeklySummary, Error> {
+    let week_start = parse_date(&week_start)?;
+    let db = state.readers.get()?;
+    let boundary = DayBoundary::load(&db)?;
+    Ok(reports::weekly_summary(
+        &db,
+        week_start,
+        boundary.date(Local::now()),
+        boundary,
+        location.as_deref(),
+    )?)
+}
+
+#[tauri::command]
+fn get_monthly_summary(
+    state: tauri::State<AppState>,
+    year: i32,
+    month: u32,
+    location: Option<String>,
+) -> Result<reports::MonthlySummary, Error> {
+    let db = state.readers.get()?;
+    Ok(reports::monthly_summary(
+        &db,
+        year,
+        month,
+        today(&db)?,
+        location.as_deref(),
+    )?)
+}
+
+/// Days from `from` to `to` (YYYY-MM-DD, inclusive) that stand out from the
+/// usual, e.g. arriving hours late or nothing on a usual office day.
+#[tauri::command]
+fn get_anomalies(
+    state: tauri::State<AppState>,
+    from: String,
+    to: String,
+) -> Result<Vec<anomalies::Anomaly>, Error> {
+    let (from, to) = (parse_date(&from)?, parse_date(&to)?);
+    let db = state.readers.get()?;
+    let boundary = DayBoundary::load(&db)?;
+    Ok(anomalies::anomalies(
+        &db,
+        from,
+        to,
+        boundary.date(Local::now()),
+        boundary,
+    )?)
+}
+
+/// The running flexitime balance against the work schedule in settings, or
+/// `None` if there isn't one.
+#[tauri::command]
+fn get_balance(state: tauri::State<AppState>) -> Result<Option<flexitime::Balance>, Error> {
+    let db = state.readers.get()?;
+    Ok(flexitime::balance(&db, today(&db)?)?)
+}
+
+/// Progress towards the weekly hours target for the current week.
+#[tauri::command]
+fn get_goal_progress(
+    state: tauri::State<AppState>,
+    location: Option<String>,
+) -> Result<reports::GoalProgress, Error> {
+    let db = state.readers.get()?;
+    let target = settings::get_f64(
+        &db,
+        settings::WEEKLY_TARGET_HOURS,
+        settings::DEFAULT_WEEKLY_TARGET_HOURS,
+    )?;
+    Ok(reports::goal_progress(
+        &db,
+        today(&db)?,
+        target,
+        location.as_deref(),
+    )?)
+}
+
+/// Turns clock in/out notifications on or off.
+#[tauri::command]
+fn set_notifications_enabled(app_handle: tauri::AppHandle, enabled: bool) -> Result<(), Error> {
+    update_setting(&app_handle, |settings| {
+        settings.notifications_enabled = enabled
+    })
+}
+
+/// Configures the go-home reminder: remind after `hours` on-site in a day (0
+/// turns it off), then again every `snooze_minutes`.
+#[tauri::command]
+fn set_go_home_reminder(
+    app_handle: tauri::AppHandle,
+    hours: f64,
+    snooze_minutes: u32,
+) -> Result<(), Error> {
+    update_setting(&app_handle, |settings| {
+        settings.go_home_after_hours = hours;
+        settings.go_home_snooze_minutes = snooze_minutes;
+    })
+}
+
+/// Silences the go-home reminder for the next `minutes`.
+#[tauri::command]
+fn snooze_go_home_reminder(state: tauri::State<AppState>, minutes: u32) {
+    let until = Local::now() + chrono::Duration::minutes(minutes as i64);
+    state.go_home.lock_unpoisoned().snooze(until);
+}
+
+/// Sets how many minutes presence can drop out for without ending the current
+/// session. 0 ends sessions on the first missed check.
+#[tauri::command]
+fn set_grace_period(app_handle: tauri::AppHandle, minutes: u32) -> Result<(), Error> {
+    update_setting(&app_handle, |settings| {
+        settings.grace_period_minutes = minutes
+    })
+}
+
+/// Sets how often to check, in seconds, when network change notifications
+/// aren't available.
+#[tauri::command]
+fn set_poll_interval(app_handle: tauri::AppHandle, seconds: u32) -> Result<(), Error> {
+    update_setting(&app_handle, |settings| {
+        settings.poll_interval_seconds = seconds
+    })
+}
+
+/// Sets the shortest detected session worth keeping, in minutes. Shorter
+/// sessions are discarded when they end and ignored in reports.
+#[tauri::command]
+fn set_min_session_minutes(app_handle: tauri::AppHandle, minutes: u32) -> Result<(), Error> {
+    update_setting(&app_handle, |settings| {
+        settings.min_session_minutes = minutes
+    })
+}
+
+/// Sets the hour after midnight (0-23) at which the tracking day rolls over,
+/// e.g. 4 so that working until 2am still counts towards the previous day.
+#[tauri::command]
+fn set_day_boundary_hour(app_handle: tauri::AppHandle, hour: u32) -> Result<(), Error> {
+    update_setting(&app_handle, |settings| settings.day_boundary_hour = hour)
+}
+
+/// Sets the weekly hours target, e.g. 40.
+#[tauri::command]
+fn set_weekly_target(app_handle: tauri::AppHandle, hours: f64) -> Result<(), Error> {
+    update_setting(&app_handle, |settings| settings.weekly_target_hours = hours)
+}
+
+#[tauri::command]
+fn get_settings(state: tauri::State<AppState>) -> Result<Settings, Error> {
+    let db = state.readers.get()?;
+    Ok(Settings::load(&db)?)
+}
+
+/// Validates and saves every setting at once, applying any that take effect
+/// immediately and emitting `settings-changed` with the result.
+#[tauri::command]
+fn update_settings(
+    app_handle: tauri::AppHandle,
+    mut settings: Settings,
+) -> Result<Settings, Error> {
+    if settings.http_api_enabled && settings.http_api_token.trim().is_empty() {
+        settings.http_api_token = http_api::new_token();
+    }
+    settings.validate()?;
+    let state = app_handle.state::<AppState>();
+    let previous = {
+        let db = state.db.lock_unpoisoned();
+        Settings::load(&db)?
+    };
+    if settings.app_lock_enabled != previous.app_lock_enabled {
+        if settings.app_lock_enabled {
+            if !app_lock::SYSTEM_AUTHENTICATION && !tracker_core::app_lock::has_passcode()? {
+                return Err("Set a passcode for the app lock first".into());
+            }
+        } else {
+            app_lock::require_unlocked(&app_handle)?;
+        }
+    }
+
+    // Do the part most likely to fail before anything is saved
+    if settings.autostart_enabled != previous.autostart_enabled {
+        if settings.autostart_enabled {
+            autostart::enable()?;
+        } else {
+            autostart::disable()?;
+        }
+        app_handle
+            .tray_handle()
+            .get_item("autostart")
+            .set_selected(settings.autostart_enabled)?;
+    }
+    if settings.toggle_shortcut.trim() != previous.toggle_shortcut.trim() {
+        register_toggle_shortcut(&app_handle, &settings.toggle_shortcut)?;
+        unregister_shortcut(&app_handle, &previous.toggle_shortcut)?;
+    }
+    if settings.http_api_enabled != previous.http_api_enabled
+        || settings.http_api_port != previous.http_api_port
+    {
+        let mut server = state.http_api.lock_unpoisoned();
+        *server = None;
+        if settings.http_api_enabled {
+            *server = Some(http_api::start(&app_handle, settings.http_api_port)?);
+        }
+    }
+    {
+        let db = state.db.lock_unpoisoned();
+        settings.save(&db)?;
+    }
+
+    if settings.detection_differs(&previous) {
+        if !settings.geofences.is_empty() {
+            app_handle.run_on_main_thread(location::start)?;
+        }
+        *state.detector.lock_unpoisoned() = build_detector(&settings)?;
+        let _ = state.signals.send(LoopSignal::CheckNow(None));
+    }
+    // Don't stay paused for a lock that no longer pauses
+    if !settings.pause_when_locked && state.locked.swap(false, Ordering::SeqCst) {
+        let _ = state.signals.send(LoopSignal::CheckNow(None));
     }
+    if settings.holiday_country != previous.holiday_country {
+        let app_handle = app_handle.clone();
+        thread::spawn(move || {
+            if let Err(e) = fetch_public_holidays(&app_handle) {
+                error!("Error fetching public holidays: {}", e);
+            }
+        });
+    }
+    if settings.weekly_target_hours != previous.weekly_target_hours
+        || settings.day_boundary_hour != previous.day_boundary_hour
+    {
+        tray::update_summary(&app_handle)?;
+    }
+    emit(&app_handle, "settings-changed", settings.clone());
+    Ok(settings)
+}
+
+/// Applies a change to a single setting through `update_settings`.
+fn update_setting(
+    app_handle: &tauri::AppHandle,
+    change: impl FnOnce(&mut Settings),
+) -> Result<(), Error> {
+    let mut settings = {
+        let state = app_handle.state::<AppState>();
+        let db = state.db.lock_unpoisoned();
+        Settings::load(&db)?
+    };
+    change(&mut settings);
+    update_settings(app_handle.clone(), settings).map(|_| ())
+}
+
+fn build_detector(settings: &Settings) -> Result<Box<dyn PresenceDetector>, String> {
+    settings.detector()
+}
+
+/// Returns hours tracked on every day of `year`, for a contribution-graph
+/// style heatmap.
+#[tauri::command]
+fn get_heatmap(
+    state: tauri::State<AppState>,
+    year: i32,
+    location: Option<String>,
+) -> Result<Vec<reports::HeatmapDay>, Error> {
+    let db = state.readers.get()?;
+    Ok(reports::heatmap(&db, year, location.as_deref())?)
+}
+
+/// Writes `table` ("connections" by default, or "sessions") to a CSV file at
+/// `path`, optionally limited to a date range and a subset of columns.
+/// Returns the number of rows written.
+#[tauri::command]
+fn export_csv(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    path: String,
+    from: Option<String>,
+    to: Option<String>,
+    table: Option<String>,
+    columns: Option<Vec<String>>,
+) -> Result<usize, Error> {
+    app_lock::require_unlocked(&app_handle)?;
+    for date in [&from, &to].into_iter().flatten() {
+        validate_date(date)?;
+    }
+    let db = state.readers.get()?;
+    Ok(export::export_csv(
+        &db,
+        Path::new(&path),
+        table.as_deref().unwrap_or("connections"),
+        from.as_deref(),
+        to.as_deref(),
+        columns.as_deref(),
+    )?)
+}
+
+/// Creates or corrects a day's times (HH:MM), for days the tracker missed or
+/// got wrong.
+#[tauri::command]
+fn upsert_day(
+    app_handle: tauri::AppHandle,
+    date: String,
+    earliest: String,
+    latest: String,
+) -> Result<(), Error> {
+    validate_date(&date)?;
+    let state = app_handle.state::<AppState>();
+    let mut db = state.db.lock_unpoisoned();
+    edits::upsert_day(&mut db, &date, &earliest, &latest)?;
+    emit_day(&app_handle, &db, &date);
+    Ok(())
+}
+
+/// Annotates a recorded day. An empty or missing note clears it.
+#[tauri::command]
+fn set_day_note(
+    app_handle: tauri::AppHandle,
+    date: String,
+    note: Option<String>,
+) -> Result<(), Error> {
+    validate_date(&date)?;
+    let state = app_handle.state::<AppState>();
+    let db = state.db.lock_unpoisoned();
+    let note = note
+        .as_deref()
+        .map(str::trim)
+        .filter(|note| !note.is_empty());
+    if !edits::set_day_note(&db, &date, note)? {
+        return Err(format!("No record for {}", date).into());
+    }
+    emit_day(&app_handle, &db, &date);
+    Ok(())
+}
+
+/// Tags a day as office, WFH, sick, vacation or public holiday. Passing no
+/// `day_type` removes the tag. Days don't need recorded presence to be tagged.
+#[tauri::command]
+fn set_day_type(
+    state: tauri::State<AppState>,
+    date: String,
+    day_type: Option<day_types::DayType>,
+) -> Result<(), Error> {
+    validate_date(&date)?;
+    let db = state.db.lock_unpoisoned();
+    Ok(day_types::set_day_type(&db, &date, day_type)?)
+}
+
+/// Tagged days between `from` and `to` (YYYY-MM-DD, inclusive).
+#[tauri::command]
+fn get_day_types(
+    state: tauri::State<AppState>,
+    from: String,
+    to: String,
+) -> Result<Vec<day_types::TaggedDay>, Error> {
+    validate_date(&from)?;
+    validate_date(&to)?;
+    let db = state.readers.get()?;
+    Ok(day_types::get_day_types(&db, &from, &to)?)
+}
+
+/// Public holidays between `from` and `to` (YYYY-MM-DD, inclusive) in the
+/// country and region in settings, as far as they've been fetched.
+#[tauri::command]
+fn get_public_holidays(
+    state: tauri::State<AppState>,
+    from: String,
+    to: String,
+) -> Result<Vec<holidays::Holiday>, Error> {
+    validate_date(&from)?;
+    validate_date(&to)?;
+    let db = state.readers.get()?;
+    Ok(holidays::holidays(&db, &from, &to)?)
+}
+
+/// Deletes a day and its sessions. Returns whether the day existed.
+#[tauri::command]
+fn delete_day(state: tauri::State<AppState>, date: String) -> Result<bool, Error> {
+    validate_date(&date)?;
+    let mut db = state.db.lock_unpoisoned();
+    let deleted = edits::delete_day(&mut db, &date)?;
+    if parse_date(&date)? == today(&db)? {
+        // Don't keep extending a session that no longer exists
+        *state.sessions.lock_unpoisoned() = SessionTracker::default();
+    }
+    Ok(deleted)
+}
+
+/// Reverts the last manual edit, e.g. a deleted day, returning it, or `None`
+/// if there's nothing left to undo.
+#[tauri::command]
+fn undo_last_edit(app_handle: tauri::AppHandle) -> Result<Option<audit::Edit>, Error> {
+    let state = app_handle.state::<AppState>();
+    let db = state.db.lock_unpoisoned();
+    let edit = audit::undo(&db)?;
+    refresh_edited_days(&app_handle, &db, edit.as_ref())?;
+    Ok(edit)
+}
+
+/// Reapplies the last undone edit, returning it, or `None` if there's nothing
+/// to redo.
+#[tauri::command]
+fn redo_edit(app_handle: tauri::AppHandle) -> Result<Option<audit::Edit>, Error> {
+    let state = app_handle.state::<AppState>();
+    let db = state.db.lock_unpoisoned();
+    let edit = audit::redo(&db)?;
+    refresh_edited_days(&app_handle, &db, edit.as_ref())?;
+    Ok(edit)
+}
+
+fn refresh_edited_days(
+    app_handle: &tauri::AppHandle,
+    db: &Connection,
+    edit: Option<&audit::Edit>,
+) -> Result<(), Error> {
+    let Some(edit) = edit else {
+        return Ok(());
+    };
+    let today = today(db)?.to_string();
+    if edit.dates.contains(&today) {
+        // Start a fresh session rather than extend one that may be gone
+        *app_handle.state::<AppState>().sessions.lock_unpoisoned() = SessionTracker::default();
+    }
+    for date in &edit.dates {
+        emit_day(app_handle, db, date);
+    }
+    Ok(())
+}
 
+/// Turns on encryption at rest: stores a new key in the keychain and
+/// restarts, encrypting the database and its backups as it opens them.
+/// Does nothing if it's already on.
+#[tauri::command]
+fn encrypt_database(app_handle: tauri::AppHandle) -> Result<(), Error> {
+    if app_handle.state::<AppState>().db_key.is_some() {
+        return Ok(());
+    }
+    database::create_key()?;
+    if wind_down(&app_handle) {
+        app_handle.restart();
+    }
     Ok(())
 }
+
+#[tauri::command]
+fn get_database_location(state: tauri::State<AppState>) -> String {
+    state.db_path.to_string_lossy().into_owned()
+}
+
+/// Moves the database to `folder`, e.g. a synced one, or back to the data
+/// directory if `None`, then restarts the app to open it there. Tracking is
+/// stopped first so nothing's written to the old copy after it's taken.
+#[tauri::command]
+fn move_database(app_handle: tauri::AppHandle, folder: Option<String>) -> Result<(), Error> {
+    let folder = folder.map(PathBuf::from);
+    if folder.as_ref().is_some_and(|folder| !folder.is_absolute()) {
+        return Err("Choose a folder by its full path".into());
+    }
+    // Stands in for the database while it's moved, until the restart
+    let placeholder = Connection::open_in_memory()?;
+    if !wind_down(&app_handle) {
+        return Ok(());
+    }
+    let state = app_handle.state::<AppState>();
+    let db = std::mem::replace(&mut *state.db.lock_unpoisoned(), placeholder);
+    state.readers.close_idle();
+    let moved = database::relocate(
+        db,
+        &state.data_dir,
+        folder.as_deref(),
+        state.db_key.as_deref(),
+    );
+    // The old database is left as it was if the move failed, so carry on
+    // with it after the restart
+    if let Err(e) = moved {
+        notifications::show(&app_handle, "Couldn't move the database", &e.to_string());
+    }
+    app_handle.restart();
+    Ok(())
+}
+
+/// Backs up the database now, returning the new backup's file name.
+#[tauri::command]
+fn create_backup(state: tauri::State<AppState>) -> Result<String, Error> {
+    let db = state.readers.get()?;
+    let path = backup::create_backup(
+        &db,
+        &backup::backups_dir(&state.data_dir),
+        state.db_key.as_deref(),
+    )?;
+    Ok(path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default())
+}
+
+#[tauri::command]
+fn list_backups(state: tauri::State<AppState>) -> Result<Vec<backup::BackupInfo>, Error> {
+    Ok(backup::list_backups(&backup::backups_dir(&state.data_dir))?)
+}
+
+/// Replaces the database with the backup called `name` (as returned by
+/// `list_backups`).
+#[tauri::command]
+fn restore_backup(state: tauri::State<AppState>, name: String) -> Result<(), Error> {
+    let mut db = state.db.lock_unpoisoned();
+    backup::restore_backup(
+        &mut db,
+        &backup::backups_dir(&state.data_dir),
+        &name,
+        state.db_key.as_deref(),
+    )?;
+    // The open session may not exist in the restored data
+    *state.sessions.lock_unpoisoned() = SessionTracker::default();
+    Ok(())
+}
+
+/// Archives and deletes history older than the configured age now, rather
+/// than waiting for the hourly check. Archives are gzipped JSON exports that
+/// `import_json` can restore.
+#[tauri::command]
+fn prune_now(state: tauri::State<AppState>) -> Result<retention::PruneStats, Error> {
+    let db = state.db.lock_unpoisoned();
+    let before = retention::cutoff(&db, today(&db)?)?
+        .ok_or("Set how many months of history to keep first")?;
+    Ok(retention::prune(
+        &db,
+        before,
+        &retention::archives_dir(&state.data_dir),
+    )?)
+}
+
+/// Writes presence to an .ics file at `path`, with one event per session
+/// (`granularity` "session", the default) or per day ("day").
+#[tauri::command]
+fn export_ical(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    path: String,
+    from: Option<String>,
+    to: Option<String>,
+    granularity: Option<export::IcalGranularity>,
+) -> Result<usize, Error> {
+    app_lock::require_unlocked(&app_handle)?;
+    for date in [&from, &to].into_iter().flatten() {
+        validate_date(date)?;
+    }
+    let db = state.readers.get()?;
+    Ok(export::export_ical(
+        &db,
+        Path::new(&path),
+        from.as_deref(),
+        to.as_deref(),
+        granularity.unwrap_or(export::IcalGranularity::Session),
+    )?)
+}
+
+/// Bills `client` for the time tracked from `from` to `to` (YYYY-MM-DD) at
+/// the rate in settings, writing the next numbered invoice to `path` as a
+/// PDF. A client set up in settings gets their own address, rate and location.
+#[tauri::command]
+fn generate_invoice(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    path: String,
+    from: String,
+    to: String,
+    client: String,
+) -> Result<invoices::Invoice, Error> {
+    app_lock::require_unlocked(&app_handle)?;
+    let (from, to) = (parse_date(&from)?, parse_date(&to)?);
+    let db = state.db.lock_unpoisoned();
+    let issued = today(&db)?;
+    Ok(invoices::generate(
+        &db,
+        from,
+        to,
+        &client,
+        issued,
+        Path::new(&path),
+    )?)
+}
+
+/// Writes a timesheet from `from` to `to` (YYYY-MM-DD) to `path`, as a PDF if
+/// it ends in .pdf and HTML otherwise, with per-day rows, weekly totals and a
+/// signature line. `template` is a MiniJinja file to use instead of the
+/// built-in layout; for PDFs it's plain text laid out line by line.
+#[tauri::command]
+fn generate_timesheet(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    path: String,
+    from: String,
+    to: String,
+    template: Option<String>,
+    location: Option<String>,
+) -> Result<(), Error> {
+    app_lock::require_unlocked(&app_handle)?;
+    let (from, to) = (parse_date(&from)?, parse_date(&to)?);
+    let template = template
+        .map(std::fs::read_to_string)
+        .transpose()
+        .map_err(|e| format!("Couldn't read the template: {}", e))?;
+    let db = state.readers.get()?;
+    Ok(timesheet::generate(
+        &db,
+        from,
+        to,
+        location.as_deref(),
+        template.as_deref(),
+        Path::new(&path),
+    )?)
+}
+
+/// Renders a read-only HTML report from `from` to `to` (YYYY-MM-DD), to share
+/// outside the app, and writes it to `path` or, without one, publishes it to
+/// the configured folder or bucket. Returns where it went.
+#[tauri::command]
+async fn publish_report(
+    app_handle: tauri::AppHandle,
+    from: String,
+    to: String,
+    location: Option<String>,
+    path: Option<String>,
+) -> Result<String, Error> {
+    app_lock::require_unlocked(&app_handle)?;
+    let (from, to) = (parse_date(&from)?, parse_date(&to)?);
+    let (html, destination) = {
+        let state = app_handle.state::<AppState>();
+        let db = state.readers.get()?;
+        let html = publishing::render(&db, from, to, location.as_deref(), Local::now())?;
+        if let Some(path) = path {
+            std::fs::write(&path, html)?;
+            return Ok(path);
+        }
+        let destination = publishing::destination(&db)?
+            .ok_or("Choose where to publish reports in settings first")?;
+        (html, destination)
+    };
+    tauri::async_runtime::spawn_blocking(move || {
+        publishing::publish(&destination, &publishing::file_name(from, to), &html)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(Error::from)
+}
+
+/// Writes the whole database to a JSON file at `path`.
+#[tauri::command]
+fn export_json(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<AppState>,
+    path: String,
+) -> Result<(), Error> {
+    app_lock::require_unlocked(&app_handle)?;
+    let db = state.readers.get()?;
+    Ok(export::export_json(&db, Path::new(&path))?)
+}
+
+/// Writes a zip to attach to bug reports to `path`, with the app version,
+/// platform, detector, database integrity, redacted settings and the end
+/// of the logs; see `diagnostics::collect`.
+#[tauri::command]
+fn collect_diagnostics(app_handle: tauri::AppHandle, path: String) -> Result<(), Error> {
+    app_lock::require_unlocked(&app_handle)?;
+    let state = app_handle.state::<AppState>();
+    let detector = state.detector.lock_unpoisoned().name();
+    let version = app_handle.package_info().version.to_string();
+    let db = state.readers.get()?;
+    diagnostics::collect(
+        &db,
+        &diagnostics::AppInfo {
+            version: &version,
+            detector,
+        },
+        &logging::logs_dir(&state.data_dir),
+        Path::new(&path),
+    )?;
+    Ok(())
+}
+
+/// Merges a JSON export into the database. `merge_strategy` is
+/// "keep-earliest" to keep local records on conflict or "keep-latest" to
+/// overwrite them.
+#[tauri::command]
+fn import_json(
+    state: tauri::State<AppState>,
+    path: String,
+    merge_strategy: export::MergeStrategy,
+) -> Result<export::ImportStats, Error> {
+    let mut db = state.db.lock_unpoisoned();
+    Ok(export::import_json(
+        &mut db,
+        Path::new(&path),
+        merge_strategy,
+    )?)
+}
+
+/// Returns current, longest and past runs of consecutive office days. Missing
+/// weekends break a streak only if `weekends_break_streak` is set.
+#[tauri::command]
+fn get_streaks(
+    state: tauri::State<AppState>,
+    weekends_break_streak: Option<bool>,
+    location: Option<String>,
+) -> Result<reports::Streaks, Error> {
+    let db = state.readers.get()?;
+    Ok(reports::streaks(
+        &db,
+        today(&db)?,
+        weekends_break_streak.unwrap_or(false),
+        location.as_deref(),
+    )?)
+}
+
+/// Counts arrivals (each day's earliest time) in buckets of `bucket_minutes`,
+/// optionally limited to a date range.
+#[tauri::command]
+fn get_arrival_histogram(
+    state: tauri::State<AppState>,
+    bucket_minutes: i64,
+    from: Option<String>,
+    to: Option<String>,
+    location: Option<String>,
+) -> Result<Vec<reports::HistogramBucket>, Error> {
+    if !(1..=24 * 60).contains(&bucket_minutes) {
+        return Err("bucket_minutes must be between 1 and 1440".into());
+    }
+    for date in [&from, &to].into_iter().flatten() {
+        validate_date(date)?;
+    }
+    let db = state.readers.get()?;
+    Ok(reports::arrival_histogram(
+        &db,
+        bucket_minutes,
+        from.as_deref(),
+        to.as_deref(),
+        location.as_deref(),
+    )?)
+}
+
+/// Each day's hours from `from` to `to` (YYYY-MM-DD) with rolling averages
+/// of hours and arrival time over the `window` days up to it, 7 by default.
+#[tauri::command]
+fn get_trends(
+    state: tauri::State<AppState>,
+    from: String,
+    to: String,
+    window: Option<u32>,
+    location: Option<String>,
+) -> Result<Vec<reports::TrendPoint>, Error> {
+    let window = window.unwrap_or(DEFAULT_TREND_WINDOW);
+    if !(1..=MAX_TREND_WINDOW).contains(&window) {
+        return Err(format!("window must be between 1 and {}", MAX_TREND_WINDOW).into());
+    }
+    let (from, to) = (parse_date(&from)?, parse_date(&to)?);
+    let db = state.readers.get()?;
+    let boundary = DayBoundary::load(&db)?;
+    Ok(reports::trends(
+        &db,
+        from,
+        to,
+        window,
+        boundary,
+        location.as_deref(),
+    )?)
+}
+
+/// Hours per location between `from` and `to` (YYYY-MM-DD, inclusive), most
+/// first, with each one's share of the total.
+#[tauri::command]
+fn get_location_breakdown(
+    state: tauri::State<AppState>,
+    from: String,
+    to: String,
+) -> Result<Vec<reports::LocationTotal>, Error> {
+    validate_date(&from)?;
+    validate_date(&to)?;
+    let db = state.readers.get()?;
+    Ok(reports::location_breakdown(&db, &from, &to)?)
+}
+
+/// The tracking day it currently is, taking the day boundary into account.
+fn today(db: &Connection) -> Result<NaiveDate, Error> {
+    let boundary = DayBoundary::load(db)?;
+    Ok(boundary.date(Local::now()))
+}
+
+fn validate_date(date: &str) -> Result<(), String> {
+    parse_date(date).map(|_| ())
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid date '{}', expected YYYY-MM-DD", date))
+}
+
+/// Returns the individual presence intervals, optionally limited to one day.
+#[tauri::command]
+fn get_sessions(
+    state: tauri::State<AppState>,
+    date: Option<String>,
+    location: Option<String>,
+) -> Result<Vec<Session>, Error> {
+    let db = state.readers.get()?;
+    Ok(sessions::get_sessions(
+        &db,
+        date.as_deref(),
+        location.as_deref(),
+    )?)
+}
+
+/// Changes to days and sessions between `from` and `to` (YYYY-MM-DD), either
+/// optional, newest first: what changed, from what to what, and whether
+/// tracking, an edit, an import or a sync did it. At most `limit`, 500 by
+/// default.
+#[tauri::command]
+fn get_audit_log(
+    state: tauri::State<AppState>,
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<u32>,
+) -> Result<Vec<audit::AuditEntry>, Error> {
+    for date in from.iter().chain(&to) {
+        validate_date(date)?;
+    }
+    let db = state.readers.get()?;
+    Ok(audit::entries(
+        &db,
+        from.as_deref(),
+        to.as_deref(),
+        limit.unwrap_or(500),
+    )?)
+}
+
+/// Today's times so far, counting any open session up to now.
+#[tauri::command]
+fn get_today(
+    app_handle: tauri::AppHandle,
+    location: Option<String>,
+) -> Result<reports::TodaySummary, Error> {
+    today_summary(&app_handle, location.as_deref())
+}
+
+fn today_summary(
+    app_handle: &tauri::AppHandle,
+    location: Option<&str>,
+) -> Result<reports::TodaySummary, Error> {
+    let state = app_handle.state::<AppState>();
+    let live_sessions = live_session_ids(&state);
+    let db = state.readers.get()?;
+    let boundary = DayBoundary::load(&db)?;
+    Ok(reports::today_summary(
+        &db,
+        Local::now(),
+        boundary,
+        &live_sessions,
+        location,
+    )?)
+}
+
+/// The detected and manual sessions still running, if any.
+fn live_session_ids(state: &AppState) -> Vec<i64> {
+    state
+        .sessions
+        .lock_unpoisoned()
+        .live_session_id()
+        .into_iter()
+        .chain(state.manual.lock_unpoisoned().session_id())
+        .collect()
+}
+
+/// Pushes completed sessions to Toggl Track or Clockify, if that's set up,
+/// returning how many were sent. Talks to the service without holding the
+/// database, and stops at the first failure so the rest go next time.
+fn push_time_entries(app_handle: &tauri::AppHandle) -> Result<usize, Error> {
+    let state = app_handle.state::<AppState>();
+    let _pushing = state.time_sync.lock_unpoisoned();
+    let mut skip = live_session_ids(&state);
+    let (config, pending) = {
+        let db = state.readers.get()?;
+        let Some(config) = time_sync::load(&db)? else {
+            return Ok(0);
+        };
+        // Other devices push their own sessions
+        skip.extend(folder_sync::received(&db)?);
+        let pending = time_sync::pending(&db, &config, &skip)?;
+        (config, pending)
+    };
+    for session in &pending {
+        let entry_id = time_sync::push(&config, session)?;
+        let db = state.db.lock_unpoisoned();
+        time_sync::record_synced(&db, &config, session.id, &entry_id)?;
+    }
+    if !pending.is_empty() {
+        info!("Pushed {} sessions as time entries", pending.len());
+    }
+    Ok(pending.len())
+}
+
+/// Shares this device's sessions with the others through the sync folder or
+/// server, whichever is set, and merges in theirs.
+fn sync_with_devices(app_handle: &tauri::AppHandle) -> Result<folder_sync::SyncStats, Error> {
+    let folder = sync_folder(app_handle)?;
+    let server = sync_server(app_handle)?;
+    let stats = folder_sync::SyncStats {
+        written: folder.written + server.written,
+        read: folder.read + server.read,
+    };
+    if stats.read > 0 {
+        info!("Merged {} records from other devices", stats.read);
+        emit(app_handle, "sessions-synced", stats.clone());
+        let state = app_handle.state::<AppState>();
+        let db = state.readers.get()?;
+        tray::update_menu(app_handle, &db)?;
+    }
+    Ok(stats)
+}
+
+fn sync_folder(app_handle: &tauri::AppHandle) -> Result<folder_sync::SyncStats, Error> {
+    let state = app_handle.state::<AppState>();
+    let db = state.db.lock_unpoisoned();
+    let Some(folder) = folder_sync::folder(&db)? else {
+        return Ok(folder_sync::SyncStats::default());
+    };
+    Ok(folder_sync::sync(&db, Path::new(&folder))?)
+}
+
+/// Sends this device's changes to the sync server and merges in the other
+/// devices', talking to the server without holding the database. Changes
+/// that fail to send go next time.
+fn sync_server(app_handle: &tauri::AppHandle) -> Result<folder_sync::SyncStats, Error> {
+    let state = app_handle.state::<AppState>();
+    let _syncing = state.server_sync.lock_unpoisoned();
+    let (server, outgoing, cursor) = {
+        let db = state.db.lock_unpoisoned();
+        let Some(server) = server_sync::load(&db)? else {
+            return Ok(folder_sync::SyncStats::default());
+        };
+        let outgoing = server_sync::outgoing(&db)?;
+        let cursor = server_sync::cursor(&db)?;
+        (server, outgoing, cursor)
+    };
+    if !outgoing.is_empty() {
+        server_sync::push(&server, &outgoing)?;
+        server_sync::mark_pushed(&state.db.lock_unpoisoned(), &outgoing)?;
+    }
+    let incoming = server_sync::pull(&server, cursor)?;
+    let read = server_sync::merge(&state.db.lock_unpoisoned(), incoming)?;
+    Ok(folder_sync::SyncStats {
+        written: outgoing.len(),
+        read,
+    })
+}
+
+/// Sends last week's digest once it's Monday morning, unless it already has
+/// been.
+fn send_digest_if_due(app_handle: &tauri::AppHandle) -> Result<(), Error> {
+    let due = {
+        let state = app_handle.state::<AppState>();
+        let db = state.readers.get()?;
+        digest::due(&db, Local::now())?
+    };
+    let Some(week_start) = due else {
+        return Ok(());
+    };
+    // Marked sent even if the email fails, so a broken server doesn't mean
+    // a notification every hour
+    let result = deliver_digest(app_handle, week_start);
+    let state = app_handle.state::<AppState>();
+    digest::mark_sent(&state.db.lock_unpoisoned(), week_start)?;
+    result
+}
+
+/// Shows the digest for the week starting `week_start` as a notification,
+/// and emails it if that's set up. Talks to the mail server without holding
+/// the database.
+fn deliver_digest(app_handle: &tauri::AppHandle, week_start: NaiveDate) -> Result<(), Error> {
+    let state = app_handle.state::<AppState>();
+    let (digest, email) = {
+        let db = state.readers.get()?;
+        let digest = digest::build(&db, week_start, today(&db)?)?;
+        notifications::notify(app_handle, &db, &digest.subject(), &digest.body());
+        (digest, digest::email(&db)?)
+    };
+    if let Some(email) = email {
+        digest::send_email(&email, &digest)?;
+    }
+    Ok(())
+}
+
+/// Archives and deletes history older than the configured age, if any.
+fn prune_history(app_handle: &tauri::AppHandle) -> Result<(), Error> {
+    let state = app_handle.state::<AppState>();
+    let db = state.db.lock_unpoisoned();
+    let today = today(&db)?;
+    retention::prune_if_due(&db, today, &retention::archives_dir(&state.data_dir))?;
+    Ok(())
+}
+
+/// Notifies about unusual days since the last check, up to yesterday.
+fn notify_anomalies(app_handle: &tauri::AppHandle) -> Result<(), Error> {
+    let state = app_handle.state::<AppState>();
+    let db = state.db.lock_unpoisoned();
+    let boundary = DayBoundary::load(&db)?;
+    let found = anomalies::unnotified(&db, boundary.date(Local::now()), boundary)?;
+    notifications::unusual_days(app_handle, &db, &found);
+    Ok(())
+}
+
+/// Fetches this year's and next year's public holidays for the country in
+/// settings, unless they already have been. Talks to Nager.Date without
+/// holding the database.
+fn fetch_public_holidays(app_handle: &tauri::AppHandle) -> Result<(), Error> {
+    let state = app_handle.state::<AppState>();
+    let year = Local::now().year();
+    let (country, missing) = {
+        let db = state.readers.get()?;
+        let Some(missing) = holidays::missing_years(&db, &[year, year + 1])? else {
+            return Ok(());
+        };
+        missing
+    };
+    for year in missing {
+        let fetched = holidays::fetch(&country, year)?;
+        let db = state.db.lock_unpoisoned();
+        holidays::store(&db, &country, &fetched)?;
+    }
+    Ok(())
+}
+
+/// Creates or updates `date`'s event on Google Calendar, if that's set up.
+/// Talks to Google without holding the database.
+fn publish_calendar_day(app_handle: &tauri::AppHandle, date: &str) -> Result<(), Error> {
+    let state = app_handle.state::<AppState>();
+    let (config, event, existing) = {
+        let db = state.readers.get()?;
+        let Some(config) = google_calendar::load(&db)? else {
+            return Ok(());
+        };
+        let Some(event) = google_calendar::event_for_day(&db, &config, date)? else {
+            return Ok(());
+        };
+        let existing = google_calendar::event_id(&db, date)?;
+        (config, event, existing)
+    };
+    let token = google_calendar::access_token(&config)?;
+    let id = google_calendar::upsert_event(&config, &token, existing.as_deref(), &event)?;
+    let db = state.db.lock_unpoisoned();
+    Ok(google_calendar::record_event(&db, date, &id)?)
+}
+
+/// Shares the last week's daily totals with the team, if team mode is on.
+/// Talks to the endpoint without holding the database.
+fn publish_team_totals(app_handle: &tauri::AppHandle) -> Result<(), Error> {
+    let (config, days) = {
+        let db = app_handle.state::<AppState>().readers.get()?;
+        let Some(config) = team::load(&db)? else {
+            return Ok(());
+        };
+        let days = team::recent_totals(&db, today(&db)?)?;
+        (config, days)
+    };
+    Ok(team::publish(&config, &days)?)
+}
+
+fn write_daily_note(app_handle: &tauri::AppHandle, date: &str) -> Result<Option<PathBuf>, Error> {
+    let db = app_handle.state::<AppState>().readers.get()?;
+    Ok(daily_notes::write_day(&db, date)?)
+}
+
+/// Adds or updates `date`'s (YYYY-MM-DD) line in its daily note, as happens
+/// whenever a session ends, returning the note's path or `None` if daily
+/// notes are off or nothing was recorded that day.
+#[tauri::command]
+fn export_daily_note(app_handle: tauri::AppHandle, date: String) -> Result<Option<String>, Error> {
+    validate_date(&date)?;
+    Ok(write_daily_note(&app_handle, &date)?.map(|path| path.display().to_string()))
+}
+
+/// Signs in to Google in the system browser so office days can be published
+/// to the calendar in settings, which must be saved first.
+#[tauri::command]
+async fn connect_google_calendar(app_handle: tauri::AppHandle) -> Result<(), Error> {
+    let config = {
+        let state = app_handle.state::<AppState>();
+        let db = state.readers.get()?;
+        google_calendar::load(&db)?.ok_or("Set up Google Calendar in settings first")?
+    };
+    tauri::async_runtime::spawn_blocking(move || {
+        google_calendar::authorize(&config, |url| {
+            tauri::api::shell::open(&app_handle.shell_scope(), url, None)
+                .map_err(|e| format!("Couldn't open the browser: {}", e).into())
+        })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(Error::from)
+}
+
+/// Forgets the Google sign-in.
+#[tauri::command]
+fn disconnect_google_calendar() -> Result<(), Error> {
+    Ok(keychain::delete(google_calendar::TOKEN_ACCOUNT)?)
+}
+
+/// Pushes any completed sessions not yet sent to Toggl Track or Clockify,
+/// as happens whenever a session ends, returning how many were sent.
+#[tauri::command]
+async fn sync_time_entries(app_handle: tauri::AppHandle) -> Result<usize, Error> {
+    tauri::async_runtime::spawn_blocking(move || push_time_entries(&app_handle))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Syncs with other devices through the sync folder or server now, rather
+/// than when the next session ends.
+#[tauri::command]
+async fn sync_devices(app_handle: tauri::AppHandle) -> Result<folder_sync::SyncStats, Error> {
+    tauri::async_runtime::spawn_blocking(move || sync_with_devices(&app_handle))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// The code to enter on another device to pair it for syncing through the
+/// sync server. It decrypts everything synced, so needs the app unlocked.
+#[tauri::command]
+fn get_sync_pairing_code(
+    app_handle: tauri::AppHandle,
+    state: tauri::State<AppState>,
+) -> Result<String, Error> {
+    app_lock::require_unlocked(&app_handle)?;
+    let db = state.db.lock_unpoisoned();
+    Ok(server_sync::pairing_code(&db)?)
+}
+
+/// Pairs this device with others using a code from one of them, or unpairs
+/// it if `code` is empty.
+#[tauri::command]
+fn pair_sync_device(state: tauri::State<AppState>, code: String) -> Result<(), Error> {
+    let db = state.db.lock_unpoisoned();
+    Ok(server_sync::pair(&db, &code)?)
+}
+
+/// Off the main thread, since reading the current SSID runs a command.
+#[tauri::command]
+async fn get_status(app_handle: tauri::AppHandle) -> Result<TrackingStatus, String> {
+    tauri::async_runtime::spawn_blocking(move || status(&app_handle.state::<AppState>()))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Blocks while the current SSID is read, but takes no locks until it has
+/// been, so the tracking loop isn't held up.
+fn status(state: &AppState) -> TrackingStatus {
+    let current_ssid = wifi::get_current_wifi()
+        .ok()
+        .flatten()
+        .map(|connection| connection.ssid);
+    let last_check = state.last_check.lock_unpoisoned();
+    TrackingStatus {
+        current_ssid,
+        present: last_check.as_ref().is_some_and(|check| check.present),
+        last_check: last_check
+            .as_ref()
+            .map(|check| check.at.format("%Y-%m-%d %H:%M:%S").to_string()),
+        paused: state.paused.load(Ordering::SeqCst),
+        on_site_since: state.sessions.lock_unpoisoned().open_session_start(),
+        stalled: state.stalled.load(Ordering::SeqCst),
+        check_error: state.check_error.lock_unpoisoned().clone(),
+    }
+}
+
+/// What onboarding's test button shows once an office network is picked.
+#[derive(Serialize)]
+struct NetworkTest {
+    current_ssid: Option<String>,
+    /// Whether `current_ssid` is the picked network.
+    matches: bool,
+    /// Whether a check with the saved settings detected presence.
+    present: bool,
+}
+
+/// WiFi networks in range and ones sessions have been recorded on, so
+/// onboarding can offer a picker instead of asking for the SSID.
+#[tauri::command]
+async fn list_networks(state: tauri::State<'_, AppState>) -> Result<Vec<wifi::WifiNetwork>, Error> {
+    let known = {
+        let db = state.readers.get()?;
+        sessions::known_ssids(&db)?
+    };
+    tauri::async_runtime::spawn_blocking(move || wifi::list_networks(&known))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(Error::from)
+}
+
+/// Onboarding's test button: whether the machine is on the picked network
+/// (an SSID or glob/regex pattern), and what an immediate check with the
+/// saved settings finds, as with `check_now`.
+#[tauri::command]
+async fn test_network(
+    state: tauri::State<'_, AppState>,
+    ssid: String,
+) -> Result<NetworkTest, Error> {
+    let pattern = pattern::SsidPattern::parse(&ssid)?;
+    let current_ssid = tauri::async_runtime::spawn_blocking(wifi::get_current_wifi)
+        .await
+        .map_err(|e| e.to_string())??
+        .map(|connection| connection.ssid);
+    let matches = current_ssid
+        .as_deref()
+        .is_some_and(|ssid| pattern.matches(ssid));
+    Ok(NetworkTest {
+        current_ssid,
+        matches,
+        present: check_now(state).await?,
+    })
+}
+
+/// Asks the background loop to check presence immediately instead of waiting
+/// for the next poll, and returns whether presence was detected.
+#[tauri::command]
+async fn check_now(state: tauri::State<'_, AppState>) -> Result<bool, String> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    state
+        .signals
+        .send(LoopSignal::CheckNow(Some(reply_tx)))
+        .map_err(|e| e.to_string())?;
+
+    time::timeout(CHECK_NOW_TIMEOUT, reply_rx)
+        .await
+        .map_err(|_| "Timed out waiting for the presence check".to_string())?
+        .map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+fn pause_tracking(app_handle: tauri::AppHandle) -> Result<(), Error> {
+    set_paused(&app_handle, true)
+}
+
+#[tauri::command]
+fn resume_tracking(app_handle: tauri::AppHandle) -> Result<(), Error> {
+    set_paused(&app_handle, false)
+}
+
+/// Starts a manually timed session, e.g. for working from a café. While it
+/// runs, detected presence isn't recorded as separate sessions. Returns the
+/// session's id.
+#[tauri::command]
+fn start_manual_session(app_handle: tauri::AppHandle) -> Result<i64, Error> {
+    // Close any detected session so the two don't overlap
+    end_session(&app_handle)?;
+
+    let state = app_handle.state::<AppState>();
+    let db = state.db.lock_unpoisoned();
+    let now = state.clock.now();
+    let boundary = DayBoundary::load(&db)?;
+    let id = state
+        .manual
+        .lock_unpoisoned()
+        .start(&db, now, boundary)?
+        .ok_or("The manual timer is already running")?;
+    insert_connection(&db, &MANUAL_PRESENCE, now, boundary)?;
+
+    info!("Manual session {} started", id);
+    emit_session(&app_handle, &db, "session-started", id);
+    emit_day(&app_handle, &db, &boundary.date_string(now));
+    set_manual_tray_title(&app_handle, true)?;
+    Ok(id)
+}
+
+/// Stops the manual timer, returning the finished session's id.
+#[tauri::command]
+fn stop_manual_session(app_handle: tauri::AppHandle) -> Result<i64, Error> {
+    let state = app_handle.state::<AppState>();
+    let db = state.db.lock_unpoisoned();
+    let now = state.clock.now();
+    let boundary = DayBoundary::load(&db)?;
+    let id = state
+        .manual
+        .lock_unpoisoned()
+        .stop(&db, now, boundary)?
+        .ok_or("The manual timer isn't running")?;
+    insert_connection(&db, &MANUAL_PRESENCE, now, boundary)?;
+
+    info!("Manual session {} ended", id);
+    emit_session(&app_handle, &db, "session-ended", id);
+    emit_day(&app_handle, &db, &boundary.date_string(now));
+    set_manual_tray_title(&app_handle, false)?;
+    Ok(id)
+}
+
+fn set_manual_tray_title(app_handle: &tauri::AppHandle, running: bool) -> Result<(), Error> {
+    app_handle
+        .tray_handle()
+        .get_item("manual")
+        .set_title(if running {
+            "Stop manual timer"
+        } else {
+            "Start manual timer"
+        })?;
+    Ok(tray::update_icon(app_handle)?)
+}
+
+/// Stops or restarts logging without quitting. Pausing closes any open
+/// session so the paused time isn't counted.
+fn set_paused(app_handle: &tauri::AppHandle, paused: bool) -> Result<(), Error> {
+    let state = app_handle.state::<AppState>();
+    if state.paused.swap(paused, Ordering::SeqCst) == paused {
+        return Ok(());
+    }
+    info!("Tracking {}", if paused { "paused" } else { "resumed" });
+
+    if paused {
+        end_session(app_handle)?;
+    }
+    app_handle
+        .tray_handle()
+        .get_item("pause")
+        .set_title(if paused {
+            "Resume tracking"
+        } else {
+            "Pause tracking"
+        })?;
+    Ok(tray::update_icon(app_handle)?)
+}
+
+/// Closes the open session and holds off checks until the screen is
+/// unlocked, if the `pause_when_locked` setting is on, so a locked laptop
+/// carried around in a bag doesn't count.
+fn screen_locked(app_handle: &tauri::AppHandle) -> Result<(), Error> {
+    if app_lock::enabled(app_handle)? {
+        if let Some(window) = app_handle.get_window("main") {
+            window.hide()?;
+        }
+        app_lock::lock(app_handle);
+    }
+    let state = app_handle.state::<AppState>();
+    let pause = {
+        let db = state.db.lock_unpoisoned();
+        settings::get_bool(&db, settings::PAUSE_WHEN_LOCKED, true)?
+    };
+    if !pause {
+        return Ok(());
+    }
+    info!("Screen locked, pausing checks");
+    state.locked.store(true, Ordering::SeqCst);
+    end_session(app_handle)?;
+    Ok(tray::update_icon(app_handle)?)
+}
+
+/// Whether the window's contents and exports need unlocking first.
+#[tauri::command]
+fn is_app_locked(app_handle: tauri::AppHandle) -> Result<bool, Error> {
+    app_lock::is_locked(&app_handle)
+}
+
+/// Unlocks the app with `passcode`, or with Touch ID or the account password
+/// on macOS if it's left out.
+#[tauri::command]
+async fn unlock_app(app_handle: tauri::AppHandle, passcode: Option<String>) -> Result<(), Error> {
+    tauri::async_runtime::spawn_blocking(move || app_lock::unlock(&app_handle, passcode.as_deref()))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// Sets the app lock passcode, or removes it if `passcode` is empty. Changing
+/// it needs the app unlocked.
+#[tauri::command]
+fn set_app_passcode(app_handle: tauri::AppHandle, passcode: String) -> Result<(), Error> {
+    app_lock::require_unlocked(&app_handle)?;
+    if passcode.is_empty() && !app_lock::SYSTEM_AUTHENTICATION && app_lock::enabled(&app_handle)? {
+        return Err("Turn the app lock off before removing its passcode".into());
+    }
+    Ok(tracker_core::app_lock::set_passcode(&passcode)?)
+}
+
+/// Who on the team was in between `start` and `end` (YYYY-MM-DD) and for how
+/// long, from the team endpoint.
+#[tauri::command]
+async fn get_team_summary(
+    app_handle: tauri::AppHandle,
+    start: String,
+    end: String,
+) -> Result<team::TeamSummary, Error> {
+    validate_date(&start)?;
+    validate_date(&end)?;
+    let config = {
+        let state = app_handle.state::<AppState>();
+        let db = state.readers.get()?;
+        team::load(&db)?.ok_or("Turn on team mode in settings first")?
+    };
+    tauri::async_runtime::spawn_blocking(move || team::fetch(&config, &start, &end))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(Error::from)
+}
+
+/// Stores the secret key for publishing reports to an S3-compatible bucket in
+/// the keychain, or removes it if `secret` is empty.
+#[tauri::command]
+fn set_report_bucket_secret(secret: String) -> Result<(), Error> {
+    if secret.is_empty() {
+        keychain::delete(publishing::S3_SECRET_ACCOUNT)?;
+    } else {
+        keychain::set(publishing::S3_SECRET_ACCOUNT, &secret)?;
+    }
+    Ok(())
+}
+
+/// Stores the bearer token for the team endpoint in the keychain, or removes
+/// it if `token` is empty.
+#[tauri::command]
+fn set_team_token(token: String) -> Result<(), Error> {
+    if token.is_empty() {
+        keychain::delete(team::TOKEN_ACCOUNT)?;
+    } else {
+        keychain::set(team::TOKEN_ACCOUNT, &token)?;
+    }
+    Ok(())
+}
+
+/// Stores the SMTP password for digest emails in the keychain, or removes it
+/// if `password` is empty.
+#[tauri::command]
+fn set_smtp_password(password: String) -> Result<(), Error> {
+    if password.is_empty() {
+        keychain::delete(digest::PASSWORD_ACCOUNT)?;
+    } else {
+        keychain::set(digest::PASSWORD_ACCOUNT, &password)?;
+    }
+    Ok(())
+}
+
+/// Sends last week's digest now, e.g. to check the email settings, whether
+/// or not it's already gone out.
+#[tauri::command]
+async fn send_weekly_digest(app_handle: tauri::AppHandle) -> Result<(), Error> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let today = {
+            let state = app_handle.state::<AppState>();
+            let db = state.readers.get()?;
+            today(&db)?
+        };
+        let last_week =
+            today - chrono::Duration::days(today.weekday().num_days_from_monday() as i64 + 7);
+        deliver_digest(&app_handle, last_week)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Stores the Slack user token for the status integration in the keychain,
+/// or removes it if `token` is empty.
+#[tauri::command]
+fn set_slack_token(token: String) -> Result<(), Error> {
+    match token.trim() {
+        "" => keychain::delete(slack::TOKEN_ACCOUNT)?,
+        token => keychain::set(slack::TOKEN_ACCOUNT, token)?,
+    }
+    Ok(())
+}
+
+/// Sets whether to stop counting time while the screen is locked.
+#[tauri::command]
+fn set_pause_when_locked(app_handle: tauri::AppHandle, enabled: bool) -> Result<(), Error> {
+    update_setting(&app_handle, |settings| settings.pause_when_locked = enabled)
+}
+
+/// Registers or removes the app as a login item.
+#[tauri::command]
+fn set_autostart(app_handle: tauri::AppHandle, enabled: bool) -> Result<(), Error> {
+    update_setting(&app_handle, |settings| settings.autostart_enabled = enabled)
+}
+
+/// Lets the frontend check a glob/regex SSID pattern before saving it.
+#[tauri::command]
+fn validate_ssid_pattern(pattern: String) -> Result<(), String> {
+    pattern::SsidPattern::parse(&pattern).map(|_| ())
+}
+
+fn check_presence(app_handle: &tauri::AppHandle) -> Result<bool, Error> {
+    let state = app_handle.state::<AppState>();
+    let (presence, detector_name) = {
+        let detector = state.detector.lock_unpoisoned();
+        (detector.detect()?, detector.name())
+    };
+    debug!(
+        detector = %detector_name,
+        present = presence.is_some(),
+        ssid = presence.as_ref().and_then(|presence| presence.ssid.as_deref()),
+        "Checked presence"
+    );
+
+    let db = state.db.lock_unpoisoned();
+    let now = state.clock.now();
+    let outcome = tracking::record_check(
+        &db,
+        presence,
+        &mut state.sessions.lock_unpoisoned(),
+        &mut state.manual.lock_unpoisoned(),
+        now,
+    )?;
+    if let Some(presence) = &outcome.presence {
+        emit(app_handle, "connection-detected", presence);
+    }
+    match outcome.transition {
+        Transition::Started(id) => session_started(app_handle, &db, id),
+        Transition::Ended(id) => session_ended(app_handle, &db, id, outcome.discarded),
+        // Reported like leaving and coming straight back
+        Transition::Rolled { ended, started } => {
+            session_ended(app_handle, &db, ended, outcome.discarded);
+            session_started(app_handle, &db, started);
+        }
+        Transition::Continued | Transition::Idle => {}
+    }
+    if state
+        .sessions
+        .lock_unpoisoned()
+        .open_session_start()
+        .is_some()
+        || state.manual.lock_unpoisoned().is_running()
+    {
+        state.go_home.lock_unpoisoned().check(app_handle, &db, now);
+    }
+
+    if outcome.presence.is_some() || outcome.transition != Transition::Idle {
+        emit_day(app_handle, &db, &outcome.date);
+    }
+
+    Ok(outcome.presence.is_some())
+}
+
+fn session_started(app_handle: &tauri::AppHandle, db: &Connection, id: i64) {
+    info!("Session {} started", id);
+    if let Some(session) = emit_session(app_handle, db, "session-started", id) {
+        notifications::clocked_in(app_handle, db, &session);
+    }
+}
+
+fn session_ended(app_handle: &tauri::AppHandle, db: &Connection, id: i64, discarded: bool) {
+    if discarded {
+        info!("Session {} was too short, discarded", id);
+        emit(app_handle, "session-discarded", id);
+        app_handle.state::<AppState>().slack.update(false);
+    } else {
+        info!("Session {} ended", id);
+        if let Some(session) = emit_session(app_handle, db, "session-ended", id) {
+            notifications::clocked_out(app_handle, db, &session);
+        }
+    }
+}
+
+fn end_session(app_handle: &tauri::AppHandle) -> Result<(), Error> {
+    let state = app_handle.state::<AppState>();
+    let db = state.db.lock_unpoisoned();
+    let now = state.clock.now();
+    let boundary = DayBoundary::load(&db)?;
+    let closed = state.sessions.lock_unpoisoned().close(&db, now, boundary)?;
+    if let Some(id) = closed {
+        info!("Session {} ended", id);
+        emit_session(app_handle, &db, "session-ended", id);
+        emit_day(app_handle, &db, &boundary.date_string(now));
+    }
+    Ok(())
+}
+
+/// Pushes a change to the frontend so it doesn't have to poll. Failures are
+/// logged rather than returned since the data is already saved.
+fn emit<S: Serialize + Clone>(app_handle: &tauri::AppHandle, event: &str, payload: S) {
+    if let Err(e) = app_handle.emit_all(event, payload) {
+        error!("Error emitting {}: {}", event, e);
+    }
+}
+
+/// Emits the session with `id`, returning it for further use.
+fn emit_session(
+    app_handle: &tauri::AppHandle,
+    db: &Connection,
+    event: &str,
+    id: i64,
+) -> Option<Session> {
+    match sessions::get_session(db, id) {
+        Ok(Some(session)) => {
+            emit(app_handle, event, session.clone());
+            let webhook_event = match event {
+                "session-started" => Some(WebhookEvent::ClockIn),
+                "session-ended" => Some(WebhookEvent::ClockOut),
+                _ => None,
+            };
+            if let Some(webhook_event) = webhook_event {
+                if let Err(e) = webhooks::fire(db, webhook_event, &session) {
+                    error!("Error firing webhooks: {}", e);
+                }
+                let in_office = webhook_event == WebhookEvent::ClockIn;
+                app_handle.state::<AppState>().slack.update(in_office);
+            }
+            if event == "session-ended" {
+                let app_handle = app_handle.clone();
+                let date = session.date.clone();
+                thread::spawn(move || {
+                    if let Err(e) = push_time_entries(&app_handle) {
+                        error!("Error pushing time entries: {}", e);
+                    }
+                    if let Err(e) = sync_with_devices(&app_handle) {
+                        error!("Error syncing with other devices: {}", e);
+                    }
+                    if let Err(e) = publish_team_totals(&app_handle) {
+                        error!("Error publishing totals to the team: {}", e);
+                    }
+                    if let Err(e) = publish_calendar_day(&app_handle, &date) {
+                        error!("Error publishing {} to Google Calendar: {}", date, e);
+                    }
+                    if let Err(e) = write_daily_note(&app_handle, &date) {
+                        error!("Error writing the daily note for {}: {}", date, e);
+                    }
+                });
+            }
+            Some(session)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            error!("Error loading session {}: {}", id, e);
+            None
+        }
+    }
+}
+
+fn emit_day(app_handle: &tauri::AppHandle, db: &Connection, date: &str) {
+    match connections::get_day(db, date) {
+        Ok(Some(log)) => {
+            emit(app_handle, "day-updated", log);
+            if let Err(e) = tray::update_menu(app_handle, db) {
+                error!("Error updating tray menu: {}", e);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => error!("Error loading {}: {}", date, e),
+    }
+}
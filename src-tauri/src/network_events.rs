@@ -0,0 +1,123 @@
+use std::thread;
+use tokio::sync::mpsc::UnboundedSender as Sender;
+use tracing::warn;
+
+use crate::LoopSignal;
+
+/// Starts watching for OS network-change notifications, sending
+/// `LoopSignal::NetworkChanged` every time something changes. If the watcher
+/// can't be set up or stops, `LoopSignal::NetworkWatchStopped` is sent so the
+/// receiver can fall back to plain polling.
+pub fn spawn(tx: Sender<LoopSignal>) {
+    thread::spawn(move || {
+        if let Err(e) = platform::watch(tx.clone()) {
+            warn!("Network change notifications unavailable: {}", e);
+        }
+        let _ = tx.send(LoopSignal::NetworkWatchStopped);
+    });
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+    use core_foundation::array::CFArray;
+    use core_foundation::runloop::{kCFRunLoopCommonModes, CFRunLoop};
+    use core_foundation::string::CFString;
+    use system_configuration::dynamic_store::{
+        SCDynamicStore, SCDynamicStoreBuilder, SCDynamicStoreCallBackContext,
+    };
+
+    /// Subscribes to the System Configuration dynamic store keys that change
+    /// when the WiFi association or the primary IPv4 service changes, and runs
+    /// a CFRunLoop on this thread to receive the callbacks.
+    pub fn watch(tx: Sender<LoopSignal>) -> Result<(), String> {
+        let callback_context = SCDynamicStoreCallBackContext {
+            callout: on_change,
+            info: tx,
+        };
+        let store = SCDynamicStoreBuilder::new("foundership-time-tracker")
+            .callback_context(callback_context)
+            .build();
+
+        let watch_keys: CFArray<CFString> = CFArray::from_CFTypes(&[]);
+        let watch_patterns = CFArray::from_CFTypes(&[
+            CFString::from("State:/Network/Interface/.*/AirPort"),
+            CFString::from("State:/Network/Global/IPv4"),
+        ]);
+        if !store.set_notification_keys(&watch_keys, &watch_patterns) {
+            return Err("failed to register dynamic store notification keys".to_string());
+        }
+
+        let run_loop_source = store.create_run_loop_source();
+        CFRunLoop::get_current().add_source(&run_loop_source, unsafe { kCFRunLoopCommonModes });
+        CFRunLoop::run_current();
+        Ok(())
+    }
+
+    fn on_change(
+        _store: SCDynamicStore,
+        _changed_keys: CFArray<CFString>,
+        tx: &mut Sender<LoopSignal>,
+    ) {
+        if tx.send(LoopSignal::NetworkChanged).is_err() {
+            CFRunLoop::get_current().stop();
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::*;
+    use std::ptr;
+    use windows_sys::Win32::NetworkManagement::IpHelper::NotifyAddrChange;
+
+    /// Blocks in `NotifyAddrChange`, which returns whenever the IPv4 address
+    /// table changes, e.g. when joining or leaving a WiFi network.
+    pub fn watch(tx: Sender<LoopSignal>) -> Result<(), String> {
+        loop {
+            let result = unsafe { NotifyAddrChange(ptr::null_mut(), ptr::null()) };
+            if result != 0 {
+                return Err(format!("NotifyAddrChange failed with error {}", result));
+            }
+            if tx.send(LoopSignal::NetworkChanged).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+
+    /// Follows `ip monitor`, which prints a line for every link and address
+    /// change.
+    pub fn watch(tx: Sender<LoopSignal>) -> Result<(), String> {
+        let mut child = Command::new("ip")
+            .args(&["-o", "monitor", "link", "address"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to run ip monitor: {}", e))?;
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        for line in BufReader::new(stdout).lines() {
+            if line.is_err() || tx.send(LoopSignal::NetworkChanged).is_err() {
+                break;
+            }
+        }
+
+        let _ = child.kill();
+        Err("ip monitor exited".to_string())
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
+mod platform {
+    use super::*;
+
+    pub fn watch(_tx: Sender<LoopSignal>) -> Result<(), String> {
+        Err("not supported on this platform".to_string())
+    }
+}
@@ -0,0 +1,128 @@
+use chrono::{DateTime, Duration, Local};
+use rusqlite::Connection;
+use tauri::api::notification::Notification;
+use tracing::error;
+use tracker_core::anomalies::Anomaly;
+use tracker_core::reports::format_minutes;
+use tracker_core::sessions::{self, DayBoundary, Session};
+use tracker_core::settings;
+
+/// Shows a native notification unless they've been turned off in settings.
+pub fn notify(app_handle: &tauri::AppHandle, db: &Connection, title: &str, body: &str) {
+    match settings::get_bool(db, settings::NOTIFICATIONS_ENABLED, true) {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            error!("Error reading notification setting: {}", e);
+            return;
+        }
+    }
+    show(app_handle, title, body);
+}
+
+/// Shows a native notification whatever the settings say, for ones that
+/// were asked for.
+pub fn show(app_handle: &tauri::AppHandle, title: &str, body: &str) {
+    let identifier = &app_handle.config().tauri.bundle.identifier;
+    if let Err(e) = Notification::new(identifier).title(title).body(body).show() {
+        error!("Error showing notification: {}", e);
+    }
+}
+
+/// e.g. "Clocked in at 09:04".
+pub fn clocked_in(app_handle: &tauri::AppHandle, db: &Connection, session: &Session) {
+    notify(
+        app_handle,
+        db,
+        "Clocked in",
+        &format!("Clocked in at {}", session.start),
+    );
+}
+
+/// e.g. "Clocked out at 18:30, 8h 12m today".
+pub fn clocked_out(app_handle: &tauri::AppHandle, db: &Connection, session: &Session) {
+    let today = match sessions::total_minutes(db, &session.date, None) {
+        Ok(minutes) => format!(", {} today", format_minutes(minutes)),
+        Err(e) => {
+            error!("Error totalling {}: {}", session.date, e);
+            String::new()
+        }
+    };
+    notify(
+        app_handle,
+        db,
+        "Clocked out",
+        &format!("Clocked out at {}{}", session.end, today),
+    );
+}
+
+/// Reminds the user to go home once they've been on-site for longer than the
+/// configured number of hours, repeating at most once per snooze interval.
+#[derive(Default)]
+pub struct GoHomeReminder {
+    snoozed_until: Option<DateTime<Local>>,
+}
+
+impl GoHomeReminder {
+    pub fn check(&mut self, app_handle: &tauri::AppHandle, db: &Connection, now: DateTime<Local>) {
+        if let Err(e) = self.try_check(app_handle, db, now) {
+            error!("Error checking go-home reminder: {}", e);
+        }
+    }
+
+    fn try_check(
+        &mut self,
+        app_handle: &tauri::AppHandle,
+        db: &Connection,
+        now: DateTime<Local>,
+    ) -> rusqlite::Result<()> {
+        let threshold_hours = settings::get_f64(
+            db,
+            settings::GO_HOME_AFTER_HOURS,
+            settings::DEFAULT_GO_HOME_AFTER_HOURS,
+        )?;
+        if threshold_hours <= 0.0 || self.snoozed_until.is_some_and(|until| now < until) {
+            return Ok(());
+        }
+
+        let today = DayBoundary::load(db)?.date_string(now);
+        let minutes = sessions::total_minutes(db, &today, None)?;
+        if (minutes as f64) < threshold_hours * 60.0 {
+            return Ok(());
+        }
+
+        notify(
+            app_handle,
+            db,
+            "Time to go home",
+            &format!("You've been on-site for {} today", format_minutes(minutes)),
+        );
+        let snooze_minutes = settings::get_f64(
+            db,
+            settings::GO_HOME_SNOOZE_MINUTES,
+            settings::DEFAULT_GO_HOME_SNOOZE_MINUTES,
+        )?;
+        self.snooze(now + Duration::minutes(snooze_minutes as i64));
+        Ok(())
+    }
+
+    /// Holds off the reminder until `until`.
+    pub fn snooze(&mut self, until: DateTime<Local>) {
+        self.snoozed_until = Some(until);
+    }
+}
+
+/// Points out days that stood out, one at a time or as a count if there were
+/// several since the last check.
+pub fn unusual_days(app_handle: &tauri::AppHandle, db: &Connection, anomalies: &[Anomaly]) {
+    let body = match anomalies {
+        [] => return,
+        [anomaly] => format!("{}: {}", anomaly.date, anomaly.description),
+        [first, ..] => format!(
+            "{} unusual days since {}, see the report for details",
+            anomalies.len(),
+            first.date
+        ),
+    };
+    notify(app_handle, db, "Unusual day", &body);
+}
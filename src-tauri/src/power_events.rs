@@ -0,0 +1,210 @@
+use tokio::sync::mpsc::UnboundedSender as Sender;
+use tracing::warn;
+
+use crate::LoopSignal;
+
+/// Subscribes to system sleep/wake notifications, sending `LoopSignal::Sleep`
+/// just before the machine sleeps and `LoopSignal::Wake` once it's back. Must
+/// be called on the main thread.
+pub fn register(tx: Sender<LoopSignal>) {
+    if let Err(e) = platform::register(tx) {
+        warn!("Sleep/wake notifications unavailable: {}", e);
+    }
+}
+
+/// Whether the machine is running on battery. Assumes mains power when it
+/// can't tell, e.g. on desktops.
+pub fn on_battery() -> bool {
+    platform::on_battery()
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+    use block2::RcBlock;
+    use objc2_app_kit::{
+        NSWorkspace, NSWorkspaceDidWakeNotification, NSWorkspaceWillSleepNotification,
+    };
+    use objc2_foundation::NSNotification;
+    use std::ptr::NonNull;
+
+    /// Observes NSWorkspace's sleep/wake notifications, which are posted on
+    /// the main thread's run loop that Tauri already drives.
+    pub fn register(tx: Sender<LoopSignal>) -> Result<(), String> {
+        unsafe {
+            let center = NSWorkspace::sharedWorkspace().notificationCenter();
+            for (name, sleeping) in [
+                (NSWorkspaceWillSleepNotification, true),
+                (NSWorkspaceDidWakeNotification, false),
+            ] {
+                let tx = tx.clone();
+                let block = RcBlock::new(move |_: NonNull<NSNotification>| {
+                    let _ = tx.send(if sleeping {
+                        LoopSignal::Sleep
+                    } else {
+                        LoopSignal::Wake
+                    });
+                });
+                let observer = center.addObserverForName_object_queue_usingBlock(
+                    Some(name),
+                    None,
+                    None,
+                    &block,
+                );
+                // Observers are unregistered when dropped; keep them for the
+                // lifetime of the process.
+                std::mem::forget(observer);
+            }
+        }
+        Ok(())
+    }
+
+    /// `pmset -g batt` starts with e.g. "Now drawing from 'Battery Power'".
+    pub fn on_battery() -> bool {
+        std::process::Command::new("pmset")
+            .args(&["-g", "batt"])
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).contains("'Battery Power'"))
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::*;
+    use std::ffi::c_void;
+    use std::ptr;
+    use windows_sys::Win32::System::Power::{
+        GetSystemPowerStatus, PowerRegisterSuspendResumeNotification,
+        DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS, SYSTEM_POWER_STATUS,
+    };
+
+    const DEVICE_NOTIFY_CALLBACK: u32 = 2;
+    const PBT_APMSUSPEND: u32 = 0x4;
+    const PBT_APMRESUMESUSPEND: u32 = 0x7;
+    const PBT_APMRESUMEAUTOMATIC: u32 = 0x12;
+
+    pub fn register(tx: Sender<LoopSignal>) -> Result<(), String> {
+        // Both the sender and the subscription parameters must outlive the
+        // registration, which lasts for the lifetime of the process.
+        let context = Box::into_raw(Box::new(tx));
+        let params = Box::leak(Box::new(DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS {
+            Callback: Some(on_power_event),
+            Context: context as *mut c_void,
+        }));
+
+        let mut registration = ptr::null_mut();
+        let result = unsafe {
+            PowerRegisterSuspendResumeNotification(
+                DEVICE_NOTIFY_CALLBACK,
+                params as *mut DEVICE_NOTIFY_SUBSCRIBE_PARAMETERS as _,
+                &mut registration,
+            )
+        };
+        if result != 0 {
+            return Err(format!(
+                "PowerRegisterSuspendResumeNotification failed with error {}",
+                result
+            ));
+        }
+        Ok(())
+    }
+
+    unsafe extern "system" fn on_power_event(
+        context: *const c_void,
+        event: u32,
+        _setting: *const c_void,
+    ) -> u32 {
+        let tx = &*(context as *const Sender<LoopSignal>);
+        let signal = match event {
+            PBT_APMSUSPEND => Some(LoopSignal::Sleep),
+            PBT_APMRESUMESUSPEND | PBT_APMRESUMEAUTOMATIC => Some(LoopSignal::Wake),
+            _ => None,
+        };
+        if let Some(signal) = signal {
+            let _ = tx.send(signal);
+        }
+        0
+    }
+
+    pub fn on_battery() -> bool {
+        let mut status: SYSTEM_POWER_STATUS = unsafe { std::mem::zeroed() };
+        // ACLineStatus is 0 when offline, 1 when online and 255 when unknown
+        unsafe { GetSystemPowerStatus(&mut status) != 0 && status.ACLineStatus == 0 }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::thread;
+
+    /// Follows logind's `PrepareForSleep` signal, which carries `true` before
+    /// suspending and `false` after resuming.
+    pub fn register(tx: Sender<LoopSignal>) -> Result<(), String> {
+        let mut child = Command::new("gdbus")
+            .args(&[
+                "monitor",
+                "--system",
+                "--dest",
+                "org.freedesktop.login1",
+                "--object-path",
+                "/org/freedesktop/login1",
+            ])
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to run gdbus monitor: {}", e))?;
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                let signal = if line.contains("PrepareForSleep (true") {
+                    LoopSignal::Sleep
+                } else if line.contains("PrepareForSleep (false") {
+                    LoopSignal::Wake
+                } else {
+                    continue;
+                };
+                if tx.send(signal).is_err() {
+                    break;
+                }
+            }
+            let _ = child.kill();
+        });
+        Ok(())
+    }
+
+    /// On battery when there's a mains supply and none of them are online.
+    pub fn on_battery() -> bool {
+        let Ok(entries) = std::fs::read_dir("/sys/class/power_supply") else {
+            return false;
+        };
+        let mut mains = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| {
+                std::fs::read_to_string(path.join("type")).is_ok_and(|kind| kind.trim() == "Mains")
+            })
+            .peekable();
+        mains.peek().is_some()
+            && mains.all(|path| {
+                std::fs::read_to_string(path.join("online"))
+                    .is_ok_and(|online| online.trim() == "0")
+            })
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
+mod platform {
+    use super::*;
+
+    pub fn register(_tx: Sender<LoopSignal>) -> Result<(), String> {
+        Err("not supported on this platform".to_string())
+    }
+
+    pub fn on_battery() -> bool {
+        false
+    }
+}
@@ -0,0 +1,204 @@
+use tokio::sync::mpsc::UnboundedSender as Sender;
+use tracing::warn;
+
+use crate::LoopSignal;
+
+/// Subscribes to screen lock/unlock notifications, sending
+/// `LoopSignal::ScreenLocked` and `LoopSignal::ScreenUnlocked`. Must be called
+/// on the main thread.
+pub fn register(tx: Sender<LoopSignal>) {
+    if let Err(e) = platform::register(tx) {
+        warn!("Screen lock notifications unavailable: {}", e);
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+    use block2::RcBlock;
+    use objc2_foundation::{NSDistributedNotificationCenter, NSNotification, NSString};
+    use std::ptr::NonNull;
+
+    /// Observes the distributed notifications loginwindow posts when the
+    /// screen locks and unlocks.
+    pub fn register(tx: Sender<LoopSignal>) -> Result<(), String> {
+        unsafe {
+            let center = NSDistributedNotificationCenter::defaultCenter();
+            for (name, locked) in [
+                ("com.apple.screenIsLocked", true),
+                ("com.apple.screenIsUnlocked", false),
+            ] {
+                let tx = tx.clone();
+                let block = RcBlock::new(move |_: NonNull<NSNotification>| {
+                    let _ = tx.send(if locked {
+                        LoopSignal::ScreenLocked
+                    } else {
+                        LoopSignal::ScreenUnlocked
+                    });
+                });
+                let observer = center.addObserverForName_object_queue_usingBlock(
+                    Some(&NSString::from_str(name)),
+                    None,
+                    None,
+                    &block,
+                );
+                // Kept for the lifetime of the process, like the sleep/wake
+                // observers
+                std::mem::forget(observer);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::*;
+    use std::ptr;
+    use std::sync::{Mutex, OnceLock};
+    use std::thread;
+    use windows_sys::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
+    use windows_sys::Win32::System::LibraryLoader::GetModuleHandleW;
+    use windows_sys::Win32::System::RemoteDesktop::{
+        WTSRegisterSessionNotification, NOTIFY_FOR_THIS_SESSION,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, RegisterClassW,
+        HWND_MESSAGE, MSG, WM_WTSSESSION_CHANGE, WNDCLASSW,
+    };
+
+    const WTS_SESSION_LOCK: usize = 0x7;
+    const WTS_SESSION_UNLOCK: usize = 0x8;
+
+    /// Where the window procedure sends signals, since it can't be given any
+    /// context of its own.
+    static SIGNALS: OnceLock<Mutex<Sender<LoopSignal>>> = OnceLock::new();
+
+    /// Session-switch events go to a window, so create a hidden message-only
+    /// one and pump its messages on a thread of its own.
+    pub fn register(tx: Sender<LoopSignal>) -> Result<(), String> {
+        SIGNALS
+            .set(Mutex::new(tx))
+            .map_err(|_| "already registered".to_string())?;
+        thread::spawn(|| {
+            if let Err(e) = run_message_loop() {
+                warn!("Screen lock notifications unavailable: {}", e);
+            }
+        });
+        Ok(())
+    }
+
+    fn run_message_loop() -> Result<(), String> {
+        let class_name: Vec<u16> = "FoundershipScreenEvents\0".encode_utf16().collect();
+        unsafe {
+            let instance = GetModuleHandleW(ptr::null());
+            let class = WNDCLASSW {
+                lpfnWndProc: Some(on_message),
+                hInstance: instance,
+                lpszClassName: class_name.as_ptr(),
+                ..std::mem::zeroed()
+            };
+            if RegisterClassW(&class) == 0 {
+                return Err("RegisterClassW failed".to_string());
+            }
+            let window = CreateWindowExW(
+                0,
+                class_name.as_ptr(),
+                ptr::null(),
+                0,
+                0,
+                0,
+                0,
+                0,
+                HWND_MESSAGE,
+                0,
+                instance,
+                ptr::null(),
+            );
+            if window == 0 {
+                return Err("CreateWindowExW failed".to_string());
+            }
+            if WTSRegisterSessionNotification(window, NOTIFY_FOR_THIS_SESSION) == 0 {
+                return Err("WTSRegisterSessionNotification failed".to_string());
+            }
+
+            let mut message: MSG = std::mem::zeroed();
+            while GetMessageW(&mut message, 0, 0, 0) > 0 {
+                DispatchMessageW(&message);
+            }
+        }
+        Ok(())
+    }
+
+    unsafe extern "system" fn on_message(
+        window: HWND,
+        message: u32,
+        wparam: WPARAM,
+        lparam: LPARAM,
+    ) -> LRESULT {
+        if message == WM_WTSSESSION_CHANGE {
+            let signal = match wparam {
+                WTS_SESSION_LOCK => Some(LoopSignal::ScreenLocked),
+                WTS_SESSION_UNLOCK => Some(LoopSignal::ScreenUnlocked),
+                _ => None,
+            };
+            if let (Some(signal), Some(tx)) = (signal, SIGNALS.get()) {
+                let _ = tx.lock().map(|tx| tx.send(signal));
+            }
+            return 0;
+        }
+        DefWindowProcW(window, message, wparam, lparam)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::thread;
+
+    /// Follows the desktop's screensaver `ActiveChanged` signal, which
+    /// carries `true` when the screen locks and `false` when it unlocks.
+    pub fn register(tx: Sender<LoopSignal>) -> Result<(), String> {
+        let mut child = Command::new("gdbus")
+            .args(&[
+                "monitor",
+                "--session",
+                "--dest",
+                "org.freedesktop.ScreenSaver",
+                "--object-path",
+                "/org/freedesktop/ScreenSaver",
+            ])
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("failed to run gdbus monitor: {}", e))?;
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                let signal = if line.contains("ActiveChanged (true") {
+                    LoopSignal::ScreenLocked
+                } else if line.contains("ActiveChanged (false") {
+                    LoopSignal::ScreenUnlocked
+                } else {
+                    continue;
+                };
+                if tx.send(signal).is_err() {
+                    break;
+                }
+            }
+            let _ = child.kill();
+        });
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
+mod platform {
+    use super::*;
+
+    pub fn register(_tx: Sender<LoopSignal>) -> Result<(), String> {
+        Err("not supported on this platform".to_string())
+    }
+}
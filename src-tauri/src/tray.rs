@@ -0,0 +1,220 @@
+use chrono::{Local, NaiveDate};
+use rusqlite::Connection;
+use std::sync::atomic::Ordering;
+use tauri::{CustomMenuItem, Manager, SystemTrayMenu, SystemTraySubmenu};
+use tracker_core::connections::{self, ConnectionLog, LogQuery};
+use tracker_core::flexitime;
+use tracker_core::lunch;
+use tracker_core::reports::{self, format_minutes};
+use tracker_core::sessions::DayBoundary;
+use tracker_core::settings;
+
+use crate::error::LockExt;
+use crate::{badge, today_summary, AppState};
+
+/// How many days the "Recent" submenu lists.
+const RECENT_DAYS: i64 = 5;
+
+/// The tray menu, with the pause, manual timer and login items showing the
+/// current state and the `recent` days' hours in a submenu.
+pub fn menu(
+    recent: &[ConnectionLog],
+    paused: bool,
+    manual_running: bool,
+    autostart: bool,
+) -> SystemTrayMenu {
+    let mut recent_menu = SystemTrayMenu::new();
+    for day in recent {
+        let date = NaiveDate::parse_from_str(&day.date, "%Y-%m-%d")
+            .map(|date| date.format("%a %-d %b").to_string())
+            .unwrap_or_else(|_| day.date.clone());
+        recent_menu = recent_menu.add_item(
+            CustomMenuItem::new(
+                format!("recent:{}", day.date),
+                format!("{}  {}", date, format_minutes(day.total_minutes)),
+            )
+            .disabled(),
+        );
+    }
+    if recent.is_empty() {
+        recent_menu = recent_menu.add_item(CustomMenuItem::new("recent", "No days yet").disabled());
+    }
+
+    let mut autostart_item = CustomMenuItem::new("autostart", "Start at login");
+    if autostart {
+        autostart_item = autostart_item.selected();
+    }
+    SystemTrayMenu::new()
+        .add_item(CustomMenuItem::new("toggle", "Show/Hide"))
+        .add_item(CustomMenuItem::new("check_now", "Check now"))
+        .add_item(CustomMenuItem::new(
+            "pause",
+            if paused {
+                "Resume tracking"
+            } else {
+                "Pause tracking"
+            },
+        ))
+        .add_item(CustomMenuItem::new(
+            "manual",
+            if manual_running {
+                "Stop manual timer"
+            } else {
+                "Start manual timer"
+            },
+        ))
+        .add_submenu(SystemTraySubmenu::new("Recent", recent_menu))
+        .add_item(CustomMenuItem::new("export_csv", "Export CSV…"))
+        .add_item(autostart_item)
+        .add_item(CustomMenuItem::new("open_logs", "Open logs"))
+        .add_item(CustomMenuItem::new("quit", "Quit"))
+}
+
+/// Rebuilds the tray menu so its "Recent" submenu is up to date. Takes the
+/// manual timer's lock, so don't call it while holding that.
+pub fn update_menu(app_handle: &tauri::AppHandle, db: &Connection) -> Result<(), String> {
+    let query = LogQuery {
+        limit: Some(RECENT_DAYS),
+        ..Default::default()
+    };
+    let recent = connections::get_connection_log(db, &query).map_err(|e| e.to_string())?;
+    let autostart =
+        settings::get_bool(db, settings::AUTOSTART_ENABLED, false).map_err(|e| e.to_string())?;
+    let state = app_handle.state::<AppState>();
+    let menu = menu(
+        &recent,
+        state.paused.load(Ordering::SeqCst),
+        state.manual.lock_unpoisoned().is_running(),
+        autostart,
+    );
+    app_handle
+        .tray_handle()
+        .set_menu(menu)
+        .map_err(|e| e.to_string())
+}
+
+/// Shows today's tracked time next to the tray icon (macOS) and in its
+/// tooltip along with progress towards the weekly goal, so it works as a
+/// glanceable timer.
+pub fn update_summary(app_handle: &tauri::AppHandle) -> Result<(), String> {
+    let today = today_summary(app_handle, None).map_err(|e| e.to_string())?;
+    let minutes = today.total_minutes;
+    let (goal, day_goal) = {
+        let state = app_handle.state::<AppState>();
+        let db = state.readers.get().map_err(|e| e.to_string())?;
+        let date = DayBoundary::load(&db)
+            .map_err(|e| e.to_string())?
+            .date(Local::now());
+        let target = settings::get_f64(
+            &db,
+            settings::WEEKLY_TARGET_HOURS,
+            settings::DEFAULT_WEEKLY_TARGET_HOURS,
+        )
+        .map_err(|e| e.to_string())?;
+        (
+            reports::goal_progress(&db, date, target, None).map_err(|e| e.to_string())?,
+            flexitime::expected_minutes(&db, date).map_err(|e| e.to_string())?,
+        )
+    };
+    badge::update(app_handle, minutes, day_goal);
+
+    let tray = app_handle.tray_handle();
+    #[cfg(target_os = "macos")]
+    tray.set_title(&format_minutes(minutes))
+        .map_err(|e| e.to_string())?;
+    tray.set_tooltip(&format!(
+        "Today: {}\nWeek: {} of {}",
+        lunch::format_with_lunch(minutes, today.lunch_minutes),
+        format_minutes((goal.hours_done * 60.0).round() as i64),
+        format_minutes((goal.target_hours * 60.0).round() as i64)
+    ))
+    .map_err(|e| e.to_string())
+}
+
+/// Warns from the tray that the tracking loop has stopped running, so time
+/// isn't being recorded.
+pub fn show_stalled(app_handle: &tauri::AppHandle) -> Result<(), String> {
+    let tray = app_handle.tray_handle();
+    #[cfg(target_os = "macos")]
+    tray.set_title("Stalled").map_err(|e| e.to_string())?;
+    tray.set_tooltip("Tracking stalled, time isn't being recorded")
+        .map_err(|e| e.to_string())
+}
+
+/// Side of the generated tray icons, in pixels.
+const ICON_SIZE: u32 = 32;
+
+/// What the tray icon shows, so it's clear at a glance whether clocking in
+/// actually happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum IconState {
+    /// Tracking, but not on-site: an outline that follows the menu bar's
+    /// appearance on macOS.
+    Absent,
+    /// A session or the manual timer is running: a green dot.
+    Present,
+    /// Paused by hand or for a locked screen: an amber dot.
+    Paused,
+    /// The last check failed or the loop has stalled: a red dot.
+    Error,
+}
+
+/// Swaps the tray icon to match what the tracker is doing. Takes the session
+/// and timer locks, so don't call it while holding either.
+pub fn update_icon(app_handle: &tauri::AppHandle) -> Result<(), String> {
+    let state = app_handle.state::<AppState>();
+    let failed = state.check_error.lock_unpoisoned().is_some();
+    let icon = if state.stalled.load(Ordering::SeqCst) || failed {
+        IconState::Error
+    } else if state.paused.load(Ordering::SeqCst) || state.locked.load(Ordering::SeqCst) {
+        IconState::Paused
+    } else if state.sessions.lock_unpoisoned().live_session_id().is_some()
+        || state.manual.lock_unpoisoned().is_running()
+    {
+        IconState::Present
+    } else {
+        IconState::Absent
+    };
+
+    let tray = app_handle.tray_handle();
+    tray.set_icon(render_icon(icon))
+        .map_err(|e| e.to_string())?;
+    #[cfg(target_os = "macos")]
+    tray.set_icon_as_template(icon == IconState::Absent)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Draws the icon for `state` as an antialiased circle, filled with its
+/// colour or, for `Absent`, as a black ring.
+fn render_icon(state: IconState) -> tauri::Icon {
+    let (colour, ring) = match state {
+        IconState::Absent => ([0, 0, 0], true),
+        IconState::Present => ([0x34, 0xc7, 0x59], false),
+        IconState::Paused => ([0xff, 0x9f, 0x0a], false),
+        IconState::Error => ([0xff, 0x3b, 0x30], false),
+    };
+    let centre = ICON_SIZE as f64 / 2.0;
+    let radius = centre - 4.0;
+    let mut rgba = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let distance = (x as f64 + 0.5 - centre).hypot(y as f64 + 0.5 - centre);
+            // How far inside the shape the pixel is, clamped to a pixel
+            // either side of its edge
+            let inside = if ring {
+                1.5 - (distance - radius + 1.5).abs()
+            } else {
+                radius - distance
+            };
+            let alpha = (inside + 0.5).clamp(0.0, 1.0);
+            rgba.extend_from_slice(&colour);
+            rgba.push((alpha * 255.0).round() as u8);
+        }
+    }
+    tauri::Icon::Rgba {
+        rgba,
+        width: ICON_SIZE,
+        height: ICON_SIZE,
+    }
+}
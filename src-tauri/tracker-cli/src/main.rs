@@ -0,0 +1,326 @@
+//! Runs the detection loop without a window, for servers and minimal setups,
+//! and reports on what's been recorded. Uses the desktop app's database
+//! unless told otherwise, so don't run both at once.
+
+use chrono::{Datelike, Duration, Local, NaiveDate};
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::thread;
+use tracker_core::clock::{Clock, SystemClock};
+use tracker_core::detector::PresenceDetector;
+use tracker_core::pool::ReadPool;
+use tracker_core::reports::{self, format_minutes};
+use tracker_core::sessions::{self, DayBoundary, ManualTimer, SessionTracker, Transition};
+use tracker_core::settings::Settings;
+use tracker_core::webhooks::{self, WebhookEvent};
+use tracker_core::{
+    daily_notes, database, demo, export, flexitime, google_calendar, heartbeat, holidays, lunch,
+    slack, time_sync, timesheet, tracking, wifi, Error, Result,
+};
+
+const USAGE: &str = "\
+Usage: tracker-cli [--db <path>] <command>
+
+Commands:
+  run                   Track presence until interrupted
+  report today          Show today's times so far
+  report week           Show this week's totals
+                        Reports cover every location unless limited with
+                        --location <name>
+  report locations      Show hours per location for --from to --to (this
+                        quarter by default)
+  export csv <path>     Export days to CSV, optionally limited with
+                        --from YYYY-MM-DD and --to YYYY-MM-DD
+  export timesheet <path>
+                        Write a timesheet, as PDF if <path> ends in .pdf
+                        or HTML otherwise, for --from to --to (this month
+                        by default), optionally with --template <file>
+  demo                  Fill a new database, given with --db, with made-up
+                        history for trying out the app, over --months
+                        (6 by default)
+";
+
+fn main() -> ExitCode {
+    // What tracker-core logs, e.g. sessions starting and ending, alongside
+    // the output rather than in it
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+    match run(std::env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(mut args: Vec<String>) -> Result<()> {
+    let db_option = take_option(&mut args, "--db")?;
+    let db_path = match &db_option {
+        Some(path) => PathBuf::from(path),
+        None => database::path_in(
+            &database::app_data_dir().ok_or("Couldn't find the app data directory, pass --db")?,
+        ),
+    };
+    let from = take_option(&mut args, "--from")?;
+    let to = take_option(&mut args, "--to")?;
+    let location = take_option(&mut args, "--location")?;
+    let template = take_option(&mut args, "--template")?;
+    let months = take_option(&mut args, "--months")?;
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["run"] => track(&db_path),
+        ["report", "today"] => report_today(&db_path, location.as_deref()),
+        ["report", "week"] => report_week(&db_path, location.as_deref()),
+        ["report", "locations"] => report_locations(&db_path, from.as_deref(), to.as_deref()),
+        ["export", "csv", path] => {
+            let db = database::open(&db_path)?;
+            let count = export::export_csv(
+                &db,
+                Path::new(path),
+                "connections",
+                from.as_deref(),
+                to.as_deref(),
+                None,
+            )?;
+            println!("Exported {} rows to {}", count, path);
+            Ok(())
+        }
+        ["export", "timesheet", path] => export_timesheet(
+            &db_path,
+            Path::new(path),
+            from.as_deref(),
+            to.as_deref(),
+            location.as_deref(),
+            template.as_deref(),
+        ),
+        ["demo"] => {
+            // Never the app's own database, even while it's still empty
+            if db_option.is_none() {
+                return Err("demo needs a new database given with --db <path>".into());
+            }
+            let months = match months {
+                Some(months) => months
+                    .parse()
+                    .map_err(|_| format!("Invalid number of months '{}'", months))?,
+                None => demo::DEFAULT_MONTHS,
+            };
+            let db = database::open(&db_path)?;
+            let seed = Local::now().timestamp() as u64;
+            let days = demo::seed(&db, Local::now().date_naive(), months, seed)?;
+            println!("Added {} office days to {}", days, db_path.display());
+            Ok(())
+        }
+        [] | ["help"] | ["--help"] | ["-h"] => {
+            print!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(format!("Unrecognised arguments\n\n{}", USAGE).into()),
+    }
+}
+
+fn export_timesheet(
+    db_path: &Path,
+    path: &Path,
+    from: Option<&str>,
+    to: Option<&str>,
+    location: Option<&str>,
+    template: Option<&str>,
+) -> Result<()> {
+    let parse = |date: &str| {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| Error::from(format!("Invalid date '{}', expected YYYY-MM-DD", date)))
+    };
+    let today = Local::now().date_naive();
+    let from = match from {
+        Some(from) => parse(from)?,
+        None => today.with_day(1).expect("every month has a first day"),
+    };
+    let to = to.map(parse).transpose()?.unwrap_or(today);
+    let template = template.map(std::fs::read_to_string).transpose()?;
+
+    let db = database::open(db_path)?;
+    timesheet::generate(&db, from, to, location, template.as_deref(), path)?;
+    println!("Wrote the timesheet to {}", path.display());
+    Ok(())
+}
+
+/// Removes `--name value` from `args`, returning the value.
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>> {
+    let Some(index) = args.iter().position(|arg| arg == name) else {
+        return Ok(None);
+    };
+    if index + 1 >= args.len() {
+        return Err(Error::from(format!("{} needs a value", name)));
+    }
+    let value = args.remove(index + 1);
+    args.remove(index);
+    Ok(Some(value))
+}
+
+/// Checks presence at the configured interval, recording it like the desktop
+/// app does. Settings are re-read each time so changes made in the app apply.
+fn track(db_path: &Path) -> Result<()> {
+    wifi::init();
+    let db = database::open(db_path)?;
+    // Sessions left running when the last run was interrupted
+    heartbeat::close_dangling(&db)?;
+    let clock = SystemClock;
+    let mut sessions = SessionTracker::default();
+    // Never started here, but the tracking logic shares it with the app
+    let mut manual = ManualTimer::default();
+    let slack = slack::StatusUpdater::spawn(ReadPool::new(
+        db_path.to_path_buf(),
+        database::BUSY_TIMEOUT,
+        database::key(),
+    ));
+    println!("Tracking presence in {}", db_path.display());
+    // This year's and next year's public holidays, if not fetched already
+    let year = Local::now().year();
+    if let Some((country, missing)) = holidays::missing_years(&db, &[year, year + 1])? {
+        for year in missing {
+            let fetched = holidays::fetch(&country, year)
+                .and_then(|fetched| holidays::store(&db, &country, &fetched));
+            if let Err(e) = fetched {
+                eprintln!("Error fetching public holidays: {}", e);
+            }
+        }
+    }
+
+    loop {
+        let settings = Settings::load(&db)?;
+        let detector = settings.detector()?;
+        let outcome = detector.detect().and_then(|presence| {
+            tracking::record_check(&db, presence, &mut sessions, &mut manual, clock.now())
+        });
+        match outcome {
+            Ok(outcome) => match outcome.transition {
+                Transition::Started(id) => session_started(&db, &slack, id),
+                Transition::Ended(id) => session_ended(&db, &slack, id, outcome.discarded),
+                Transition::Rolled { ended, started } => {
+                    session_ended(&db, &slack, ended, outcome.discarded);
+                    session_started(&db, &slack, started);
+                }
+                Transition::Continued | Transition::Idle => {}
+            },
+            Err(e) => eprintln!("Error checking presence: {}", e),
+        }
+        thread::sleep(std::time::Duration::from_secs(
+            settings.poll_interval_seconds.into(),
+        ));
+    }
+}
+
+fn session_started(db: &Connection, slack: &slack::StatusUpdater, id: i64) {
+    println!("Session {} started", id);
+    if let Err(e) = webhooks::fire_for_session(db, WebhookEvent::ClockIn, id) {
+        eprintln!("Error firing webhooks: {}", e);
+    }
+    slack.update(true);
+}
+
+fn session_ended(db: &Connection, slack: &slack::StatusUpdater, id: i64, discarded: bool) {
+    if discarded {
+        println!("Session {} was too short, discarded", id);
+        slack.update(false);
+        return;
+    }
+    println!("Session {} ended", id);
+    if let Err(e) = webhooks::fire_for_session(db, WebhookEvent::ClockOut, id) {
+        eprintln!("Error firing webhooks: {}", e);
+    }
+    slack.update(false);
+    match time_sync::sync(db, &[]) {
+        Ok(0) => {}
+        Ok(count) => println!("Pushed {} sessions as time entries", count),
+        Err(e) => eprintln!("Error pushing time entries: {}", e),
+    }
+    // The session's own day, which is yesterday's when it rolled over
+    let date = match sessions::get_session(db, id) {
+        Ok(Some(session)) => session.date,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("Error reading session {}: {}", id, e);
+            return;
+        }
+    };
+    if let Err(e) = google_calendar::publish_day(db, &date) {
+        eprintln!("Error publishing to Google Calendar: {}", e);
+    }
+    if let Err(e) = daily_notes::write_day(db, &date) {
+        eprintln!("Error writing the daily note: {}", e);
+    }
+}
+
+fn report_today(db_path: &Path, location: Option<&str>) -> Result<()> {
+    let db = database::open(db_path)?;
+    let boundary = DayBoundary::load(&db)?;
+    let today = reports::today_summary(&db, Local::now(), boundary, &[], location)?;
+
+    println!("{}", today.date);
+    match (&today.earliest, &today.latest) {
+        (Some(earliest), Some(latest)) => println!("  {} to {}", earliest, latest),
+        _ => println!("  Not in yet"),
+    }
+    println!(
+        "  Total {}",
+        lunch::format_with_lunch(today.total_minutes, today.lunch_minutes)
+    );
+    Ok(())
+}
+
+fn report_week(db_path: &Path, location: Option<&str>) -> Result<()> {
+    let db = database::open(db_path)?;
+    let boundary = DayBoundary::load(&db)?;
+    let today = boundary.date(Local::now());
+    let week_start = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+    let week = reports::weekly_summary(&db, week_start, today, boundary, location)?;
+
+    println!("Week of {}", week.week_start);
+    for day in &week.days {
+        println!(
+            "  {}  {} to {}  {}",
+            day.date,
+            day.earliest,
+            day.latest,
+            lunch::format_with_lunch(day.total_minutes, day.lunch_minutes)
+        );
+    }
+    println!("  Total {}", format_minutes(week.total_minutes));
+    if let Some(active_minutes) = week.active_minutes {
+        println!("  Focus {}", format_minutes(active_minutes));
+    }
+    if let Some(schedule) = &week.schedule {
+        println!(
+            "  Expected {}, balance {}",
+            format_minutes(schedule.expected_minutes),
+            flexitime::format_balance(schedule.balance_minutes)
+        );
+    }
+    Ok(())
+}
+
+fn report_locations(db_path: &Path, from: Option<&str>, to: Option<&str>) -> Result<()> {
+    let db = database::open(db_path)?;
+    let today = DayBoundary::load(&db)?.date(Local::now());
+    let quarter_start = NaiveDate::from_ymd_opt(today.year(), today.month0() / 3 * 3 + 1, 1)
+        .expect("quarters start on valid dates");
+    let from = from.map_or_else(|| quarter_start.to_string(), str::to_string);
+    let to = to.map_or_else(|| today.to_string(), str::to_string);
+    let locations = reports::location_breakdown(&db, &from, &to)?;
+
+    println!("{} to {}", from, to);
+    for location in &locations {
+        println!(
+            "  {}  {} over {} days ({:.0}%)",
+            location.location.as_deref().unwrap_or("Unnamed"),
+            format_minutes(location.total_minutes),
+            location.days,
+            location.share * 100.0
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,256 @@
+use chrono::{Datelike, Duration, NaiveDate};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::connections::{self, LogQuery};
+use crate::error::Result;
+use crate::holidays;
+use crate::reports::format_minutes;
+use crate::sessions::{format_minute_of_day, DayBoundary};
+use crate::{day_types, settings};
+
+/// How much later than usual an arrival has to be to stand out.
+pub const LATE_ARRIVAL_MINUTES: i64 = 3 * 60;
+/// How much earlier than usual a departure has to be to stand out.
+pub const EARLY_DEPARTURE_MINUTES: i64 = 4 * 60;
+/// How far back to look for what's usual.
+const BASELINE_DAYS: i64 = 90;
+/// Fewer recorded days than this aren't enough to know what's usual.
+const MIN_BASELINE_DAYS: usize = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AnomalyKind {
+    LateArrival,
+    EarlyDeparture,
+    /// Nothing recorded on a weekday that usually has presence, without the
+    /// day being tagged or a public holiday.
+    NoTime,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Anomaly {
+    pub date: String,
+    pub kind: AnomalyKind,
+    /// e.g. "Arrived 11:40, 3h 05m later than usual (08:35)".
+    pub description: String,
+}
+
+fn median(mut values: Vec<i64>) -> Option<i64> {
+    values.sort_unstable();
+    values.get(values.len() / 2).copied()
+}
+
+/// Days from `from` to `to` that stand out from the usual arrival and
+/// departure times and weekdays over the preceding months. Only days before
+/// `today` can have left early or have no time.
+pub fn anomalies(
+    db: &Connection,
+    from: NaiveDate,
+    to: NaiveDate,
+    today: NaiveDate,
+    boundary: DayBoundary,
+) -> Result<Vec<Anomaly>> {
+    let baseline_from = from - Duration::days(BASELINE_DAYS);
+    let (baseline_str, from_str, to_str) =
+        (baseline_from.to_string(), from.to_string(), to.to_string());
+    let query = LogQuery {
+        from: Some(&baseline_str),
+        to: Some(&to_str),
+        ..Default::default()
+    };
+    let log = connections::get_connection_log(db, &query)?;
+    if log.len() < MIN_BASELINE_DAYS {
+        return Ok(Vec::new());
+    }
+
+    let usual_arrival = median(
+        log.iter()
+            .filter_map(|day| boundary.minute_of_day(&day.earliest))
+            .collect(),
+    );
+    let usual_departure = median(
+        log.iter()
+            .filter_map(|day| boundary.minute_of_day(&day.latest))
+            .collect(),
+    );
+    // How often each weekday has presence, out of how many there were since
+    // tracking started. The log is newest first.
+    let mut present = [0; 7];
+    let mut weekdays = [0; 7];
+    let first_day = log
+        .last()
+        .and_then(|day| NaiveDate::parse_from_str(&day.date, "%Y-%m-%d").ok())
+        .unwrap_or(baseline_from);
+    let baseline_end = to.min(today - Duration::days(1));
+    for date in first_day
+        .iter_days()
+        .take_while(|date| *date <= baseline_end)
+    {
+        weekdays[date.weekday().num_days_from_monday() as usize] += 1;
+    }
+    let days: HashMap<&str, &connections::ConnectionLog> =
+        log.iter().map(|day| (day.date.as_str(), day)).collect();
+    for day in &log {
+        if let Ok(date) = NaiveDate::parse_from_str(&day.date, "%Y-%m-%d") {
+            if date <= baseline_end {
+                present[date.weekday().num_days_from_monday() as usize] += 1;
+            }
+        }
+    }
+    let tagged: HashSet<String> = day_types::get_day_types(db, &from_str, &to_str)?
+        .into_iter()
+        .map(|day| day.date)
+        .collect();
+    let holidays = holidays::holiday_dates(db, &from_str, &to_str)?;
+
+    let mut anomalies = Vec::new();
+    for date in from.iter_days().take_while(|date| *date <= to) {
+        let key = date.to_string();
+        let mut flag = |kind, description| {
+            anomalies.push(Anomaly {
+                date: key.clone(),
+                kind,
+                description,
+            })
+        };
+        match days.get(key.as_str()) {
+            Some(day) => {
+                let arrival = boundary.minute_of_day(&day.earliest);
+                if let (Some(arrival), Some(usual)) = (arrival, usual_arrival) {
+                    if arrival - usual >= LATE_ARRIVAL_MINUTES {
+                        flag(
+                            AnomalyKind::LateArrival,
+                            format!(
+                                "Arrived {}, {} later than usual ({})",
+                                day.earliest,
+                                format_minutes(arrival - usual),
+                                format_minute_of_day(usual)
+                            ),
+                        );
+                    }
+                }
+                let departure = boundary.minute_of_day(&day.latest);
+                if let (Some(departure), Some(usual)) = (departure, usual_departure) {
+                    if date < today && usual - departure >= EARLY_DEPARTURE_MINUTES {
+                        flag(
+                            AnomalyKind::EarlyDeparture,
+                            format!(
+                                "Left {}, {} earlier than usual ({})",
+                                day.latest,
+                                format_minutes(usual - departure),
+                                format_minute_of_day(usual)
+                            ),
+                        );
+                    }
+                }
+            }
+            None => {
+                let weekday = date.weekday().num_days_from_monday() as usize;
+                let usual_day = weekday < 5 && present[weekday] * 2 >= weekdays[weekday].max(1);
+                if date < today && usual_day && !tagged.contains(&key) && !holidays.contains(&key) {
+                    flag(
+                        AnomalyKind::NoTime,
+                        format!(
+                            "No time recorded on a {} you're usually in",
+                            date.format("%A")
+                        ),
+                    );
+                }
+            }
+        }
+    }
+    Ok(anomalies)
+}
+
+/// Anomalies on days not already notified about, up to yesterday, marking
+/// them as notified. The first call only covers yesterday.
+pub fn unnotified(
+    db: &Connection,
+    today: NaiveDate,
+    boundary: DayBoundary,
+) -> Result<Vec<Anomaly>> {
+    let yesterday = today - Duration::days(1);
+    let from = settings::get(db, settings::ANOMALIES_NOTIFIED_THROUGH)?
+        .and_then(|date| NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok())
+        .map_or(yesterday, |date| date + Duration::days(1));
+    if from > yesterday {
+        return Ok(Vec::new());
+    }
+    let anomalies = anomalies(db, from, yesterday, today, boundary)?;
+    settings::set(
+        db,
+        settings::ANOMALIES_NOTIFIED_THROUGH,
+        &yesterday.to_string(),
+    )?;
+    Ok(anomalies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn unusual_days_stand_out() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        // Four weeks of weekdays 09:00-17:30, then a late Monday, an early
+        // Tuesday and nothing on Wednesday
+        let mut day = date("2024-02-05");
+        while day < date("2024-03-04") {
+            if day.weekday().num_days_from_monday() < 5 {
+                db.execute(
+                    "INSERT INTO connections (date, earliest, latest) VALUES (?1, '09:00', '17:30')",
+                    [day.to_string()],
+                )
+                .unwrap();
+            }
+            day += Duration::days(1);
+        }
+        db.execute_batch(
+            "INSERT INTO connections (date, earliest, latest) VALUES ('2024-03-04', '12:15', '18:00');
+             INSERT INTO connections (date, earliest, latest) VALUES ('2024-03-05', '09:00', '13:00');",
+        )
+        .unwrap();
+
+        let boundary = DayBoundary { hour: 4 };
+        let found = anomalies(
+            &db,
+            date("2024-03-04"),
+            date("2024-03-10"),
+            date("2024-03-07"),
+            boundary,
+        )
+        .unwrap();
+        let kinds: Vec<(&str, AnomalyKind)> = found
+            .iter()
+            .map(|anomaly| (anomaly.date.as_str(), anomaly.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("2024-03-04", AnomalyKind::LateArrival),
+                ("2024-03-05", AnomalyKind::EarlyDeparture),
+                ("2024-03-06", AnomalyKind::NoTime),
+            ]
+        );
+        assert_eq!(
+            found[0].description,
+            "Arrived 12:15, 3h 15m later than usual (09:00)"
+        );
+    }
+
+    #[test]
+    fn after_midnight_counts_as_late_evening() {
+        let boundary = DayBoundary { hour: 4 };
+        assert_eq!(boundary.minute_of_day("01:30"), Some(25 * 60 + 30));
+        assert_eq!(boundary.minute_of_day("09:00"), Some(9 * 60));
+        assert_eq!(format_minute_of_day(25 * 60 + 30), "01:30");
+    }
+}
@@ -0,0 +1,69 @@
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::error::Result;
+use crate::keychain;
+
+/// Keychain account holding the app lock passcode, salted and hashed.
+pub const PASSCODE_ACCOUNT: &str = "app_lock";
+pub const MIN_PASSCODE_LENGTH: usize = 4;
+/// SHA-256 rounds per check, to slow down guessing should the hash leak.
+const ROUNDS: u32 = 100_000;
+
+fn hash(salt: &str, passcode: &str) -> String {
+    let mut digest = Sha256::digest(format!("{}{}", salt, passcode));
+    for _ in 1..ROUNDS {
+        digest = Sha256::digest(digest);
+    }
+    format!("{:x}", digest)
+}
+
+/// `passcode` as stored: a random salt and the hash, separated by a colon.
+fn encode(passcode: &str) -> String {
+    let salt = Uuid::new_v4().simple().to_string();
+    format!("{}:{}", salt, hash(&salt, passcode))
+}
+
+fn matches(stored: &str, passcode: &str) -> bool {
+    stored
+        .split_once(':')
+        .is_some_and(|(salt, expected)| hash(salt, passcode) == expected)
+}
+
+/// Stores a new passcode, or removes it if `passcode` is empty.
+pub fn set_passcode(passcode: &str) -> Result<()> {
+    if passcode.is_empty() {
+        return keychain::delete(PASSCODE_ACCOUNT);
+    }
+    if passcode.chars().count() < MIN_PASSCODE_LENGTH {
+        return Err(format!(
+            "The passcode needs at least {} characters",
+            MIN_PASSCODE_LENGTH
+        )
+        .into());
+    }
+    keychain::set(PASSCODE_ACCOUNT, &encode(passcode))
+}
+
+pub fn has_passcode() -> Result<bool> {
+    Ok(keychain::get(PASSCODE_ACCOUNT)?.is_some())
+}
+
+/// Whether `passcode` is the one set. False if none is.
+pub fn verify_passcode(passcode: &str) -> Result<bool> {
+    Ok(keychain::get(PASSCODE_ACCOUNT)?.is_some_and(|stored| matches(&stored, passcode)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passcode_is_salted_and_checked() {
+        let stored = encode("2468");
+        assert_ne!(stored, encode("2468"));
+        assert!(matches(&stored, "2468"));
+        assert!(!matches(&stored, "1357"));
+        assert!(!matches("not a hash", "2468"));
+    }
+}
@@ -0,0 +1,496 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+use crate::error::Result;
+
+/// Changes made by tracking, or anything that doesn't say otherwise. Sessions
+/// default to their own `source` instead.
+pub const SOURCE_AUTO: &str = "auto";
+/// Changes made by hand, e.g. editing or deleting a day.
+pub const SOURCE_MANUAL: &str = "manual";
+pub const SOURCE_IMPORT: &str = "import";
+/// Sessions merged in from other devices.
+pub const SOURCE_SYNC: &str = "sync";
+/// History archived and deleted once it's past the configured age.
+pub const SOURCE_RETENTION: &str = "retention";
+/// Manual edits being reverted or reapplied; see `undo` and `redo`.
+pub const SOURCE_UNDO: &str = "undo";
+pub const SOURCE_REDO: &str = "redo";
+
+const SESSION_COLUMNS: [&str; 12] = [
+    "id",
+    "date",
+    "start",
+    "end",
+    "ssid",
+    "label",
+    "source",
+    "start_at",
+    "end_at",
+    "rule",
+    "idle_seconds",
+    "active_seconds",
+];
+const CONNECTION_COLUMNS: [&str; 8] = [
+    "date",
+    "earliest",
+    "latest",
+    "ssid",
+    "label",
+    "note",
+    "earliest_at",
+    "latest_at",
+];
+/// Columns tracking moves forward as time goes on. Updates to only these
+/// aren't logged unless a source was set, or the log would grow on every
+/// check; the row's insert is logged, and its end is in the next change.
+const SESSION_GROWTH: [&str; 4] = ["end", "end_at", "idle_seconds", "active_seconds"];
+const CONNECTION_GROWTH: [&str; 2] = ["latest", "latest_at"];
+
+/// A change to a day or session.
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    /// Unix time of the change.
+    pub at: i64,
+    /// "connections" for a day, "sessions" for a session.
+    pub table: String,
+    pub date: String,
+    pub session_id: Option<i64>,
+    /// The change this was part of, e.g. one `delete_day` that deleted a
+    /// day and its sessions. Missing from entries logged before changes
+    /// were grouped.
+    pub change_id: Option<i64>,
+    /// "insert", "update" or "delete".
+    pub action: String,
+    pub source: String,
+    /// The row before and after, as JSON objects; `None` for an insert's old
+    /// value and a delete's new one.
+    pub old_value: Option<serde_json::Value>,
+    pub new_value: Option<serde_json::Value>,
+}
+
+/// A manual edit that's been undone or redone.
+#[derive(Debug, Serialize)]
+pub struct Edit {
+    pub change_id: i64,
+    /// Unix time the edit was made.
+    pub at: i64,
+    /// The days it touched, to refresh.
+    pub dates: Vec<String>,
+}
+
+fn json_sql(row: &str, columns: &[&str]) -> String {
+    let pairs: Vec<String> = columns
+        .iter()
+        .map(|column| format!("'{0}', {1}.\"{0}\"", column, row))
+        .collect();
+    format!("json_object({})", pairs.join(", "))
+}
+
+/// Triggers logging every insert, update and delete on `table` to
+/// `audit_log`, attributed to the source set with `set_source`, or else
+/// `default_source` (with `{row}` standing for `NEW` or `OLD`), or else
+/// `SOURCE_AUTO`.
+fn triggers_sql(
+    table: &str,
+    columns: &[&str],
+    growth: &[&str],
+    session_id: &str,
+    default_source: &str,
+) -> String {
+    let source = |row: &str| {
+        format!(
+            "COALESCE((SELECT source FROM audit_source), {}, '{}')",
+            default_source.replace("{row}", row),
+            SOURCE_AUTO
+        )
+    };
+    let changed: Vec<String> = columns
+        .iter()
+        .filter(|column| !growth.contains(column))
+        .map(|column| format!("OLD.\"{0}\" IS NOT NEW.\"{0}\"", column))
+        .collect();
+    format!(
+        "DROP TRIGGER IF EXISTS audit_{table}_insert;
+        CREATE TRIGGER audit_{table}_insert AFTER INSERT ON {table} BEGIN
+            INSERT INTO audit_log
+                (table_name, date, session_id, change_id, action, source, new_value)
+            VALUES ('{table}', NEW.date, {new_id}, {change_id}, 'insert', {new_source}, {new});
+        END;
+        DROP TRIGGER IF EXISTS audit_{table}_update;
+        CREATE TRIGGER audit_{table}_update AFTER UPDATE ON {table}
+        WHEN EXISTS (SELECT 1 FROM audit_source) OR {changed}
+        BEGIN
+            INSERT INTO audit_log
+                (table_name, date, session_id, change_id, action, source, old_value, new_value)
+            VALUES ('{table}', NEW.date, {new_id}, {change_id}, 'update', {new_source}, {old},
+                {new});
+        END;
+        DROP TRIGGER IF EXISTS audit_{table}_delete;
+        CREATE TRIGGER audit_{table}_delete AFTER DELETE ON {table} BEGIN
+            INSERT INTO audit_log
+                (table_name, date, session_id, change_id, action, source, old_value)
+            VALUES ('{table}', OLD.date, {old_id}, {change_id}, 'delete', {old_source}, {old});
+        END;",
+        change_id = "(SELECT change_id FROM audit_source)",
+        table = table,
+        changed = changed.join(" OR "),
+        new_id = session_id.replace("{row}", "NEW"),
+        old_id = session_id.replace("{row}", "OLD"),
+        new_source = source("NEW"),
+        old_source = source("OLD"),
+        new = json_sql("NEW", columns),
+        old = json_sql("OLD", columns),
+    )
+}
+
+/// The append-only log of changes to days and sessions, filled in by
+/// triggers, the changes its entries are grouped into, and the table
+/// `set_source` attributes them with. The triggers are replaced each time,
+/// so this also brings them up to date.
+pub fn create_tables(db: &Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            table_name TEXT NOT NULL,
+            date TEXT NOT NULL,
+            session_id INTEGER,
+            change_id INTEGER,
+            action TEXT NOT NULL,
+            source TEXT NOT NULL,
+            old_value TEXT,
+            new_value TEXT
+        );
+        CREATE INDEX IF NOT EXISTS audit_log_change ON audit_log (change_id);
+        CREATE INDEX IF NOT EXISTS audit_log_date ON audit_log (date);
+        CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log BEGIN
+            SELECT RAISE(ABORT, 'The audit log is append-only');
+        END;
+        CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log BEGIN
+            SELECT RAISE(ABORT, 'The audit log is append-only');
+        END;
+        CREATE TABLE IF NOT EXISTS audit_changes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+            source TEXT NOT NULL,
+            undone_by INTEGER,
+            redoable INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE TABLE IF NOT EXISTS audit_source (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            source TEXT NOT NULL,
+            change_id INTEGER
+        );",
+    )?;
+    db.execute_batch(&triggers_sql(
+        "sessions",
+        &SESSION_COLUMNS,
+        &SESSION_GROWTH,
+        "{row}.id",
+        "{row}.source",
+    ))?;
+    db.execute_batch(&triggers_sql(
+        "connections",
+        &CONNECTION_COLUMNS,
+        &CONNECTION_GROWTH,
+        "NULL",
+        "NULL",
+    ))
+}
+
+/// Starts a change attributed to `source`, which the changes made through
+/// `db` are part of until `clear_source`, returning its id. Only call it
+/// inside a transaction, clearing it before committing, so no other
+/// connection ever sees it. A new manual edit means there's nothing to redo.
+pub fn set_source(db: &Connection, source: &str) -> rusqlite::Result<i64> {
+    db.execute("INSERT INTO audit_changes (source) VALUES (?1)", [source])?;
+    let change_id = db.last_insert_rowid();
+    if source == SOURCE_MANUAL {
+        db.execute(
+            "UPDATE audit_changes SET redoable = 0 WHERE redoable = 1",
+            [],
+        )?;
+    }
+    db.execute(
+        "INSERT OR REPLACE INTO audit_source (id, source, change_id) VALUES (1, ?1, ?2)",
+        params![source, change_id],
+    )?;
+    Ok(change_id)
+}
+
+pub fn clear_source(db: &Connection) -> rusqlite::Result<()> {
+    db.execute("DELETE FROM audit_source", [])?;
+    Ok(())
+}
+
+/// Changes to days between `from` and `to` inclusive, either of which can be
+/// left open, newest first, at most `limit` of them.
+pub fn entries(
+    db: &Connection,
+    from: Option<&str>,
+    to: Option<&str>,
+    limit: u32,
+) -> Result<Vec<AuditEntry>> {
+    let mut stmt = db.prepare(
+        "SELECT id, at, table_name, date, session_id, change_id, action, source, old_value,
+            new_value
+         FROM audit_log
+         WHERE (?1 IS NULL OR date >= ?1) AND (?2 IS NULL OR date <= ?2)
+         ORDER BY id DESC
+         LIMIT ?3",
+    )?;
+    let json = |value: Option<String>| value.and_then(|value| serde_json::from_str(&value).ok());
+    let entries = stmt.query_map(params![from, to, limit], |row| {
+        Ok(AuditEntry {
+            id: row.get(0)?,
+            at: row.get(1)?,
+            table: row.get(2)?,
+            date: row.get(3)?,
+            session_id: row.get(4)?,
+            change_id: row.get(5)?,
+            action: row.get(6)?,
+            source: row.get(7)?,
+            old_value: json(row.get(8)?),
+            new_value: json(row.get(9)?),
+        })
+    })?;
+    Ok(entries.collect::<rusqlite::Result<_>>()?)
+}
+
+/// The columns logged for `table`, and the one that identifies a row.
+fn table_columns(table: &str) -> rusqlite::Result<(&'static [&'static str], &'static str)> {
+    match table {
+        "sessions" => Ok((&SESSION_COLUMNS, "id")),
+        "connections" => Ok((&CONNECTION_COLUMNS, "date")),
+        _ => Err(rusqlite::Error::InvalidParameterName(table.to_string())),
+    }
+}
+
+/// Puts the row logged as `value` back into `table`, adding or overwriting
+/// it.
+fn restore_row(db: &Connection, table: &str, value: &str) -> rusqlite::Result<()> {
+    let (columns, key) = table_columns(table)?;
+    let values: Vec<String> = columns
+        .iter()
+        .map(|column| format!("json_extract(?1, '$.{}')", column))
+        .collect();
+    let updates: Vec<String> = columns
+        .iter()
+        .filter(|column| **column != key)
+        .map(|column| format!("\"{0}\" = excluded.\"{0}\"", column))
+        .collect();
+    db.execute(
+        &format!(
+            "INSERT INTO {} (\"{}\") VALUES ({}) ON CONFLICT(\"{}\") DO UPDATE SET {}",
+            table,
+            columns.join("\", \""),
+            values.join(", "),
+            key,
+            updates.join(", ")
+        ),
+        [value],
+    )?;
+    Ok(())
+}
+
+fn remove_row(db: &Connection, table: &str, value: &str) -> rusqlite::Result<()> {
+    let (_, key) = table_columns(table)?;
+    db.execute(
+        &format!(
+            "DELETE FROM {0} WHERE \"{1}\" = json_extract(?1, '$.{1}')",
+            table, key
+        ),
+        [value],
+    )?;
+    Ok(())
+}
+
+/// Reverts (`forward` false) or reapplies the entries logged for a change,
+/// as part of a new change attributed to `source`. Returns the new change's
+/// id and the days touched.
+fn replay(
+    db: &Connection,
+    change_id: i64,
+    forward: bool,
+    source: &str,
+) -> Result<(i64, Vec<String>)> {
+    let replay_id = set_source(db, source)?;
+    let mut stmt = db.prepare(&format!(
+        "SELECT table_name, date, action, old_value, new_value FROM audit_log
+         WHERE change_id = ?1 ORDER BY id {}",
+        if forward { "ASC" } else { "DESC" }
+    ))?;
+    let rows = stmt
+        .query_map([change_id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut dates = Vec::new();
+    for (table, date, action, old, new) in rows {
+        match (forward, action.as_str(), old, new) {
+            (false, "insert", _, Some(new)) => remove_row(db, &table, &new)?,
+            (false, "update" | "delete", Some(old), _) => restore_row(db, &table, &old)?,
+            (true, "insert" | "update", _, Some(new)) => restore_row(db, &table, &new)?,
+            (true, "delete", Some(old), _) => remove_row(db, &table, &old)?,
+            _ => {}
+        }
+        if !dates.contains(&date) {
+            dates.push(date);
+        }
+    }
+    clear_source(db)?;
+    Ok((replay_id, dates))
+}
+
+/// Reverts the last manual edit that hasn't been undone, putting every day
+/// and session it touched back as it was just before, or returns `None` if
+/// there's nothing to undo. Anything that's happened to those rows since,
+/// e.g. tracking extending a day, is overwritten too.
+pub fn undo(db: &Connection) -> Result<Option<Edit>> {
+    let tx = db.unchecked_transaction()?;
+    let Some((change_id, at)) = tx
+        .query_row(
+            "SELECT id, at FROM audit_changes WHERE source = ?1 AND undone_by IS NULL
+             ORDER BY id DESC LIMIT 1",
+            [SOURCE_MANUAL],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    let (undo_id, dates) = replay(&tx, change_id, false, SOURCE_UNDO)?;
+    tx.execute(
+        "UPDATE audit_changes SET undone_by = ?1, redoable = 1 WHERE id = ?2",
+        params![undo_id, change_id],
+    )?;
+    tx.commit()?;
+    Ok(Some(Edit {
+        change_id,
+        at,
+        dates,
+    }))
+}
+
+/// Reapplies the edit undone most recently, unless there's been a manual
+/// edit since, returning `None` if there's nothing to redo.
+pub fn redo(db: &Connection) -> Result<Option<Edit>> {
+    let tx = db.unchecked_transaction()?;
+    let Some((change_id, at)) = tx
+        .query_row(
+            "SELECT id, at FROM audit_changes WHERE redoable = 1
+             ORDER BY undone_by DESC LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    let (_, dates) = replay(&tx, change_id, true, SOURCE_REDO)?;
+    tx.execute(
+        "UPDATE audit_changes SET undone_by = NULL, redoable = 0 WHERE id = ?1",
+        [change_id],
+    )?;
+    tx.commit()?;
+    Ok(Some(Edit {
+        change_id,
+        at,
+        dates,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    #[test]
+    fn changes_are_logged_with_their_source() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        db.execute(
+            "INSERT INTO sessions (date, start, end) VALUES ('2024-03-04', '09:00', '09:05')",
+            [],
+        )
+        .unwrap();
+        // Tracking extending the session isn't worth a row
+        db.execute("UPDATE sessions SET end = '12:00'", []).unwrap();
+        let tx = db.transaction().unwrap();
+        set_source(&tx, SOURCE_MANUAL).unwrap();
+        tx.execute("UPDATE sessions SET start = '08:30'", [])
+            .unwrap();
+        clear_source(&tx).unwrap();
+        tx.commit().unwrap();
+        db.execute("DELETE FROM sessions", []).unwrap();
+
+        let entries = entries(&db, Some("2024-03-04"), None, 10).unwrap();
+        let actions: Vec<(&str, &str)> = entries
+            .iter()
+            .map(|entry| (entry.action.as_str(), entry.source.as_str()))
+            .collect();
+        assert_eq!(
+            actions,
+            vec![("delete", "auto"), ("update", "manual"), ("insert", "auto")]
+        );
+        assert_eq!(entries[1].old_value.as_ref().unwrap()["start"], "09:00");
+        assert_eq!(entries[1].new_value.as_ref().unwrap()["end"], "12:00");
+        assert!(db.execute("DELETE FROM audit_log", []).is_err());
+    }
+
+    #[test]
+    fn undo_and_redo_a_deleted_day() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        db.execute_batch(
+            "INSERT INTO connections (date, earliest, latest, note)
+                VALUES ('2024-03-04', '09:00', '17:00', 'offsite');
+            INSERT INTO sessions (date, start, end) VALUES ('2024-03-04', '09:00', '17:00');",
+        )
+        .unwrap();
+        let count = |db: &Connection| -> (i64, i64) {
+            db.query_row(
+                "SELECT (SELECT COUNT(*) FROM connections), (SELECT COUNT(*) FROM sessions)",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
+        };
+        let delete = |db: &Connection| {
+            let tx = db.unchecked_transaction().unwrap();
+            set_source(&tx, SOURCE_MANUAL).unwrap();
+            tx.execute_batch("DELETE FROM sessions; DELETE FROM connections;")
+                .unwrap();
+            clear_source(&tx).unwrap();
+            tx.commit().unwrap();
+        };
+        delete(&db);
+
+        let undone = undo(&db).unwrap().unwrap();
+        assert_eq!(undone.dates, vec!["2024-03-04"]);
+        assert_eq!(count(&db), (1, 1));
+        let note: String = db
+            .query_row("SELECT note FROM connections", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(note, "offsite");
+        assert!(undo(&db).unwrap().is_none());
+
+        redo(&db).unwrap().unwrap();
+        assert_eq!(count(&db), (0, 0));
+        assert!(redo(&db).unwrap().is_none());
+
+        // A new edit after undoing leaves nothing to redo
+        undo(&db).unwrap().unwrap();
+        delete(&db);
+        assert!(redo(&db).unwrap().is_none());
+        assert_eq!(count(&db), (0, 0));
+    }
+}
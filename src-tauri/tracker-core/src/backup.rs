@@ -0,0 +1,287 @@
+use chrono::Local;
+use rusqlite::backup::{Backup, Progress};
+use rusqlite::{Connection, ErrorCode};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::database;
+use crate::error::{Error, Result};
+
+/// How many daily backups to keep before deleting the oldest.
+pub const BACKUPS_TO_KEEP: usize = 7;
+
+const PREFIX: &str = "connections-";
+const EXTENSION: &str = ".db";
+/// Pages copied per step of SQLite's online backup.
+const PAGES_PER_STEP: i32 = 256;
+
+#[derive(Serialize)]
+pub struct BackupInfo {
+    pub name: String,
+    pub size_bytes: u64,
+}
+
+/// What `open_or_recover` did about a corrupt database.
+#[derive(Debug, Serialize)]
+pub struct Recovery {
+    /// What SQLite found wrong with it.
+    pub problems: Vec<String>,
+    /// The backup put in its place, or `None` if there wasn't a sound one,
+    /// leaving the damaged database in use.
+    pub restored: Option<String>,
+    /// Where the damaged database was moved to, to recover by hand.
+    pub moved_to: Option<PathBuf>,
+}
+
+pub fn backups_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("backups")
+}
+
+/// Copies the live database into `dir` using SQLite's online backup API, so
+/// the copy is consistent even if the tracker writes mid-backup. The copy is
+/// encrypted with `key` if the database is. Returns the new file's path.
+pub fn create_backup(db: &Connection, dir: &Path, key: Option<&str>) -> Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!(
+        "{}{}{}",
+        PREFIX,
+        Local::now().format("%Y-%m-%d-%H%M%S"),
+        EXTENSION
+    ));
+    copy_to(db, &path, key)?;
+    info!("Backed up database to {:?}", path);
+    Ok(path)
+}
+
+/// Copies the live database to a new file at `path`, consistently and
+/// encrypted with `key` as `create_backup` does.
+pub fn copy_to(db: &Connection, path: &Path, key: Option<&str>) -> Result<()> {
+    let mut copy = open(path, key)?;
+    copy_database(db, &mut copy)
+}
+
+/// Backs up unless a backup was already taken today, then deletes all but
+/// the newest `keep` backups.
+pub fn backup_if_due(db: &Connection, dir: &Path, keep: usize, key: Option<&str>) -> Result<()> {
+    let today = format!("{}{}", PREFIX, Local::now().format("%Y-%m-%d"));
+    let done_today = list_backups(dir)?
+        .iter()
+        .any(|backup| backup.name.starts_with(&today));
+    if !done_today {
+        create_backup(db, dir, key)?;
+    }
+    rotate(dir, keep)
+}
+
+/// Backups in `dir`, newest first.
+pub fn list_backups(dir: &Path) -> Result<Vec<BackupInfo>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut backups = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with(PREFIX) && name.ends_with(EXTENSION) {
+            let size_bytes = entry.metadata()?.len();
+            backups.push(BackupInfo { name, size_bytes });
+        }
+    }
+    // Names embed a sortable timestamp
+    backups.sort_by(|a, b| b.name.cmp(&a.name));
+    Ok(backups)
+}
+
+fn rotate(dir: &Path, keep: usize) -> Result<()> {
+    for backup in list_backups(dir)?.into_iter().skip(keep) {
+        info!("Removing old backup {}", backup.name);
+        fs::remove_file(dir.join(&backup.name))?;
+    }
+    Ok(())
+}
+
+/// Replaces the live database's contents with the backup called `name`.
+pub fn restore_backup(
+    db: &mut Connection,
+    dir: &Path,
+    name: &str,
+    key: Option<&str>,
+) -> Result<()> {
+    // Only accept names of files we created, not arbitrary paths
+    if !list_backups(dir)?.iter().any(|backup| backup.name == name) {
+        return Err(format!("No backup named '{}'", name).into());
+    }
+    let backup = open(&dir.join(name), key)?;
+    copy_database(&backup, db)?;
+    info!("Restored database from {}", name);
+    Ok(())
+}
+
+/// Opens the database at `path` as `database::open_with_key` does, after
+/// checking its integrity. A corrupt one is moved aside, to the same name
+/// with `.corrupt-<time>` on the end, and replaced with the newest backup in
+/// `dir` that passes the check, so the app starts with at most a day's
+/// history missing instead of failing. Returns what was done, if anything.
+pub fn open_or_recover(
+    path: &Path,
+    dir: &Path,
+    key: Option<&str>,
+) -> Result<(Connection, Option<Recovery>)> {
+    let (db, problems) = match database::open_with_key(path, key) {
+        Ok(db) => match database::integrity_check(&db) {
+            Ok(problems) if problems.is_empty() => return Ok((db, None)),
+            Ok(problems) => (Some(db), problems),
+            Err(e) if is_corrupt(&e) => (Some(db), vec![e.to_string()]),
+            Err(e) => return Err(e),
+        },
+        Err(e) if is_corrupt(&e) => (None, vec![e.to_string()]),
+        Err(e) => return Err(e),
+    };
+    error!("Database is corrupt: {}", problems.join("; "));
+
+    let Some(name) = newest_sound_backup(dir, key)? else {
+        let db = db.ok_or_else(|| {
+            format!(
+                "The database is corrupt and there's no backup to restore: {}",
+                problems.join("; ")
+            )
+        })?;
+        return Ok((
+            db,
+            Some(Recovery {
+                problems,
+                restored: None,
+                moved_to: None,
+            }),
+        ));
+    };
+    drop(db);
+    let suffix = format!(".corrupt-{}", Local::now().format("%Y-%m-%d-%H%M%S"));
+    let moved_to = with_suffix(path, &suffix);
+    fs::rename(path, &moved_to)?;
+    // The write-ahead log belongs to the damaged copy, not the backup
+    for journal in ["-wal", "-shm"] {
+        let journal_path = with_suffix(path, journal);
+        if journal_path.exists() {
+            fs::rename(&journal_path, with_suffix(&moved_to, journal))?;
+        }
+    }
+    fs::copy(dir.join(&name), path)?;
+    let db = database::open_with_key(path, key)?;
+    info!("Restored database from {}", name);
+    Ok((
+        db,
+        Some(Recovery {
+            problems,
+            restored: Some(name),
+            moved_to: Some(moved_to),
+        }),
+    ))
+}
+
+fn is_corrupt(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::Database(rusqlite::Error::SqliteFailure(failure, _))
+            if failure.code == ErrorCode::DatabaseCorrupt
+    )
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// The newest backup in `dir` that opens with `key` and passes the
+/// integrity check.
+fn newest_sound_backup(dir: &Path, key: Option<&str>) -> Result<Option<String>> {
+    for backup in list_backups(dir)? {
+        let sound =
+            open(&dir.join(&backup.name), key).and_then(|db| database::integrity_check(&db));
+        match sound {
+            Ok(problems) if problems.is_empty() => return Ok(Some(backup.name)),
+            Ok(problems) => warn!(
+                "Backup {} is corrupt too: {}",
+                backup.name,
+                problems.join("; ")
+            ),
+            Err(e) => warn!("Couldn't check backup {}: {}", backup.name, e),
+        }
+    }
+    Ok(None)
+}
+
+/// Encrypts any backups in `dir` taken before the database was, so turning
+/// encryption on doesn't leave plaintext copies of the history behind and
+/// every backup can still be restored.
+pub fn encrypt_backups(dir: &Path, key: &str) -> Result<()> {
+    for backup in list_backups(dir)? {
+        if database::encrypt_file(&dir.join(&backup.name), key)? {
+            info!("Encrypted backup {}", backup.name);
+        }
+    }
+    Ok(())
+}
+
+fn open(path: &Path, key: Option<&str>) -> Result<Connection> {
+    let db = Connection::open(path)?;
+    if let Some(key) = key {
+        database::unlock(&db, key)?;
+    }
+    Ok(db)
+}
+
+/// Copies all of `from` over `to`. Done by hand rather than with
+/// `Connection::backup` so both ends can be given the key first.
+fn copy_database(from: &Connection, to: &mut Connection) -> Result<()> {
+    Backup::new(from, to)
+        .and_then(|backup| {
+            backup.run_to_completion(PAGES_PER_STEP, Duration::ZERO, None::<fn(Progress)>)
+        })
+        .map_err(Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[test]
+    fn corrupt_database_is_replaced_with_backup() {
+        let data_dir = std::env::temp_dir().join(format!("tracker-backup-{}", Uuid::new_v4()));
+        let path = data_dir.join(database::FILE_NAME);
+        let dir = backups_dir(&data_dir);
+        let db = database::open_with_key(&path, None).unwrap();
+        db.execute(
+            "INSERT INTO connections (date, earliest, latest) VALUES ('2024-03-04', '09:00', '17:00')",
+            [],
+        )
+        .unwrap();
+        create_backup(&db, &dir, None).unwrap();
+        database::checkpoint(&db).unwrap();
+        drop(db);
+        // Scribble over a page past the schema
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[3 * 4096..4 * 4096].fill(0xff);
+        fs::write(&path, bytes).unwrap();
+
+        let (db, recovery) = open_or_recover(&path, &dir, None).unwrap();
+
+        let recovery = recovery.unwrap();
+        assert!(recovery.restored.is_some());
+        assert!(recovery.moved_to.unwrap().exists());
+        let days: i64 = db
+            .query_row("SELECT COUNT(*) FROM connections", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(days, 1);
+        drop(db);
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
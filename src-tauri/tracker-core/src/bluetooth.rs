@@ -0,0 +1,219 @@
+use std::process::Command;
+
+use crate::error::{Error, Result};
+use crate::network;
+use crate::process::{CommandExt, COMMAND_TIMEOUT};
+use crate::wifi::normalize_mac;
+
+/// A Bluetooth device in range of the machine.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BluetoothDevice {
+    pub name: Option<String>,
+    /// Lowercase and colon-separated. Windows doesn't report it.
+    pub address: Option<String>,
+}
+
+impl BluetoothDevice {
+    /// Whether this is `device`, given as an address like
+    /// "AA:BB:CC:DD:EE:FF" or otherwise as the device's name.
+    pub fn matches(&self, device: &str) -> bool {
+        let device = device.trim();
+        if network::validate_mac(device).is_ok() {
+            return self.address.as_deref() == Some(normalize_mac(device).as_str());
+        }
+        self.name
+            .as_deref()
+            .is_some_and(|name| name.eq_ignore_ascii_case(device))
+    }
+}
+
+/// Devices currently in range. That means connected ones on macOS and
+/// Windows, which offer no way to scan from the command line, and on Linux
+/// also any found by a short scan, so beacons that never connect work there.
+pub fn nearby_devices() -> Result<Vec<BluetoothDevice>> {
+    platform::nearby_devices()
+}
+
+fn run(command: &'static str, args: &[&str]) -> Result<String> {
+    let output = Command::new(command)
+        .args(args)
+        .output_within(COMMAND_TIMEOUT)
+        .map_err(Error::command(command))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parses `system_profiler SPBluetoothDataType -json`, which lists connected
+/// devices as `{"device_connected": [{"<name>": {"device_address": ...}}]}`.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_system_profiler(json: &str) -> Result<Vec<BluetoothDevice>> {
+    let value: serde_json::Value = serde_json::from_str(json)
+        .map_err(|e| format!("Unexpected system_profiler output: {}", e))?;
+    let controllers = value["SPBluetoothDataType"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    Ok(controllers
+        .iter()
+        .filter_map(|controller| controller["device_connected"].as_array())
+        .flatten()
+        .filter_map(|entry| entry.as_object())
+        .flatten()
+        .map(|(name, properties)| BluetoothDevice {
+            name: Some(name.clone()),
+            address: properties["device_address"].as_str().map(normalize_mac),
+        })
+        .collect())
+}
+
+/// Parses `bluetoothctl` lines mentioning a device, e.g.
+/// "Device AA:BB:CC:DD:EE:FF Office Speaker" or, during a scan,
+/// "[CHG] Device AA:BB:CC:DD:EE:FF RSSI: -61".
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_bluetoothctl(output: &str) -> Vec<BluetoothDevice> {
+    let mut devices: Vec<BluetoothDevice> = Vec::new();
+    for line in output.lines() {
+        let Some((_, rest)) = line.split_once("Device ") else {
+            continue;
+        };
+        let (address, detail) = rest.split_once(' ').unwrap_or((rest, ""));
+        if network::validate_mac(address).is_err() {
+            continue;
+        }
+        let address = normalize_mac(address);
+        // Property changes rather than names, e.g. "RSSI: -61"
+        let name = Some(detail.trim())
+            .filter(|detail| !detail.is_empty() && !detail.contains(": "))
+            .map(str::to_string);
+        match devices
+            .iter_mut()
+            .find(|device| device.address.as_deref() == Some(address.as_str()))
+        {
+            Some(device) => {
+                if device.name.is_none() {
+                    device.name = name;
+                }
+            }
+            None => devices.push(BluetoothDevice {
+                name,
+                address: Some(address),
+            }),
+        }
+    }
+    devices
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+
+    pub fn nearby_devices() -> Result<Vec<BluetoothDevice>> {
+        parse_system_profiler(&run("system_profiler", &["SPBluetoothDataType", "-json"])?)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::*;
+
+    /// How long to scan for devices that don't connect, like beacons.
+    const SCAN_SECONDS: &str = "4";
+
+    pub fn nearby_devices() -> Result<Vec<BluetoothDevice>> {
+        // Only devices seen during the scan are listed in its output, unlike
+        // `bluetoothctl devices`, which includes every device ever seen
+        let mut output = run("bluetoothctl", &["--timeout", SCAN_SECONDS, "scan", "on"])?;
+        output.push_str(&run("bluetoothctl", &["devices", "Connected"])?);
+        Ok(parse_bluetoothctl(&output))
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::*;
+
+    /// Lists paired devices whose "is connected" device property
+    /// (DEVPKEY_Device_IsConnected) is set.
+    pub fn nearby_devices() -> Result<Vec<BluetoothDevice>> {
+        let output = run(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                "Get-PnpDevice -Class Bluetooth | Where-Object { \
+                 ($_ | Get-PnpDeviceProperty -KeyName '{83DA6326-97A6-4088-9453-A1923F573B29} 15').Data \
+                 } | ForEach-Object { $_.FriendlyName }",
+            ],
+        )?;
+        Ok(output
+            .lines()
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| BluetoothDevice {
+                name: Some(name.to_string()),
+                address: None,
+            })
+            .collect())
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
+mod platform {
+    use super::*;
+
+    pub fn nearby_devices() -> Result<Vec<BluetoothDevice>> {
+        Err("Bluetooth detection is not supported on this platform".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_connected_devices_from_system_profiler() {
+        let json = r#"{"SPBluetoothDataType": [{
+            "controller_properties": {"controller_state": "attrib_on"},
+            "device_connected": [
+                {"Office Speaker": {"device_address": "AA:BB:CC:DD:EE:FF"}}
+            ],
+            "device_not_connected": [
+                {"AirPods": {"device_address": "11:22:33:44:55:66"}}
+            ]
+        }]}"#;
+
+        let devices = parse_system_profiler(json).unwrap();
+        assert_eq!(
+            devices,
+            vec![BluetoothDevice {
+                name: Some("Office Speaker".to_string()),
+                address: Some("aa:bb:cc:dd:ee:ff".to_string()),
+            }]
+        );
+        assert!(devices[0].matches("office speaker"));
+        assert!(devices[0].matches("aa-bb-cc-dd-ee-ff"));
+        assert!(!devices[0].matches("AirPods"));
+    }
+
+    #[test]
+    fn parses_devices_seen_by_bluetoothctl() {
+        let output = "Discovery started\n\
+            [CHG] Controller 00:11:22:33:44:55 Discovering: yes\n\
+            [CHG] Device AA:BB:CC:DD:EE:FF RSSI: -61\n\
+            [NEW] Device 66:77:88:99:AA:BB Desk Beacon\n\
+            Device AA:BB:CC:DD:EE:FF Office Speaker\n";
+
+        let devices = parse_bluetoothctl(output);
+        assert_eq!(devices.len(), 2);
+        assert!(devices[0].matches("Office Speaker"));
+        assert!(devices[1].matches("66:77:88:99:aa:bb"));
+        assert!(devices[1].matches("Desk Beacon"));
+    }
+}
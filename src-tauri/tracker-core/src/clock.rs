@@ -0,0 +1,46 @@
+use chrono::{DateTime, Duration, Local};
+use std::sync::Mutex;
+
+use crate::error::LockExt;
+
+/// Where the tracker gets the current time, so tests can control it.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Local>;
+}
+
+/// The real wall clock.
+#[derive(Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+/// Stays at whatever time it was last set to.
+pub struct MockClock {
+    now: Mutex<DateTime<Local>>,
+}
+
+impl MockClock {
+    pub fn new(now: DateTime<Local>) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Local>) {
+        *self.now.lock_unpoisoned() = now;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock_unpoisoned() += by;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Local> {
+        *self.now.lock_unpoisoned()
+    }
+}
@@ -0,0 +1,120 @@
+use chrono::{DateTime, Local};
+use rusqlite::{params, Connection, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::detector::Presence;
+use crate::sessions::{location_sql, DayBoundary, MINUTES_SQL};
+
+/// Recorded in `connections` for time covered by the manual timer.
+pub const MANUAL_PRESENCE: Presence = Presence {
+    ssid: None,
+    label: None,
+    vpn: false,
+    rule: None,
+    idle: false,
+    active: None,
+};
+
+/// One day's first and last presence.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConnectionLog {
+    pub date: String,
+    /// HH:MM in the current time zone, converted from the stored UTC
+    /// timestamps.
+    pub earliest: String,
+    pub latest: String,
+    pub ssid: Option<String>,
+    pub label: Option<String>,
+    /// Minutes actually connected, excluding gaps between sessions.
+    pub total_minutes: i64,
+    /// Free-text annotation, e.g. "left early for dentist".
+    pub note: Option<String>,
+}
+
+/// Filters for `get_connection_log`. Dates are inclusive, as YYYY-MM-DD.
+#[derive(Default)]
+pub struct LogQuery<'a> {
+    pub from: Option<&'a str>,
+    pub to: Option<&'a str>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    /// Only days with sessions at this location, totalling just those.
+    pub location: Option<&'a str>,
+}
+
+pub fn get_connection_log(db: &Connection, query: &LogQuery) -> Result<Vec<ConnectionLog>> {
+    let mut stmt = db.prepare(&format!(
+        "SELECT date,
+            COALESCE(strftime('%H:%M', earliest_at, 'unixepoch', 'localtime'), earliest),
+            COALESCE(strftime('%H:%M', latest_at, 'unixepoch', 'localtime'), latest),
+            ssid, label,
+            COALESCE((SELECT SUM({0}) FROM sessions s
+                WHERE s.date = connections.date AND {1}), 0),
+            note
+         FROM connections
+         WHERE (?1 IS NULL OR date >= ?1) AND (?2 IS NULL OR date <= ?2)
+            AND (?5 IS NULL OR EXISTS (
+                SELECT 1 FROM sessions s WHERE s.date = connections.date AND {1}))
+         ORDER BY date DESC
+         LIMIT COALESCE(?3, -1) OFFSET COALESCE(?4, 0)",
+        MINUTES_SQL,
+        location_sql("?5")
+    ))?;
+    let logs = stmt.query_map(
+        params![
+            query.from,
+            query.to,
+            query.limit,
+            query.offset,
+            query.location
+        ],
+        |row| {
+            Ok(ConnectionLog {
+                date: row.get(0)?,
+                earliest: row.get(1)?,
+                latest: row.get(2)?,
+                ssid: row.get(3)?,
+                label: row.get(4)?,
+                total_minutes: row.get(5)?,
+                note: row.get(6)?,
+            })
+        },
+    )?;
+
+    logs.collect()
+}
+
+/// The log for a single day, if anything was recorded on it.
+pub fn get_day(db: &Connection, date: &str) -> Result<Option<ConnectionLog>> {
+    let query = LogQuery {
+        from: Some(date),
+        to: Some(date),
+        ..Default::default()
+    };
+    Ok(get_connection_log(db, &query)?.into_iter().next())
+}
+
+/// Widens the tracking day's earliest/latest to include `now`.
+pub fn insert_connection(
+    db: &Connection,
+    presence: &Presence,
+    now: DateTime<Local>,
+    boundary: DayBoundary,
+) -> Result<()> {
+    let date = boundary.date_string(now);
+    let time = now.format("%H:%M").to_string();
+
+    // Compare UTC timestamps rather than local times, which go backwards
+    // across midnight, DST changes and time zones
+    db.execute(
+        "INSERT INTO connections (date, earliest, latest, earliest_at, latest_at, ssid, label)
+         VALUES (?1, ?2, ?2, ?3, ?3, ?4, ?5)
+         ON CONFLICT(date) DO UPDATE SET
+         earliest = CASE WHEN ?3 < earliest_at THEN ?2 ELSE earliest END,
+         earliest_at = MIN(earliest_at, ?3),
+         latest = CASE WHEN ?3 > latest_at THEN ?2 ELSE latest END,
+         latest_at = MAX(latest_at, ?3)",
+        params![date, time, now.timestamp(), presence.ssid, presence.label],
+    )?;
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::connections::{self, ConnectionLog};
+use crate::error::Result;
+use crate::reports::format_minutes;
+use crate::settings;
+
+pub const DEFAULT_FILE_NAME: &str = "{{date}}.md";
+pub const DEFAULT_TEMPLATE: &str = "# {{date}}\n\n";
+pub const DEFAULT_LINE: &str = "Office: {{earliest}}\u{2013}{{latest}} ({{total}})";
+
+/// Writing each day's times into a Markdown note per day, e.g. the daily
+/// notes in an Obsidian vault, as configured by the user. Templates take
+/// `{{date}}`, `{{earliest}}`, `{{latest}}` and `{{total}}`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DailyNoteSetting {
+    /// Folder the notes live in, e.g. the vault's daily notes folder.
+    pub folder: String,
+    #[serde(default = "default_file_name")]
+    pub file_name: String,
+    /// Content for notes that don't exist yet, before the line is added.
+    #[serde(default = "default_template")]
+    pub template: String,
+    /// The line to add, or update if a line starting with the same text
+    /// before its first placeholder is already there.
+    #[serde(default = "default_line")]
+    pub line: String,
+}
+
+fn default_file_name() -> String {
+    DEFAULT_FILE_NAME.to_string()
+}
+
+fn default_template() -> String {
+    DEFAULT_TEMPLATE.to_string()
+}
+
+fn default_line() -> String {
+    DEFAULT_LINE.to_string()
+}
+
+impl DailyNoteSetting {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.folder.trim().is_empty() {
+            return Err("Daily notes need a folder".to_string());
+        }
+        let file_name = render(&self.file_name, "2024-03-04", None);
+        if file_name.trim().is_empty() || file_name.contains(['/', '\\']) {
+            return Err(format!("Invalid daily note file name '{}'", self.file_name));
+        }
+        if self.prefix().is_empty() {
+            return Err(
+                "The daily note line needs some text before its first placeholder, \
+                 e.g. \"Office:\", to find it again"
+                    .to_string(),
+            );
+        }
+        if self.line.contains('\n') {
+            return Err("The daily note line can't span lines".to_string());
+        }
+        Ok(())
+    }
+
+    /// The text identifying the line in an existing note.
+    fn prefix(&self) -> &str {
+        self.line.split("{{").next().unwrap_or_default().trim()
+    }
+
+    fn path(&self, date: &str) -> PathBuf {
+        Path::new(self.folder.trim()).join(render(&self.file_name, date, None))
+    }
+}
+
+fn render(template: &str, date: &str, day: Option<&ConnectionLog>) -> String {
+    let rendered = template.replace("{{date}}", date);
+    match day {
+        Some(day) => rendered
+            .replace("{{earliest}}", &day.earliest)
+            .replace("{{latest}}", &day.latest)
+            .replace("{{total}}", &format_minutes(day.total_minutes)),
+        None => rendered,
+    }
+}
+
+/// `note` with the line starting `prefix` replaced by `line`, or `line`
+/// appended if there isn't one.
+fn update_note(note: &str, prefix: &str, line: &str) -> String {
+    let mut replaced = false;
+    let mut lines: Vec<&str> = note
+        .lines()
+        .map(|existing| {
+            if !replaced && existing.trim_start().starts_with(prefix) {
+                replaced = true;
+                line
+            } else {
+                existing
+            }
+        })
+        .collect();
+    if !replaced {
+        lines.push(line);
+    }
+    let mut updated = lines.join("\n");
+    updated.push('\n');
+    updated
+}
+
+/// The configured daily notes, if they're turned on.
+pub fn load(db: &Connection) -> Result<Option<DailyNoteSetting>> {
+    Ok(settings::get(db, settings::DAILY_NOTES)?.and_then(|json| serde_json::from_str(&json).ok()))
+}
+
+/// Adds or updates `date`'s line in its note, creating the note from the
+/// template if needed. Returns the note's path, or `None` if daily notes
+/// are off or nothing was recorded that day.
+pub fn write_day(db: &Connection, date: &str) -> Result<Option<PathBuf>> {
+    let Some(config) = load(db)? else {
+        return Ok(None);
+    };
+    let Some(day) = connections::get_day(db, date)? else {
+        return Ok(None);
+    };
+    let path = config.path(date);
+    let note = match fs::read_to_string(&path) {
+        Ok(note) => note,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            render(&config.template, date, Some(&day))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let line = render(&config.line, date, Some(&day));
+    fs::write(&path, update_note(&note, config.prefix(), &line))?;
+    Ok(Some(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_is_appended_then_updated() {
+        let note = update_note("# 2024-03-04\n\n- standup", "Office:", "Office: 09:05");
+        assert_eq!(note, "# 2024-03-04\n\n- standup\nOffice: 09:05\n");
+        let note = update_note(&note, "Office:", "Office: 09:05\u{2013}18:20");
+        assert_eq!(
+            note,
+            "# 2024-03-04\n\n- standup\nOffice: 09:05\u{2013}18:20\n"
+        );
+    }
+
+    #[test]
+    fn notes_are_named_by_date() {
+        let config = DailyNoteSetting {
+            folder: "/vault/Daily".to_string(),
+            file_name: default_file_name(),
+            template: default_template(),
+            line: default_line(),
+        };
+        assert!(config.validate().is_ok());
+        assert_eq!(
+            config.path("2024-03-04"),
+            Path::new("/vault/Daily/2024-03-04.md")
+        );
+        assert_eq!(config.prefix(), "Office:");
+
+        let nameless = DailyNoteSetting {
+            line: "{{total}} in the office".to_string(),
+            ..config
+        };
+        assert!(nameless.validate().is_err());
+    }
+}
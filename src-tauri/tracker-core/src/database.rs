@@ -0,0 +1,321 @@
+use rusqlite::{params, Connection, DatabaseName};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::error::Result;
+use crate::{backup, keychain, migrations};
+
+/// The database's file name inside the app's data directory, or the folder
+/// it was moved to.
+pub const FILE_NAME: &str = "connections.db";
+/// File in the data directory holding the folder the database was moved to,
+/// if it was. Kept outside the database, which can't say where it is.
+pub const LOCATION_FILE: &str = "database-location.txt";
+/// How long a connection waits for another to finish writing before giving
+/// up.
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Keychain account holding the SQLCipher key. The database is encrypted
+/// if and only if there's one.
+pub const KEY_ACCOUNT: &str = "database";
+/// Matches the bundle identifier in tauri.conf.json, which Tauri names the
+/// data directory after.
+const APP_IDENTIFIER: &str = "dev.britannio.foundership-time-tracker";
+
+/// Opens the database at `path` for writing, creating it if needed and
+/// bringing its schema up to date. Uses the key in the keychain, if any.
+pub fn open(path: &Path) -> Result<Connection> {
+    open_with_key(path, key().as_deref())
+}
+
+/// Like `open`, but with the key already read. A plaintext database is
+/// encrypted with `key` first, so turning encryption on takes effect the
+/// next time the database is opened.
+pub fn open_with_key(path: &Path, key: Option<&str>) -> Result<Connection> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if let Some(key) = key {
+        if encrypt_file(path, key)? {
+            info!("Encrypted {:?}", path);
+        }
+    }
+
+    let mut db = Connection::open(path)?;
+    match key {
+        Some(key) => unlock(&db, key)?,
+        None if !readable(&db) => {
+            return Err(format!("{:?} is encrypted but its key isn't in the keychain", path).into())
+        }
+        None => {}
+    }
+    // WAL lets readers carry on while the tracking loop writes, and the
+    // timeout makes a locked database wait rather than fail straight away
+    db.pragma_update(None, "journal_mode", "WAL")?;
+    db.busy_timeout(BUSY_TIMEOUT)?;
+    migrations::run(&mut db)?;
+    Ok(db)
+}
+
+/// The database key from the keychain, or `None` if encryption is off. A
+/// keychain that can't be read counts as off, so a plaintext database still
+/// opens without one.
+pub fn key() -> Option<String> {
+    keychain::get(KEY_ACCOUNT).unwrap_or_else(|e| {
+        warn!("{}", e);
+        None
+    })
+}
+
+/// Turns encryption on by storing a new random key in the keychain, unless
+/// there's one already. Takes effect the next time the database is opened.
+pub fn create_key() -> Result<()> {
+    if keychain::get(KEY_ACCOUNT)?.is_none() {
+        let key = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        keychain::set(KEY_ACCOUNT, &key)?;
+    }
+    Ok(())
+}
+
+/// Gives a newly opened connection the key it needs to read an encrypted
+/// database, checking it's the right one.
+pub fn unlock(db: &Connection, key: &str) -> Result<()> {
+    db.pragma_update(None, "key", key)?;
+    if !readable(db) {
+        return Err("The database couldn't be decrypted with the key in the keychain".into());
+    }
+    Ok(())
+}
+
+/// Whether `db` can be read, i.e. it's plaintext or has been given its key.
+fn readable(db: &Connection) -> bool {
+    db.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(()))
+        .is_ok()
+}
+
+/// Encrypts the plaintext database at `path` with `key` in place, through
+/// a copy so a failure part way leaves the original as it was. Returns
+/// whether it needed encrypting; missing and already encrypted files are
+/// left alone.
+pub fn encrypt_file(path: &Path, key: &str) -> Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    let encrypted = path.with_extension("encrypting");
+    {
+        let plain = Connection::open(path)?;
+        if !readable(&plain) {
+            return Ok(false);
+        }
+        match fs::remove_file(&encrypted) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        plain.execute(
+            "ATTACH DATABASE ?1 AS encrypted KEY ?2",
+            params![encrypted.to_string_lossy(), key],
+        )?;
+        plain.query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))?;
+        // sqlcipher_export copies everything but the schema version
+        let version: i64 = plain.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        plain.pragma_update(
+            Some(DatabaseName::Attached("encrypted")),
+            "user_version",
+            version,
+        )?;
+        plain.execute("DETACH DATABASE encrypted", [])?;
+    }
+    // Closing the last connection checkpointed the write-ahead log, and it
+    // wouldn't apply to the encrypted copy anyway
+    for suffix in ["-wal", "-shm"] {
+        let mut log = path.as_os_str().to_owned();
+        log.push(suffix);
+        match fs::remove_file(log) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    fs::rename(&encrypted, path)?;
+    Ok(true)
+}
+
+/// Moves everything in the write-ahead log into the database file and
+/// empties the log, so nothing is left waiting on it once the app exits.
+pub fn checkpoint(db: &Connection) -> Result<()> {
+    db.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+    Ok(())
+}
+
+/// Where the database for the data directory `data_dir` is: in the folder
+/// named in its `LOCATION_FILE`, or in `data_dir` itself.
+pub fn path_in(data_dir: &Path) -> PathBuf {
+    let folder = fs::read_to_string(data_dir.join(LOCATION_FILE))
+        .ok()
+        .map(|folder| PathBuf::from(folder.trim()))
+        .filter(|folder| folder.is_absolute());
+    folder
+        .unwrap_or_else(|| data_dir.to_path_buf())
+        .join(FILE_NAME)
+}
+
+/// Moves the database `db` for `data_dir` to `folder`, e.g. one synced
+/// between machines, or back to `data_dir` if `None`, returning its new
+/// path. The copy is checked before the move is recorded and `db` closed
+/// and deleted, so stop everything else using it first and reopen the
+/// database from `path_in` after. Won't overwrite a database already there.
+pub fn relocate(
+    db: Connection,
+    data_dir: &Path,
+    folder: Option<&Path>,
+    key: Option<&str>,
+) -> Result<PathBuf> {
+    let from = path_in(data_dir);
+    let folder = folder.unwrap_or(data_dir);
+    if !folder.is_absolute() {
+        return Err(format!("Invalid folder {:?}, expected a full path", folder).into());
+    }
+    let to = folder.join(FILE_NAME);
+    if to == from {
+        return Ok(to);
+    }
+    if to.exists() {
+        return Err(format!("There's already a database in {:?}", folder).into());
+    }
+    fs::create_dir_all(folder)?;
+    checkpoint(&db)?;
+    backup::copy_to(&db, &to, key)?;
+    let copy = Connection::open(&to)?;
+    if let Some(key) = key {
+        unlock(&copy, key)?;
+    }
+    let problems = integrity_check(&copy)?;
+    if !problems.is_empty() {
+        drop(copy);
+        fs::remove_file(&to)?;
+        return Err(format!(
+            "The copy in {:?} is damaged: {}",
+            folder,
+            problems.join("; ")
+        )
+        .into());
+    }
+
+    if folder == data_dir {
+        match fs::remove_file(data_dir.join(LOCATION_FILE)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    } else {
+        fs::write(
+            data_dir.join(LOCATION_FILE),
+            folder.to_string_lossy().as_bytes(),
+        )?;
+    }
+    drop(db);
+    for suffix in ["", "-wal", "-shm"] {
+        let mut old = from.as_os_str().to_owned();
+        old.push(suffix);
+        match fs::remove_file(PathBuf::from(old)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                warn!("Couldn't delete the old database: {}", e)
+            }
+            _ => {}
+        }
+    }
+    info!("Moved the database to {:?}", to);
+    Ok(to)
+}
+
+/// What SQLite's integrity check finds wrong with the database, or nothing
+/// if it's sound.
+pub fn integrity_check(db: &Connection) -> Result<Vec<String>> {
+    let mut stmt = db.prepare("PRAGMA integrity_check")?;
+    let problems = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(problems
+        .into_iter()
+        .filter(|problem| problem != "ok")
+        .collect())
+}
+
+/// Where the desktop app keeps its data, so other front ends share its
+/// database. Mirrors Tauri's `app_data_dir`.
+pub fn app_data_dir() -> Option<PathBuf> {
+    platform_data_dir().map(|dir| dir.join(APP_IDENTIFIER))
+}
+
+#[cfg(target_os = "macos")]
+fn platform_data_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+}
+
+#[cfg(target_os = "windows")]
+fn platform_data_dir() -> Option<PathBuf> {
+    std::env::var_os("APPDATA").map(PathBuf::from)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn platform_data_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plaintext_database_is_encrypted_in_place() {
+        let dir = std::env::temp_dir().join(format!("tracker-database-{}", Uuid::new_v4()));
+        let path = dir.join(FILE_NAME);
+        drop(open_with_key(&path, None).unwrap());
+
+        let db = open_with_key(&path, Some("secret")).unwrap();
+        assert!(!readable(&Connection::open(&path).unwrap()));
+        let version: i64 = db
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert!(version > 0);
+        drop(db);
+        assert!(open_with_key(&path, None).is_err());
+        assert!(open_with_key(&path, Some("wrong")).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn relocated_database_is_found_and_can_move_back() {
+        let dir = std::env::temp_dir().join(format!("tracker-database-{}", Uuid::new_v4()));
+        let data_dir = dir.join("data");
+        let synced = dir.join("synced");
+        let db = open_with_key(&path_in(&data_dir), None).unwrap();
+        db.execute(
+            "INSERT INTO settings (key, value) VALUES ('moved', '1')",
+            [],
+        )
+        .unwrap();
+
+        let moved = relocate(db, &data_dir, Some(&synced), None).unwrap();
+        assert_eq!(moved, synced.join(FILE_NAME));
+        assert_eq!(path_in(&data_dir), moved);
+        assert!(!data_dir.join(FILE_NAME).exists());
+        let db = open_with_key(&moved, None).unwrap();
+        let value: String = db
+            .query_row(
+                "SELECT value FROM settings WHERE key = 'moved'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(value, "1");
+        relocate(db, &data_dir, None, None).unwrap();
+        assert_eq!(path_in(&data_dir), data_dir.join(FILE_NAME));
+        assert!(!moved.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+use chrono::{Datelike, NaiveDate};
+use rusqlite::{params, Connection, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::holidays;
+
+/// How a day should be accounted for. Days with recorded presence count as
+/// office days unless tagged otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DayType {
+    Office,
+    Wfh,
+    Sick,
+    Vacation,
+    PublicHoliday,
+}
+
+impl DayType {
+    fn as_str(self) -> &'static str {
+        match self {
+            DayType::Office => "office",
+            DayType::Wfh => "wfh",
+            DayType::Sick => "sick",
+            DayType::Vacation => "vacation",
+            DayType::PublicHoliday => "public-holiday",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "office" => DayType::Office,
+            "wfh" => DayType::Wfh,
+            "sick" => DayType::Sick,
+            "vacation" => DayType::Vacation,
+            "public-holiday" => DayType::PublicHoliday,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Serialize)]
+pub struct TaggedDay {
+    pub date: String,
+    pub day_type: DayType,
+}
+
+/// How the days in a range were spent.
+#[derive(Default, Serialize)]
+pub struct DayTypeCounts {
+    pub office: i64,
+    pub wfh: i64,
+    pub sick: i64,
+    pub vacation: i64,
+    pub public_holiday: i64,
+    /// Days worked, wherever from.
+    pub billable: i64,
+    /// Billable days plus days off that shouldn't count as absences.
+    pub creditable: i64,
+    /// Past weekdays with neither presence nor a tag.
+    pub unaccounted: i64,
+}
+
+pub fn create_table(db: &Connection) -> Result<()> {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS day_types (
+            date TEXT PRIMARY KEY,
+            day_type TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Tags `date`, or removes its tag when `day_type` is `None`.
+pub fn set_day_type(db: &Connection, date: &str, day_type: Option<DayType>) -> Result<()> {
+    match day_type {
+        Some(day_type) => db.execute(
+            "INSERT INTO day_types (date, day_type) VALUES (?1, ?2)
+             ON CONFLICT(date) DO UPDATE SET day_type = ?2",
+            params![date, day_type.as_str()],
+        )?,
+        None => db.execute("DELETE FROM day_types WHERE date = ?1", [date])?,
+    };
+    Ok(())
+}
+
+/// Tags `date` unless the user has already tagged it.
+pub fn tag_if_untagged(db: &Connection, date: &str, day_type: DayType) -> Result<()> {
+    db.execute(
+        "INSERT INTO day_types (date, day_type) VALUES (?1, ?2)
+         ON CONFLICT(date) DO NOTHING",
+        params![date, day_type.as_str()],
+    )?;
+    Ok(())
+}
+
+/// Tagged days between `from` and `to` inclusive, oldest first.
+pub fn get_day_types(db: &Connection, from: &str, to: &str) -> Result<Vec<TaggedDay>> {
+    Ok(tags(db, from, to)?
+        .into_iter()
+        .map(|(date, day_type)| TaggedDay { date, day_type })
+        .collect())
+}
+
+fn tags(db: &Connection, from: &str, to: &str) -> Result<Vec<(String, DayType)>> {
+    let mut stmt = db.prepare(
+        "SELECT date, day_type FROM day_types WHERE date BETWEEN ?1 AND ?2 ORDER BY date",
+    )?;
+    let rows = stmt
+        .query_map([from, to], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    // Skip values written by a newer version rather than failing the report
+    Ok(rows
+        .into_iter()
+        .filter_map(|(date, day_type)| DayType::parse(&day_type).map(|t| (date, t)))
+        .collect())
+}
+
+/// Classifies every day between `from` and `to`. Weekdays after `today`
+/// haven't happened yet, so they're never unaccounted, and untagged public
+/// holidays without presence count as holidays rather than absences.
+pub fn count_day_types(
+    db: &Connection,
+    from: NaiveDate,
+    to: NaiveDate,
+    today: NaiveDate,
+) -> Result<DayTypeCounts> {
+    let from_str = from.format("%Y-%m-%d").to_string();
+    let to_str = to.format("%Y-%m-%d").to_string();
+
+    let tags: HashMap<String, DayType> = tags(db, &from_str, &to_str)?.into_iter().collect();
+    let mut stmt = db.prepare("SELECT date FROM connections WHERE date BETWEEN ?1 AND ?2")?;
+    let present = stmt
+        .query_map([&from_str, &to_str], |row| row.get::<_, String>(0))?
+        .collect::<Result<HashSet<_>>>()?;
+    let holidays = holidays::holiday_dates(db, &from_str, &to_str)?;
+
+    let mut counts = DayTypeCounts::default();
+    for date in from.iter_days().take_while(|date| *date <= to) {
+        let key = date.format("%Y-%m-%d").to_string();
+        let day_type = match tags.get(&key) {
+            Some(&day_type) => day_type,
+            None if present.contains(&key) => DayType::Office,
+            None if holidays.contains(&key) => DayType::PublicHoliday,
+            None => {
+                if date <= today && date.weekday().number_from_monday() <= 5 {
+                    counts.unaccounted += 1;
+                }
+                continue;
+            }
+        };
+        match day_type {
+            DayType::Office => counts.office += 1,
+            DayType::Wfh => counts.wfh += 1,
+            DayType::Sick => counts.sick += 1,
+            DayType::Vacation => counts.vacation += 1,
+            DayType::PublicHoliday => counts.public_holiday += 1,
+        }
+    }
+    counts.billable = counts.office + counts.wfh;
+    counts.creditable = counts.billable + counts.sick + counts.vacation + counts.public_holiday;
+    Ok(counts)
+}
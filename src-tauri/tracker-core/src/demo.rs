@@ -0,0 +1,211 @@
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Weekday};
+use rusqlite::{params, Connection};
+use std::f64::consts::PI;
+
+use crate::day_types::{self, DayType};
+use crate::error::Result;
+
+/// How much history `seed` generates unless told otherwise.
+pub const DEFAULT_MONTHS: u32 = 6;
+pub const MAX_MONTHS: u32 = 60;
+
+const HQ: (&str, &str) = ("Foundership", "HQ");
+const CO_WORKING: (&str, &str) = ("WorkHub Guest", "Co-working");
+
+/// Whether anything has been recorded yet. Demo data only goes into a
+/// database without any, so it never mixes with real history.
+pub fn has_history(db: &Connection) -> Result<bool> {
+    Ok(db.query_row(
+        "SELECT EXISTS (SELECT 1 FROM connections) OR EXISTS (SELECT 1 FROM sessions)",
+        [],
+        |row| row.get(0),
+    )?)
+}
+
+/// Fills an empty database with `months` of made-up history ending the day
+/// before `today`, for developing and screenshotting the UI and reports
+/// without weeks of real tracking. Arrivals cluster around 9:10 with the
+/// odd late start, days run about eight and a half hours, some have a
+/// lunch break out of the office, Fridays are shorter and often at a
+/// co-working space, and the rest are working from home, sick or on
+/// vacation. The same `seed` always generates the same history. Returns the
+/// number of office days.
+pub fn seed(db: &Connection, today: NaiveDate, months: u32, seed: u64) -> Result<usize> {
+    if months == 0 || months > MAX_MONTHS {
+        return Err(format!("Demo history must be 1 to {} months", MAX_MONTHS).into());
+    }
+    if has_history(db)? {
+        return Err("The database already has history, so demo data would mix with it".into());
+    }
+    let mut rng = Rng(seed);
+    let from = today - Duration::days(i64::from(months) * 365 / 12);
+    let tx = db.unchecked_transaction()?;
+    let mut office_days = 0;
+    for date in from.iter_days().take_while(|date| *date < today) {
+        let weekend = matches!(date.weekday(), Weekday::Sat | Weekday::Sun);
+        let sessions = if weekend {
+            // Now and then popping in for a couple of hours
+            if rng.uniform() >= 0.04 {
+                continue;
+            }
+            let start = rng.normal(11.0 * 60.0, 45.0);
+            vec![(start, start + rng.normal(150.0, 40.0).max(45.0))]
+        } else {
+            let roll = rng.uniform();
+            let day_type = if roll < 0.03 {
+                Some(DayType::Vacation)
+            } else if roll < 0.05 {
+                Some(DayType::Sick)
+            } else if roll < 0.2 {
+                Some(DayType::Wfh)
+            } else {
+                None
+            };
+            if let Some(day_type) = day_type {
+                day_types::set_day_type(&tx, &date.to_string(), Some(day_type))?;
+                continue;
+            }
+            let mut arrival = rng.normal(9.0 * 60.0 + 10.0, 20.0);
+            if rng.uniform() < 0.1 {
+                arrival += rng.normal(90.0, 30.0);
+            }
+            let arrival = arrival.clamp(7.0 * 60.0, 12.0 * 60.0);
+            let mut length = rng.normal(8.5 * 60.0, 45.0);
+            if date.weekday() == Weekday::Fri {
+                length -= 60.0;
+            }
+            let leaving = (arrival + length.clamp(4.0 * 60.0, 11.0 * 60.0)).min(23.0 * 60.0);
+            let lunch = rng.normal(12.5 * 60.0, 20.0);
+            if rng.uniform() < 0.25 && arrival + 60.0 < lunch && lunch + 120.0 < leaving {
+                let back = lunch + rng.uniform() * 45.0 + 30.0;
+                vec![(arrival, lunch), (back, leaving)]
+            } else {
+                vec![(arrival, leaving)]
+            }
+        };
+        let (ssid, label) = if date.weekday() == Weekday::Fri && rng.uniform() < 0.5 {
+            CO_WORKING
+        } else {
+            HQ
+        };
+        if insert_day(&tx, date, &sessions, ssid, label)? {
+            office_days += 1;
+        }
+    }
+    tx.commit()?;
+    Ok(office_days)
+}
+
+/// Records `sessions`, as minutes of the day, and the day they make up.
+/// Skips days whose times don't exist locally, e.g. lost to a DST change.
+fn insert_day(
+    db: &Connection,
+    date: NaiveDate,
+    sessions: &[(f64, f64)],
+    ssid: &str,
+    label: &str,
+) -> Result<bool> {
+    let at = |minute: f64| {
+        let time = date.and_hms_opt(0, 0, 0)? + Duration::minutes(minute.round() as i64);
+        Some((
+            time,
+            Local.from_local_datetime(&time).earliest()?.timestamp(),
+        ))
+    };
+    let times: Option<Vec<((NaiveDateTime, i64), (NaiveDateTime, i64))>> = sessions
+        .iter()
+        .map(|(start, end)| Some((at(*start)?, at(*end)?)))
+        .collect();
+    let Some(times) = times else {
+        return Ok(false);
+    };
+    let (Some(((earliest, earliest_at), _)), Some((_, (latest, latest_at)))) =
+        (times.first().copied(), times.last().copied())
+    else {
+        return Ok(false);
+    };
+    let hm = |time: NaiveDateTime| time.format("%H:%M").to_string();
+    for ((start, start_at), (end, end_at)) in &times {
+        db.execute(
+            "INSERT INTO sessions (date, start, end, ssid, label, start_at, end_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                date.to_string(),
+                hm(*start),
+                hm(*end),
+                ssid,
+                label,
+                start_at,
+                end_at
+            ],
+        )?;
+    }
+    db.execute(
+        "INSERT INTO connections (date, earliest, latest, ssid, label, earliest_at, latest_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            date.to_string(),
+            hm(earliest),
+            hm(latest),
+            ssid,
+            label,
+            earliest_at,
+            latest_at
+        ],
+    )?;
+    Ok(true)
+}
+
+/// SplitMix64, which is plenty for made-up history and saves a dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1).
+    fn uniform(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Normally distributed, by the Box-Muller transform.
+    fn normal(&mut self, mean: f64, std_dev: f64) -> f64 {
+        let u = 1.0 - self.uniform();
+        let v = self.uniform();
+        mean + std_dev * (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    #[test]
+    fn seeds_plausible_history_once() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        let today = NaiveDate::from_ymd_opt(2024, 6, 3).unwrap();
+
+        let office_days = seed(&db, today, 3, 7).unwrap();
+
+        // About 65 weekdays, most of them in the office
+        assert!((35..=65).contains(&office_days), "{}", office_days);
+        let (earliest, latest, last_date): (String, String, String) = db
+            .query_row(
+                "SELECT MIN(earliest), MAX(latest), MAX(date) FROM connections",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert!(earliest.as_str() >= "07:00", "{}", earliest);
+        assert!(latest.as_str() <= "23:00", "{}", latest);
+        assert!(last_date.as_str() < "2024-06-03");
+        assert!(seed(&db, today, 3, 7).is_err());
+    }
+}
@@ -0,0 +1,711 @@
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+use crate::bluetooth;
+use crate::error::Result;
+use crate::idle;
+use crate::location::{self, Coordinates};
+use crate::network::{self, Cidr};
+use crate::pattern::SsidPattern;
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+use crate::process::{CommandExt, COMMAND_TIMEOUT};
+use crate::wifi::{self, WifiConnection};
+
+/// What a detector saw when it decided the machine is at the office.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Presence {
+    /// The SSID that matched, when detection is WiFi-based.
+    pub ssid: Option<String>,
+    /// User-facing name of the place, e.g. "Main Office".
+    pub label: Option<String>,
+    /// Whether the office network was only reached through a VPN, i.e. the
+    /// user is working remotely.
+    pub vpn: bool,
+    /// Name of the rule that matched, when detection is rule-based.
+    pub rule: Option<String>,
+    /// Whether there's been no keyboard or mouse input for longer than the
+    /// idle threshold, i.e. the machine is here but the user may not be.
+    pub idle: bool,
+    /// Whether there's been keyboard or mouse input within the idle
+    /// threshold, for focus hours, or `None` if input wasn't checked.
+    pub active: Option<bool>,
+}
+
+/// Decides whether the machine is currently "at the office".
+pub trait PresenceDetector: Send + Sync {
+    /// Short identifier used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Returns `Some` while present, describing what matched.
+    fn detect(&self) -> Result<Option<Presence>>;
+}
+
+/// A WiFi network that counts as being at the office.
+#[derive(Clone, Debug)]
+pub struct TargetNetwork {
+    pub pattern: SsidPattern,
+    pub label: Option<String>,
+    /// Access point MAC prefixes the connection must come from, for SSIDs
+    /// like "eduroam" that are broadcast far beyond the office. Empty means
+    /// any access point is accepted.
+    pub bssid_prefixes: Vec<String>,
+}
+
+impl TargetNetwork {
+    pub fn new(pattern: &str, label: Option<&str>) -> Result<Self, String> {
+        Ok(Self {
+            pattern: SsidPattern::parse(pattern)?,
+            label: label.map(str::to_string),
+            bssid_prefixes: Vec::new(),
+        })
+    }
+
+    pub fn with_bssid_prefixes(mut self, prefixes: &[&str]) -> Self {
+        self.bssid_prefixes = prefixes.iter().map(|p| wifi::normalize_mac(p)).collect();
+        self
+    }
+
+    fn matches(&self, connection: &WifiConnection) -> bool {
+        if !self.pattern.matches(&connection.ssid) {
+            return false;
+        }
+        if self.bssid_prefixes.is_empty() {
+            return true;
+        }
+        match &connection.bssid {
+            Some(bssid) => self
+                .bssid_prefixes
+                .iter()
+                .any(|prefix| bssid.starts_with(prefix.as_str())),
+            None => {
+                debug!("BSSID unavailable, cannot check it against the allow-list");
+                false
+            }
+        }
+    }
+}
+
+/// Present while connected to any of the target WiFi networks.
+pub struct WifiSsidDetector {
+    targets: Vec<TargetNetwork>,
+}
+
+impl WifiSsidDetector {
+    pub fn new(targets: Vec<TargetNetwork>) -> Self {
+        Self { targets }
+    }
+
+    /// Presence for `connection`, if it's to one of the targets.
+    fn presence_for(&self, connection: &WifiConnection) -> Option<Presence> {
+        self.targets
+            .iter()
+            .find(|target| target.matches(connection))
+            .map(|target| Presence {
+                ssid: Some(connection.ssid.clone()),
+                label: target.label.clone(),
+                vpn: false,
+                rule: None,
+                idle: false,
+                active: None,
+            })
+    }
+}
+
+impl PresenceDetector for WifiSsidDetector {
+    fn name(&self) -> &'static str {
+        "wifi-ssid"
+    }
+
+    fn detect(&self) -> Result<Option<Presence>> {
+        match wifi::get_current_wifi()? {
+            Some(connection) => {
+                debug!(
+                    "Current WiFi SSID: {} (BSSID {})",
+                    connection.ssid,
+                    connection.bssid.as_deref().unwrap_or("unknown")
+                );
+                Ok(self.presence_for(&connection))
+            }
+            None => {
+                debug!("No WiFi connection detected");
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// A wired connection that counts as being at the office, e.g. a docking
+/// station's ethernet. Every condition that's set must hold.
+#[derive(Clone, Debug)]
+pub struct WiredNetwork {
+    /// MAC address of the default gateway, which identifies the office
+    /// router wherever the machine plugs in.
+    pub gateway_mac: Option<String>,
+    /// Interface that must have an active link, e.g. "en7" or "eth0".
+    pub interface: Option<String>,
+    /// Block the machine's address must fall in; checked against
+    /// `interface` when that's set, otherwise any interface.
+    pub subnet: Option<Cidr>,
+    pub label: Option<String>,
+}
+
+impl WiredNetwork {
+    fn matches(&self) -> Result<bool> {
+        if let Some(interface) = &self.interface {
+            let up = link_is_up(interface)?;
+            debug!(
+                "Wired interface {} is {}",
+                interface,
+                if up { "up" } else { "down" }
+            );
+            if !up {
+                return Ok(false);
+            }
+        }
+        if let Some(subnet) = &self.subnet {
+            let in_subnet = network::interface_addresses()?.iter().any(|address| {
+                self.interface
+                    .as_ref()
+                    .is_none_or(|interface| &address.interface == interface)
+                    && subnet.contains(address.ip)
+            });
+            if !in_subnet {
+                return Ok(false);
+            }
+        }
+        if let Some(expected) = &self.gateway_mac {
+            let mac = match network::default_gateway()? {
+                Some(gateway) => network::neighbor_mac(gateway.ip)?,
+                None => None,
+            };
+            debug!(
+                "Default gateway MAC: {}",
+                mac.as_deref().unwrap_or("unknown")
+            );
+            if mac.as_deref() != Some(wifi::normalize_mac(expected).as_str()) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Present while any of the configured wired connections is active.
+pub struct WiredNetworkDetector {
+    networks: Vec<WiredNetwork>,
+}
+
+impl WiredNetworkDetector {
+    pub fn new(networks: Vec<WiredNetwork>) -> Self {
+        Self { networks }
+    }
+}
+
+impl PresenceDetector for WiredNetworkDetector {
+    fn name(&self) -> &'static str {
+        "wired"
+    }
+
+    fn detect(&self) -> Result<Option<Presence>> {
+        for network in &self.networks {
+            if network.matches()? {
+                return Ok(Some(Presence {
+                    ssid: None,
+                    label: network.label.clone(),
+                    vpn: false,
+                    rule: None,
+                    idle: false,
+                    active: None,
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A network identified by addressing rather than by how the machine is
+/// connected to it, so it works for wired links, mesh networks and SSIDs the
+/// OS won't reveal. Every condition that's set must hold.
+#[derive(Clone, Debug)]
+pub struct AddressRule {
+    /// Block one of the machine's addresses must fall in.
+    pub subnet: Option<Cidr>,
+    /// Address the default gateway must have.
+    pub gateway: Option<IpAddr>,
+    pub label: Option<String>,
+}
+
+/// Present while the machine's addresses or default gateway match any of
+/// the rules.
+pub struct NetworkAddressDetector {
+    rules: Vec<AddressRule>,
+}
+
+impl NetworkAddressDetector {
+    pub fn new(rules: Vec<AddressRule>) -> Self {
+        Self { rules }
+    }
+}
+
+impl PresenceDetector for NetworkAddressDetector {
+    fn name(&self) -> &'static str {
+        "network-address"
+    }
+
+    fn detect(&self) -> Result<Option<Presence>> {
+        // Only look up what some rule needs
+        let addresses = if self.rules.iter().any(|rule| rule.subnet.is_some()) {
+            network::interface_addresses()?
+        } else {
+            Vec::new()
+        };
+        let gateway = if self.rules.iter().any(|rule| rule.gateway.is_some()) {
+            let gateway = network::default_gateway()?.map(|gateway| gateway.ip);
+            debug!(
+                "Default gateway: {}",
+                gateway.map_or("none".to_string(), |ip| ip.to_string())
+            );
+            gateway
+        } else {
+            None
+        };
+
+        Ok(self
+            .rules
+            .iter()
+            .find(|rule| {
+                rule.subnet.is_none_or(|subnet| {
+                    addresses.iter().any(|address| subnet.contains(address.ip))
+                }) && rule
+                    .gateway
+                    .is_none_or(|expected| gateway == Some(expected))
+            })
+            .map(|rule| Presence {
+                ssid: None,
+                label: rule.label.clone(),
+                vpn: false,
+                rule: None,
+                idle: false,
+                active: None,
+            }))
+    }
+}
+
+/// A host that's only reachable from the office network, e.g. an internal
+/// server at "10.0.0.5:443".
+#[derive(Clone, Debug)]
+pub struct Probe {
+    /// "host:port" to open a TCP connection to.
+    pub address: String,
+    pub timeout: Duration,
+    pub label: Option<String>,
+}
+
+/// Present while any of the probed hosts accepts a connection in time.
+pub struct ReachabilityDetector {
+    probes: Vec<Probe>,
+}
+
+impl ReachabilityDetector {
+    pub fn new(probes: Vec<Probe>) -> Self {
+        Self { probes }
+    }
+}
+
+impl PresenceDetector for ReachabilityDetector {
+    fn name(&self) -> &'static str {
+        "reachability"
+    }
+
+    fn detect(&self) -> Result<Option<Presence>> {
+        Ok(self
+            .probes
+            .iter()
+            .find(|probe| {
+                let reachable = network::can_connect(&probe.address, probe.timeout);
+                debug!(
+                    "{} is {}reachable",
+                    probe.address,
+                    if reachable { "" } else { "not " }
+                );
+                reachable
+            })
+            .map(|probe| Presence {
+                ssid: None,
+                label: probe.label.clone(),
+                vpn: false,
+                rule: None,
+                idle: false,
+                active: None,
+            }))
+    }
+}
+
+/// A circle around the office, for places whose WiFi never gets joined.
+#[derive(Clone, Debug)]
+pub struct Geofence {
+    pub center: Coordinates,
+    pub radius_m: f64,
+    pub label: Option<String>,
+}
+
+/// Present while the OS places the machine inside any of the geofences.
+pub struct GeofenceDetector {
+    fences: Vec<Geofence>,
+}
+
+impl GeofenceDetector {
+    /// Fixes less precise than this can't place the machine at a building.
+    const MAX_ACCURACY_M: f64 = 1000.0;
+
+    pub fn new(fences: Vec<Geofence>) -> Self {
+        Self { fences }
+    }
+}
+
+impl PresenceDetector for GeofenceDetector {
+    fn name(&self) -> &'static str {
+        "geofence"
+    }
+
+    fn detect(&self) -> Result<Option<Presence>> {
+        let Some(fix) = location::current_fix()? else {
+            debug!("No location fix yet");
+            return Ok(None);
+        };
+        if fix.age > location::MAX_FIX_AGE || fix.accuracy_m > Self::MAX_ACCURACY_M {
+            debug!(
+                "Ignoring location fix from {}s ago, accurate to {:.0}m",
+                fix.age.as_secs(),
+                fix.accuracy_m
+            );
+            return Ok(None);
+        }
+        Ok(self
+            .fences
+            .iter()
+            .find(|fence| fix.coordinates.distance_m(fence.center) <= fence.radius_m)
+            .map(|fence| Presence {
+                ssid: None,
+                label: fence.label.clone(),
+                vpn: false,
+                rule: None,
+                idle: false,
+                active: None,
+            }))
+    }
+}
+
+/// A Bluetooth device that's only in range at the office, e.g. a beacon or
+/// the office smart speaker.
+#[derive(Clone, Debug)]
+pub struct BluetoothTarget {
+    /// Address like "AA:BB:CC:DD:EE:FF", or the device's name.
+    pub device: String,
+    pub label: Option<String>,
+}
+
+/// Present while any of the target devices is nearby.
+pub struct BluetoothDetector {
+    targets: Vec<BluetoothTarget>,
+}
+
+impl BluetoothDetector {
+    pub fn new(targets: Vec<BluetoothTarget>) -> Self {
+        Self { targets }
+    }
+}
+
+impl PresenceDetector for BluetoothDetector {
+    fn name(&self) -> &'static str {
+        "bluetooth"
+    }
+
+    fn detect(&self) -> Result<Option<Presence>> {
+        let devices = bluetooth::nearby_devices()?;
+        debug!("{} Bluetooth devices nearby", devices.len());
+        Ok(self
+            .targets
+            .iter()
+            .find(|target| devices.iter().any(|device| device.matches(&target.device)))
+            .map(|target| Presence {
+                ssid: None,
+                label: target.label.clone(),
+                vpn: false,
+                rule: None,
+                idle: false,
+                active: None,
+            }))
+    }
+}
+
+/// Present when any of its detectors is, reporting the first match. One
+/// failing, e.g. WiFi while the adapter is switched off, doesn't stop the
+/// others; it's only an error when they all fail.
+pub struct AnyDetector {
+    detectors: Vec<Box<dyn PresenceDetector>>,
+}
+
+impl AnyDetector {
+    pub fn new(detectors: Vec<Box<dyn PresenceDetector>>) -> Self {
+        Self { detectors }
+    }
+}
+
+impl PresenceDetector for AnyDetector {
+    fn name(&self) -> &'static str {
+        "any"
+    }
+
+    fn detect(&self) -> Result<Option<Presence>> {
+        let mut first_error = None;
+        let mut failures = 0;
+        for detector in &self.detectors {
+            match detector.detect() {
+                Ok(Some(presence)) => return Ok(Some(presence)),
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("{} detector failed: {}", detector.name(), e);
+                    failures += 1;
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) if failures == self.detectors.len() => Err(e),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn link_is_up(interface: &str) -> Result<bool> {
+    let output = std::process::Command::new("ifconfig")
+        .arg(interface)
+        .output_within(COMMAND_TIMEOUT)
+        .map_err(crate::error::Error::command("ifconfig"))?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).contains("status: active"))
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn link_is_up(interface: &str) -> Result<bool> {
+    let state = std::fs::read_to_string(format!("/sys/class/net/{}/operstate", interface))?;
+    Ok(state.trim() == "up")
+}
+
+#[cfg(target_os = "windows")]
+pub(crate) fn link_is_up(interface: &str) -> Result<bool> {
+    let output = std::process::Command::new("netsh")
+        .args(&["interface", "show", "interface"])
+        .output_within(COMMAND_TIMEOUT)
+        .map_err(crate::error::Error::command("netsh"))?;
+    // Rows look like: "Enabled        Connected      Dedicated        Ethernet"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout.lines().any(|line| {
+        line.trim_end().ends_with(interface) && line.split_whitespace().nth(1) == Some("Connected")
+    }))
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
+pub(crate) fn link_is_up(_interface: &str) -> Result<bool> {
+    Err("Wired network detection is not supported on this platform".into())
+}
+
+/// What to make of presence that's only seen through a VPN, e.g. address
+/// rules or probes matching while connected to the office VPN from home.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VpnPolicy {
+    /// Not presence at all.
+    #[default]
+    Ignore,
+    /// Presence, but the day counts as working from home.
+    Remote,
+}
+
+/// Applies a `VpnPolicy` to another detector's matches. Matches that
+/// involved the office WiFi are left alone, since a VPN can't make the
+/// machine join it.
+pub struct VpnAwareDetector {
+    inner: Box<dyn PresenceDetector>,
+    policy: VpnPolicy,
+}
+
+impl VpnAwareDetector {
+    pub fn new(inner: Box<dyn PresenceDetector>, policy: VpnPolicy) -> Self {
+        Self { inner, policy }
+    }
+}
+
+impl PresenceDetector for VpnAwareDetector {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn detect(&self) -> Result<Option<Presence>> {
+        let Some(presence) = self.inner.detect()? else {
+            return Ok(None);
+        };
+        if presence.ssid.is_some() {
+            return Ok(Some(presence));
+        }
+        let vpn = network::active_vpn().unwrap_or_else(|e| {
+            warn!("Couldn't check for a VPN: {}", e);
+            None
+        });
+        let Some(interface) = vpn else {
+            return Ok(Some(presence));
+        };
+        debug!("Presence seen through VPN interface {}", interface);
+        Ok(match self.policy {
+            VpnPolicy::Ignore => None,
+            VpnPolicy::Remote => Some(Presence {
+                vpn: true,
+                rule: None,
+                ..presence
+            }),
+        })
+    }
+}
+
+/// What to make of presence while there's been no input for a while, e.g.
+/// the laptop on the office WiFi but closed in a bag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdlePolicy {
+    /// Presence counts whatever the input, which is only checked for focus
+    /// hours.
+    #[default]
+    Off,
+    /// Not presence, so the idle time isn't counted.
+    Exclude,
+    /// Presence, with the idle time recorded against the session.
+    Flag,
+}
+
+/// Notes whether there's been input within `threshold` on another detector's
+/// matches, and applies an `IdlePolicy` to them once there hasn't.
+pub struct IdleAwareDetector {
+    inner: Box<dyn PresenceDetector>,
+    policy: IdlePolicy,
+    threshold: Duration,
+}
+
+impl IdleAwareDetector {
+    pub fn new(inner: Box<dyn PresenceDetector>, policy: IdlePolicy, threshold: Duration) -> Self {
+        Self {
+            inner,
+            policy,
+            threshold,
+        }
+    }
+}
+
+impl PresenceDetector for IdleAwareDetector {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn detect(&self) -> Result<Option<Presence>> {
+        let Some(presence) = self.inner.detect()? else {
+            return Ok(None);
+        };
+        // Count the machine as in use when it can't tell, without counting
+        // towards focus hours
+        let idle_for = match idle::time_since_input() {
+            Ok(idle_for) => idle_for,
+            Err(e) if self.policy == IdlePolicy::Off => {
+                debug!("Couldn't check for input: {}", e);
+                return Ok(Some(presence));
+            }
+            Err(e) => {
+                warn!("Couldn't check for input: {}", e);
+                return Ok(Some(presence));
+            }
+        };
+        let active = idle_for < self.threshold;
+        let presence = Presence {
+            active: Some(active),
+            ..presence
+        };
+        if active {
+            return Ok(Some(presence));
+        }
+        debug!("No input for {} minutes", idle_for.as_secs() / 60);
+        Ok(match self.policy {
+            IdlePolicy::Off => Some(presence),
+            IdlePolicy::Exclude => None,
+            IdlePolicy::Flag => Some(Presence {
+                idle: true,
+                ..presence
+            }),
+        })
+    }
+}
+
+/// Reports whatever it was last told to, for exercising the tracking logic
+/// without real hardware.
+#[derive(Default)]
+pub struct MockDetector {
+    present: AtomicBool,
+}
+
+impl MockDetector {
+    pub fn new(present: bool) -> Self {
+        Self {
+            present: AtomicBool::new(present),
+        }
+    }
+
+    pub fn set_present(&self, present: bool) {
+        self.present.store(present, Ordering::SeqCst);
+    }
+}
+
+impl PresenceDetector for MockDetector {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn detect(&self) -> Result<Option<Presence>> {
+        Ok(self.present.load(Ordering::SeqCst).then(|| Presence {
+            ssid: None,
+            label: None,
+            vpn: false,
+            rule: None,
+            idle: false,
+            active: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_ssids_to_allow_listed_access_points() {
+        let detector = WifiSsidDetector::new(vec![TargetNetwork::new("eduroam", Some("Campus"))
+            .unwrap()
+            .with_bssid_prefixes(&["00-1A-2B"])]);
+        let connection = |bssid: Option<&str>| WifiConnection {
+            ssid: "eduroam".to_string(),
+            bssid: bssid.map(str::to_string),
+        };
+        let presence = detector.presence_for(&connection(Some("00:1a:2b:3c:4d:5e")));
+        assert_eq!(
+            presence.and_then(|presence| presence.label),
+            Some("Campus".to_string())
+        );
+        assert!(detector
+            .presence_for(&connection(Some("f0:9f:c2:00:11:22")))
+            .is_none());
+        assert!(detector.presence_for(&connection(None)).is_none());
+    }
+}
@@ -0,0 +1,161 @@
+use chrono::Local;
+use rusqlite::Connection;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use url::Url;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use crate::database;
+use crate::error::Result;
+use crate::settings::Settings;
+
+/// How much of the newest logs goes in.
+pub const LOG_LINES: usize = 200;
+const REDACTED: &str = "[redacted]";
+/// Settings whose names contain any of these are left out.
+const SECRET_WORDS: [&str; 5] = ["token", "secret", "password", "passcode", "key"];
+
+/// What the app knows about itself that isn't in the database.
+pub struct AppInfo<'a> {
+    pub version: &'a str,
+    /// Name of the presence detector in use, e.g. "wifi-ssid".
+    pub detector: &'a str,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    app_version: &'a str,
+    os: &'static str,
+    arch: &'static str,
+    detector: &'a str,
+    schema_version: i64,
+    /// What SQLite's integrity check found; empty if nothing.
+    integrity_problems: Vec<String>,
+    settings: Value,
+    generated: String,
+}
+
+/// Writes a zip to `path` for attaching to a bug report: `diagnostics.json`
+/// with the app version, platform, detector, schema version, integrity
+/// check result and settings, with secrets and the paths of URLs left out,
+/// and `log.txt` with the last `LOG_LINES` lines of the logs in `logs_dir`.
+/// No days or sessions go in.
+pub fn collect(db: &Connection, app: &AppInfo, logs_dir: &Path, path: &Path) -> Result<()> {
+    let mut settings = serde_json::to_value(Settings::load(db)?)
+        .map_err(|e| format!("Couldn't serialize settings: {}", e))?;
+    redact(&mut settings);
+    let report = Report {
+        app_version: app.version,
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        detector: app.detector,
+        schema_version: db.query_row("PRAGMA user_version", [], |row| row.get(0))?,
+        integrity_problems: database::integrity_check(db)?,
+        settings,
+        generated: Local::now().to_rfc3339(),
+    };
+    let json = serde_json::to_string_pretty(&report)
+        .map_err(|e| format!("Couldn't serialize diagnostics: {}", e))?;
+
+    let zip_error = |e: zip::result::ZipError| format!("Couldn't write {:?}: {}", path, e);
+    let mut zip = ZipWriter::new(File::create(path)?);
+    zip.start_file("diagnostics.json", FileOptions::default())
+        .map_err(zip_error)?;
+    zip.write_all(json.as_bytes())?;
+    zip.start_file("log.txt", FileOptions::default())
+        .map_err(zip_error)?;
+    zip.write_all(log_tail(logs_dir, LOG_LINES)?.as_bytes())?;
+    zip.finish().map_err(zip_error)?;
+    Ok(())
+}
+
+/// The last `count` lines across the log files in `dir`, which are named so
+/// they sort oldest first.
+fn log_tail(dir: &Path, count: usize) -> Result<String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(String::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut files = entries
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    files.retain(|file| file.is_file());
+    files.sort();
+    let mut lines: Vec<String> = Vec::new();
+    for file in files.iter().rev() {
+        let contents = String::from_utf8_lossy(&fs::read(file)?).into_owned();
+        let mut older: Vec<String> = contents.lines().map(str::to_string).collect();
+        older.append(&mut lines);
+        lines = older;
+        if lines.len() >= count {
+            break;
+        }
+    }
+    let skip = lines.len().saturating_sub(count);
+    Ok(lines[skip..]
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect())
+}
+
+/// Blanks out secrets, such as the HTTP API token, and cuts URLs down to
+/// their host, since webhook and endpoint URLs often carry one in their
+/// path or query.
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.to_lowercase();
+                if SECRET_WORDS.iter().any(|word| key.contains(word)) {
+                    if !matches!(value, Value::Null) {
+                        *value = Value::String(REDACTED.to_string());
+                    }
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        Value::String(text) => {
+            if let Ok(url) = Url::parse(text) {
+                if let Some(host) = url.host_str() {
+                    *text = format!("{}://{}/{}", url.scheme(), host, REDACTED);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn redacts_secrets_and_url_paths() {
+        let mut settings = json!({
+            "http_api_token": "hunter2hunter2hunter2",
+            "webhooks": [{"url": "https://hooks.slack.com/services/T0/B0/XYZ", "template": ""}],
+            "networks": [{"ssid": "CorpNet"}],
+            "report_destination": {"type": "s3", "access_key_id": "AKIA", "bucket": "hours"},
+            "sync_server": null,
+        });
+        redact(&mut settings);
+        assert_eq!(
+            settings,
+            json!({
+                "http_api_token": "[redacted]",
+                "webhooks": [{"url": "https://hooks.slack.com/[redacted]", "template": ""}],
+                "networks": [{"ssid": "CorpNet"}],
+                "report_destination": {"type": "s3", "access_key_id": "[redacted]", "bucket": "hours"},
+                "sync_server": null,
+            })
+        );
+    }
+}
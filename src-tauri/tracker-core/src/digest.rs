@@ -0,0 +1,240 @@
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, Timelike};
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::reports::{self, format_minutes, DayMinutes};
+use crate::sessions::DayBoundary;
+use crate::{keychain, settings};
+
+/// Keychain account holding the SMTP password.
+pub const PASSWORD_ACCOUNT: &str = "smtp";
+pub const DEFAULT_SMTP_PORT: u16 = 587;
+/// The digest goes out on Monday once it's past this hour.
+const SEND_HOUR: u32 = 8;
+
+/// Where to email the digest, as configured by the user. The password is in
+/// the keychain.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EmailSetting {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub username: String,
+    /// e.g. "Time Tracker <me@example.com>".
+    pub from: String,
+    pub to: String,
+}
+
+fn default_port() -> u16 {
+    DEFAULT_SMTP_PORT
+}
+
+impl EmailSetting {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.host.trim().is_empty() {
+            return Err("Digest emails need an SMTP server".to_string());
+        }
+        for address in [&self.from, &self.to] {
+            address
+                .parse::<Mailbox>()
+                .map_err(|_| format!("Invalid email address '{}'", address))?;
+        }
+        Ok(())
+    }
+}
+
+/// How last week went.
+#[derive(Serialize)]
+pub struct Digest {
+    pub week_start: String,
+    pub total_minutes: i64,
+    pub target_hours: f64,
+    pub office_days: i64,
+    pub best_day: Option<DayMinutes>,
+    pub worst_day: Option<DayMinutes>,
+}
+
+impl Digest {
+    pub fn subject(&self) -> String {
+        format!("Your week of {}", self.week_start)
+    }
+
+    pub fn body(&self) -> String {
+        let mut body = format!(
+            "{} in the office over {} days",
+            format_minutes(self.total_minutes),
+            self.office_days
+        );
+        if self.target_hours > 0.0 {
+            body.push_str(&format!(
+                ", {:.0}% of your {}h target",
+                self.total_minutes as f64 / 60.0 / self.target_hours * 100.0,
+                self.target_hours
+            ));
+        }
+        body.push('.');
+        for (label, day) in [("Longest", &self.best_day), ("Shortest", &self.worst_day)] {
+            if let Some(day) = day {
+                body.push_str(&format!(
+                    "\n{} day: {} ({})",
+                    label,
+                    day.date,
+                    format_minutes(day.total_minutes)
+                ));
+            }
+        }
+        body
+    }
+}
+
+/// The digest for the week starting on `week_start`.
+pub fn build(db: &Connection, week_start: NaiveDate, today: NaiveDate) -> Result<Digest> {
+    let boundary = DayBoundary::load(db)?;
+    let week = reports::weekly_summary(db, week_start, today, boundary, None)?;
+    let target_hours = settings::get_f64(
+        db,
+        settings::WEEKLY_TARGET_HOURS,
+        settings::DEFAULT_WEEKLY_TARGET_HOURS,
+    )?;
+    let day = |day: &reports::DayTotal| DayMinutes {
+        date: day.date.clone(),
+        total_minutes: day.total_minutes,
+    };
+    // Only worth pointing out a shortest day if there was more than one
+    let worst_day = (week.days.len() > 1)
+        .then(|| week.days.iter().min_by_key(|day| day.total_minutes))
+        .flatten()
+        .map(day);
+    Ok(Digest {
+        week_start: week.week_start.clone(),
+        total_minutes: week.total_minutes,
+        target_hours,
+        office_days: week.office_days,
+        best_day: week
+            .days
+            .iter()
+            .max_by_key(|day| day.total_minutes)
+            .map(day),
+        worst_day,
+    })
+}
+
+/// The start of last week if its digest is due: it's past Monday morning and
+/// it hasn't been sent yet. Call `mark_sent` once it has.
+pub fn due(db: &Connection, now: DateTime<Local>) -> Result<Option<NaiveDate>> {
+    if !settings::get_bool(db, settings::WEEKLY_DIGEST_ENABLED, true)? {
+        return Ok(None);
+    }
+    let today = now.date_naive();
+    let this_week = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+    if today == this_week && now.hour() < SEND_HOUR {
+        return Ok(None);
+    }
+    let last_week = this_week - Duration::days(7);
+    let sent = settings::get(db, settings::WEEKLY_DIGEST_SENT_FOR)?;
+    if sent.as_deref() == Some(last_week.to_string().as_str()) {
+        return Ok(None);
+    }
+    Ok(Some(last_week))
+}
+
+pub fn mark_sent(db: &Connection, week_start: NaiveDate) -> Result<()> {
+    settings::set(
+        db,
+        settings::WEEKLY_DIGEST_SENT_FOR,
+        &week_start.to_string(),
+    )?;
+    Ok(())
+}
+
+/// The configured email, if digests should be emailed too.
+pub fn email(db: &Connection) -> Result<Option<EmailSetting>> {
+    Ok(
+        settings::get(db, settings::DIGEST_EMAIL)?
+            .and_then(|json| serde_json::from_str(&json).ok()),
+    )
+}
+
+/// Emails `digest` over SMTP with STARTTLS, or implicit TLS on port 465.
+pub fn send_email(config: &EmailSetting, digest: &Digest) -> Result<()> {
+    let parse = |address: &str| {
+        address
+            .parse::<Mailbox>()
+            .map_err(|_| format!("Invalid email address '{}'", address))
+    };
+    let message = Message::builder()
+        .from(parse(&config.from)?)
+        .to(parse(&config.to)?)
+        .subject(digest.subject())
+        .header(ContentType::TEXT_PLAIN)
+        .body(digest.body())
+        .map_err(|e| format!("Couldn't build the digest email: {}", e))?;
+
+    let host = config.host.trim();
+    let builder = if config.port == 465 {
+        SmtpTransport::relay(host)
+    } else {
+        SmtpTransport::starttls_relay(host)
+    }
+    .map_err(|e| format!("Couldn't connect to {}: {}", host, e))?
+    .port(config.port);
+    let builder = match keychain::get(PASSWORD_ACCOUNT)? {
+        Some(password) => builder.credentials(Credentials::new(config.username.clone(), password)),
+        None => builder,
+    };
+    builder
+        .build()
+        .send(&message)
+        .map_err(|e| format!("Couldn't send the digest email: {}", e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+    use chrono::TimeZone;
+
+    fn db() -> Connection {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        db
+    }
+
+    #[test]
+    fn due_once_after_monday_morning() {
+        let db = db();
+        let monday = |hour| Local.with_ymd_and_hms(2024, 3, 11, hour, 0, 0).unwrap();
+        assert_eq!(due(&db, monday(7)).unwrap(), None);
+        let last_week = NaiveDate::from_ymd_opt(2024, 3, 4).unwrap();
+        assert_eq!(due(&db, monday(9)).unwrap(), Some(last_week));
+        mark_sent(&db, last_week).unwrap();
+        assert_eq!(due(&db, monday(10)).unwrap(), None);
+    }
+
+    #[test]
+    fn body_compares_with_target() {
+        let digest = Digest {
+            week_start: "2024-03-04".to_string(),
+            total_minutes: 32 * 60,
+            target_hours: 40.0,
+            office_days: 4,
+            best_day: Some(DayMinutes {
+                date: "2024-03-05".to_string(),
+                total_minutes: 9 * 60 + 5,
+            }),
+            worst_day: None,
+        };
+        assert_eq!(
+            digest.body(),
+            "32h 00m in the office over 4 days, 80% of your 40h target.\n\
+             Longest day: 2024-03-05 (9h 05m)"
+        );
+    }
+}
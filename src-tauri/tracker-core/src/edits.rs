@@ -0,0 +1,105 @@
+use chrono::NaiveTime;
+use rusqlite::{params, Connection};
+
+use crate::audit;
+use crate::error::Result;
+use crate::sessions::{local_end_epoch_sql, local_epoch_sql, SOURCE_MANUAL};
+
+/// Creates or overwrites a day's earliest/latest times, e.g. to backfill a
+/// day the tracker missed. If the day has no sessions yet, one spanning
+/// `earliest` to `latest` is added so the day's total isn't zero. A `latest`
+/// before `earliest` is on the next day, for days that run past midnight.
+pub fn upsert_day(db: &mut Connection, date: &str, earliest: &str, latest: &str) -> Result<()> {
+    let start = parse_time(earliest)?;
+    let end = parse_time(latest)?;
+    if start == end {
+        return Err(format!("earliest and latest are both {}", earliest).into());
+    }
+
+    let earliest_at = local_epoch_sql("?1", "?2");
+    let latest_at = local_end_epoch_sql("?1", "?2", "?3");
+    let tx = db.transaction()?;
+    audit::set_source(&tx, audit::SOURCE_MANUAL)?;
+    tx.execute(
+        &format!(
+            "INSERT INTO connections (date, earliest, latest, earliest_at, latest_at)
+             VALUES (?1, ?2, ?3, {0}, {1})
+             ON CONFLICT(date) DO UPDATE SET
+                earliest = ?2, latest = ?3, earliest_at = {0}, latest_at = {1}",
+            earliest_at, latest_at
+        ),
+        params![date, earliest, latest],
+    )?;
+    tx.execute(
+        &format!(
+            "INSERT INTO sessions (date, start, end, start_at, end_at, source)
+             SELECT ?1, ?2, ?3, {}, {}, ?4
+             WHERE NOT EXISTS (SELECT 1 FROM sessions WHERE date = ?1)",
+            earliest_at, latest_at
+        ),
+        params![date, earliest, latest, SOURCE_MANUAL],
+    )?;
+    audit::clear_source(&tx)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Sets or clears a day's note. Returns whether the day exists.
+pub fn set_day_note(db: &Connection, date: &str, note: Option<&str>) -> Result<bool> {
+    let tx = db.unchecked_transaction()?;
+    audit::set_source(&tx, audit::SOURCE_MANUAL)?;
+    let updated = tx.execute(
+        "UPDATE connections SET note = ?1 WHERE date = ?2",
+        params![note, date],
+    )?;
+    audit::clear_source(&tx)?;
+    tx.commit()?;
+    Ok(updated > 0)
+}
+
+/// Removes a day and all of its sessions. Returns whether anything existed.
+pub fn delete_day(db: &mut Connection, date: &str) -> Result<bool> {
+    let tx = db.transaction()?;
+    audit::set_source(&tx, audit::SOURCE_MANUAL)?;
+    let days = tx.execute("DELETE FROM connections WHERE date = ?1", [date])?;
+    let sessions = tx.execute("DELETE FROM sessions WHERE date = ?1", [date])?;
+    audit::clear_source(&tx)?;
+    tx.commit()?;
+    Ok(days + sessions > 0)
+}
+
+/// Checks a time is in the HH:MM format the tracker stores.
+pub fn parse_time(time: &str) -> Result<NaiveTime, String> {
+    if time.len() != 5 {
+        return Err(format!("Invalid time '{}', expected HH:MM", time));
+    }
+    NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|_| format!("Invalid time '{}', expected HH:MM", time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{migrations, sessions};
+
+    #[test]
+    fn upserts_days_past_midnight() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        upsert_day(&mut db, "2024-03-04", "20:00", "01:30").unwrap();
+
+        let (earliest_at, latest_at): (i64, i64) = db
+            .query_row(
+                "SELECT earliest_at, latest_at FROM connections WHERE date = '2024-03-04'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(latest_at - earliest_at, 330 * 60);
+        assert_eq!(
+            sessions::total_minutes(&db, "2024-03-04", None).unwrap(),
+            330
+        );
+        assert!(upsert_day(&mut db, "2024-03-05", "09:00", "09:00").is_err());
+    }
+}
@@ -0,0 +1,67 @@
+use serde::{Serialize, Serializer};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Everything that can go wrong while tracking.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to run {command}: {source}")]
+    Command {
+        command: &'static str,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{0}")]
+    Message(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Wraps a failure to spawn an external command.
+    pub fn command(command: &'static str) -> impl FnOnce(std::io::Error) -> Self {
+        move |source| Error::Command { command, source }
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Message(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Message(message.to_string())
+    }
+}
+
+/// Lets commands that return plain string errors use `?` on tracker errors.
+impl From<Error> for String {
+    fn from(error: Error) -> Self {
+        error.to_string()
+    }
+}
+
+/// Sent to the frontend as just the message.
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+pub trait LockExt<T> {
+    /// Locks the mutex even if a thread panicked while holding it. The data
+    /// behind our locks stays consistent between statements, so carrying on
+    /// beats every later command panicking too.
+    fn lock_unpoisoned(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> LockExt<T> for Mutex<T> {
+    fn lock_unpoisoned(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
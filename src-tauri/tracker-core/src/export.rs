@@ -0,0 +1,613 @@
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rusqlite::types::Value;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::audit;
+use crate::error::Result;
+use crate::rounding;
+use crate::sessions::{self, local_end_epoch_sql, local_epoch_sql, Session, MINUTES_SQL};
+
+/// Bumped whenever the JSON export format changes. Version 1 had no day
+/// types, and is still imported.
+const JSON_EXPORT_VERSION: u32 = 2;
+
+/// Everything in the database, for moving history between machines.
+#[derive(Serialize, Deserialize)]
+struct JsonExport {
+    version: u32,
+    connections: Vec<ConnectionRow>,
+    sessions: Vec<Session>,
+    #[serde(default)]
+    day_types: Vec<DayTypeRow>,
+}
+
+/// A WFH, sick, vacation or other tag. Kept as stored so tags from a newer
+/// version survive the trip.
+#[derive(Serialize, Deserialize)]
+struct DayTypeRow {
+    date: String,
+    day_type: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ConnectionRow {
+    date: String,
+    earliest: String,
+    latest: String,
+    ssid: Option<String>,
+    label: Option<String>,
+    #[serde(default)]
+    note: Option<String>,
+    /// UTC epoch seconds; missing from older exports.
+    #[serde(default)]
+    earliest_at: Option<i64>,
+    #[serde(default)]
+    latest_at: Option<i64>,
+}
+
+/// What to do when an imported day or session already exists locally.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergeStrategy {
+    /// Keep the record that was there first, i.e. the local one.
+    KeepEarliest,
+    /// Overwrite the local record with the imported one.
+    KeepLatest,
+}
+
+#[derive(Serialize)]
+pub struct ImportStats {
+    pub connections: usize,
+    pub sessions: usize,
+    pub day_types: usize,
+}
+
+/// Columns that can be exported from each table, as (name, SQL expression).
+const CONNECTION_COLUMNS: &[(&str, &str)] = &[
+    ("date", "date"),
+    (
+        "earliest",
+        "COALESCE(strftime('%H:%M', earliest_at, 'unixepoch', 'localtime'), earliest)",
+    ),
+    (
+        "latest",
+        "COALESCE(strftime('%H:%M', latest_at, 'unixepoch', 'localtime'), latest)",
+    ),
+    ("earliest_at", "earliest_at"),
+    ("latest_at", "latest_at"),
+    ("ssid", "ssid"),
+    ("label", "label"),
+    ("note", "note"),
+    (
+        "total_minutes",
+        "COALESCE((SELECT SUM(minutes) FROM session_minutes sm WHERE sm.date = t.date), 0)",
+    ),
+];
+
+const SESSION_COLUMNS: &[(&str, &str)] = &[
+    ("id", "id"),
+    ("date", "date"),
+    (
+        "start",
+        "COALESCE(strftime('%H:%M', start_at, 'unixepoch', 'localtime'), start)",
+    ),
+    (
+        "end",
+        "COALESCE(strftime('%H:%M', end_at, 'unixepoch', 'localtime'), end)",
+    ),
+    ("start_at", "start_at"),
+    ("end_at", "end_at"),
+    ("ssid", "ssid"),
+    ("label", "label"),
+    ("source", "source"),
+    ("rule", "rule"),
+    ("minutes", "(end_at - start_at) / 60"),
+    ("idle_minutes", "idle_seconds / 60"),
+    ("active_minutes", "active_seconds / 60"),
+];
+
+/// Columns holding time worked, which report rounding applies to.
+const ROUNDED_COLUMNS: &[&str] = &["total_minutes", "minutes"];
+
+/// Writes the rows of `table` ("connections" or "sessions") dated between
+/// `from` and `to` to a CSV file, oldest first. `columns` picks and orders the
+/// columns; all of them are written when it's `None`. Minutes are rounded if
+/// report rounding is set. Returns the number of rows written.
+pub fn export_csv(
+    db: &Connection,
+    path: &Path,
+    table: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+    columns: Option<&[String]>,
+) -> Result<usize> {
+    let available = match table {
+        "connections" => CONNECTION_COLUMNS,
+        "sessions" => SESSION_COLUMNS,
+        _ => return Err(format!("Unknown table '{}'", table).into()),
+    };
+
+    let mut selected: Vec<(&str, String)> = match columns {
+        None => available
+            .iter()
+            .map(|&(name, expr)| (name, expr.to_string()))
+            .collect(),
+        Some(columns) => columns
+            .iter()
+            .map(|name| {
+                available
+                    .iter()
+                    .find(|(column, _)| column == name)
+                    .map(|&(name, expr)| (name, expr.to_string()))
+                    .ok_or_else(|| format!("Unknown column '{}' for {}", name, table))
+            })
+            .collect::<Result<_, String>>()?,
+    };
+    if selected.is_empty() {
+        return Err("At least one column must be exported".into());
+    }
+    if let Some(rounding) = rounding::load(db)? {
+        for (name, expr) in &mut selected {
+            if ROUNDED_COLUMNS.contains(name) {
+                *expr = rounding.sql(expr);
+            }
+        }
+    }
+
+    let sql = format!(
+        "WITH session_minutes AS (SELECT s.date, {} AS minutes FROM sessions s)
+         SELECT {} FROM {} t
+         WHERE (?1 IS NULL OR date >= ?1) AND (?2 IS NULL OR date <= ?2)
+         ORDER BY date",
+        MINUTES_SQL,
+        selected
+            .iter()
+            .map(|(_, expr)| expr.as_str())
+            .collect::<Vec<_>>()
+            .join(", "),
+        table
+    );
+
+    let mut writer = csv::Writer::from_path(path).map_err(|e| e.to_string())?;
+    writer
+        .write_record(selected.iter().map(|(name, _)| name))
+        .map_err(|e| e.to_string())?;
+
+    let mut stmt = db.prepare(&sql)?;
+    let mut rows = stmt.query([from, to])?;
+    let mut count = 0;
+    while let Some(row) = rows.next()? {
+        let record = (0..selected.len())
+            .map(|i| row.get::<_, Value>(i).map(format_value))
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        writer.write_record(&record).map_err(|e| e.to_string())?;
+        count += 1;
+    }
+
+    writer.flush()?;
+    Ok(count)
+}
+
+fn format_value(value: Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Integer(i) => i.to_string(),
+        Value::Real(f) => f.to_string(),
+        Value::Text(s) => s,
+        Value::Blob(b) => String::from_utf8_lossy(&b).into_owned(),
+    }
+}
+
+/// Dumps every connection, session and day type to a JSON file.
+pub fn export_json(db: &Connection, path: &Path) -> Result<()> {
+    let export = json_export(db, None)?;
+    let json = serde_json::to_string_pretty(&export).map_err(|e| e.to_string())?;
+    fs::write(path, json)?;
+    Ok(())
+}
+
+/// Dumps the connections, sessions and day types from before `before` (YYYY-MM-DD) to a
+/// gzipped JSON file, in the same format as `export_json` so `import_json`
+/// can restore it.
+pub fn archive_json(db: &Connection, before: &str, path: &Path) -> Result<()> {
+    let export = json_export(db, Some(before))?;
+    let file = File::create(path)?;
+    let mut encoder = GzEncoder::new(file, Compression::default());
+    serde_json::to_writer(&mut encoder, &export).map_err(|e| e.to_string())?;
+    encoder.finish()?.flush()?;
+    Ok(())
+}
+
+fn json_export(db: &Connection, before: Option<&str>) -> Result<JsonExport> {
+    let mut stmt = db.prepare(
+        "SELECT date, earliest, latest, ssid, label, note, earliest_at, latest_at
+             FROM connections WHERE ?1 IS NULL OR date < ?1 ORDER BY date",
+    )?;
+    let connections = stmt
+        .query_map([before], |row| {
+            Ok(ConnectionRow {
+                date: row.get(0)?,
+                earliest: row.get(1)?,
+                latest: row.get(2)?,
+                ssid: row.get(3)?,
+                label: row.get(4)?,
+                note: row.get(5)?,
+                earliest_at: row.get(6)?,
+                latest_at: row.get(7)?,
+            })
+        })
+        .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())?;
+    let sessions = match before {
+        Some(before) => sessions::sessions_before(db, before),
+        None => sessions::get_sessions(db, None, None),
+    }?;
+    let mut stmt = db.prepare(
+        "SELECT date, day_type FROM day_types WHERE ?1 IS NULL OR date < ?1 ORDER BY date",
+    )?;
+    let day_types = stmt
+        .query_map([before], |row| {
+            Ok(DayTypeRow {
+                date: row.get(0)?,
+                day_type: row.get(1)?,
+            })
+        })
+        .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())?;
+
+    Ok(JsonExport {
+        version: JSON_EXPORT_VERSION,
+        connections,
+        sessions,
+        day_types,
+    })
+}
+
+/// Restores a file written by `export_json`, or by `archive_json` if it ends
+/// in .gz, merging it into the existing data in a single transaction. Days
+/// and their tags are matched by date and sessions by date and start time.
+/// Returns how many records were written.
+pub fn import_json(
+    db: &mut Connection,
+    path: &Path,
+    strategy: MergeStrategy,
+) -> Result<ImportStats> {
+    let json = if path.extension().is_some_and(|extension| extension == "gz") {
+        let file = File::open(path)?;
+        let mut json = String::new();
+        GzDecoder::new(file).read_to_string(&mut json)?;
+        json
+    } else {
+        fs::read_to_string(path)?
+    };
+    let import: JsonExport = serde_json::from_str(&json).map_err(|e| e.to_string())?;
+    if !(1..=JSON_EXPORT_VERSION).contains(&import.version) {
+        return Err(format!(
+            "Unsupported export version {} (expected {} or older)",
+            import.version, JSON_EXPORT_VERSION
+        )
+        .into());
+    }
+
+    let on_conflict = match strategy {
+        MergeStrategy::KeepEarliest => "DO NOTHING",
+        MergeStrategy::KeepLatest => {
+            "DO UPDATE SET earliest = excluded.earliest, latest = excluded.latest,
+                earliest_at = excluded.earliest_at, latest_at = excluded.latest_at,
+                ssid = excluded.ssid, label = excluded.label, note = excluded.note"
+        }
+    };
+
+    let tx = db.transaction()?;
+    audit::set_source(&tx, audit::SOURCE_IMPORT)?;
+    let mut stats = ImportStats {
+        connections: 0,
+        sessions: 0,
+        day_types: 0,
+    };
+    for row in &import.connections {
+        stats.connections += tx.execute(
+            &format!(
+                "INSERT INTO connections
+                        (date, earliest, latest, ssid, label, note, earliest_at, latest_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, COALESCE(?7, {}), COALESCE(?8, {}))
+                     ON CONFLICT(date) {}",
+                local_epoch_sql("?1", "?2"),
+                local_end_epoch_sql("?1", "?2", "?3"),
+                on_conflict
+            ),
+            params![
+                row.date,
+                row.earliest,
+                row.latest,
+                row.ssid,
+                row.label,
+                row.note,
+                row.earliest_at,
+                row.latest_at
+            ],
+        )?;
+    }
+    for session in &import.sessions {
+        let existing: Option<i64> = tx
+            .query_row(
+                "SELECT id FROM sessions WHERE date = ?1 AND start = ?2",
+                [&session.date, &session.start],
+                |row| row.get(0),
+            )
+            .optional()?;
+        stats.sessions += match (existing, strategy) {
+            (Some(_), MergeStrategy::KeepEarliest) => 0,
+            (Some(id), MergeStrategy::KeepLatest) => tx.execute(
+                &format!(
+                    "UPDATE sessions SET end = ?1, ssid = ?2, label = ?3, source = ?4,
+                            end_at = COALESCE(?5, {}), rule = ?7, idle_seconds = ?8,
+                            active_seconds = ?9
+                         WHERE id = ?6",
+                    local_end_epoch_sql("date", "start", "?1")
+                ),
+                params![
+                    session.end,
+                    session.ssid,
+                    session.label,
+                    session.source,
+                    session.end_at,
+                    id,
+                    session.rule,
+                    session.idle_seconds,
+                    session.active_seconds
+                ],
+            )?,
+            (None, _) => tx.execute(
+                &format!(
+                    "INSERT INTO sessions
+                            (date, start, end, ssid, label, source, start_at, end_at, rule,
+                                idle_seconds, active_seconds)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, COALESCE(?7, {}), COALESCE(?8, {}), ?9,
+                            ?10, ?11)",
+                    local_epoch_sql("?1", "?2"),
+                    local_end_epoch_sql("?1", "?2", "?3")
+                ),
+                params![
+                    session.date,
+                    session.start,
+                    session.end,
+                    session.ssid,
+                    session.label,
+                    session.source,
+                    session.start_at,
+                    session.end_at,
+                    session.rule,
+                    session.idle_seconds,
+                    session.active_seconds
+                ],
+            )?,
+        };
+    }
+    let on_conflict = match strategy {
+        MergeStrategy::KeepEarliest => "DO NOTHING",
+        MergeStrategy::KeepLatest => "DO UPDATE SET day_type = excluded.day_type",
+    };
+    for row in &import.day_types {
+        stats.day_types += tx.execute(
+            &format!(
+                "INSERT INTO day_types (date, day_type) VALUES (?1, ?2)
+                     ON CONFLICT(date) {}",
+                on_conflict
+            ),
+            [&row.date, &row.day_type],
+        )?;
+    }
+    audit::clear_source(&tx)?;
+    tx.commit()?;
+
+    Ok(stats)
+}
+
+/// Whether an iCalendar export has one event per session or one per day.
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IcalGranularity {
+    Session,
+    Day,
+}
+
+/// Writes presence as an .ics calendar with one VEVENT per session, or per day
+/// spanning its earliest to latest time. Times are written in UTC so calendar
+/// apps show them correctly in any time zone. Returns the number of events
+/// written.
+pub fn export_ical(
+    db: &Connection,
+    path: &Path,
+    from: Option<&str>,
+    to: Option<&str>,
+    granularity: IcalGranularity,
+) -> Result<usize> {
+    let sql = match granularity {
+        IcalGranularity::Session => {
+            "SELECT 'session-' || id, start_at, end_at, label FROM sessions
+             WHERE (?1 IS NULL OR date >= ?1) AND (?2 IS NULL OR date <= ?2)
+             ORDER BY date, start"
+        }
+        IcalGranularity::Day => {
+            "SELECT 'day-' || date, earliest_at, latest_at, label FROM connections
+             WHERE (?1 IS NULL OR date >= ?1) AND (?2 IS NULL OR date <= ?2)
+             ORDER BY date"
+        }
+    };
+
+    let mut stmt = db.prepare(sql)?;
+    let events = stmt
+        .query_map([from, to], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })
+        .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())?;
+
+    let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ");
+    let mut ics = String::new();
+    ics.push_str("BEGIN:VCALENDAR\r\n");
+    ics.push_str("VERSION:2.0\r\n");
+    ics.push_str("PRODID:-//Foundership//Time Tracker//EN\r\n");
+    for (uid, start, end, label) in &events {
+        let summary = match label {
+            Some(label) => format!("Office: {}", label),
+            None => "Office".to_string(),
+        };
+        ics.push_str("BEGIN:VEVENT\r\n");
+        ics.push_str(&format!("UID:{}@foundership-time-tracker\r\n", uid));
+        ics.push_str(&format!("DTSTAMP:{}\r\n", stamp));
+        ics.push_str(&format!("DTSTART:{}\r\n", ical_datetime(*start)));
+        ics.push_str(&format!("DTEND:{}\r\n", ical_datetime(*end)));
+        ics.push_str(&format!("SUMMARY:{}\r\n", escape_ical_text(&summary)));
+        ics.push_str("END:VEVENT\r\n");
+    }
+    ics.push_str("END:VCALENDAR\r\n");
+
+    fs::write(path, ics)?;
+    Ok(events.len())
+}
+
+/// Formats UTC epoch seconds as e.g. "20240105T093000Z".
+fn ical_datetime(epoch: i64) -> String {
+    chrono::DateTime::from_timestamp(epoch, 0)
+        .unwrap_or_default()
+        .format("%Y%m%dT%H%M%SZ")
+        .to_string()
+}
+
+fn escape_ical_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::day_types::{self, DayType};
+    use crate::{edits, migrations};
+    use uuid::Uuid;
+
+    fn db() -> Connection {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        db
+    }
+
+    fn dump(db: &Connection) -> serde_json::Value {
+        serde_json::to_value(json_export(db, None).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn json_round_trips_into_a_new_database() {
+        let dir = std::env::temp_dir().join(format!("tracker-export-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut db = db();
+        edits::upsert_day(&mut db, "2024-01-08", "09:00", "17:30").unwrap();
+        edits::upsert_day(&mut db, "2024-01-09", "20:00", "01:30").unwrap();
+        edits::set_day_note(&db, "2024-01-08", Some("Offsite")).unwrap();
+        day_types::set_day_type(&db, "2024-01-10", Some(DayType::Wfh)).unwrap();
+
+        let path = dir.join("export.json");
+        export_json(&db, &path).unwrap();
+        let mut restored = self::db();
+        let stats = import_json(&mut restored, &path, MergeStrategy::KeepEarliest).unwrap();
+        assert_eq!(
+            (stats.connections, stats.sessions, stats.day_types),
+            (2, 2, 1)
+        );
+        assert_eq!(dump(&restored), dump(&db));
+
+        // Exports from before day types were added
+        let v1 = dir.join("v1.json");
+        fs::write(&v1, r#"{"version": 1, "connections": [], "sessions": []}"#).unwrap();
+        assert!(import_json(&mut restored, &v1, MergeStrategy::KeepLatest).is_ok());
+
+        let archive = dir.join("archive.json.gz");
+        archive_json(&db, "2024-01-09", &archive).unwrap();
+        let mut restored = self::db();
+        let stats = import_json(&mut restored, &archive, MergeStrategy::KeepEarliest).unwrap();
+        assert_eq!((stats.connections, stats.sessions), (1, 1));
+
+        let future = dir.join("future.json");
+        fs::write(
+            &future,
+            r#"{"version": 99, "connections": [], "sessions": []}"#,
+        )
+        .unwrap();
+        assert!(import_json(&mut restored, &future, MergeStrategy::KeepLatest).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn merge_strategy_decides_conflicting_days_and_sessions() {
+        let dir = std::env::temp_dir().join(format!("tracker-export-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut other = db();
+        edits::upsert_day(&mut other, "2024-01-08", "09:00", "18:00").unwrap();
+        let path = dir.join("export.json");
+        export_json(&other, &path).unwrap();
+        let latest = |db: &Connection| -> (String, String) {
+            db.query_row(
+                "SELECT c.latest, s.end FROM connections c JOIN sessions s USING (date)",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
+        };
+
+        let mut db = db();
+        edits::upsert_day(&mut db, "2024-01-08", "09:00", "17:00").unwrap();
+        let stats = import_json(&mut db, &path, MergeStrategy::KeepEarliest).unwrap();
+        assert_eq!((stats.connections, stats.sessions), (0, 0));
+        assert_eq!(latest(&db), ("17:00".to_string(), "17:00".to_string()));
+
+        let stats = import_json(&mut db, &path, MergeStrategy::KeepLatest).unwrap();
+        assert_eq!((stats.connections, stats.sessions), (1, 1));
+        assert_eq!(latest(&db), ("18:00".to_string(), "18:00".to_string()));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn csv_picks_columns_and_ical_is_in_utc() {
+        let dir = std::env::temp_dir().join(format!("tracker-export-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut db = db();
+        edits::upsert_day(&mut db, "2024-01-08", "09:00", "17:00").unwrap();
+
+        let csv = dir.join("sessions.csv");
+        let columns = ["date".to_string(), "minutes".to_string()];
+        let count = export_csv(&db, &csv, "sessions", None, None, Some(&columns)).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            fs::read_to_string(&csv).unwrap(),
+            "date,minutes\n2024-01-08,480\n"
+        );
+        let unknown = ["hours".to_string()];
+        assert!(export_csv(&db, &csv, "sessions", None, None, Some(&unknown)).is_err());
+
+        assert_eq!(ical_datetime(1_704_706_200), "20240108T093000Z");
+        let ics = dir.join("days.ics");
+        let start: i64 = db
+            .query_row("SELECT earliest_at FROM connections", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(
+            export_ical(&db, &ics, None, None, IcalGranularity::Day).unwrap(),
+            1
+        );
+        let ics = fs::read_to_string(&ics).unwrap();
+        assert!(ics.contains(&format!("DTSTART:{}\r\n", ical_datetime(start))));
+        assert!(ics.contains("UID:day-2024-01-08@foundership-time-tracker\r\n"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
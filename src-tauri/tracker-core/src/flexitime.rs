@@ -0,0 +1,262 @@
+use chrono::{Datelike, NaiveDate};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::day_types::{self, DayType};
+use crate::{holidays, reports, settings};
+
+/// Contracted hours, as configured by the user.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WorkSchedule {
+    /// Hours expected on each weekday, Monday first.
+    pub hours: [f64; 7],
+    /// The day the balance starts from, as YYYY-MM-DD.
+    pub since: String,
+    /// Balance carried over from before `since`, e.g. from a timesheet.
+    #[serde(default)]
+    pub opening_balance_minutes: i64,
+}
+
+impl WorkSchedule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self
+            .hours
+            .iter()
+            .any(|hours| !hours.is_finite() || !(0.0..=24.0).contains(hours))
+        {
+            return Err("Expected hours must be between 0 and 24 a day".to_string());
+        }
+        self.since_date()?;
+        Ok(())
+    }
+
+    fn since_date(&self) -> Result<NaiveDate, String> {
+        NaiveDate::parse_from_str(&self.since, "%Y-%m-%d")
+            .map_err(|_| format!("Invalid balance start date '{}'", self.since))
+    }
+
+    fn expected_on(&self, date: NaiveDate) -> i64 {
+        (self.hours[date.weekday().num_days_from_monday() as usize] * 60.0).round() as i64
+    }
+}
+
+/// The configured schedule, if there is one.
+pub fn load(db: &Connection) -> rusqlite::Result<Option<WorkSchedule>> {
+    Ok(settings::get(db, settings::WORK_SCHEDULE)?
+        .and_then(|json| serde_json::from_str(&json).ok()))
+}
+
+/// Time worked against the schedule over some days.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Period {
+    pub expected_minutes: i64,
+    pub worked_minutes: i64,
+    /// Worked minus expected, so negative when short.
+    pub balance_minutes: i64,
+}
+
+/// Compares every day from `from` to `to` that's both on or after the
+/// schedule's start and not after `today`. Days tagged as anything but
+/// office and public holidays aren't expected; today is only expected up to
+/// what's been worked so far, so it can't count against the balance until
+/// it's over.
+pub fn period(
+    db: &Connection,
+    schedule: &WorkSchedule,
+    from: NaiveDate,
+    to: NaiveDate,
+    today: NaiveDate,
+) -> rusqlite::Result<Period> {
+    let since = schedule
+        .since_date()
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+    let (from, to) = (from.max(since), to.min(today));
+    if from > to {
+        return Ok(Period::default());
+    }
+    let (from_str, to_str) = (from.to_string(), to.to_string());
+    let worked: HashMap<String, i64> = reports::day_totals(db, &from_str, &to_str, None)?
+        .into_iter()
+        .map(|day| (day.date, day.total_minutes))
+        .collect();
+    let tags: HashMap<String, DayType> = day_types::get_day_types(db, &from_str, &to_str)?
+        .into_iter()
+        .map(|day| (day.date, day.day_type))
+        .collect();
+    let holidays = holidays::holiday_dates(db, &from_str, &to_str)?;
+
+    let mut period = Period::default();
+    for date in from.iter_days().take_while(|date| *date <= to) {
+        let key = date.to_string();
+        let worked = worked.get(&key).copied().unwrap_or(0);
+        let off = is_day_off(tags.get(&key), holidays.contains(&key));
+        let mut expected = if off { 0 } else { schedule.expected_on(date) };
+        if date == today {
+            expected = expected.min(worked);
+        }
+        period.expected_minutes += expected;
+        period.worked_minutes += worked;
+    }
+    period.balance_minutes = period.worked_minutes - period.expected_minutes;
+    Ok(period)
+}
+
+/// Minutes to work on `date` to be on track: the schedule's hours for that
+/// weekday if one is set, otherwise a fifth of the weekly target on
+/// weekdays. Nothing's expected on days off, as in `period`.
+pub fn expected_minutes(db: &Connection, date: NaiveDate) -> rusqlite::Result<i64> {
+    let key = date.to_string();
+    let tag = day_types::get_day_types(db, &key, &key)?
+        .into_iter()
+        .next()
+        .map(|day| day.day_type);
+    if is_day_off(
+        tag.as_ref(),
+        !holidays::holiday_dates(db, &key, &key)?.is_empty(),
+    ) {
+        return Ok(0);
+    }
+    if let Some(schedule) = load(db)? {
+        return Ok(schedule.expected_on(date));
+    }
+    if date.weekday().num_days_from_monday() >= 5 {
+        return Ok(0);
+    }
+    let weekly = settings::get_f64(
+        db,
+        settings::WEEKLY_TARGET_HOURS,
+        settings::DEFAULT_WEEKLY_TARGET_HOURS,
+    )?;
+    Ok((weekly * 60.0 / 5.0).round() as i64)
+}
+
+/// Whether a day with `tag` isn't a working one: tagged as anything but
+/// office, or an untagged public holiday.
+fn is_day_off(tag: Option<&DayType>, holiday: bool) -> bool {
+    match tag {
+        Some(DayType::Office) => false,
+        Some(_) => true,
+        None => holiday,
+    }
+}
+
+/// The running flexitime balance.
+#[derive(Serialize)]
+pub struct Balance {
+    pub since: String,
+    pub expected_minutes: i64,
+    pub worked_minutes: i64,
+    /// Including the opening balance.
+    pub balance_minutes: i64,
+}
+
+/// The balance from the schedule's start to `today`, or `None` if no
+/// schedule is set.
+pub fn balance(db: &Connection, today: NaiveDate) -> rusqlite::Result<Option<Balance>> {
+    let Some(schedule) = load(db)? else {
+        return Ok(None);
+    };
+    let period = period(db, &schedule, NaiveDate::MIN, today, today)?;
+    Ok(Some(Balance {
+        since: schedule.since.clone(),
+        expected_minutes: period.expected_minutes,
+        worked_minutes: period.worked_minutes,
+        balance_minutes: schedule.opening_balance_minutes + period.balance_minutes,
+    }))
+}
+
+/// Formats a balance as e.g. "+1h 05m" or "-0h 30m".
+pub fn format_balance(minutes: i64) -> String {
+    let sign = if minutes < 0 { '-' } else { '+' };
+    format!("{}{}", sign, reports::format_minutes(minutes.abs()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+    use rusqlite::params;
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    fn worked(db: &Connection, date: &str, minutes: i64) {
+        db.execute(
+            "INSERT INTO connections (date, earliest, latest) VALUES (?1, '09:00', '17:00')",
+            [date],
+        )
+        .unwrap();
+        db.execute(
+            "INSERT INTO sessions (date, start, end, start_at, end_at, source)
+             VALUES (?1, '09:00', '17:00', 0, ?2, 'manual')",
+            params![date, minutes * 60],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn balance_counts_expected_days_up_to_today() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        let schedule = WorkSchedule {
+            hours: [8.0, 8.0, 8.0, 8.0, 8.0, 0.0, 0.0],
+            since: "2024-03-04".to_string(),
+            opening_balance_minutes: 0,
+        };
+        // Mon 9h, Tue 7h, Wed on vacation, Thu 2h so far today
+        worked(&db, "2024-03-04", 9 * 60);
+        worked(&db, "2024-03-05", 7 * 60);
+        day_types::set_day_type(&db, "2024-03-06", Some(DayType::Vacation)).unwrap();
+        worked(&db, "2024-03-07", 2 * 60);
+
+        let week = period(
+            &db,
+            &schedule,
+            date("2024-03-04"),
+            date("2024-03-10"),
+            date("2024-03-07"),
+        )
+        .unwrap();
+        assert_eq!(week.expected_minutes, 18 * 60);
+        assert_eq!(week.worked_minutes, 18 * 60);
+        assert_eq!(week.balance_minutes, 0);
+
+        // Friday with nothing tracked counts against it once it's over
+        let week = period(
+            &db,
+            &schedule,
+            date("2024-03-04"),
+            date("2024-03-10"),
+            date("2024-03-09"),
+        )
+        .unwrap();
+        assert_eq!(week.balance_minutes, -14 * 60);
+        assert_eq!(format_balance(week.balance_minutes), "-14h 00m");
+    }
+
+    #[test]
+    fn expects_the_schedule_or_a_fifth_of_the_weekly_target() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        settings::set(&db, settings::WEEKLY_TARGET_HOURS, "35").unwrap();
+        assert_eq!(expected_minutes(&db, date("2024-03-04")).unwrap(), 7 * 60);
+        assert_eq!(expected_minutes(&db, date("2024-03-09")).unwrap(), 0);
+        day_types::set_day_type(&db, "2024-03-05", Some(DayType::Sick)).unwrap();
+        assert_eq!(expected_minutes(&db, date("2024-03-05")).unwrap(), 0);
+
+        let schedule = WorkSchedule {
+            hours: [8.0, 8.0, 8.0, 8.0, 4.5, 0.0, 0.0],
+            since: "2024-03-04".to_string(),
+            opening_balance_minutes: 0,
+        };
+        settings::set(
+            &db,
+            settings::WORK_SCHEDULE,
+            &serde_json::to_string(&schedule).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(expected_minutes(&db, date("2024-03-08")).unwrap(), 270);
+    }
+}
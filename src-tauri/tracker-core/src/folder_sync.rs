@@ -0,0 +1,458 @@
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::audit;
+use crate::error::Result;
+use crate::sessions::{self, local_end_epoch_sql, local_epoch_sql, Session};
+use crate::settings;
+
+/// Each device appends to its own file in the folder, named after its id.
+const EXTENSION: &str = "jsonl";
+
+/// One line of a device's file: the latest version of one of its sessions,
+/// or `None` once it's been deleted. Only the device that recorded a session
+/// ever writes it, and the version written last wins; see `apply`.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Record {
+    pub id: String,
+    /// Unix time in milliseconds this version was shared. Missing from
+    /// files written before it was added.
+    #[serde(default)]
+    pub updated_at: i64,
+    pub session: Option<Session>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SyncStats {
+    /// Records appended to this device's file.
+    pub written: usize,
+    /// Records read from other devices' files.
+    pub read: usize,
+}
+
+/// Which sessions have been shared with or received from other devices, and
+/// how far each other device's file has been read.
+pub fn create_tables(db: &Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "CREATE TABLE IF NOT EXISTS sync_records (
+            sync_id TEXT PRIMARY KEY,
+            device TEXT NOT NULL,
+            session_id INTEGER NOT NULL,
+            record TEXT NOT NULL,
+            updated_at INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE INDEX IF NOT EXISTS sync_records_session ON sync_records (session_id);
+        CREATE TABLE IF NOT EXISTS sync_files (
+            device TEXT PRIMARY KEY,
+            bytes_read INTEGER NOT NULL
+        );",
+    )
+}
+
+/// The configured folder, or `None` if syncing is off.
+pub fn folder(db: &Connection) -> rusqlite::Result<Option<String>> {
+    Ok(settings::get(db, settings::SYNC_FOLDER)?.filter(|folder| !folder.is_empty()))
+}
+
+/// This device's id, made up the first time it's needed.
+pub fn device_id(db: &Connection) -> rusqlite::Result<String> {
+    if let Some(id) = settings::get(db, settings::SYNC_DEVICE_ID)? {
+        return Ok(id);
+    }
+    let id = Uuid::new_v4().to_string();
+    settings::set(db, settings::SYNC_DEVICE_ID, &id)?;
+    Ok(id)
+}
+
+/// Sessions received from other devices, which aren't this device's to push
+/// anywhere else.
+pub fn received(db: &Connection) -> rusqlite::Result<Vec<i64>> {
+    let Some(device) = settings::get(db, settings::SYNC_DEVICE_ID)? else {
+        return Ok(Vec::new());
+    };
+    let mut stmt = db.prepare("SELECT session_id FROM sync_records WHERE device != ?1")?;
+    let ids = stmt.query_map([device], |row| row.get(0))?;
+    ids.collect()
+}
+
+/// Appends this device's new, changed and deleted sessions to its file in
+/// `folder`, then merges in whatever the other devices have appended to
+/// theirs since the last sync.
+pub fn sync(db: &Connection, folder: &Path) -> Result<SyncStats> {
+    let device = device_id(db)?;
+    Ok(SyncStats {
+        written: write(db, folder, &device)?,
+        read: read(db, folder, &device)?,
+    })
+}
+
+/// This device's sessions that are new or have changed since they were last
+/// shared, and those deleted since, stamped `updated_at`. Nothing's marked
+/// shared until `mark_shared`, so a failed send is retried next time.
+pub(crate) fn changes(db: &Connection, device: &str, updated_at: i64) -> Result<Vec<Record>> {
+    let mut shared: HashMap<i64, (String, String)> = HashMap::new();
+    {
+        let mut stmt =
+            db.prepare("SELECT session_id, sync_id, record FROM sync_records WHERE device = ?1")?;
+        let rows = stmt.query_map([device], |row| {
+            Ok((row.get(0)?, (row.get(1)?, row.get(2)?)))
+        })?;
+        for row in rows {
+            let (session_id, record) = row?;
+            shared.insert(session_id, record);
+        }
+    }
+    let received = |id: i64| -> rusqlite::Result<bool> {
+        db.prepare_cached("SELECT 1 FROM sync_records WHERE session_id = ?1 AND device != ?2")?
+            .exists(params![id, device])
+    };
+
+    let mut records = Vec::new();
+    for session in sessions::get_sessions(db, None, None)? {
+        if received(session.id)? {
+            continue;
+        }
+        let json = serde_json::to_string(&session).map_err(|e| e.to_string())?;
+        let id = match shared.remove(&session.id) {
+            Some((_, record)) if record == json => continue,
+            Some((sync_id, _)) => sync_id,
+            None => Uuid::new_v4().to_string(),
+        };
+        records.push(Record {
+            id,
+            updated_at,
+            session: Some(session),
+        });
+    }
+    // Whatever's left has been deleted here since it was shared
+    for (_, (id, _)) in shared {
+        records.push(Record {
+            id,
+            updated_at,
+            session: None,
+        });
+    }
+    Ok(records)
+}
+
+/// Records that `records`, from `changes`, have been sent to the other
+/// devices.
+pub(crate) fn mark_shared(db: &Connection, device: &str, records: &[Record]) -> Result<()> {
+    let tx = db.unchecked_transaction()?;
+    for record in records {
+        match &record.session {
+            Some(session) => {
+                let json = serde_json::to_string(session).map_err(|e| e.to_string())?;
+                tx.execute(
+                    "INSERT OR REPLACE INTO sync_records
+                        (sync_id, device, session_id, record, updated_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![record.id, device, session.id, json, record.updated_at],
+                )?;
+            }
+            None => {
+                tx.execute("DELETE FROM sync_records WHERE sync_id = ?1", [&record.id])?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Forgets what this device has shared, so every one of its sessions is
+/// sent again under the same ids, e.g. after pairing with a new server.
+pub(crate) fn reshare(db: &Connection, device: &str) -> rusqlite::Result<()> {
+    db.execute(
+        "UPDATE sync_records SET record = '' WHERE device = ?1",
+        [device],
+    )?;
+    Ok(())
+}
+
+fn write(db: &Connection, folder: &Path, device: &str) -> Result<usize> {
+    let records = changes(db, device, Utc::now().timestamp_millis())?;
+    if records.is_empty() {
+        return Ok(0);
+    }
+
+    fs::create_dir_all(folder)?;
+    let mut out = String::new();
+    for record in &records {
+        out.push_str(&serde_json::to_string(record).map_err(|e| e.to_string())?);
+        out.push('\n');
+    }
+    // Written before being marked shared, so a failed write is retried next
+    // time
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(folder.join(format!("{}.{}", device, EXTENSION)))?
+        .write_all(out.as_bytes())?;
+    mark_shared(db, device, &records)?;
+    Ok(records.len())
+}
+
+fn read(db: &Connection, folder: &Path, device: &str) -> Result<usize> {
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let mut read = 0;
+    for entry in entries {
+        let path = entry?.path();
+        let Some(other) = path
+            .file_stem()
+            .filter(|_| {
+                path.extension()
+                    .is_some_and(|extension| extension == EXTENSION)
+            })
+            .and_then(|stem| stem.to_str())
+        else {
+            continue;
+        };
+        if other != device {
+            read += read_file(db, &path, other)?;
+        }
+    }
+    Ok(read)
+}
+
+/// Applies the complete lines `other` has added to its file since it was
+/// last read. A line still being written, or synced, is left for next time.
+fn read_file(db: &Connection, path: &Path, other: &str) -> Result<usize> {
+    let tx = db.unchecked_transaction()?;
+    audit::set_source(&tx, audit::SOURCE_SYNC)?;
+    let mut bytes_read: u64 = tx
+        .query_row(
+            "SELECT bytes_read FROM sync_files WHERE device = ?1",
+            [other],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(0);
+    let mut file = File::open(path)?;
+    // A file that's shrunk has been replaced, so read it again from the
+    // start; applying a record twice does no harm
+    if file.metadata()?.len() < bytes_read {
+        bytes_read = 0;
+    }
+    file.seek(SeekFrom::Start(bytes_read))?;
+    let mut new = Vec::new();
+    file.read_to_end(&mut new)?;
+    let Some(end) = new.iter().rposition(|&byte| byte == b'\n') else {
+        return Ok(0);
+    };
+
+    let mut applied = 0;
+    for line in new[..=end].split(|&byte| byte == b'\n') {
+        if line.is_empty() {
+            continue;
+        }
+        match serde_json::from_slice::<Record>(line) {
+            Ok(record) => {
+                apply(&tx, other, &record)?;
+                applied += 1;
+            }
+            Err(e) => warn!("Skipping unreadable sync record from {}: {}", other, e),
+        }
+    }
+    tx.execute(
+        "INSERT OR REPLACE INTO sync_files (device, bytes_read) VALUES (?1, ?2)",
+        params![other, bytes_read + end as u64 + 1],
+    )?;
+    audit::clear_source(&tx)?;
+    tx.commit()?;
+    Ok(applied)
+}
+
+/// Adds, updates or deletes this device's copy of another device's session,
+/// widening the day's earliest and latest times to cover it. A record older
+/// than the version already applied is ignored, so the last write wins
+/// whatever order records arrive in.
+pub(crate) fn apply(db: &Connection, other: &str, record: &Record) -> rusqlite::Result<()> {
+    let existing: Option<(i64, i64)> = db
+        .query_row(
+            "SELECT session_id, updated_at FROM sync_records WHERE sync_id = ?1",
+            [&record.id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    if existing.is_some_and(|(_, updated_at)| updated_at > record.updated_at) {
+        return Ok(());
+    }
+    let existing = existing.map(|(id, _)| id);
+    let Some(session) = &record.session else {
+        if let Some(id) = existing {
+            db.execute("DELETE FROM sessions WHERE id = ?1", [id])?;
+            db.execute("DELETE FROM sync_records WHERE sync_id = ?1", [&record.id])?;
+        }
+        return Ok(());
+    };
+    let id = match existing {
+        Some(id) => {
+            db.execute(
+                &format!(
+                    "UPDATE sessions SET date = ?1, start = ?2, end = ?3, ssid = ?4, label = ?5,
+                        source = ?6, start_at = COALESCE(?7, {}), end_at = COALESCE(?8, {}),
+                        rule = ?9, idle_seconds = ?10, active_seconds = ?12
+                     WHERE id = ?11",
+                    local_epoch_sql("?1", "?2"),
+                    local_end_epoch_sql("?1", "?2", "?3")
+                ),
+                params![
+                    session.date,
+                    session.start,
+                    session.end,
+                    session.ssid,
+                    session.label,
+                    session.source,
+                    session.start_at,
+                    session.end_at,
+                    session.rule,
+                    session.idle_seconds,
+                    id,
+                    session.active_seconds
+                ],
+            )?;
+            id
+        }
+        None => {
+            db.execute(
+                &format!(
+                    "INSERT INTO sessions
+                        (date, start, end, ssid, label, source, start_at, end_at, rule,
+                            idle_seconds, active_seconds)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, COALESCE(?7, {}), COALESCE(?8, {}), ?9,
+                        ?10, ?11)",
+                    local_epoch_sql("?1", "?2"),
+                    local_end_epoch_sql("?1", "?2", "?3")
+                ),
+                params![
+                    session.date,
+                    session.start,
+                    session.end,
+                    session.ssid,
+                    session.label,
+                    session.source,
+                    session.start_at,
+                    session.end_at,
+                    session.rule,
+                    session.idle_seconds,
+                    session.active_seconds
+                ],
+            )?;
+            db.last_insert_rowid()
+        }
+    };
+    let json = serde_json::to_string(session)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    db.execute(
+        "INSERT OR REPLACE INTO sync_records (sync_id, device, session_id, record, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![record.id, other, id, json, record.updated_at],
+    )?;
+    db.execute(
+        "INSERT INTO connections (date, earliest, latest, earliest_at, latest_at, ssid, label)
+         SELECT date, start, end, start_at, end_at, ssid, label FROM sessions WHERE id = ?1
+         ON CONFLICT(date) DO UPDATE SET
+         earliest = CASE WHEN excluded.earliest_at < earliest_at
+            THEN excluded.earliest ELSE earliest END,
+         earliest_at = MIN(earliest_at, excluded.earliest_at),
+         latest = CASE WHEN excluded.latest_at > latest_at THEN excluded.latest ELSE latest END,
+         latest_at = MAX(latest_at, excluded.latest_at)",
+        [id],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn device(folder: &Path) -> Connection {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        settings::set(&db, settings::SYNC_FOLDER, &folder.to_string_lossy()).unwrap();
+        db
+    }
+
+    fn add_session(db: &Connection, date: &str, start: &str, end: &str) -> i64 {
+        db.execute(
+            &format!(
+                "INSERT INTO connections (date, earliest, latest, earliest_at, latest_at)
+                 VALUES (?1, ?2, ?3, {}, {})
+                 ON CONFLICT(date) DO NOTHING",
+                local_epoch_sql("?1", "?2"),
+                local_end_epoch_sql("?1", "?2", "?3")
+            ),
+            [date, start, end],
+        )
+        .unwrap();
+        db.execute(
+            &format!(
+                "INSERT INTO sessions (date, start, end, start_at, end_at)
+                 VALUES (?1, ?2, ?3, {}, {})",
+                local_epoch_sql("?1", "?2"),
+                local_end_epoch_sql("?1", "?2", "?3")
+            ),
+            [date, start, end],
+        )
+        .unwrap();
+        db.last_insert_rowid()
+    }
+
+    fn starts(db: &Connection) -> Vec<String> {
+        let mut starts: Vec<String> = sessions::get_sessions(db, None, None)
+            .unwrap()
+            .into_iter()
+            .map(|session| format!("{} {}-{}", session.date, session.start, session.end))
+            .collect();
+        starts.sort();
+        starts
+    }
+
+    #[test]
+    fn devices_end_up_with_the_union_of_sessions() {
+        let folder = std::env::temp_dir().join(format!("tracker-sync-{}", Uuid::new_v4()));
+        let (laptop, desktop) = (device(&folder), device(&folder));
+        let morning = add_session(&laptop, "2024-03-04", "09:00", "12:00");
+        add_session(&desktop, "2024-03-04", "13:00", "17:30");
+
+        sync(&laptop, &folder).unwrap();
+        let stats = sync(&desktop, &folder).unwrap();
+        assert_eq!((stats.written, stats.read), (1, 1));
+        sync(&laptop, &folder).unwrap();
+        let both = vec![
+            "2024-03-04 09:00-12:00".to_string(),
+            "2024-03-04 13:00-17:30".to_string(),
+        ];
+        assert_eq!(starts(&laptop), both);
+        assert_eq!(starts(&desktop), both);
+        let day = crate::connections::get_day(&desktop, "2024-03-04")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (day.earliest.as_str(), day.latest.as_str()),
+            ("09:00", "17:30")
+        );
+
+        // Nothing's echoed back, and edits and deletions carry over
+        assert_eq!(sync(&desktop, &folder).unwrap().written, 0);
+        laptop
+            .execute("DELETE FROM sessions WHERE id = ?1", [morning])
+            .unwrap();
+        sync(&laptop, &folder).unwrap();
+        sync(&desktop, &folder).unwrap();
+        assert_eq!(starts(&desktop), vec!["2024-03-04 13:00-17:30".to_string()]);
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
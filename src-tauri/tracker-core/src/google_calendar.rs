@@ -0,0 +1,393 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Duration as ChronoDuration, NaiveDate, SecondsFormat, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::error::Result;
+use crate::reports::format_minutes;
+use crate::{connections, keychain, settings};
+
+/// Keychain account holding the OAuth refresh token.
+pub const TOKEN_ACCOUNT: &str = "google_calendar";
+const AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const EVENTS_URL: &str = "https://www.googleapis.com/calendar/v3/calendars";
+/// Only what's needed to manage events, not to read the rest of the
+/// calendar.
+const SCOPE: &str = "https://www.googleapis.com/auth/calendar.events";
+const TIMEOUT: Duration = Duration::from_secs(15);
+/// How long to wait for the user to finish signing in in the browser.
+const AUTHORIZE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Publishing each office day to a dedicated Google Calendar, as configured
+/// by the user. The client is a "Desktop app" OAuth client from their own
+/// Google Cloud project.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GoogleCalendarSetting {
+    pub client_id: String,
+    /// Not actually secret for desktop clients, but Google still wants it.
+    pub client_secret: String,
+    /// e.g. "abc123@group.calendar.google.com".
+    pub calendar_id: String,
+    /// An all-day event rather than one from arrival to leaving.
+    #[serde(default)]
+    pub all_day: bool,
+}
+
+impl GoogleCalendarSetting {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.client_id.trim().is_empty() || self.client_secret.trim().is_empty() {
+            return Err("Google Calendar needs an OAuth client id and secret".to_string());
+        }
+        if self.calendar_id.trim().is_empty() {
+            return Err("Google Calendar needs a calendar id".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Remembers the event made for each day, so it's updated rather than
+/// duplicated as the day goes on.
+pub fn create_table(db: &Connection) -> rusqlite::Result<()> {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS calendar_events (
+            date TEXT PRIMARY KEY,
+            event_id TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// The configured calendar, if publishing is turned on.
+pub fn load(db: &Connection) -> Result<Option<GoogleCalendarSetting>> {
+    Ok(settings::get(db, settings::GOOGLE_CALENDAR)?
+        .and_then(|json| serde_json::from_str(&json).ok()))
+}
+
+/// Signs in through the system browser, which `open` is given the consent
+/// page's URL to show, and stores the refresh token in the keychain. Google
+/// redirects back to a one-off listener on localhost, with PKCE so the code
+/// is no use to anything else that sees it.
+pub fn authorize(
+    config: &GoogleCalendarSetting,
+    open: impl FnOnce(&str) -> Result<()>,
+) -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let redirect_uri = format!("http://127.0.0.1:{}", listener.local_addr()?.port());
+    let verifier = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let state = uuid::Uuid::new_v4().simple().to_string();
+
+    let mut url = Url::parse(AUTH_URL).map_err(|e| e.to_string())?;
+    url.query_pairs_mut()
+        .append_pair("client_id", config.client_id.trim())
+        .append_pair("redirect_uri", &redirect_uri)
+        .append_pair("response_type", "code")
+        .append_pair("scope", SCOPE)
+        .append_pair("code_challenge", &code_challenge(&verifier))
+        .append_pair("code_challenge_method", "S256")
+        .append_pair("state", &state)
+        // Needed for a refresh token, even when signing in again
+        .append_pair("access_type", "offline")
+        .append_pair("prompt", "consent");
+    open(url.as_str())?;
+
+    let code = wait_for_code(&listener, &state)?;
+    let response: serde_json::Value = ureq::post(TOKEN_URL)
+        .timeout(TIMEOUT)
+        .send_form(&[
+            ("code", code.as_str()),
+            ("client_id", config.client_id.trim()),
+            ("client_secret", config.client_secret.trim()),
+            ("redirect_uri", &redirect_uri),
+            ("grant_type", "authorization_code"),
+            ("code_verifier", &verifier),
+        ])
+        .map_err(|e| format!("Google rejected the sign-in: {}", e))?
+        .into_json()?;
+    let refresh_token = response["refresh_token"]
+        .as_str()
+        .ok_or("Google didn't return a refresh token")?;
+    keychain::set(TOKEN_ACCOUNT, refresh_token)
+}
+
+fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// Takes the redirect from the browser, answering it with a page saying
+/// whether it worked, and returns the authorization code.
+fn wait_for_code(listener: &TcpListener, state: &str) -> Result<String> {
+    listener.set_nonblocking(true)?;
+    let deadline = Instant::now() + AUTHORIZE_TIMEOUT;
+    let mut stream = loop {
+        match listener.accept() {
+            Ok((stream, _)) => break stream,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                if Instant::now() > deadline {
+                    return Err("Timed out waiting for Google sign-in".into());
+                }
+                thread::sleep(Duration::from_millis(200));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    };
+    stream.set_nonblocking(false)?;
+
+    // e.g. "GET /?state=...&code=... HTTP/1.1"
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let url = Url::parse(&format!("http://localhost{}", path)).map_err(|e| e.to_string())?;
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+    let result: Result<String> = match (param("code"), param("error")) {
+        _ if param("state").as_deref() != Some(state) => Err("Sign-in state didn't match".into()),
+        (Some(code), _) => Ok(code),
+        (None, Some(error)) => Err(format!("Google sign-in failed: {}", error).into()),
+        (None, None) => Err("Google didn't return a code".into()),
+    };
+
+    let message = match &result {
+        Ok(_) => "Connected to Google Calendar. You can close this tab.".to_string(),
+        Err(e) => format!("Couldn't connect to Google Calendar: {}", e),
+    };
+    let _ = write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        message.len(),
+        message
+    );
+    result
+}
+
+/// A short-lived access token from the stored refresh token.
+pub fn access_token(config: &GoogleCalendarSetting) -> Result<String> {
+    let refresh_token = keychain::get(TOKEN_ACCOUNT)?
+        .ok_or("Not connected to Google Calendar, sign in from settings")?;
+    let response: serde_json::Value = ureq::post(TOKEN_URL)
+        .timeout(TIMEOUT)
+        .send_form(&[
+            ("refresh_token", refresh_token.as_str()),
+            ("client_id", config.client_id.trim()),
+            ("client_secret", config.client_secret.trim()),
+            ("grant_type", "refresh_token"),
+        ])
+        .map_err(|e| format!("Couldn't refresh the Google token: {}", e))?
+        .into_json()?;
+    Ok(response["access_token"]
+        .as_str()
+        .ok_or("Google didn't return an access token")?
+        .to_string())
+}
+
+/// The event for `date`, e.g. "Office 09:05–18:20 (8h 34m)", or `None` if
+/// nothing was recorded that day.
+pub fn event_for_day(
+    db: &Connection,
+    config: &GoogleCalendarSetting,
+    date: &str,
+) -> Result<Option<serde_json::Value>> {
+    let Some(day) = connections::get_day(db, date)? else {
+        return Ok(None);
+    };
+    let (earliest_at, latest_at): (Option<i64>, Option<i64>) = db.query_row(
+        "SELECT earliest_at, latest_at FROM connections WHERE date = ?1",
+        [date],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let summary = format!(
+        "Office {}\u{2013}{} ({})",
+        day.earliest,
+        day.latest,
+        format_minutes(day.total_minutes)
+    );
+
+    let (start, end) = match (config.all_day, earliest_at, latest_at) {
+        (false, Some(earliest_at), Some(latest_at)) => (
+            json!({ "dateTime": rfc3339(earliest_at)? }),
+            json!({ "dateTime": rfc3339(latest_at.max(earliest_at))? }),
+        ),
+        // Days from before timestamps were stored only have local times
+        _ => {
+            let start = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| format!("Invalid date '{}'", date))?;
+            let end = start + ChronoDuration::days(1);
+            (
+                json!({ "date": start.to_string() }),
+                json!({ "date": end.to_string() }),
+            )
+        }
+    };
+    Ok(Some(json!({
+        "summary": summary,
+        "description": day.note.unwrap_or_default(),
+        "start": start,
+        "end": end,
+        "transparency": "transparent",
+    })))
+}
+
+fn rfc3339(epoch: i64) -> Result<String> {
+    Ok(Utc
+        .timestamp_opt(epoch, 0)
+        .single()
+        .ok_or_else(|| format!("Invalid timestamp {}", epoch))?
+        .to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// The event already published for `date`, if any.
+pub fn event_id(db: &Connection, date: &str) -> Result<Option<String>> {
+    Ok(db
+        .query_row(
+            "SELECT event_id FROM calendar_events WHERE date = ?1",
+            [date],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+pub fn record_event(db: &Connection, date: &str, event_id: &str) -> Result<()> {
+    db.execute(
+        "INSERT OR REPLACE INTO calendar_events (date, event_id) VALUES (?1, ?2)",
+        params![date, event_id],
+    )?;
+    Ok(())
+}
+
+/// Updates `existing` with `event`, or creates it if there's no event yet or
+/// it's since been deleted from the calendar. Returns the event's id.
+pub fn upsert_event(
+    config: &GoogleCalendarSetting,
+    access_token: &str,
+    existing: Option<&str>,
+    event: &serde_json::Value,
+) -> Result<String> {
+    let events_url = format!(
+        "{}/{}/events",
+        EVENTS_URL,
+        encode_path_segment(config.calendar_id.trim())
+    );
+    let authorization = format!("Bearer {}", access_token);
+    if let Some(id) = existing {
+        let result = ureq::put(&format!("{}/{}", events_url, encode_path_segment(id)))
+            .timeout(TIMEOUT)
+            .set("Authorization", &authorization)
+            .send_json(event);
+        match result {
+            Ok(_) => return Ok(id.to_string()),
+            Err(ureq::Error::Status(404 | 410, _)) => {}
+            Err(e) => return Err(format!("Couldn't update the calendar event: {}", e).into()),
+        }
+    }
+    let response: serde_json::Value = ureq::post(&events_url)
+        .timeout(TIMEOUT)
+        .set("Authorization", &authorization)
+        .send_json(event)
+        .map_err(|e| format!("Couldn't create the calendar event: {}", e))?
+        .into_json()?;
+    Ok(response["id"]
+        .as_str()
+        .ok_or("Google didn't return an event id")?
+        .to_string())
+}
+
+fn encode_path_segment(segment: &str) -> String {
+    url::form_urlencoded::byte_serialize(segment.as_bytes())
+        .collect::<String>()
+        .replace('+', "%20")
+}
+
+/// Publishes `date` to the configured calendar, if there is one.
+pub fn publish_day(db: &Connection, date: &str) -> Result<()> {
+    let Some(config) = load(db)? else {
+        return Ok(());
+    };
+    let Some(event) = event_for_day(db, &config, date)? else {
+        return Ok(());
+    };
+    let existing = event_id(db, date)?;
+    let id = upsert_event(
+        &config,
+        &access_token(&config)?,
+        existing.as_deref(),
+        &event,
+    )?;
+    record_event(db, date, &id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn config(all_day: bool) -> GoogleCalendarSetting {
+        GoogleCalendarSetting {
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            calendar_id: "office@group.calendar.google.com".to_string(),
+            all_day,
+        }
+    }
+
+    #[test]
+    fn day_becomes_timed_or_all_day_event() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        assert!(event_for_day(&db, &config(false), "2024-03-04")
+            .unwrap()
+            .is_none());
+        db.execute(
+            "INSERT INTO connections (date, earliest, latest, earliest_at, latest_at)
+             VALUES ('2024-03-04', '09:00', '17:30', 1709542800, 1709573400)",
+            [],
+        )
+        .unwrap();
+
+        let timed = event_for_day(&db, &config(false), "2024-03-04")
+            .unwrap()
+            .unwrap();
+        assert_eq!(timed["start"]["dateTime"], "2024-03-04T09:00:00Z");
+        assert_eq!(timed["end"]["dateTime"], "2024-03-04T17:30:00Z");
+        assert!(timed["summary"].as_str().unwrap().starts_with("Office "));
+
+        let all_day = event_for_day(&db, &config(true), "2024-03-04")
+            .unwrap()
+            .unwrap();
+        assert_eq!(all_day["start"]["date"], "2024-03-04");
+        assert_eq!(all_day["end"]["date"], "2024-03-05");
+    }
+
+    #[test]
+    fn pkce_challenge_is_base64url_sha256() {
+        // From RFC 7636, appendix B
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r7wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn calendar_ids_are_escaped_in_paths() {
+        assert_eq!(
+            encode_path_segment("office@group.calendar.google.com"),
+            "office%40group.calendar.google.com"
+        );
+        assert_eq!(encode_path_segment("a b"), "a%20b");
+    }
+}
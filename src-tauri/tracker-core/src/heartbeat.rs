@@ -0,0 +1,106 @@
+use chrono::{DateTime, Local};
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use tracing::info;
+
+use crate::sessions::{self, SOURCE_AUTO};
+use crate::settings;
+
+/// Keeps the time of the latest poll in a single row, along with the
+/// sessions that were still accruing time then, so those can be closed
+/// properly after the app quits without ending them or crashes.
+pub fn create_table(db: &Connection) -> Result<()> {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS heartbeat (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            at INTEGER NOT NULL,
+            session_id INTEGER,
+            manual_session_id INTEGER
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Records a poll at `now`, with the detected and manual sessions that are
+/// still running, if any.
+pub fn record(
+    db: &Connection,
+    now: DateTime<Local>,
+    session_id: Option<i64>,
+    manual_session_id: Option<i64>,
+) -> Result<()> {
+    db.execute(
+        "INSERT INTO heartbeat (id, at, session_id, manual_session_id) VALUES (1, ?1, ?2, ?3)
+         ON CONFLICT(id) DO UPDATE SET
+            at = ?1, session_id = ?2, manual_session_id = ?3",
+        params![now.timestamp(), session_id, manual_session_id],
+    )?;
+    Ok(())
+}
+
+/// Ends the sessions left running by the last run at its last heartbeat,
+/// widening their days to match, and discards detected ones that turn out
+/// too short. Call before tracking starts. Returns the ids of the sessions
+/// that were kept.
+pub fn close_dangling(db: &Connection) -> Result<Vec<i64>> {
+    let Some((at, ids)) = db
+        .query_row(
+            "SELECT at, session_id, manual_session_id FROM heartbeat WHERE id = 1",
+            [],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    [row.get::<_, Option<i64>>(1)?, row.get(2)?],
+                ))
+            },
+        )
+        .optional()?
+    else {
+        return Ok(Vec::new());
+    };
+    let min_minutes = settings::get_f64(
+        db,
+        settings::MIN_SESSION_MINUTES,
+        settings::DEFAULT_MIN_SESSION_MINUTES,
+    )?;
+
+    let mut closed = Vec::new();
+    for id in ids.into_iter().flatten() {
+        let Some((date, source)) = db
+            .query_row(
+                "SELECT date, source FROM sessions WHERE id = ?1",
+                [id],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()?
+        else {
+            continue;
+        };
+        // Never shorten a session, e.g. one already closed before quitting
+        db.execute(
+            "UPDATE sessions SET
+                end = strftime('%H:%M', MAX(end_at, ?2), 'unixepoch', 'localtime'),
+                end_at = MAX(end_at, ?2)
+             WHERE id = ?1",
+            params![id, at],
+        )?;
+        db.execute(
+            "UPDATE connections SET
+                latest = strftime('%H:%M', MAX(latest_at, ?2), 'unixepoch', 'localtime'),
+                latest_at = MAX(latest_at, ?2)
+             WHERE date = ?1",
+            params![date, at],
+        )?;
+        if source == SOURCE_AUTO && sessions::discard_if_short(db, id, min_minutes as i64)? {
+            info!("Dangling session {} was too short, discarded", id);
+            continue;
+        }
+        info!("Closed dangling session {}", id);
+        closed.push(id);
+    }
+    db.execute(
+        "UPDATE heartbeat SET session_id = NULL, manual_session_id = NULL WHERE id = 1",
+        [],
+    )?;
+    Ok(closed)
+}
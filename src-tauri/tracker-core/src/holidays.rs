@@ -0,0 +1,166 @@
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::error::Result;
+use crate::settings;
+
+const NAGER_URL: &str = "https://date.nager.at/api/v3/PublicHolidays";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A public holiday, from Nager.Date.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Holiday {
+    pub date: String,
+    /// In English, e.g. "Easter Monday".
+    pub name: String,
+    /// ISO 3166-2 regions it's limited to, e.g. "GB-SCT", or `None` if it's
+    /// nationwide.
+    #[serde(default)]
+    pub counties: Option<Vec<String>>,
+}
+
+/// Holidays fetched so far, kept so reports never wait on the network.
+pub fn create_table(db: &Connection) -> rusqlite::Result<()> {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS public_holidays (
+            country TEXT NOT NULL,
+            date TEXT NOT NULL,
+            name TEXT NOT NULL,
+            counties TEXT,
+            PRIMARY KEY (country, date, name)
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// The configured country and region, if holidays are turned on.
+fn configured(db: &Connection) -> rusqlite::Result<Option<(String, String)>> {
+    let country = settings::get(db, settings::HOLIDAY_COUNTRY)?.unwrap_or_default();
+    if country.is_empty() {
+        return Ok(None);
+    }
+    let region = settings::get(db, settings::HOLIDAY_REGION)?.unwrap_or_default();
+    Ok(Some((country, region)))
+}
+
+/// Which of `years` haven't been fetched for the configured country yet.
+pub fn missing_years(db: &Connection, years: &[i32]) -> Result<Option<(String, Vec<i32>)>> {
+    let Some((country, _)) = configured(db)? else {
+        return Ok(None);
+    };
+    let mut stmt =
+        db.prepare("SELECT 1 FROM public_holidays WHERE country = ?1 AND date LIKE ?2")?;
+    let mut missing = Vec::new();
+    for &year in years {
+        if !stmt.exists(params![country, format!("{}-%", year)])? {
+            missing.push(year);
+        }
+    }
+    Ok(Some((country, missing)))
+}
+
+/// Downloads `country`'s holidays for `year`.
+pub fn fetch(country: &str, year: i32) -> Result<Vec<Holiday>> {
+    let holidays = ureq::get(&format!("{}/{}/{}", NAGER_URL, year, country))
+        .timeout(TIMEOUT)
+        .call()
+        .map_err(|e| format!("Couldn't fetch public holidays: {}", e))?
+        .into_json()?;
+    Ok(holidays)
+}
+
+/// Caches fetched holidays, replacing any already stored for the same days.
+pub fn store(db: &Connection, country: &str, holidays: &[Holiday]) -> Result<()> {
+    let tx = db.unchecked_transaction()?;
+    for holiday in holidays {
+        tx.execute(
+            "INSERT OR REPLACE INTO public_holidays (country, date, name, counties)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                country,
+                holiday.date,
+                holiday.name,
+                holiday.counties.as_ref().map(|counties| counties.join(","))
+            ],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Holidays between `from` and `to` inclusive in the configured country,
+/// counting regional ones only in the configured region.
+pub fn holidays(db: &Connection, from: &str, to: &str) -> rusqlite::Result<Vec<Holiday>> {
+    let Some((country, region)) = configured(db)? else {
+        return Ok(Vec::new());
+    };
+    let mut stmt = db.prepare(
+        "SELECT date, name, counties FROM public_holidays
+         WHERE country = ?1 AND date BETWEEN ?2 AND ?3
+            AND (counties IS NULL OR ',' || counties || ',' LIKE '%,' || ?4 || ',%')
+         ORDER BY date",
+    )?;
+    let holidays = stmt.query_map(params![country, from, to, region], |row| {
+        Ok(Holiday {
+            date: row.get(0)?,
+            name: row.get(1)?,
+            counties: row
+                .get::<_, Option<String>>(2)?
+                .map(|counties| counties.split(',').map(str::to_string).collect()),
+        })
+    })?;
+    holidays.collect()
+}
+
+/// Just the dates of `holidays`.
+pub fn holiday_dates(db: &Connection, from: &str, to: &str) -> rusqlite::Result<HashSet<String>> {
+    Ok(holidays(db, from, to)?
+        .into_iter()
+        .map(|holiday| holiday.date)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn db(region: &str) -> Connection {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        settings::set(&db, settings::HOLIDAY_COUNTRY, "GB").unwrap();
+        settings::set(&db, settings::HOLIDAY_REGION, region).unwrap();
+        let holidays: Vec<Holiday> = serde_json::from_str(
+            r#"[
+                {"date": "2024-01-01", "localName": "New Year's Day", "name": "New Year's Day",
+                 "countryCode": "GB", "global": false, "counties": ["GB-NIR", "GB-SCT"]},
+                {"date": "2024-01-02", "localName": "2 January", "name": "2 January",
+                 "countryCode": "GB", "global": false, "counties": ["GB-SCT"]},
+                {"date": "2024-03-29", "localName": "Good Friday", "name": "Good Friday",
+                 "countryCode": "GB", "global": true, "counties": null}
+            ]"#,
+        )
+        .unwrap();
+        store(&db, "GB", &holidays).unwrap();
+        db
+    }
+
+    #[test]
+    fn regional_holidays_only_count_in_their_region() {
+        let dates = holiday_dates(&db("GB-SCT"), "2024-01-01", "2024-12-31").unwrap();
+        assert_eq!(dates.len(), 3);
+        let dates = holiday_dates(&db("GB-ENG"), "2024-01-01", "2024-12-31").unwrap();
+        assert_eq!(dates, HashSet::from(["2024-03-29".to_string()]));
+    }
+
+    #[test]
+    fn fetched_years_are_remembered() {
+        let db = db("");
+        let (country, missing) = missing_years(&db, &[2024, 2025]).unwrap().unwrap();
+        assert_eq!(country, "GB");
+        assert_eq!(missing, vec![2025]);
+    }
+}
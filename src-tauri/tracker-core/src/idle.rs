@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use crate::error::Result;
+
+/// How long it's been since the last keyboard or mouse input, so a laptop
+/// left on the office WiFi in a bag can be told apart from one in use.
+pub fn time_since_input() -> Result<Duration> {
+    platform::time_since_input()
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+
+    const COMBINED_SESSION_STATE: i32 = 0;
+    /// `kCGAnyInputEventType`, i.e. every kind of input event.
+    const ANY_INPUT_EVENT: u32 = !0;
+
+    #[link(name = "CoreGraphics", kind = "framework")]
+    extern "C" {
+        fn CGEventSourceSecondsSinceLastEventType(state_id: i32, event_type: u32) -> f64;
+    }
+
+    pub fn time_since_input() -> Result<Duration> {
+        let seconds = unsafe {
+            CGEventSourceSecondsSinceLastEventType(COMBINED_SESSION_STATE, ANY_INPUT_EVENT)
+        };
+        Ok(Duration::from_secs_f64(seconds.max(0.0)))
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::*;
+    use windows_sys::Win32::System::SystemInformation::GetTickCount;
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::{GetLastInputInfo, LASTINPUTINFO};
+
+    pub fn time_since_input() -> Result<Duration> {
+        let mut info = LASTINPUTINFO {
+            cbSize: std::mem::size_of::<LASTINPUTINFO>() as u32,
+            dwTime: 0,
+        };
+        if unsafe { GetLastInputInfo(&mut info) } == 0 {
+            return Err("GetLastInputInfo failed".into());
+        }
+        // Both are milliseconds since boot, which wrap after 49.7 days
+        let idle_ms = unsafe { GetTickCount() }.wrapping_sub(info.dwTime);
+        Ok(Duration::from_millis(idle_ms.into()))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::*;
+    use crate::error::Error;
+    use crate::process::{CommandExt, COMMAND_TIMEOUT};
+    use std::process::Command;
+
+    /// `xprintidle` prints the X session's idle time in milliseconds.
+    pub fn time_since_input() -> Result<Duration> {
+        let output = Command::new("xprintidle")
+            .output_within(COMMAND_TIMEOUT)
+            .map_err(Error::command("xprintidle"))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let idle_ms: u64 = stdout
+            .trim()
+            .parse()
+            .map_err(|_| format!("Unexpected xprintidle output '{}'", stdout.trim()))?;
+        Ok(Duration::from_millis(idle_ms))
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
+mod platform {
+    use super::*;
+
+    pub fn time_since_input() -> Result<Duration> {
+        Err("Idle detection is not supported on this platform".into())
+    }
+}
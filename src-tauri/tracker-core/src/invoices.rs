@@ -0,0 +1,368 @@
+use chrono::{Duration, NaiveDate};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::error::Result;
+use crate::reports;
+use crate::rounding::{Rounding, RoundingMode};
+use crate::settings;
+use crate::timesheet;
+
+/// Laid out line by line in a fixed-width font, like timesheet PDFs.
+pub const TEMPLATE: &str = include_str!("../templates/invoice.txt");
+pub const DEFAULT_NUMBER_PREFIX: &str = "INV-";
+pub const DEFAULT_DUE_DAYS: u32 = 30;
+
+/// Someone to bill, with their own rate or location if they differ from the
+/// defaults.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InvoiceClient {
+    pub name: String,
+    /// Postal address, over as many lines as needed.
+    #[serde(default)]
+    pub address: String,
+    #[serde(default)]
+    pub hourly_rate: Option<f64>,
+    /// Only bill time at this location, e.g. the client's office.
+    #[serde(default)]
+    pub location: Option<String>,
+}
+
+/// Billing tracked time, as configured by the user.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InvoiceSetting {
+    pub hourly_rate: f64,
+    /// ISO 4217 code, e.g. "GBP".
+    pub currency: String,
+    /// Your name and address, at the top of each invoice.
+    #[serde(default)]
+    pub sender: String,
+    /// Applied to each day's time before it's billed.
+    #[serde(default)]
+    pub rounding: Option<Rounding>,
+    #[serde(default = "default_number_prefix")]
+    pub number_prefix: String,
+    #[serde(default = "default_due_days")]
+    pub due_days: u32,
+    #[serde(default)]
+    pub clients: Vec<InvoiceClient>,
+}
+
+fn default_number_prefix() -> String {
+    DEFAULT_NUMBER_PREFIX.to_string()
+}
+
+fn default_due_days() -> u32 {
+    DEFAULT_DUE_DAYS
+}
+
+fn valid_rate(rate: f64) -> bool {
+    rate.is_finite() && rate > 0.0
+}
+
+impl InvoiceSetting {
+    pub fn validate(&self) -> Result<(), String> {
+        if !valid_rate(self.hourly_rate) {
+            return Err("The hourly rate must be more than zero".to_string());
+        }
+        if self.currency.len() != 3 || !self.currency.chars().all(|c| c.is_ascii_uppercase()) {
+            return Err(format!(
+                "Invalid currency '{}', expected a code like GBP",
+                self.currency
+            ));
+        }
+        if let Some(rounding) = &self.rounding {
+            rounding.validate()?;
+        }
+        for (index, client) in self.clients.iter().enumerate() {
+            if client.name.trim().is_empty() {
+                return Err("Invoice clients need a name".to_string());
+            }
+            if self.clients[..index]
+                .iter()
+                .any(|other| other.name.eq_ignore_ascii_case(&client.name))
+            {
+                return Err(format!(
+                    "There's more than one client named '{}'",
+                    client.name
+                ));
+            }
+            if client.hourly_rate.is_some_and(|rate| !valid_rate(rate)) {
+                return Err(format!(
+                    "The hourly rate for '{}' must be more than zero",
+                    client.name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The configured client called `name`, or one with just that name and
+    /// the defaults.
+    fn client(&self, name: &str) -> InvoiceClient {
+        self.clients
+            .iter()
+            .find(|client| client.name.eq_ignore_ascii_case(name))
+            .cloned()
+            .unwrap_or_else(|| InvoiceClient {
+                name: name.to_string(),
+                address: String::new(),
+                hourly_rate: None,
+                location: None,
+            })
+    }
+}
+
+/// Numbers every invoice generated, so they run on without gaps or reuse.
+pub fn create_table(db: &Connection) -> rusqlite::Result<()> {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS invoices (
+            number INTEGER PRIMARY KEY AUTOINCREMENT,
+            client TEXT NOT NULL,
+            start_date TEXT NOT NULL,
+            end_date TEXT NOT NULL,
+            issued TEXT NOT NULL,
+            minutes INTEGER NOT NULL,
+            amount_cents INTEGER NOT NULL,
+            currency TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Invoicing settings, if they've been set up.
+pub fn load(db: &Connection) -> Result<Option<InvoiceSetting>> {
+    Ok(settings::get(db, settings::INVOICING)?.and_then(|json| serde_json::from_str(&json).ok()))
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct InvoiceLine {
+    pub date: String,
+    /// After rounding.
+    pub minutes: i64,
+    pub amount: String,
+}
+
+/// What the invoice template is rendered with. Amounts are formatted with
+/// two decimal places.
+#[derive(Clone, Debug, Serialize)]
+pub struct Invoice {
+    pub number: String,
+    pub sender: String,
+    pub client: String,
+    pub client_address: String,
+    pub from: String,
+    pub to: String,
+    pub issued: String,
+    pub due: String,
+    pub currency: String,
+    pub hourly_rate: String,
+    /// e.g. "up to 15 minutes".
+    pub rounding: Option<String>,
+    pub lines: Vec<InvoiceLine>,
+    pub total_minutes: i64,
+    pub total: String,
+    #[serde(skip)]
+    pub total_cents: i64,
+}
+
+fn format_cents(cents: i64) -> String {
+    format!("{}.{:02}", cents / 100, cents % 100)
+}
+
+fn describe(rounding: &Rounding) -> String {
+    let direction = match rounding.mode {
+        RoundingMode::Nearest => "to the nearest",
+        RoundingMode::Up => "up to",
+        RoundingMode::Down => "down to",
+    };
+    format!("{} {} minutes", direction, rounding.minutes)
+}
+
+/// The invoice for `client` from `from` to `to`, numbered `number`, with a
+/// line per day anything was tracked.
+fn build(
+    db: &Connection,
+    config: &InvoiceSetting,
+    client: &InvoiceClient,
+    from: NaiveDate,
+    to: NaiveDate,
+    issued: NaiveDate,
+    number: i64,
+) -> Result<Invoice> {
+    let rate = client.hourly_rate.unwrap_or(config.hourly_rate);
+    let cents = |minutes: i64| (rate * minutes as f64 / 60.0 * 100.0).round() as i64;
+    let days = reports::day_totals(
+        db,
+        &from.to_string(),
+        &to.to_string(),
+        client.location.as_deref(),
+    )?;
+
+    let mut lines = Vec::new();
+    let (mut total_minutes, mut total_cents) = (0, 0);
+    for day in days {
+        let minutes = match &config.rounding {
+            Some(rounding) => rounding.apply(day.total_minutes),
+            None => day.total_minutes,
+        };
+        if minutes == 0 {
+            continue;
+        }
+        let amount = cents(minutes);
+        total_minutes += minutes;
+        total_cents += amount;
+        lines.push(InvoiceLine {
+            date: day.date,
+            minutes,
+            amount: format_cents(amount),
+        });
+    }
+    Ok(Invoice {
+        number: format!("{}{:04}", config.number_prefix, number),
+        sender: config.sender.clone(),
+        client: client.name.clone(),
+        client_address: client.address.clone(),
+        from: from.to_string(),
+        to: to.to_string(),
+        issued: issued.to_string(),
+        due: (issued + Duration::days(config.due_days.into())).to_string(),
+        currency: config.currency.clone(),
+        hourly_rate: format!("{:.2}", rate),
+        rounding: config.rounding.as_ref().map(describe),
+        lines,
+        total_minutes,
+        total: format_cents(total_cents),
+        total_cents,
+    })
+}
+
+/// Bills `client` for the time tracked from `from` to `to`, writing the next
+/// numbered invoice to `path` as a PDF. The number is only used up if the
+/// PDF is written.
+pub fn generate(
+    db: &Connection,
+    from: NaiveDate,
+    to: NaiveDate,
+    client: &str,
+    issued: NaiveDate,
+    path: &Path,
+) -> Result<Invoice> {
+    if from > to {
+        return Err(format!("{} is after {}", from, to).into());
+    }
+    if client.trim().is_empty() {
+        return Err("Invoices need a client".into());
+    }
+    let config = load(db)?.ok_or("Set up invoicing in settings first")?;
+    let client = config.client(client.trim());
+
+    let tx = db.unchecked_transaction()?;
+    // Reserve the number with placeholder totals, filled in once built
+    tx.execute(
+        "INSERT INTO invoices (client, start_date, end_date, issued, minutes, amount_cents, currency)
+         VALUES (?1, ?2, ?3, ?4, 0, 0, ?5)",
+        params![
+            client.name,
+            from.to_string(),
+            to.to_string(),
+            issued.to_string(),
+            config.currency
+        ],
+    )?;
+    let number = tx.last_insert_rowid();
+    let invoice = build(&tx, &config, &client, from, to, issued, number)?;
+    if invoice.lines.is_empty() {
+        return Err(format!("No time was tracked from {} to {}", from, to).into());
+    }
+    tx.execute(
+        "UPDATE invoices SET minutes = ?1, amount_cents = ?2 WHERE number = ?3",
+        params![invoice.total_minutes, invoice.total_cents, number],
+    )?;
+
+    let text = timesheet::render(&invoice, TEMPLATE, false)?;
+    timesheet::write_pdf(&text, &format!("Invoice {}", invoice.number), path)?;
+    tx.commit()?;
+    Ok(invoice)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn config() -> InvoiceSetting {
+        InvoiceSetting {
+            hourly_rate: 60.0,
+            currency: "GBP".to_string(),
+            sender: "Jo Bloggs".to_string(),
+            rounding: Some(Rounding {
+                minutes: 15,
+                mode: RoundingMode::Up,
+            }),
+            number_prefix: default_number_prefix(),
+            due_days: default_due_days(),
+            clients: vec![InvoiceClient {
+                name: "Acme".to_string(),
+                address: "1 High Street".to_string(),
+                hourly_rate: Some(90.0),
+                location: None,
+            }],
+        }
+    }
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn days_are_rounded_then_billed() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        db.execute_batch(
+            "INSERT INTO connections (date, earliest, latest) VALUES ('2024-03-04', '09:00', '17:00');
+             INSERT INTO sessions (date, start, end, start_at, end_at, source)
+                VALUES ('2024-03-04', '09:00', '09:50', 0, 3000, 'manual');",
+        )
+        .unwrap();
+
+        let config = config();
+        let invoice = build(
+            &db,
+            &config,
+            &config.client("acme"),
+            date("2024-03-01"),
+            date("2024-03-31"),
+            date("2024-04-01"),
+            7,
+        )
+        .unwrap();
+        assert_eq!(invoice.number, "INV-0007");
+        assert_eq!(invoice.client_address, "1 High Street");
+        assert_eq!(invoice.due, "2024-05-01");
+        // 50 minutes rounds up to an hour at the client's rate
+        assert_eq!(invoice.total_minutes, 60);
+        assert_eq!(invoice.total, "90.00");
+
+        let text = timesheet::render(&invoice, TEMPLATE, false).unwrap();
+        assert!(text.contains("INVOICE INV-0007"));
+        assert!(text.contains("each day's time rounded up to 15 minutes"));
+    }
+
+    #[test]
+    fn rates_and_currencies_are_validated() {
+        assert!(config().validate().is_ok());
+        let free = InvoiceSetting {
+            hourly_rate: 0.0,
+            ..config()
+        };
+        assert!(free.validate().is_err());
+        let lowercase = InvoiceSetting {
+            currency: "gbp".to_string(),
+            ..config()
+        };
+        assert!(lowercase.validate().is_err());
+    }
+}
@@ -0,0 +1,34 @@
+use crate::error::Result;
+
+/// Matches the bundle identifier in tauri.conf.json.
+const SERVICE: &str = "dev.britannio.foundership-time-tracker";
+
+/// Reads the secret stored under `account`, e.g. an API token, from the OS
+/// keychain (Keychain on macOS, Credential Manager on Windows, the Secret
+/// Service on Linux) rather than the settings table.
+pub fn get(account: &str) -> Result<Option<String>> {
+    match entry(account)?.get_password() {
+        Ok(secret) => Ok(Some(secret)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(format!("Couldn't read {} from the keychain: {}", account, e).into()),
+    }
+}
+
+pub fn set(account: &str, secret: &str) -> Result<()> {
+    entry(account)?
+        .set_password(secret)
+        .map_err(|e| format!("Couldn't save {} to the keychain: {}", account, e).into())
+}
+
+/// Removes the secret under `account`, if there is one.
+pub fn delete(account: &str) -> Result<()> {
+    match entry(account)?.delete_password() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(format!("Couldn't remove {} from the keychain: {}", account, e).into()),
+    }
+}
+
+fn entry(account: &str) -> Result<keyring::Entry> {
+    keyring::Entry::new(SERVICE, account)
+        .map_err(|e| format!("Couldn't open the keychain: {}", e).into())
+}
@@ -0,0 +1,53 @@
+//! Everything the time tracker does that doesn't need a window: detecting
+//! presence, storing it, and reporting on it. The Tauri app and anything
+//! else that tracks time are thin layers over this crate.
+
+pub mod anomalies;
+pub mod app_lock;
+pub mod audit;
+pub mod backup;
+pub mod bluetooth;
+pub mod clock;
+pub mod connections;
+pub mod daily_notes;
+pub mod database;
+pub mod day_types;
+pub mod demo;
+pub mod detector;
+pub mod diagnostics;
+pub mod digest;
+pub mod edits;
+pub mod error;
+pub mod export;
+pub mod flexitime;
+pub mod folder_sync;
+pub mod google_calendar;
+pub mod heartbeat;
+pub mod holidays;
+pub mod idle;
+pub mod invoices;
+pub mod keychain;
+pub mod location;
+pub mod lunch;
+pub mod migrations;
+pub mod network;
+pub mod pattern;
+pub mod pool;
+pub mod process;
+pub mod publishing;
+pub mod reports;
+pub mod retention;
+pub mod rounding;
+pub mod rules;
+pub mod server_sync;
+pub mod sessions;
+pub mod settings;
+pub mod slack;
+pub mod team;
+pub mod time_sync;
+pub mod timesheet;
+pub mod tracking;
+pub mod webhooks;
+pub mod wifi;
+
+pub use error::{Error, Result};
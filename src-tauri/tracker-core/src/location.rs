@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use crate::error::Result;
+
+/// Fixes older than this are ignored, e.g. the last one from before the
+/// machine went to sleep.
+pub const MAX_FIX_AGE: Duration = Duration::from_secs(10 * 60);
+
+/// A point on the Earth in degrees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Where the OS last placed the machine.
+#[derive(Clone, Copy, Debug)]
+pub struct Fix {
+    pub coordinates: Coordinates,
+    /// Radius in metres the true position is probably within.
+    pub accuracy_m: f64,
+    pub age: Duration,
+}
+
+impl Coordinates {
+    /// Great-circle distance in metres, by the haversine formula.
+    pub fn distance_m(self, other: Coordinates) -> f64 {
+        const EARTH_RADIUS_M: f64 = 6_371_000.0;
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (other.longitude - self.longitude).to_radians();
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+    }
+}
+
+/// Starts location updates so that `current_fix` has something to report.
+/// Needs the main thread on macOS; calling it again does nothing.
+pub fn start() {
+    platform::start()
+}
+
+/// The most recent fix, or `None` before the first one arrives.
+pub fn current_fix() -> Result<Option<Fix>> {
+    platform::current_fix()
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+    use objc2_core_location::CLLocationManager;
+    use std::sync::Once;
+
+    static STARTED: Once = Once::new();
+
+    pub fn start() {
+        STARTED.call_once(|| unsafe {
+            let manager = CLLocationManager::new();
+            manager.requestWhenInUseAuthorization();
+            manager.startUpdatingLocation();
+            // Updates stop when the manager is deallocated, so keep it for
+            // the life of the process
+            std::mem::forget(manager);
+        });
+    }
+
+    /// Any manager reports the latest fix delivered to the app, so a fresh
+    /// one can be used from the tracking thread.
+    pub fn current_fix() -> Result<Option<Fix>> {
+        unsafe {
+            let Some(location) = CLLocationManager::new().location() else {
+                return Ok(None);
+            };
+            let accuracy_m = location.horizontalAccuracy();
+            // Negative accuracy means the coordinates are invalid
+            if accuracy_m < 0.0 {
+                return Ok(None);
+            }
+            let coordinate = location.coordinate();
+            Ok(Some(Fix {
+                coordinates: Coordinates {
+                    latitude: coordinate.latitude,
+                    longitude: coordinate.longitude,
+                },
+                accuracy_m,
+                age: Duration::from_secs_f64(-location.timestamp().timeIntervalSinceNow().min(0.0)),
+            }))
+        }
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+mod platform {
+    use super::*;
+
+    pub fn start() {}
+
+    pub fn current_fix() -> Result<Option<Fix>> {
+        Err("Location detection is not supported on this platform".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_distance_between_coordinates() {
+        let london = Coordinates {
+            latitude: 51.5074,
+            longitude: -0.1278,
+        };
+        let paris = Coordinates {
+            latitude: 48.8566,
+            longitude: 2.3522,
+        };
+        let distance = london.distance_m(paris);
+        assert!((distance - 343_500.0).abs() < 2_000.0, "{}", distance);
+        assert_eq!(london.distance_m(london), 0.0);
+    }
+}
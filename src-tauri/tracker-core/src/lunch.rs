@@ -0,0 +1,167 @@
+use chrono::{Local, NaiveDateTime, NaiveTime, TimeZone};
+use rusqlite::{params, Connection, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::edits;
+use crate::reports::format_minutes;
+use crate::sessions::{location_sql, MINUTES_SQL};
+use crate::settings;
+
+/// Which gaps between sessions count as a lunch break rather than time away.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LunchBreak {
+    /// The part of the day lunch falls in, as HH:MM. A gap counts if any of
+    /// it is within these.
+    pub window_start: String,
+    pub window_end: String,
+    pub min_minutes: i64,
+    pub max_minutes: i64,
+}
+
+impl Default for LunchBreak {
+    fn default() -> Self {
+        Self {
+            window_start: "11:30".to_string(),
+            window_end: "14:30".to_string(),
+            min_minutes: 30,
+            max_minutes: 90,
+        }
+    }
+}
+
+impl LunchBreak {
+    pub fn validate(&self) -> Result<(), String> {
+        let (start, end) = self.window()?;
+        if start >= end {
+            return Err("The lunch window must end after it starts".to_string());
+        }
+        if self.min_minutes < 1 {
+            return Err("Lunch must last at least a minute".to_string());
+        }
+        if self.max_minutes < self.min_minutes {
+            return Err("The longest lunch can't be shorter than the shortest".to_string());
+        }
+        Ok(())
+    }
+
+    fn window(&self) -> Result<(NaiveTime, NaiveTime), String> {
+        Ok((
+            edits::parse_time(&self.window_start)?,
+            edits::parse_time(&self.window_end)?,
+        ))
+    }
+
+    /// Minutes of the first gap between `sessions`, given as local start and
+    /// end times in order, that's long enough, short enough and around
+    /// midday to be lunch.
+    pub fn find(&self, sessions: &[(NaiveDateTime, NaiveDateTime)]) -> Option<i64> {
+        let (window_start, window_end) = self.window().ok()?;
+        let mut left: Option<NaiveDateTime> = None;
+        for &(start, end) in sessions {
+            if let Some(left) = left.filter(|&left| left < start) {
+                let minutes = (start - left).num_minutes();
+                if (self.min_minutes..=self.max_minutes).contains(&minutes)
+                    && left.date() == start.date()
+                    && left.time() < window_end
+                    && start.time() > window_start
+                {
+                    return Some(minutes);
+                }
+            }
+            // Sessions can overlap, e.g. a manual one over tracked time
+            left = Some(left.map_or(end, |left| left.max(end)));
+        }
+        None
+    }
+}
+
+/// The configured lunch break. On by default; saving `None` turns it off.
+pub fn load(db: &Connection) -> Result<Option<LunchBreak>> {
+    Ok(match settings::get(db, settings::LUNCH_BREAK)? {
+        Some(json) => serde_json::from_str(&json).ok().flatten(),
+        None => Some(LunchBreak::default()),
+    })
+}
+
+/// Minutes of lunch on `date`, from the gaps between sessions at `location`
+/// (or anywhere), or `None` if there wasn't one or detection is off.
+/// Sessions too short to count are skipped, so a blip of presence while out
+/// doesn't split the break.
+pub fn minutes_on(
+    db: &Connection,
+    lunch: &LunchBreak,
+    date: &str,
+    location: Option<&str>,
+) -> Result<Option<i64>> {
+    let mut stmt = db.prepare(&format!(
+        "SELECT s.start_at, s.end_at FROM sessions s
+         WHERE s.date = ?1 AND {} AND {} > 0
+         ORDER BY s.start_at",
+        location_sql("?2"),
+        MINUTES_SQL
+    ))?;
+    let local = |epoch: i64| {
+        Local
+            .timestamp_opt(epoch, 0)
+            .earliest()
+            .map(|at| at.naive_local())
+    };
+    let mut sessions = Vec::new();
+    for session in stmt.query_map(params![date, location], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))
+    })? {
+        let (start, end) = session?;
+        if let (Some(start), Some(end)) = (local(start), local(end)) {
+            sessions.push((start, end));
+        }
+    }
+    Ok(lunch.find(&sessions))
+}
+
+/// Formats time worked with any lunch after it, e.g. "8h 07m + 45m lunch".
+pub fn format_with_lunch(total_minutes: i64, lunch_minutes: Option<i64>) -> String {
+    let total = format_minutes(total_minutes);
+    match lunch_minutes {
+        Some(lunch) if lunch < 60 => format!("{} + {}m lunch", total, lunch),
+        Some(lunch) => format!("{} + {} lunch", total, format_minutes(lunch)),
+        None => total,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn finds_midday_gaps_of_lunch_length() {
+        let at = |time: &str| {
+            NaiveDate::from_ymd_opt(2024, 3, 4)
+                .unwrap()
+                .and_time(NaiveTime::parse_from_str(time, "%H:%M").unwrap())
+        };
+        let lunch = LunchBreak::default();
+        let day = [(at("09:00"), at("12:15")), (at("13:00"), at("17:30"))];
+        assert_eq!(lunch.find(&day), Some(45));
+        // Too long, and too early
+        assert_eq!(
+            lunch.find(&[(at("09:00"), at("11:00")), (at("13:00"), at("17:00"))]),
+            None
+        );
+        assert_eq!(
+            lunch.find(&[(at("08:00"), at("09:00")), (at("09:45"), at("11:00"))]),
+            None
+        );
+        // Overlapping sessions leave no gap
+        assert_eq!(
+            lunch.find(&[
+                (at("09:00"), at("15:00")),
+                (at("12:00"), at("12:30")),
+                (at("13:00"), at("17:00")),
+            ]),
+            None
+        );
+        assert_eq!(format_with_lunch(487, Some(45)), "8h 07m + 45m lunch");
+        assert_eq!(format_with_lunch(487, None), "8h 07m");
+    }
+}
@@ -0,0 +1,382 @@
+use rusqlite::{Connection, Result, Transaction};
+use tracing::info;
+
+use crate::{
+    audit, day_types, folder_sync, google_calendar, heartbeat, holidays, invoices, sessions,
+    settings, time_sync,
+};
+
+type Migration = fn(&Transaction) -> Result<()>;
+
+/// Schema changes in the order they were made. The database's `user_version`
+/// pragma records how many have been applied, so only append to this list.
+///
+/// Databases created before migrations existed are at version 0 but may
+/// already have some of these changes, so the early migrations tolerate
+/// tables and columns that already exist.
+const MIGRATIONS: &[Migration] = &[
+    create_connections,
+    add_network_columns,
+    create_sessions,
+    add_session_source,
+    add_day_note,
+    create_day_types,
+    create_settings,
+    add_timestamps,
+    add_session_date_index,
+    add_session_rule,
+    add_session_idle_seconds,
+    create_heartbeat,
+    create_synced_sessions,
+    create_calendar_events,
+    create_invoices,
+    create_public_holidays,
+    create_sync_records,
+    add_sync_updated_at,
+    create_audit_log,
+    add_audit_changes,
+    add_session_active_seconds,
+];
+
+/// Brings the database up to the latest schema, applying each outstanding
+/// migration in its own transaction.
+pub fn run(db: &mut Connection) -> Result<(), String> {
+    let version = user_version(db).map_err(|e| e.to_string())?;
+    if version > MIGRATIONS.len() {
+        return Err(format!(
+            "Database schema version {} is newer than this app supports ({})",
+            version,
+            MIGRATIONS.len()
+        ));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = db.transaction().map_err(|e| e.to_string())?;
+        migration(&tx).map_err(|e| format!("Migration {} failed: {}", index + 1, e))?;
+        tx.pragma_update(None, "user_version", index + 1)
+            .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+        info!("Applied database migration {}", index + 1);
+    }
+    Ok(())
+}
+
+fn user_version(db: &Connection) -> Result<usize> {
+    db.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+fn create_connections(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS connections (
+            date TEXT PRIMARY KEY,
+            earliest TEXT NOT NULL,
+            latest TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn add_network_columns(tx: &Transaction) -> Result<()> {
+    add_column_if_missing(tx, "connections", "ssid", "TEXT")?;
+    add_column_if_missing(tx, "connections", "label", "TEXT")
+}
+
+fn create_sessions(tx: &Transaction) -> Result<()> {
+    sessions::create_table(tx)
+}
+
+fn add_session_source(tx: &Transaction) -> Result<()> {
+    add_column_if_missing(tx, "sessions", "source", "TEXT NOT NULL DEFAULT 'auto'")
+}
+
+fn add_day_note(tx: &Transaction) -> Result<()> {
+    add_column_if_missing(tx, "connections", "note", "TEXT")
+}
+
+fn create_day_types(tx: &Transaction) -> Result<()> {
+    day_types::create_table(tx)
+}
+
+fn create_settings(tx: &Transaction) -> Result<()> {
+    settings::create_table(tx)
+}
+
+/// Adds UTC timestamps and fills them in for existing rows, reading the
+/// stored local times in the current time zone.
+fn add_timestamps(tx: &Transaction) -> Result<()> {
+    add_column_if_missing(tx, "connections", "earliest_at", "INTEGER")?;
+    add_column_if_missing(tx, "connections", "latest_at", "INTEGER")?;
+    add_column_if_missing(tx, "sessions", "start_at", "INTEGER")?;
+    add_column_if_missing(tx, "sessions", "end_at", "INTEGER")?;
+    tx.execute(
+        &format!(
+            "UPDATE connections SET earliest_at = {}, latest_at = {} WHERE earliest_at IS NULL",
+            sessions::local_epoch_sql("date", "earliest"),
+            sessions::local_end_epoch_sql("date", "earliest", "latest")
+        ),
+        [],
+    )?;
+    tx.execute(
+        &format!(
+            "UPDATE sessions SET start_at = {}, end_at = {} WHERE start_at IS NULL",
+            sessions::local_epoch_sql("date", "start"),
+            sessions::local_end_epoch_sql("date", "start", "end")
+        ),
+        [],
+    )?;
+    Ok(())
+}
+
+/// Sessions are almost always looked up by day or range of days, ordered by
+/// start time.
+fn add_session_date_index(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS sessions_date ON sessions (date, start_at)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn add_session_rule(tx: &Transaction) -> Result<()> {
+    add_column_if_missing(tx, "sessions", "rule", "TEXT")
+}
+
+fn add_session_idle_seconds(tx: &Transaction) -> Result<()> {
+    add_column_if_missing(tx, "sessions", "idle_seconds", "INTEGER NOT NULL DEFAULT 0")
+}
+
+fn create_heartbeat(tx: &Transaction) -> Result<()> {
+    heartbeat::create_table(tx)
+}
+
+fn create_synced_sessions(tx: &Transaction) -> Result<()> {
+    time_sync::create_table(tx)
+}
+
+fn create_calendar_events(tx: &Transaction) -> Result<()> {
+    google_calendar::create_table(tx)
+}
+
+fn create_invoices(tx: &Transaction) -> Result<()> {
+    invoices::create_table(tx)
+}
+
+fn create_public_holidays(tx: &Transaction) -> Result<()> {
+    holidays::create_table(tx)
+}
+
+fn create_sync_records(tx: &Transaction) -> Result<()> {
+    folder_sync::create_tables(tx)
+}
+
+fn add_sync_updated_at(tx: &Transaction) -> Result<()> {
+    add_column_if_missing(
+        tx,
+        "sync_records",
+        "updated_at",
+        "INTEGER NOT NULL DEFAULT 0",
+    )
+}
+
+fn create_audit_log(tx: &Transaction) -> Result<()> {
+    audit::create_tables(tx)
+}
+
+/// Groups audit entries into changes that can be undone, recreating the
+/// triggers to fill the new columns in.
+fn add_audit_changes(tx: &Transaction) -> Result<()> {
+    add_column_if_missing(tx, "audit_log", "change_id", "INTEGER")?;
+    add_column_if_missing(tx, "audit_source", "change_id", "INTEGER")?;
+    audit::create_tables(tx)
+}
+
+fn add_session_active_seconds(tx: &Transaction) -> Result<()> {
+    add_column_if_missing(tx, "sessions", "active_seconds", "INTEGER")?;
+    // The audit triggers log every column
+    audit::create_tables(tx)
+}
+
+fn add_column_if_missing(db: &Connection, table: &str, column: &str, decl: &str) -> Result<()> {
+    let exists = db
+        .prepare(&format!(
+            "SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1",
+            table
+        ))?
+        .exists([column])?;
+    if !exists {
+        db.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Local, NaiveDate, TimeZone};
+
+    fn columns(db: &Connection, table: &str) -> Vec<String> {
+        let mut stmt = db
+            .prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap()
+    }
+
+    fn local_epoch(date: &str, time: &str) -> i64 {
+        let naive = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .unwrap()
+            .and_time(chrono::NaiveTime::parse_from_str(time, "%H:%M").unwrap());
+        Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .unwrap()
+            .timestamp()
+    }
+
+    #[test]
+    fn creates_latest_schema_from_scratch() {
+        let mut db = Connection::open_in_memory().unwrap();
+        run(&mut db).unwrap();
+
+        assert_eq!(user_version(&db).unwrap(), MIGRATIONS.len());
+        let connection_columns = columns(&db, "connections");
+        for column in ["ssid", "label", "note", "earliest_at", "latest_at"] {
+            assert!(
+                connection_columns.contains(&column.to_string()),
+                "{}",
+                column
+            );
+        }
+        let session_columns = columns(&db, "sessions");
+        for column in [
+            "source",
+            "start_at",
+            "end_at",
+            "rule",
+            "idle_seconds",
+            "active_seconds",
+        ] {
+            assert!(session_columns.contains(&column.to_string()), "{}", column);
+        }
+        assert!(!columns(&db, "day_types").is_empty());
+        assert!(!columns(&db, "settings").is_empty());
+        assert!(!columns(&db, "heartbeat").is_empty());
+        assert!(!columns(&db, "synced_sessions").is_empty());
+        assert!(!columns(&db, "calendar_events").is_empty());
+        assert!(!columns(&db, "invoices").is_empty());
+        assert!(!columns(&db, "public_holidays").is_empty());
+        assert!(columns(&db, "sync_records").contains(&"updated_at".to_string()));
+        assert!(!columns(&db, "sync_files").is_empty());
+        assert!(columns(&db, "audit_log").contains(&"change_id".to_string()));
+        assert!(!columns(&db, "audit_changes").is_empty());
+        let index: String = db
+            .query_row(
+                "SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'sessions'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(index, "sessions_date");
+    }
+
+    #[test]
+    fn upgrades_original_schema_keeping_data() {
+        let mut db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE connections (
+                date TEXT PRIMARY KEY,
+                earliest TEXT NOT NULL,
+                latest TEXT NOT NULL
+            );
+            INSERT INTO connections VALUES ('2024-03-04', '09:15', '17:40');",
+        )
+        .unwrap();
+
+        run(&mut db).unwrap();
+
+        let (earliest, latest, earliest_at, latest_at): (String, String, i64, i64) = db
+            .query_row(
+                "SELECT earliest, latest, earliest_at, latest_at FROM connections
+                 WHERE date = '2024-03-04'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!((earliest.as_str(), latest.as_str()), ("09:15", "17:40"));
+        assert_eq!(earliest_at, local_epoch("2024-03-04", "09:15"));
+        assert_eq!(latest_at, local_epoch("2024-03-04", "17:40"));
+    }
+
+    #[test]
+    fn upgrades_pre_migration_database_with_sessions() {
+        let mut db = Connection::open_in_memory().unwrap();
+        db.execute_batch(
+            "CREATE TABLE connections (
+                date TEXT PRIMARY KEY,
+                earliest TEXT NOT NULL,
+                latest TEXT NOT NULL,
+                ssid TEXT,
+                label TEXT
+            );
+            CREATE TABLE sessions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                date TEXT NOT NULL,
+                start TEXT NOT NULL,
+                end TEXT NOT NULL,
+                ssid TEXT,
+                label TEXT
+            );
+            INSERT INTO connections VALUES ('2024-03-04', '22:00', '01:30', 'eduroam', NULL);
+            INSERT INTO sessions (date, start, end, ssid)
+                VALUES ('2024-03-04', '22:00', '01:30', 'eduroam');",
+        )
+        .unwrap();
+
+        run(&mut db).unwrap();
+
+        let (source, start_at, end_at): (String, i64, i64) = db
+            .query_row("SELECT source, start_at, end_at FROM sessions", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+        assert_eq!(source, sessions::SOURCE_AUTO);
+        assert_eq!(start_at, local_epoch("2024-03-04", "22:00"));
+        // Ran past midnight
+        assert_eq!(end_at, local_epoch("2024-03-05", "01:30"));
+    }
+
+    #[test]
+    fn running_again_changes_nothing() {
+        let mut db = Connection::open_in_memory().unwrap();
+        run(&mut db).unwrap();
+        db.execute(
+            "INSERT INTO connections (date, earliest, latest, earliest_at, latest_at)
+             VALUES ('2024-03-04', '09:00', '17:00', 1, 2)",
+            [],
+        )
+        .unwrap();
+
+        run(&mut db).unwrap();
+
+        assert_eq!(user_version(&db).unwrap(), MIGRATIONS.len());
+        let earliest_at: i64 = db
+            .query_row("SELECT earliest_at FROM connections", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(earliest_at, 1);
+    }
+
+    #[test]
+    fn rejects_newer_schema() {
+        let mut db = Connection::open_in_memory().unwrap();
+        db.pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+
+        assert!(run(&mut db).is_err());
+    }
+}
@@ -0,0 +1,504 @@
+use std::net::{IpAddr, Ipv6Addr, TcpStream, ToSocketAddrs};
+use std::process::Command;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Result};
+use crate::process::{CommandExt, COMMAND_TIMEOUT};
+use crate::wifi::normalize_mac;
+
+/// The router traffic leaves through.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Gateway {
+    pub ip: IpAddr,
+    /// Interface the default route goes out of, e.g. "en0" or "eth0".
+    pub interface: Option<String>,
+}
+
+/// An address assigned to one of the machine's interfaces.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceAddress {
+    pub interface: String,
+    pub ip: IpAddr,
+}
+
+/// A block of addresses such as `10.20.0.0/16`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Parses `address/prefix`; a bare address matches only itself.
+    pub fn parse(cidr: &str) -> Result<Self, String> {
+        let (address, prefix) = match cidr.trim().split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (cidr.trim(), None),
+        };
+        let network: IpAddr = address
+            .parse()
+            .map_err(|_| format!("Invalid IP address '{}'", address))?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|len| *len <= max_len)
+                .ok_or_else(|| format!("Invalid prefix length in '{}'", cidr))?,
+            None => max_len,
+        };
+        Ok(Self {
+            network,
+            prefix_len,
+        })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Checks `mac` is six colon- or dash-separated pairs of hex digits.
+pub fn validate_mac(mac: &str) -> Result<(), String> {
+    let parts: Vec<&str> = mac.trim().split([':', '-']).collect();
+    let valid = parts.len() == 6
+        && parts
+            .iter()
+            .all(|part| part.len() == 2 && u8::from_str_radix(part, 16).is_ok());
+    if !valid {
+        return Err(format!("Invalid MAC address '{}'", mac));
+    }
+    Ok(())
+}
+
+/// Checks the start of a MAC address, e.g. "00:1a:2b" for a vendor's
+/// access points: one to six hex octets.
+pub fn validate_mac_prefix(prefix: &str) -> Result<(), String> {
+    let parts: Vec<&str> = prefix.trim().split([':', '-']).collect();
+    let valid = parts.len() <= 6
+        && parts
+            .iter()
+            .all(|part| part.len() == 2 && u8::from_str_radix(part, 16).is_ok());
+    if !valid {
+        return Err(format!("Invalid access point MAC prefix '{}'", prefix));
+    }
+    Ok(())
+}
+
+/// The default IPv4 gateway, or `None` when there's no default route.
+pub fn default_gateway() -> Result<Option<Gateway>> {
+    platform::default_gateway()
+}
+
+/// The MAC address the machine has cached for `ip`, lowercase and
+/// colon-separated. Routers are always in the cache while they're in use.
+pub fn neighbor_mac(ip: IpAddr) -> Result<Option<String>> {
+    platform::neighbor_mac(ip)
+}
+
+/// Every address assigned to an interface, loopback included.
+pub fn interface_addresses() -> Result<Vec<InterfaceAddress>> {
+    platform::interface_addresses()
+}
+
+/// The interface carrying a VPN tunnel, if one is up. macOS keeps a few
+/// `utun` interfaces around for system services, but those only ever have
+/// link-local addresses, so only tunnels with a routable address count.
+pub fn active_vpn() -> Result<Option<String>> {
+    Ok(interface_addresses()?
+        .into_iter()
+        .find(|address| is_vpn_interface(&address.interface) && is_routable(address.ip))
+        .map(|address| address.interface))
+}
+
+/// Recognises tunnels by name: `utun3` on macOS, `tun0`/`wg0`/`ppp0` on
+/// Linux, and adapters named after the VPN client on Windows.
+fn is_vpn_interface(name: &str) -> bool {
+    let name = name.to_lowercase();
+    ["utun", "tun", "tap", "ppp", "wg", "ipsec"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+        || ["vpn", "wireguard"].iter().any(|word| name.contains(word))
+}
+
+fn is_routable(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => !ip.is_loopback() && !ip.is_link_local(),
+        // fe80::/10
+        IpAddr::V6(ip) => !ip.is_loopback() && ip.segments()[0] & 0xffc0 != 0xfe80,
+    }
+}
+
+/// Whether a TCP connection to `address` ("host:port") can be opened within
+/// `timeout`, name lookup included. Lookups can't be cancelled, so they run
+/// on their own thread and a slow one is abandoned rather than waited for.
+pub fn can_connect(address: &str, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    let (tx, rx) = mpsc::channel();
+    let address = address.to_string();
+    thread::spawn(move || {
+        let connected = address.to_socket_addrs().is_ok_and(|mut addrs| {
+            addrs.any(|addr| {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                !remaining.is_zero() && TcpStream::connect_timeout(&addr, remaining).is_ok()
+            })
+        });
+        let _ = tx.send(connected);
+    });
+    rx.recv_timeout(timeout).unwrap_or(false)
+}
+
+/// Splits "host:port", accepting bracketed IPv6 hosts like "[fd00::5]:443".
+pub fn parse_host_port(address: &str) -> Result<(&str, u16), String> {
+    let invalid = || format!("Expected host:port, got '{}'", address);
+    let (host, port) = address.trim().rsplit_once(':').ok_or_else(invalid)?;
+    let host = match host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+    {
+        Some(host) => host
+            .parse::<Ipv6Addr>()
+            .map(|_| host)
+            .map_err(|_| invalid())?,
+        None if host.is_empty() || host.contains(':') => return Err(invalid()),
+        None => host,
+    };
+    Ok((host, port.parse().map_err(|_| invalid())?))
+}
+
+fn run(command: &'static str, args: &[&str]) -> Result<String> {
+    let output = Command::new(command)
+        .args(args)
+        .output_within(COMMAND_TIMEOUT)
+        .map_err(Error::command(command))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Picks the first thing that looks like a MAC address out of a line of ARP
+/// output. Incomplete entries show as "(incomplete)" and are skipped.
+fn find_mac(line: &str) -> Option<String> {
+    line.split_whitespace()
+        .find(|word| {
+            let parts: Vec<&str> = word.split([':', '-']).collect();
+            parts.len() == 6
+                && parts.iter().all(|part| {
+                    !part.is_empty() && part.len() <= 2 && u8::from_str_radix(part, 16).is_ok()
+                })
+        })
+        .map(|mac| {
+            // macOS drops leading zeros, e.g. "0:1b:2c:3:4:5"
+            normalize_mac(mac)
+                .split(':')
+                .map(|part| format!("{:0>2}", part))
+                .collect::<Vec<_>>()
+                .join(":")
+        })
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+
+    /// `route -n get default` prints e.g. "gateway: 192.168.1.1" and
+    /// "interface: en0".
+    pub fn default_gateway() -> Result<Option<Gateway>> {
+        let Ok(output) = run("route", &["-n", "get", "default"]) else {
+            // Fails with "not in table" when there's no default route
+            return Ok(None);
+        };
+        let field = |name: &str| {
+            output.lines().find_map(|line| {
+                line.trim()
+                    .strip_prefix(name)
+                    .map(|value| value.trim().to_string())
+            })
+        };
+        Ok(field("gateway:")
+            .and_then(|ip| ip.parse().ok())
+            .map(|ip| Gateway {
+                ip,
+                interface: field("interface:"),
+            }))
+    }
+
+    /// `arp -n 192.168.1.1` prints e.g.
+    /// "? (192.168.1.1) at aa:bb:cc:dd:ee:ff on en0 ifscope [ethernet]".
+    pub fn neighbor_mac(ip: IpAddr) -> Result<Option<String>> {
+        let output = run("arp", &["-n", &ip.to_string()])?;
+        Ok(output.lines().find_map(find_mac))
+    }
+
+    /// Reads `ifconfig`, where each interface starts an unindented line like
+    /// "en0: flags=..." followed by indented "inet 192.168.1.5 netmask ..."
+    /// and "inet6 fe80::1%en0 prefixlen 64" lines.
+    pub fn interface_addresses() -> Result<Vec<InterfaceAddress>> {
+        let output = run("ifconfig", &[])?;
+        let mut addresses = Vec::new();
+        let mut interface = None;
+        for line in output.lines() {
+            if !line.starts_with(char::is_whitespace) {
+                interface = line.split(':').next().map(str::to_string);
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let (Some("inet" | "inet6"), Some(ip), Some(interface)) =
+                (words.next(), words.next(), &interface)
+            else {
+                continue;
+            };
+            // Drop the "%en0" zone from link-local addresses
+            if let Ok(ip) = ip.split('%').next().unwrap_or(ip).parse() {
+                addresses.push(InterfaceAddress {
+                    interface: interface.clone(),
+                    ip,
+                });
+            }
+        }
+        Ok(addresses)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::*;
+    use std::fs;
+    use std::net::Ipv4Addr;
+
+    /// Reads `/proc/net/route`, whose default route has destination
+    /// `00000000` and a little-endian hex gateway, e.g.
+    /// "eth0 00000000 0101A8C0 0003 ...".
+    pub fn default_gateway() -> Result<Option<Gateway>> {
+        let routes = fs::read_to_string("/proc/net/route")?;
+        Ok(routes.lines().skip(1).find_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                [interface, "00000000", gateway, ..] => {
+                    let gateway = u32::from_str_radix(gateway, 16).ok()?;
+                    Some(Gateway {
+                        ip: IpAddr::V4(Ipv4Addr::from(u32::from_be(gateway))),
+                        interface: Some(interface.to_string()),
+                    })
+                }
+                _ => None,
+            }
+        }))
+    }
+
+    /// Reads `/proc/net/arp`: "IP address HW type Flags HW address Mask Device".
+    pub fn neighbor_mac(ip: IpAddr) -> Result<Option<String>> {
+        let table = fs::read_to_string("/proc/net/arp")?;
+        let ip = ip.to_string();
+        Ok(table
+            .lines()
+            .skip(1)
+            .filter(|line| line.split_whitespace().next() == Some(ip.as_str()))
+            .find_map(find_mac)
+            .filter(|mac| mac != "00:00:00:00:00:00"))
+    }
+
+    /// `ip -o addr show` prints one address per line, e.g.
+    /// "2: eth0    inet 192.168.1.5/24 brd 192.168.1.255 scope global eth0".
+    pub fn interface_addresses() -> Result<Vec<InterfaceAddress>> {
+        let output = run("ip", &["-o", "addr", "show"])?;
+        Ok(output
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                match fields.as_slice() {
+                    [_, interface, "inet" | "inet6", address, ..] => Some(InterfaceAddress {
+                        interface: interface.to_string(),
+                        ip: address.split('/').next()?.parse().ok()?,
+                    }),
+                    _ => None,
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::*;
+
+    /// Asks PowerShell rather than parsing `route print`, whose layout
+    /// changes with the display language.
+    pub fn default_gateway() -> Result<Option<Gateway>> {
+        let output = run(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                "Get-NetRoute -DestinationPrefix 0.0.0.0/0 | Sort-Object RouteMetric | \
+                 Select-Object -First 1 | ForEach-Object { \"$($_.NextHop)|$($_.InterfaceAlias)\" }",
+            ],
+        )?;
+        Ok(output.lines().find_map(|line| {
+            let (ip, interface) = line.trim().split_once('|')?;
+            Some(Gateway {
+                ip: ip.parse().ok()?,
+                interface: (!interface.is_empty()).then(|| interface.to_string()),
+            })
+        }))
+    }
+
+    /// `arp -a 192.168.1.1` prints e.g.
+    /// "  192.168.1.1           aa-bb-cc-dd-ee-ff     dynamic".
+    pub fn neighbor_mac(ip: IpAddr) -> Result<Option<String>> {
+        let Ok(output) = run("arp", &["-a", &ip.to_string()]) else {
+            // Fails when there's no entry for the address
+            return Ok(None);
+        };
+        let ip = ip.to_string();
+        Ok(output
+            .lines()
+            .filter(|line| line.split_whitespace().next() == Some(ip.as_str()))
+            .find_map(find_mac))
+    }
+
+    pub fn interface_addresses() -> Result<Vec<InterfaceAddress>> {
+        let output = run(
+            "powershell",
+            &[
+                "-NoProfile",
+                "-Command",
+                "Get-NetIPAddress | ForEach-Object { \"$($_.InterfaceAlias)|$($_.IPAddress)\" }",
+            ],
+        )?;
+        Ok(output
+            .lines()
+            .filter_map(|line| {
+                let (interface, ip) = line.trim().rsplit_once('|')?;
+                Some(InterfaceAddress {
+                    interface: interface.to_string(),
+                    ip: ip.split('%').next()?.parse().ok()?,
+                })
+            })
+            .collect())
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
+mod platform {
+    use super::*;
+
+    pub fn default_gateway() -> Result<Option<Gateway>> {
+        Err("Gateway detection is not supported on this platform".into())
+    }
+
+    pub fn neighbor_mac(_ip: IpAddr) -> Result<Option<String>> {
+        Err("Gateway detection is not supported on this platform".into())
+    }
+
+    pub fn interface_addresses() -> Result<Vec<InterfaceAddress>> {
+        Err("Address detection is not supported on this platform".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cidr_matches_addresses_in_block() {
+        let cidr = Cidr::parse("10.20.0.0/16").unwrap();
+        assert!(cidr.contains("10.20.3.4".parse().unwrap()));
+        assert!(!cidr.contains("10.21.0.1".parse().unwrap()));
+        assert!(!cidr.contains("::1".parse().unwrap()));
+
+        let single = Cidr::parse("192.168.1.1").unwrap();
+        assert!(single.contains("192.168.1.1".parse().unwrap()));
+        assert!(!single.contains("192.168.1.2".parse().unwrap()));
+
+        assert!(Cidr::parse("0.0.0.0/0")
+            .unwrap()
+            .contains("8.8.8.8".parse().unwrap()));
+        assert!(Cidr::parse("fd00::/8")
+            .unwrap()
+            .contains("fd12::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn cidr_rejects_bad_input() {
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("office").is_err());
+    }
+
+    #[test]
+    fn parses_probe_addresses() {
+        assert_eq!(parse_host_port("10.0.0.5:443"), Ok(("10.0.0.5", 443)));
+        assert_eq!(
+            parse_host_port("printer.local:631"),
+            Ok(("printer.local", 631))
+        );
+        assert_eq!(parse_host_port("[fd00::5]:443"), Ok(("fd00::5", 443)));
+        assert!(parse_host_port("fd00::5:443").is_err());
+        assert!(parse_host_port("10.0.0.5").is_err());
+        assert!(parse_host_port("10.0.0.5:https").is_err());
+        assert!(parse_host_port(":443").is_err());
+    }
+
+    #[test]
+    fn connects_to_listening_port() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        assert!(can_connect(&address, Duration::from_secs(1)));
+        drop(listener);
+        assert!(!can_connect(&address, Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn recognises_vpn_interfaces() {
+        for name in [
+            "utun3",
+            "tun0",
+            "wg0",
+            "ppp0",
+            "Cisco AnyConnect VPN",
+            "WireGuard Tunnel",
+        ] {
+            assert!(is_vpn_interface(name), "{}", name);
+        }
+        for name in ["en0", "eth0", "wlan0", "Wi-Fi", "lo0"] {
+            assert!(!is_vpn_interface(name), "{}", name);
+        }
+        assert!(is_routable("10.8.0.2".parse().unwrap()));
+        assert!(!is_routable("fe80::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn finds_macs_in_arp_output() {
+        assert_eq!(
+            find_mac("? (192.168.1.1) at 0:1b:2c:3:4:5 on en0 ifscope [ethernet]").as_deref(),
+            Some("00:1b:2c:03:04:05")
+        );
+        assert_eq!(
+            find_mac("  192.168.1.1           AA-BB-CC-DD-EE-FF     dynamic").as_deref(),
+            Some("aa:bb:cc:dd:ee:ff")
+        );
+        assert_eq!(find_mac("? (192.168.1.1) at (incomplete) on en0"), None);
+    }
+}
@@ -0,0 +1,98 @@
+use regex::Regex;
+
+/// How a target SSID is compared against the connected network.
+///
+/// Parsed from the user's setting:
+/// - `re:^CorpNet-(2G|5G)$` is a regular expression
+/// - `CorpNet-*` is a glob, where `*` matches any run of characters and `?`
+///   matches exactly one
+/// - anything else must match exactly
+#[derive(Clone, Debug)]
+pub enum SsidPattern {
+    Exact(String),
+    Glob(Regex),
+    Regex(Regex),
+}
+
+impl SsidPattern {
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        if pattern.is_empty() {
+            return Err("SSID pattern must not be empty".to_string());
+        }
+
+        if let Some(expr) = pattern.strip_prefix("re:") {
+            return Regex::new(expr)
+                .map(SsidPattern::Regex)
+                .map_err(|e| format!("Invalid SSID regex '{}': {}", expr, e));
+        }
+
+        if pattern.contains(['*', '?']) {
+            let expr = glob_to_regex(pattern);
+            return Regex::new(&expr)
+                .map(SsidPattern::Glob)
+                .map_err(|e| format!("Invalid SSID glob '{}': {}", pattern, e));
+        }
+
+        Ok(SsidPattern::Exact(pattern.to_string()))
+    }
+
+    pub fn matches(&self, ssid: &str) -> bool {
+        match self {
+            SsidPattern::Exact(expected) => expected == ssid,
+            SsidPattern::Glob(regex) | SsidPattern::Regex(regex) => regex.is_match(ssid),
+        }
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut expr = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => expr.push_str(".*"),
+            '?' => expr.push('.'),
+            c => expr.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    expr.push('$');
+    expr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, ssid: &str) -> bool {
+        SsidPattern::parse(pattern).unwrap().matches(ssid)
+    }
+
+    #[test]
+    fn exact_patterns_match_only_themselves() {
+        assert!(matches("eduroam", "eduroam"));
+        assert!(!matches("eduroam", "eduroam-guest"));
+        assert!(!matches("eduroam", "Eduroam"));
+    }
+
+    #[test]
+    fn globs_match_whole_ssid() {
+        assert!(matches("CorpNet-*", "CorpNet-5G"));
+        assert!(matches("CorpNet-?G", "CorpNet-2G"));
+        assert!(!matches("CorpNet-?G", "CorpNet-10G"));
+        assert!(!matches("CorpNet-*", "Guest-CorpNet-5G"));
+        // Regex characters in a glob are literal
+        assert!(matches("Lab (A).*", "Lab (A).1"));
+        assert!(!matches("Lab (A).*", "Lab A.1"));
+    }
+
+    #[test]
+    fn regexes_are_used_as_written() {
+        assert!(matches("re:^CorpNet-(2G|5G)$", "CorpNet-5G"));
+        assert!(!matches("re:^CorpNet-(2G|5G)$", "CorpNet-6G"));
+        assert!(matches("re:Corp", "MegaCorp"));
+    }
+
+    #[test]
+    fn rejects_empty_and_invalid_patterns() {
+        assert!(SsidPattern::parse("").is_err());
+        assert!(SsidPattern::parse("re:(").is_err());
+    }
+}
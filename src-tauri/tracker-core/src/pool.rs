@@ -0,0 +1,89 @@
+use rusqlite::{Connection, OpenFlags, Result};
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::error::LockExt;
+
+/// Idle connections kept open for reuse; busier moments open extra ones that
+/// are closed when returned.
+const MAX_IDLE: usize = 4;
+
+/// Read-only connections to the database, handed out one per caller. Under
+/// WAL they read alongside the single writing connection, so a slow report
+/// doesn't hold up inserts and vice versa.
+pub struct ReadPool {
+    path: PathBuf,
+    busy_timeout: Duration,
+    /// The SQLCipher key, if the database is encrypted.
+    key: Option<String>,
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl ReadPool {
+    pub fn new(path: PathBuf, busy_timeout: Duration, key: Option<String>) -> Self {
+        Self {
+            path,
+            busy_timeout,
+            key,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn get(&self) -> Result<PooledConnection<'_>> {
+        let idle = self.idle.lock_unpoisoned().pop();
+        let connection = match idle {
+            Some(connection) => connection,
+            None => {
+                let connection = Connection::open_with_flags(
+                    &self.path,
+                    OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+                )?;
+                if let Some(key) = &self.key {
+                    connection.pragma_update(None, "key", key)?;
+                }
+                connection.busy_timeout(self.busy_timeout)?;
+                connection
+            }
+        };
+        Ok(PooledConnection {
+            pool: self,
+            connection: Some(connection),
+        })
+    }
+
+    /// Closes the idle connections, e.g. so the file can be deleted on
+    /// Windows. Ones in use close when they're returned.
+    pub fn close_idle(&self) {
+        self.idle.lock_unpoisoned().clear();
+    }
+}
+
+/// A connection borrowed from a `ReadPool`, returned to it when dropped.
+pub struct PooledConnection<'a> {
+    pool: &'a ReadPool,
+    connection: Option<Connection>,
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.connection
+            .as_ref()
+            .expect("connection is present until drop")
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        let Some(connection) = self.connection.take() else {
+            return;
+        };
+        let mut idle = self.pool.idle.lock_unpoisoned();
+        if idle.len() < MAX_IDLE {
+            idle.push(connection);
+        }
+    }
+}
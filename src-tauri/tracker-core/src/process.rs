@@ -0,0 +1,84 @@
+use std::io::{self, ErrorKind, Read};
+use std::process::{Command, Output, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long a command run to check presence gets before it's killed, so a
+/// stalled `networksetup` or `nmcli` fails that check instead of holding up
+/// every check after it.
+pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often to look whether the command has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+pub trait CommandExt {
+    /// Runs the command to completion like `Command::output`, but kills it
+    /// and fails with `ErrorKind::TimedOut` if it's still running after
+    /// `timeout`.
+    fn output_within(&mut self, timeout: Duration) -> io::Result<Output>;
+}
+
+impl CommandExt for Command {
+    fn output_within(&mut self, timeout: Duration) -> io::Result<Output> {
+        let mut child = self
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // Drain both pipes while it runs, since one filling up would block it
+        let stdout = read_to_end(child.stdout.take());
+        let stderr = read_to_end(child.stderr.take());
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(io::Error::new(
+                    ErrorKind::TimedOut,
+                    format!("timed out after {:?}", timeout),
+                ));
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap_or_default(),
+            stderr: stderr.join().unwrap_or_default(),
+        })
+    }
+}
+
+fn read_to_end(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kills_commands_that_overrun() {
+        let output = Command::new("echo")
+            .arg("office")
+            .output_within(COMMAND_TIMEOUT)
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "office\n");
+
+        let started = Instant::now();
+        let error = Command::new("sleep")
+            .arg("5")
+            .output_within(Duration::from_millis(100))
+            .unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,288 @@
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use url::Url;
+
+use crate::error::Result;
+use crate::timesheet::{self, Timesheet};
+use crate::{keychain, settings};
+
+/// Read-only, with none of the day notes, for sharing outside the app.
+pub const REPORT_TEMPLATE: &str = include_str!("../templates/report.html");
+/// Keychain account holding the secret key for an S3 destination.
+pub const S3_SECRET_ACCOUNT: &str = "report_s3";
+const CONTENT_TYPE: &str = "text/html; charset=utf-8";
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where published reports go, stored as JSON tagged by `type`, e.g.
+/// `{"type": "folder", "path": "/Users/me/Sites/hours"}`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum ReportDestination {
+    /// A local folder, e.g. one served as a static site or shared through
+    /// Dropbox.
+    Folder { path: String },
+    /// A bucket on AWS S3 or a compatible service such as Cloudflare R2 or
+    /// MinIO, addressed path-style. The secret key is in the keychain.
+    S3 {
+        /// e.g. "https://s3.eu-west-2.amazonaws.com".
+        endpoint: String,
+        /// e.g. "eu-west-2", or "auto" for R2.
+        region: String,
+        bucket: String,
+        access_key_id: String,
+        /// Where the bucket is readable from, if not the endpoint, e.g. a
+        /// CDN in front of it.
+        #[serde(default)]
+        public_url: String,
+    },
+}
+
+impl ReportDestination {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            ReportDestination::Folder { path } => {
+                if !Path::new(path.trim()).is_absolute() {
+                    return Err(format!(
+                        "Invalid report folder '{}', expected a full path",
+                        path.trim()
+                    ));
+                }
+            }
+            ReportDestination::S3 {
+                endpoint,
+                region,
+                bucket,
+                access_key_id,
+                public_url,
+            } => {
+                let web = |url: &str| url.starts_with("https://") || url.starts_with("http://");
+                if !web(endpoint.trim()) {
+                    return Err(format!("Invalid bucket URL '{}'", endpoint.trim()));
+                }
+                if !public_url.trim().is_empty() && !web(public_url.trim()) {
+                    return Err(format!("Invalid public URL '{}'", public_url.trim()));
+                }
+                if region.trim().is_empty()
+                    || bucket.trim().is_empty()
+                    || access_key_id.trim().is_empty()
+                {
+                    return Err("S3 destinations need a region, bucket and access key".to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A timesheet with the figures the report leads with.
+#[derive(Serialize)]
+struct Report {
+    #[serde(flatten)]
+    timesheet: Timesheet,
+    office_days: usize,
+    average_minutes: i64,
+    generated: String,
+}
+
+/// The configured destination, if there is one.
+pub fn destination(db: &Connection) -> Result<Option<ReportDestination>> {
+    Ok(settings::get(db, settings::REPORT_DESTINATION)?
+        .and_then(|json| serde_json::from_str(&json).ok()))
+}
+
+/// The file name a report for `from` to `to` is published under.
+pub fn file_name(from: NaiveDate, to: NaiveDate) -> String {
+    format!("office-hours-{}-to-{}.html", from, to)
+}
+
+/// The report for `from` to `to` as a standalone HTML page, optionally at
+/// just one location. Day notes are left out, since they're often private.
+pub fn render(
+    db: &Connection,
+    from: NaiveDate,
+    to: NaiveDate,
+    location: Option<&str>,
+    generated: DateTime<Local>,
+) -> Result<String> {
+    if from > to {
+        return Err(format!("{} is after {}", from, to).into());
+    }
+    let mut timesheet = timesheet::timesheet(db, from, to, location)?;
+    for day in timesheet.weeks.iter_mut().flat_map(|week| &mut week.days) {
+        day.note = None;
+    }
+    let office_days = timesheet
+        .weeks
+        .iter()
+        .flat_map(|week| &week.days)
+        .filter(|day| day.total_minutes > 0)
+        .count();
+    let report = Report {
+        average_minutes: timesheet.total_minutes / office_days.max(1) as i64,
+        office_days,
+        generated: generated.format("%Y-%m-%d %H:%M").to_string(),
+        timesheet,
+    };
+    timesheet::render(&report, REPORT_TEMPLATE, true)
+}
+
+/// Puts `html` at `destination` as `name`, returning the path or URL it can
+/// be found at.
+pub fn publish(destination: &ReportDestination, name: &str, html: &str) -> Result<String> {
+    match destination {
+        ReportDestination::Folder { path } => {
+            let folder = PathBuf::from(path.trim());
+            fs::create_dir_all(&folder)?;
+            let path = folder.join(name);
+            fs::write(&path, html)?;
+            Ok(path.to_string_lossy().into_owned())
+        }
+        ReportDestination::S3 {
+            endpoint,
+            region,
+            bucket,
+            access_key_id,
+            public_url,
+        } => {
+            let secret = keychain::get(S3_SECRET_ACCOUNT)?
+                .ok_or("Add the bucket's secret key before publishing")?;
+            let url = Url::parse(&format!(
+                "{}/{}/{}",
+                endpoint.trim().trim_end_matches('/'),
+                bucket.trim(),
+                name
+            ))
+            .map_err(|e| format!("Invalid bucket URL: {}", e))?;
+            let credentials = Credentials {
+                access_key_id: access_key_id.trim(),
+                secret: &secret,
+                region: region.trim(),
+            };
+            let mut request = ureq::put(url.as_str()).timeout(TIMEOUT);
+            for (header, value) in credentials.sign(&url, html.as_bytes(), Utc::now()) {
+                request = request.set(header, &value);
+            }
+            request
+                .send_bytes(html.as_bytes())
+                .map_err(|e| format!("Couldn't upload the report: {}", e))?;
+            Ok(match public_url.trim() {
+                "" => url.to_string(),
+                public_url => format!("{}/{}", public_url.trim_end_matches('/'), name),
+            })
+        }
+    }
+}
+
+struct Credentials<'a> {
+    access_key_id: &'a str,
+    secret: &'a str,
+    region: &'a str,
+}
+
+impl Credentials<'_> {
+    /// The headers that sign a PUT of `body` to `url` with AWS Signature
+    /// Version 4, which S3-compatible services all accept.
+    fn sign(&self, url: &Url, body: &[u8], now: DateTime<Utc>) -> Vec<(&'static str, String)> {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let day = now.format("%Y%m%d").to_string();
+        let payload_hash = format!("{:x}", Sha256::digest(body));
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let signed_headers = "content-type;host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "PUT\n{}\n\ncontent-type:{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            url.path(),
+            CONTENT_TYPE,
+            host,
+            payload_hash,
+            amz_date,
+            signed_headers,
+            payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", day, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+            amz_date,
+            scope,
+            Sha256::digest(canonical_request)
+        );
+        let mut key = format!("AWS4{}", self.secret).into_bytes();
+        for part in [day.as_str(), self.region, "s3", "aws4_request"] {
+            key = hmac_sha256(&key, part.as_bytes());
+        }
+        let signature: String = hmac_sha256(&key, string_to_sign.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        vec![
+            ("Content-Type", CONTENT_TYPE.to_string()),
+            ("x-amz-content-sha256", payload_hash),
+            ("x-amz-date", amz_date),
+            (
+                "Authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                    self.access_key_id, scope, signed_headers, signature
+                ),
+            ),
+        ]
+    }
+}
+
+/// HMAC-SHA256, as in RFC 2104.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 64;
+    let mut block = [0; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<u8>>();
+    let inner = Sha256::digest([pad(0x36), message.to_vec()].concat());
+    Sha256::digest([pad(0x5c), inner.to_vec()].concat()).to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+    use chrono::TimeZone;
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        let hex: String = mac.iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(
+            hex,
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn report_leaves_out_notes() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        db.execute(
+            "INSERT INTO connections (date, earliest, latest, note)
+             VALUES ('2024-03-04', '09:00', '17:00', 'interview with a candidate')",
+            [],
+        )
+        .unwrap();
+        let generated = Local.with_ymd_and_hms(2024, 3, 8, 12, 0, 0).unwrap();
+        let html = render(&db, date("2024-03-04"), date("2024-03-08"), None, generated).unwrap();
+        assert!(html.contains("Mon 2024-03-04"));
+        assert!(!html.contains("interview"));
+    }
+}
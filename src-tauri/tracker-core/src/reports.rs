@@ -0,0 +1,998 @@
+use chrono::{DateTime, Datelike, Duration, Local, Months, NaiveDate};
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::day_types::{self, DayTypeCounts};
+use crate::rounding::{self, Rounding};
+use crate::sessions::{self, format_minute_of_day, location_sql, DayBoundary, MINUTES_SQL};
+use crate::{flexitime, holidays, lunch};
+
+/// Rows shaped like `connections`, one per recorded day. When a location is
+/// bound to `param`, they're built from that location's sessions instead, so
+/// arrivals and departures are at that location.
+fn days_table(param: &str) -> String {
+    format!(
+        "(SELECT date, earliest, latest, earliest_at, latest_at
+          FROM connections
+          WHERE {0} IS NULL
+          UNION ALL
+          SELECT date, MIN(start), MAX(end), MIN(start_at), MAX(end_at)
+          FROM sessions
+          WHERE label = {0}
+          GROUP BY date)",
+        param
+    )
+}
+
+/// SQL for the minutes with keyboard or mouse input over the sessions `s`
+/// aggregated, i.e. focus hours, or NULL if input wasn't checked in any of
+/// them. Sessions too short to count don't count here either.
+fn active_minutes_sql() -> String {
+    format!(
+        "(SUM(CASE WHEN {} > 0 THEN s.active_seconds ELSE s.active_seconds * 0 END) / 60)",
+        MINUTES_SQL
+    )
+}
+
+/// One day's figures in a summary.
+#[derive(Serialize)]
+pub struct DayTotal {
+    pub date: String,
+    pub earliest: String,
+    pub latest: String,
+    pub total_minutes: i64,
+    /// Of `total_minutes`, those with keyboard or mouse input, or `None` if
+    /// input wasn't checked.
+    pub active_minutes: Option<i64>,
+    /// A gap between sessions taken as lunch, not counted in
+    /// `total_minutes`.
+    pub lunch_minutes: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct WeeklySummary {
+    pub week_start: String,
+    pub week_end: String,
+    pub days: Vec<DayTotal>,
+    pub total_minutes: i64,
+    pub total_hours: f64,
+    /// Focus time, as in `DayTotal`. Never rounded.
+    pub active_minutes: Option<i64>,
+    /// Mean of each day's earliest time, as HH:MM.
+    pub average_arrival: Option<String>,
+    /// Mean of each day's latest time, as HH:MM.
+    pub average_departure: Option<String>,
+    pub office_days: i64,
+    pub day_types: DayTypeCounts,
+    /// Time worked against the work schedule so far, if there is one.
+    pub schedule: Option<flexitime::Period>,
+}
+
+/// Summarises the week starting on `week_start`, counting only time at
+/// `location` when one is given. Day types apply to whole days, so they're
+/// never filtered. With report rounding set, each day is rounded and the
+/// total is the sum of the rounded days.
+pub fn weekly_summary(
+    db: &Connection,
+    week_start: NaiveDate,
+    today: NaiveDate,
+    boundary: DayBoundary,
+    location: Option<&str>,
+) -> Result<WeeklySummary> {
+    let from = week_start.format("%Y-%m-%d").to_string();
+    let week_end = week_start + Duration::days(6);
+    let to = week_end.format("%Y-%m-%d").to_string();
+
+    let mut days = day_totals(db, &from, &to, location)?;
+    let rounding = rounding::load(db)?;
+
+    // Local times of day, which SQLite reads as times on 2000-01-01, so
+    // averaging their epoch seconds and formatting back gives a mean time.
+    // Shifting by the day boundary first keeps departures after midnight in
+    // order.
+    let (office_days, average_arrival, average_departure, total_minutes) = db.query_row(
+        &format!(
+            "SELECT COUNT(*),
+                strftime('%H:%M', AVG(strftime('%s',
+                    time(earliest_at, 'unixepoch', 'localtime'), ?3)), 'unixepoch', ?4),
+                strftime('%H:%M', AVG(strftime('%s',
+                    time(latest_at, 'unixepoch', 'localtime'), ?3)), 'unixepoch', ?4),
+                (SELECT COALESCE(SUM({}), 0) FROM sessions s
+                 WHERE s.date BETWEEN ?1 AND ?2 AND {})
+             FROM {}
+             WHERE date BETWEEN ?1 AND ?2",
+            MINUTES_SQL,
+            location_sql("?5"),
+            days_table("?5")
+        ),
+        params![
+            from,
+            to,
+            boundary.sql_shift(),
+            format!("+{} hours", boundary.hour),
+            location
+        ],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+    )?;
+    let day_types = day_types::count_day_types(db, week_start, week_end, today)?;
+    let schedule = flexitime::load(db)?
+        .map(|schedule| flexitime::period(db, &schedule, week_start, week_end, today))
+        .transpose()?;
+    let total_minutes = match rounding {
+        Some(rounding) => {
+            for day in &mut days {
+                day.total_minutes = rounding.apply(day.total_minutes);
+            }
+            days.iter().map(|day| day.total_minutes).sum()
+        }
+        None => total_minutes,
+    };
+    let active_minutes = days
+        .iter()
+        .filter_map(|day| day.active_minutes)
+        .reduce(|total, minutes| total + minutes);
+
+    Ok(WeeklySummary {
+        week_start: from,
+        week_end: to,
+        days,
+        total_minutes,
+        total_hours: total_minutes as f64 / 60.0,
+        active_minutes,
+        average_arrival,
+        average_departure,
+        office_days,
+        day_types,
+        schedule,
+    })
+}
+
+/// A day and how long was tracked on it.
+#[derive(Serialize)]
+pub struct DayMinutes {
+    pub date: String,
+    pub total_minutes: i64,
+}
+
+/// A Monday-to-Sunday week within a monthly summary. Weeks that straddle the
+/// month boundary only count the days inside the month.
+#[derive(Serialize)]
+pub struct WeekTotal {
+    pub week_start: String,
+    pub total_minutes: i64,
+    pub office_days: i64,
+}
+
+#[derive(Serialize)]
+pub struct MonthlySummary {
+    pub year: i32,
+    pub month: u32,
+    pub total_minutes: i64,
+    pub total_hours: f64,
+    /// Focus time, as in `DayTotal`. Never rounded.
+    pub active_minutes: Option<i64>,
+    pub office_days: i64,
+    pub longest_day: Option<DayMinutes>,
+    pub shortest_day: Option<DayMinutes>,
+    pub weeks: Vec<WeekTotal>,
+    pub day_types: DayTypeCounts,
+    /// Time worked against the work schedule so far, if there is one.
+    pub schedule: Option<flexitime::Period>,
+}
+
+/// Common table expression `days(date, minutes)` over the recorded days
+/// between ?1 and ?2 inclusive, at the location bound to ?3 if it's set, with
+/// each day's minutes rounded if `rounding` is given.
+fn days_cte(rounding: Option<Rounding>) -> String {
+    let minutes = format!(
+        "COALESCE((SELECT SUM({}) FROM sessions s WHERE s.date = d.date AND {}), 0)",
+        MINUTES_SQL,
+        location_sql("?3")
+    );
+    format!(
+        "WITH days AS (
+            SELECT date, {} AS minutes
+            FROM {} d
+            WHERE date BETWEEN ?1 AND ?2
+        )",
+        match rounding {
+            Some(rounding) => rounding.sql(&minutes),
+            None => minutes,
+        },
+        days_table("?3")
+    )
+}
+
+/// Summarises `month` of `year`, failing if there's no such month.
+pub fn monthly_summary(
+    db: &Connection,
+    year: i32,
+    month: u32,
+    today: NaiveDate,
+    location: Option<&str>,
+) -> crate::Result<MonthlySummary> {
+    let invalid = || format!("Invalid month {}-{:02}", year, month);
+    let first = NaiveDate::from_ymd_opt(year, month, 1).ok_or_else(invalid)?;
+    let last = first
+        .checked_add_months(Months::new(1))
+        .ok_or_else(invalid)?
+        - Duration::days(1);
+    let from = first.format("%Y-%m-%d").to_string();
+    let to = last.format("%Y-%m-%d").to_string();
+    let days = days_cte(rounding::load(db)?);
+
+    let (office_days, total_minutes) = db.query_row(
+        &format!(
+            "{} SELECT COUNT(*), COALESCE(SUM(minutes), 0) FROM days",
+            days
+        ),
+        params![from, to, location],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let active_minutes = db.query_row(
+        &format!(
+            "SELECT {} FROM sessions s WHERE s.date BETWEEN ?1 AND ?2 AND {}",
+            active_minutes_sql(),
+            location_sql("?3")
+        ),
+        params![from, to, location],
+        |row| row.get(0),
+    )?;
+
+    let extreme_day = |order: &str| {
+        db.query_row(
+            &format!(
+                "{} SELECT date, minutes FROM days ORDER BY minutes {}, date LIMIT 1",
+                days, order
+            ),
+            params![from, to, location],
+            |row| {
+                Ok(DayMinutes {
+                    date: row.get(0)?,
+                    total_minutes: row.get(1)?,
+                })
+            },
+        )
+        .optional()
+    };
+    let longest_day = extreme_day("DESC")?;
+    let shortest_day = extreme_day("ASC")?;
+
+    // date(d, '-6 days', 'weekday 1') is the Monday on or before d
+    let mut stmt = db.prepare(&format!(
+        "{} SELECT date(date, '-6 days', 'weekday 1') AS week_start, SUM(minutes), COUNT(*)
+         FROM days
+         GROUP BY week_start
+         ORDER BY week_start",
+        days
+    ))?;
+    let weeks = stmt
+        .query_map(params![from, to, location], |row| {
+            Ok(WeekTotal {
+                week_start: row.get(0)?,
+                total_minutes: row.get(1)?,
+                office_days: row.get(2)?,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    let day_types = day_types::count_day_types(db, first, last, today)?;
+    let schedule = flexitime::load(db)?
+        .map(|schedule| flexitime::period(db, &schedule, first, last, today))
+        .transpose()?;
+
+    Ok(MonthlySummary {
+        year,
+        month,
+        total_minutes,
+        total_hours: total_minutes as f64 / 60.0,
+        active_minutes,
+        office_days,
+        longest_day,
+        shortest_day,
+        weeks,
+        day_types,
+        schedule,
+    })
+}
+
+#[derive(Serialize)]
+pub struct HeatmapDay {
+    pub date: String,
+    pub hours: f64,
+}
+
+/// One entry for every calendar day of `year`, with 0 hours for days without
+/// any presence. Fails for years chrono can't represent.
+pub fn heatmap(
+    db: &Connection,
+    year: i32,
+    location: Option<&str>,
+) -> crate::Result<Vec<HeatmapDay>> {
+    let invalid = || format!("Invalid year {}", year);
+    let first = NaiveDate::from_ymd_opt(year, 1, 1).ok_or_else(invalid)?;
+    let last = NaiveDate::from_ymd_opt(year, 12, 31).ok_or_else(invalid)?;
+
+    let mut stmt = db.prepare(&format!(
+        "{} SELECT date, minutes FROM days ORDER BY date",
+        days_cte(None)
+    ))?;
+    let minutes = stmt
+        .query_map(
+            params![
+                first.format("%Y-%m-%d").to_string(),
+                last.format("%Y-%m-%d").to_string(),
+                location
+            ],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+        )?
+        .collect::<Result<HashMap<_, _>>>()?;
+
+    Ok(first
+        .iter_days()
+        .take_while(|date| *date <= last)
+        .map(|date| {
+            let date = date.format("%Y-%m-%d").to_string();
+            let hours = minutes.get(&date).map_or(0.0, |&m| m as f64 / 60.0);
+            HeatmapDay { date, hours }
+        })
+        .collect())
+}
+
+/// A run of consecutive office days.
+#[derive(Serialize)]
+pub struct Streak {
+    pub start: String,
+    pub end: String,
+    pub days: i64,
+}
+
+#[derive(Serialize)]
+pub struct Streaks {
+    /// Length of the streak still running today, or 0.
+    pub current: i64,
+    pub longest: i64,
+    /// Every streak, oldest first.
+    pub history: Vec<Streak>,
+}
+
+/// Finds runs of consecutive office days. When `weekends_break` is false,
+/// missing Saturdays and Sundays don't end a streak (but weekend office days
+/// still count towards it).
+pub fn streaks(
+    db: &Connection,
+    today: NaiveDate,
+    weekends_break: bool,
+    location: Option<&str>,
+) -> Result<Streaks> {
+    let mut stmt = db.prepare(&format!(
+        "SELECT date FROM {} ORDER BY date",
+        days_table("?1")
+    ))?;
+    let dates = stmt
+        .query_map([location], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .filter_map(|date| NaiveDate::parse_from_str(&date, "%Y-%m-%d").ok());
+
+    // Whether every day strictly between `a` and `b` may be skipped
+    let only_skippable_between = |a: NaiveDate, b: NaiveDate| {
+        a.iter_days()
+            .skip(1)
+            .take_while(|day| *day < b)
+            .all(|day| !weekends_break && day.weekday().number_from_monday() >= 6)
+    };
+
+    let mut runs: Vec<(NaiveDate, NaiveDate, i64)> = Vec::new();
+    for date in dates {
+        match runs.last_mut() {
+            Some((_, end, days)) if only_skippable_between(*end, date) => {
+                *end = date;
+                *days += 1;
+            }
+            _ => runs.push((date, date, 1)),
+        }
+    }
+
+    // Today isn't over yet, so a streak ending yesterday is still running
+    let current = match runs.last() {
+        Some(&(_, end, days)) if end == today || only_skippable_between(end, today) => days,
+        _ => 0,
+    };
+    let longest = runs.iter().map(|&(_, _, days)| days).max().unwrap_or(0);
+    let history = runs
+        .into_iter()
+        .map(|(start, end, days)| Streak {
+            start: start.format("%Y-%m-%d").to_string(),
+            end: end.format("%Y-%m-%d").to_string(),
+            days,
+        })
+        .collect();
+
+    Ok(Streaks {
+        current,
+        longest,
+        history,
+    })
+}
+
+/// How many days' arrivals fell in `[start, start + bucket_minutes)`.
+#[derive(Serialize)]
+pub struct HistogramBucket {
+    /// Bucket start as HH:MM.
+    pub start: String,
+    pub count: i64,
+}
+
+/// Bins each day's earliest time into `bucket_minutes`-wide buckets. Empty
+/// buckets between the first and last arrival are included so the result can
+/// be plotted directly.
+pub fn arrival_histogram(
+    db: &Connection,
+    bucket_minutes: i64,
+    from: Option<&str>,
+    to: Option<&str>,
+    location: Option<&str>,
+) -> Result<Vec<HistogramBucket>> {
+    let mut stmt = db.prepare(&format!(
+        "SELECT (CAST(strftime('%H', earliest_at, 'unixepoch', 'localtime') AS INTEGER) * 60
+                + CAST(strftime('%M', earliest_at, 'unixepoch', 'localtime') AS INTEGER))
+                / ?1 * ?1 AS bucket,
+            COUNT(*)
+         FROM {}
+         WHERE (?2 IS NULL OR date >= ?2) AND (?3 IS NULL OR date <= ?3)
+         GROUP BY bucket
+         ORDER BY bucket",
+        days_table("?4")
+    ))?;
+    let counts = stmt
+        .query_map(params![bucket_minutes, from, to, location], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    let (Some(&(first, _)), Some(&(last, _))) = (counts.first(), counts.last()) else {
+        return Ok(Vec::new());
+    };
+    let mut counts = counts.into_iter().peekable();
+    let buckets = (first..=last)
+        .step_by(bucket_minutes as usize)
+        .map(|bucket| HistogramBucket {
+            start: format!("{:02}:{:02}", bucket / 60, bucket % 60),
+            count: counts
+                .next_if(|&(b, _)| b == bucket)
+                .map_or(0, |(_, count)| count),
+        })
+        .collect();
+
+    Ok(buckets)
+}
+
+/// Time at one location within a range.
+#[derive(Serialize)]
+pub struct LocationTotal {
+    /// `None` for sessions that didn't match a named location.
+    pub location: Option<String>,
+    pub total_minutes: i64,
+    pub total_hours: f64,
+    /// Days with any time there.
+    pub days: i64,
+    /// Fraction of all the time in the range, from 0 to 1.
+    pub share: f64,
+}
+
+/// Time per location between `from` and `to` inclusive, most first.
+pub fn location_breakdown(db: &Connection, from: &str, to: &str) -> Result<Vec<LocationTotal>> {
+    let mut stmt = db.prepare(&format!(
+        "SELECT s.label, SUM({}) AS minutes, COUNT(DISTINCT s.date)
+         FROM sessions s
+         WHERE s.date BETWEEN ?1 AND ?2
+         GROUP BY s.label
+         HAVING minutes > 0
+         ORDER BY minutes DESC, s.label",
+        MINUTES_SQL
+    ))?;
+    let mut locations = stmt
+        .query_map([from, to], |row| {
+            let total_minutes: i64 = row.get(1)?;
+            Ok(LocationTotal {
+                location: row.get(0)?,
+                total_minutes,
+                total_hours: total_minutes as f64 / 60.0,
+                days: row.get(2)?,
+                share: 0.0,
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+    let total: i64 = locations
+        .iter()
+        .map(|location| location.total_minutes)
+        .sum();
+    for location in &mut locations {
+        location.share = location.total_minutes as f64 / total as f64;
+    }
+    Ok(locations)
+}
+
+/// A day in a trend, with averages over the window ending on it.
+#[derive(Serialize)]
+pub struct TrendPoint {
+    pub date: String,
+    pub hours: f64,
+    /// Mean hours per calendar day, so days away pull it down too.
+    pub average_hours: f64,
+    /// Mean arrival over the days with presence, as HH:MM.
+    pub average_arrival: Option<String>,
+}
+
+/// Rolling averages over `window` days (e.g. 7 or 30) for each day from
+/// `from` to `to`, looking back before `from` to fill the first windows.
+pub fn trends(
+    db: &Connection,
+    from: NaiveDate,
+    to: NaiveDate,
+    window: u32,
+    boundary: DayBoundary,
+    location: Option<&str>,
+) -> Result<Vec<TrendPoint>> {
+    let window = window.max(1) as usize;
+    let start = from - Duration::days(window as i64 - 1);
+    let days: HashMap<String, DayTotal> = day_totals(
+        db,
+        &start.format("%Y-%m-%d").to_string(),
+        &to.format("%Y-%m-%d").to_string(),
+        location,
+    )?
+    .into_iter()
+    .map(|day| (day.date.clone(), day))
+    .collect();
+    let series: Vec<(String, i64, Option<i64>)> = start
+        .iter_days()
+        .take_while(|date| *date <= to)
+        .map(|date| {
+            let date = date.format("%Y-%m-%d").to_string();
+            let (minutes, arrival) = days.get(&date).map_or((0, None), |day| {
+                (day.total_minutes, boundary.minute_of_day(&day.earliest))
+            });
+            (date, minutes, arrival)
+        })
+        .collect();
+
+    let mut points = Vec::new();
+    let (mut minutes, mut arrivals, mut arrival_count) = (0, 0, 0);
+    for (index, (date, day_minutes, arrival)) in series.iter().enumerate() {
+        minutes += day_minutes;
+        if let Some(arrival) = arrival {
+            arrivals += arrival;
+            arrival_count += 1;
+        }
+        if index >= window {
+            let (_, old_minutes, old_arrival) = &series[index - window];
+            minutes -= old_minutes;
+            if let Some(old_arrival) = old_arrival {
+                arrivals -= old_arrival;
+                arrival_count -= 1;
+            }
+        }
+        if index + 1 >= window {
+            points.push(TrendPoint {
+                date: date.clone(),
+                hours: *day_minutes as f64 / 60.0,
+                average_hours: minutes as f64 / 60.0 / window as f64,
+                average_arrival: (arrival_count > 0)
+                    .then(|| format_minute_of_day(arrivals / arrival_count)),
+            });
+        }
+    }
+    Ok(points)
+}
+
+/// How the current week is going against the weekly hours target.
+#[derive(Serialize)]
+pub struct GoalProgress {
+    pub week_start: String,
+    pub target_hours: f64,
+    pub hours_done: f64,
+    pub hours_remaining: f64,
+    /// Where the week will end up if the average so far per weekday holds.
+    pub projected_hours: f64,
+    /// Weekdays this week that are public holidays, which the target is
+    /// reduced by a fifth for each of.
+    pub public_holidays: i64,
+}
+
+pub fn goal_progress(
+    db: &Connection,
+    today: NaiveDate,
+    target_hours: f64,
+    location: Option<&str>,
+) -> Result<GoalProgress> {
+    let week_start = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+    let from = week_start.format("%Y-%m-%d").to_string();
+    let to = today.format("%Y-%m-%d").to_string();
+
+    let minutes: i64 = db.query_row(
+        &format!(
+            "SELECT COALESCE(SUM({}), 0) FROM sessions s WHERE s.date BETWEEN ?1 AND ?2 AND {}",
+            MINUTES_SQL,
+            location_sql("?3")
+        ),
+        params![from, to, location],
+        |row| row.get(0),
+    )?;
+    let hours_done = minutes as f64 / 60.0;
+
+    let friday = (week_start + Duration::days(4))
+        .format("%Y-%m-%d")
+        .to_string();
+    let holidays = holidays::holiday_dates(db, &from, &friday)?;
+    let working_days = 5 - holidays.len() as i64;
+    let target_hours = target_hours * working_days as f64 / 5.0;
+
+    // Today counts as elapsed so a morning's hours don't project a huge week
+    let weekdays_elapsed = today.weekday().number_from_monday().min(5) as i64;
+    let working_days_elapsed =
+        weekdays_elapsed - holidays.iter().filter(|date| **date <= to).count() as i64;
+    let projected_hours = if working_days_elapsed > 0 {
+        hours_done / working_days_elapsed as f64 * working_days as f64
+    } else {
+        hours_done
+    }
+    .max(hours_done);
+
+    Ok(GoalProgress {
+        week_start: from,
+        target_hours,
+        hours_done,
+        hours_remaining: (target_hours - hours_done).max(0.0),
+        projected_hours,
+        public_holidays: holidays.len() as i64,
+    })
+}
+
+/// The current day so far, with open sessions counted up to now rather than
+/// to their last poll.
+#[derive(Serialize)]
+pub struct TodaySummary {
+    pub date: String,
+    /// Arrival and last-seen times as HH:MM, or `None` before arriving.
+    pub earliest: Option<String>,
+    pub latest: Option<String>,
+    pub total_minutes: i64,
+    pub session_open: bool,
+    pub minutes_since_arrival: Option<i64>,
+    /// As in `DayTotal`; one still going isn't known until it's over.
+    pub lunch_minutes: Option<i64>,
+}
+
+/// Summarises the tracking day containing `now`. `live_sessions` are the ids
+/// of sessions still accruing time.
+pub fn today_summary(
+    db: &Connection,
+    now: DateTime<Local>,
+    boundary: DayBoundary,
+    live_sessions: &[i64],
+    location: Option<&str>,
+) -> Result<TodaySummary> {
+    let date = boundary.date_string(now);
+    let arrival: Option<(String, Option<String>, Option<i64>)> = db
+        .query_row(
+            &format!(
+                "SELECT
+                    COALESCE(strftime('%H:%M', earliest_at, 'unixepoch', 'localtime'), earliest),
+                    COALESCE(strftime('%H:%M', latest_at, 'unixepoch', 'localtime'), latest),
+                    earliest_at
+                 FROM {} WHERE date = ?1",
+                days_table("?2")
+            ),
+            params![date, location],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+
+    // Swap each live session's stored minutes for its minutes up to now
+    let mut total_minutes = sessions::total_minutes(db, &date, location)?;
+    let mut session_open = false;
+    for &id in live_sessions {
+        let minutes: Option<(i64, i64)> = db
+            .query_row(
+                &format!(
+                    "SELECT {}, MAX(?2 - s.start_at, 0) / 60 FROM sessions s
+                     WHERE s.id = ?1 AND s.date = ?3 AND {}",
+                    MINUTES_SQL,
+                    location_sql("?4")
+                ),
+                params![id, now.timestamp(), date, location],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        if let Some((stored, live)) = minutes {
+            total_minutes += live - stored;
+            session_open = true;
+        }
+    }
+
+    let lunch_minutes = match lunch::load(db)? {
+        Some(lunch) => lunch::minutes_on(db, &lunch, &date, location)?,
+        None => None,
+    };
+    let (earliest, latest, earliest_at) = match arrival {
+        Some((earliest, latest, earliest_at)) => (Some(earliest), latest, earliest_at),
+        None => (None, None, None),
+    };
+    Ok(TodaySummary {
+        date,
+        earliest,
+        latest: if session_open {
+            Some(now.format("%H:%M").to_string())
+        } else {
+            latest
+        },
+        total_minutes,
+        session_open,
+        minutes_since_arrival: earliest_at.map(|at| (now.timestamp() - at).max(0) / 60),
+        lunch_minutes,
+    })
+}
+
+/// Per-day figures for every recorded day between `from` and `to` inclusive.
+pub fn day_totals(
+    db: &Connection,
+    from: &str,
+    to: &str,
+    location: Option<&str>,
+) -> Result<Vec<DayTotal>> {
+    let mut stmt = db.prepare(&format!(
+        "SELECT date,
+            COALESCE(strftime('%H:%M', earliest_at, 'unixepoch', 'localtime'), earliest),
+            COALESCE(strftime('%H:%M', latest_at, 'unixepoch', 'localtime'), latest),
+            COALESCE((SELECT SUM({0}) FROM sessions s WHERE s.date = d.date AND {1}), 0),
+            (SELECT {2} FROM sessions s WHERE s.date = d.date AND {1})
+         FROM {3} d
+         WHERE date BETWEEN ?1 AND ?2
+         ORDER BY date",
+        MINUTES_SQL,
+        location_sql("?3"),
+        active_minutes_sql(),
+        days_table("?3")
+    ))?;
+    let days = stmt.query_map(params![from, to, location], |row| {
+        Ok(DayTotal {
+            date: row.get(0)?,
+            earliest: row.get(1)?,
+            latest: row.get(2)?,
+            total_minutes: row.get(3)?,
+            active_minutes: row.get(4)?,
+            lunch_minutes: None,
+        })
+    })?;
+    let mut days = days.collect::<Result<Vec<_>>>()?;
+
+    if let Some(lunch) = lunch::load(db)? {
+        for day in &mut days {
+            day.lunch_minutes = lunch::minutes_on(db, &lunch, &day.date, location)?;
+        }
+    }
+    Ok(days)
+}
+
+/// Formats a duration as e.g. "7h 05m".
+pub fn format_minutes(minutes: i64) -> String {
+    format!("{}h {:02}m", minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+    use crate::sessions::{local_end_epoch_sql, local_epoch_sql, SOURCE_MANUAL};
+
+    fn db() -> Connection {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        db
+    }
+
+    /// Records a session at `label` and widens its day to cover it.
+    fn add(db: &Connection, date: &str, start: &str, end: &str, label: Option<&str>) {
+        db.execute(
+            &format!(
+                "INSERT INTO sessions (date, start, end, start_at, end_at, source, label)
+                 VALUES (?1, ?2, ?3, {}, {}, ?4, ?5)",
+                local_epoch_sql("?1", "?2"),
+                local_end_epoch_sql("?1", "?2", "?3")
+            ),
+            params![date, start, end, SOURCE_MANUAL, label],
+        )
+        .unwrap();
+        db.execute(
+            &format!(
+                "INSERT INTO connections (date, earliest, latest, earliest_at, latest_at)
+                 VALUES (?1, ?2, ?3, {0}, {1})
+                 ON CONFLICT(date) DO UPDATE SET
+                    earliest = MIN(earliest, ?2), latest = MAX(latest, ?3),
+                    earliest_at = MIN(earliest_at, {0}), latest_at = MAX(latest_at, {1})",
+                local_epoch_sql("?1", "?2"),
+                local_end_epoch_sql("?1", "?2", "?3")
+            ),
+            params![date, start, end],
+        )
+        .unwrap();
+    }
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn weekly_summary_covers_monday_to_sunday() {
+        let db = db();
+        // Either side of the week of Monday 2024-01-08
+        add(&db, "2024-01-07", "10:00", "12:00", None);
+        add(&db, "2024-01-15", "10:00", "12:00", None);
+        add(&db, "2024-01-08", "09:00", "12:00", Some("HQ"));
+        add(&db, "2024-01-08", "12:45", "17:00", Some("Lab"));
+        add(&db, "2024-01-14", "10:00", "14:00", Some("HQ"));
+        let boundary = DayBoundary { hour: 4 };
+
+        let week =
+            weekly_summary(&db, date("2024-01-08"), date("2024-01-14"), boundary, None).unwrap();
+        assert_eq!(week.week_end, "2024-01-14");
+        assert_eq!(week.office_days, 2);
+        assert_eq!(week.total_minutes, 180 + 255 + 240);
+        assert_eq!(week.average_arrival.as_deref(), Some("09:30"));
+        assert_eq!(week.days[0].lunch_minutes, Some(45));
+        assert_eq!(week.active_minutes, None);
+
+        let lab = weekly_summary(
+            &db,
+            date("2024-01-08"),
+            date("2024-01-14"),
+            boundary,
+            Some("Lab"),
+        )
+        .unwrap();
+        assert_eq!(lab.office_days, 1);
+        assert_eq!(lab.total_minutes, 255);
+        assert_eq!(lab.days[0].earliest, "12:45");
+
+        let locations = location_breakdown(&db, "2024-01-08", "2024-01-14").unwrap();
+        let split: Vec<(Option<&str>, i64, i64)> = locations
+            .iter()
+            .map(|total| (total.location.as_deref(), total.total_minutes, total.days))
+            .collect();
+        assert_eq!(split, vec![(Some("HQ"), 420, 2), (Some("Lab"), 255, 1)]);
+    }
+
+    #[test]
+    fn monthly_summary_breaks_down_five_and_six_week_months() {
+        let db = db();
+        // February 2024 starts on a Thursday and September 2024 on a Sunday
+        for day in ["2024-01-31", "2024-02-01", "2024-02-08", "2024-02-15"] {
+            add(&db, day, "09:00", "17:00", None);
+        }
+        for day in ["2024-02-22", "2024-02-29"] {
+            add(&db, day, "09:00", "13:00", None);
+        }
+        for day in [
+            "2024-08-31",
+            "2024-09-01",
+            "2024-09-08",
+            "2024-09-15",
+            "2024-09-22",
+            "2024-09-29",
+            "2024-09-30",
+        ] {
+            add(&db, day, "09:00", "17:00", None);
+        }
+        let today = date("2024-10-01");
+
+        let february = monthly_summary(&db, 2024, 2, today, None).unwrap();
+        let weeks: Vec<(&str, i64)> = february
+            .weeks
+            .iter()
+            .map(|week| (week.week_start.as_str(), week.office_days))
+            .collect();
+        assert_eq!(
+            weeks,
+            vec![
+                ("2024-01-29", 1),
+                ("2024-02-05", 1),
+                ("2024-02-12", 1),
+                ("2024-02-19", 1),
+                ("2024-02-26", 1),
+            ]
+        );
+        assert_eq!(february.office_days, 5);
+        assert_eq!(february.total_minutes, 3 * 480 + 2 * 240);
+        assert_eq!(february.longest_day.unwrap().date, "2024-02-01");
+        assert_eq!(february.shortest_day.unwrap().date, "2024-02-22");
+
+        let september = monthly_summary(&db, 2024, 9, today, None).unwrap();
+        assert_eq!(september.weeks.len(), 6);
+        assert_eq!(september.weeks[0].week_start, "2024-08-26");
+        assert_eq!(september.weeks[0].office_days, 1);
+        assert_eq!(september.weeks[5].week_start, "2024-09-30");
+
+        assert!(monthly_summary(&db, 2024, 13, today, None).is_err());
+        assert!(heatmap(&db, i32::MAX, None).is_err());
+        let year = heatmap(&db, 2024, None).unwrap();
+        assert_eq!(year.len(), 366);
+        assert_eq!(year[59].date, "2024-02-29");
+        assert_eq!(year[59].hours, 4.0);
+    }
+
+    #[test]
+    fn streaks_are_broken_by_missed_weekdays_and_optionally_weekends() {
+        let db = db();
+        for day in [
+            "2024-01-08",
+            "2024-01-09",
+            "2024-01-10",
+            "2024-01-11",
+            "2024-01-12",
+            "2024-01-15",
+            "2024-01-16",
+            // Wednesday the 17th off, e.g. a holiday
+            "2024-01-18",
+        ] {
+            add(&db, day, "09:00", "17:00", None);
+        }
+        let today = date("2024-01-19");
+
+        let streaks = streaks(&db, today, false, None).unwrap();
+        let runs: Vec<(&str, &str, i64)> = streaks
+            .history
+            .iter()
+            .map(|streak| (streak.start.as_str(), streak.end.as_str(), streak.days))
+            .collect();
+        assert_eq!(
+            runs,
+            vec![
+                ("2024-01-08", "2024-01-16", 7),
+                ("2024-01-18", "2024-01-18", 1)
+            ]
+        );
+        assert_eq!((streaks.current, streaks.longest), (1, 7));
+
+        let weekends_break = super::streaks(&db, today, true, None).unwrap();
+        let lengths: Vec<i64> = weekends_break
+            .history
+            .iter()
+            .map(|streak| streak.days)
+            .collect();
+        assert_eq!(lengths, vec![5, 2, 1]);
+    }
+
+    #[test]
+    fn histograms_and_trends_fill_gaps() {
+        let db = db();
+        add(&db, "2024-01-08", "09:00", "17:00", None);
+        add(&db, "2024-01-09", "09:10", "17:10", None);
+        add(&db, "2024-01-11", "10:10", "14:10", None);
+
+        let buckets = arrival_histogram(&db, 30, None, None, None).unwrap();
+        let counts: Vec<(&str, i64)> = buckets
+            .iter()
+            .map(|bucket| (bucket.start.as_str(), bucket.count))
+            .collect();
+        assert_eq!(counts, vec![("09:00", 2), ("09:30", 0), ("10:00", 1)]);
+
+        let boundary = DayBoundary { hour: 4 };
+        let points = trends(
+            &db,
+            date("2024-01-10"),
+            date("2024-01-11"),
+            3,
+            boundary,
+            None,
+        )
+        .unwrap();
+        assert_eq!(points.len(), 2);
+        // 8h + 8h + nothing, then 8h + nothing + 4h
+        assert_eq!(points[0].average_hours, 16.0 / 3.0);
+        assert_eq!(points[0].average_arrival.as_deref(), Some("09:05"));
+        assert_eq!(points[1].hours, 4.0);
+        assert_eq!(points[1].average_hours, 4.0);
+    }
+}
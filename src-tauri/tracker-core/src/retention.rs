@@ -0,0 +1,162 @@
+use chrono::{Datelike, Months, NaiveDate};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::error::Result;
+use crate::{audit, export, settings};
+
+const PREFIX: &str = "history-before-";
+const EXTENSION: &str = ".json.gz";
+
+#[derive(Debug, Serialize)]
+pub struct PruneStats {
+    /// The archive's file name, or `None` if there was nothing to prune.
+    pub archive: Option<String>,
+    pub connections: usize,
+    pub sessions: usize,
+}
+
+pub fn archives_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("archives")
+}
+
+/// The first day to keep, or `None` if history is kept forever. Rounded down
+/// to the start of the month so history is archived a month at a time rather
+/// than a day at a time.
+pub fn cutoff(db: &Connection, today: NaiveDate) -> Result<Option<NaiveDate>> {
+    let months = settings::get_f64(db, settings::RETENTION_MONTHS, 0.0)? as u32;
+    if months == 0 {
+        return Ok(None);
+    }
+    Ok(today
+        .with_day(1)
+        .and_then(|month| month.checked_sub_months(Months::new(months))))
+}
+
+/// Archives every day before `before` into a gzipped JSON export in `dir`,
+/// which `export::import_json` can restore, then deletes them. Nothing is
+/// deleted unless the archive was written. Day tags are kept.
+pub fn prune(db: &Connection, before: NaiveDate, dir: &Path) -> Result<PruneStats> {
+    let before = before.to_string();
+    let tx = db.unchecked_transaction()?;
+    let count = |table: &str| -> rusqlite::Result<usize> {
+        tx.query_row(
+            &format!("SELECT COUNT(*) FROM {} WHERE date < ?1", table),
+            [&before],
+            |row| row.get(0),
+        )
+    };
+    let (connections, sessions) = (count("connections")?, count("sessions")?);
+    if connections == 0 && sessions == 0 {
+        return Ok(PruneStats {
+            archive: None,
+            connections,
+            sessions,
+        });
+    }
+
+    fs::create_dir_all(dir)?;
+    let mut name = format!("{}{}{}", PREFIX, before, EXTENSION);
+    let mut copy = 1;
+    while dir.join(&name).exists() {
+        copy += 1;
+        name = format!("{}{}-{}{}", PREFIX, before, copy, EXTENSION);
+    }
+    export::archive_json(&tx, &before, &dir.join(&name))?;
+    audit::set_source(&tx, audit::SOURCE_RETENTION)?;
+
+    // Forgotten rather than deleted as far as other devices are concerned,
+    // so pruning here doesn't prune there too
+    for table in ["synced_sessions", "sync_records"] {
+        tx.execute(
+            &format!(
+                "DELETE FROM {} WHERE session_id IN (SELECT id FROM sessions WHERE date < ?1)",
+                table
+            ),
+            [&before],
+        )?;
+    }
+    tx.execute("DELETE FROM sessions WHERE date < ?1", [&before])?;
+    tx.execute("DELETE FROM connections WHERE date < ?1", [&before])?;
+    tx.execute("DELETE FROM calendar_events WHERE date < ?1", [&before])?;
+    audit::clear_source(&tx)?;
+    tx.commit()?;
+    info!(
+        "Archived {} days and {} sessions to {}",
+        connections, sessions, name
+    );
+    Ok(PruneStats {
+        archive: Some(name),
+        connections,
+        sessions,
+    })
+}
+
+/// Prunes history older than the configured age, if there's a limit.
+pub fn prune_if_due(db: &Connection, today: NaiveDate, dir: &Path) -> Result<Option<PruneStats>> {
+    match cutoff(db, today)? {
+        Some(before) => prune(db, before, dir).map(Some),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::MergeStrategy;
+    use crate::migrations;
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn cutoff_is_the_start_of_a_month() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        assert_eq!(cutoff(&db, date("2024-03-20")).unwrap(), None);
+        settings::set(&db, settings::RETENTION_MONTHS, "24").unwrap();
+        assert_eq!(
+            cutoff(&db, date("2024-03-20")).unwrap(),
+            Some(date("2022-03-01"))
+        );
+    }
+
+    #[test]
+    fn pruned_days_can_be_restored_from_the_archive() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        for day in ["2022-02-28", "2022-03-01"] {
+            db.execute(
+                "INSERT INTO connections (date, earliest, latest) VALUES (?1, '09:00', '17:00')",
+                [day],
+            )
+            .unwrap();
+            db.execute(
+                "INSERT INTO sessions (date, start, end) VALUES (?1, '09:00', '17:00')",
+                [day],
+            )
+            .unwrap();
+        }
+        let dir = std::env::temp_dir().join(format!("tracker-archives-{}", uuid::Uuid::new_v4()));
+
+        let stats = prune(&db, date("2022-03-01"), &dir).unwrap();
+        assert_eq!((stats.connections, stats.sessions), (1, 1));
+        let remaining: i64 = db
+            .query_row("SELECT COUNT(*) FROM connections", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(remaining, 1);
+        assert!(prune(&db, date("2022-03-01"), &dir)
+            .unwrap()
+            .archive
+            .is_none());
+
+        let archive = dir.join(stats.archive.unwrap());
+        let restored = export::import_json(&mut db, &archive, MergeStrategy::KeepEarliest).unwrap();
+        assert_eq!((restored.connections, restored.sessions), (1, 1));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,97 @@
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::settings;
+
+pub const MAX_ROUNDING_MINUTES: i64 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundingMode {
+    Nearest,
+    Up,
+    Down,
+}
+
+/// Rounding durations to a multiple of `minutes`, e.g. up to the next 15.
+/// Only ever applied to figures on the way out; stored times stay exact.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rounding {
+    pub minutes: i64,
+    pub mode: RoundingMode,
+}
+
+impl Rounding {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=MAX_ROUNDING_MINUTES).contains(&self.minutes) {
+            return Err(format!(
+                "Rounding must be to between 1 and {} minutes",
+                MAX_ROUNDING_MINUTES
+            ));
+        }
+        Ok(())
+    }
+
+    /// `minutes` rounded to a multiple of the increment. Halfway rounds up.
+    pub fn apply(&self, minutes: i64) -> i64 {
+        let step = self.minutes.max(1);
+        let minutes = minutes.max(0);
+        match self.mode {
+            RoundingMode::Nearest => (minutes + step / 2) / step * step,
+            RoundingMode::Up => (minutes + step - 1) / step * step,
+            RoundingMode::Down => minutes / step * step,
+        }
+    }
+
+    /// SQL rounding the integer `minutes` expression like `apply`.
+    pub fn sql(&self, minutes: &str) -> String {
+        let step = self.minutes.max(1);
+        let offset = match self.mode {
+            RoundingMode::Nearest => step / 2,
+            RoundingMode::Up => step - 1,
+            RoundingMode::Down => 0,
+        };
+        format!("((MAX({}, 0) + {}) / {} * {})", minutes, offset, step, step)
+    }
+}
+
+/// The rounding applied to summaries and exports, if any.
+pub fn load(db: &Connection) -> rusqlite::Result<Option<Rounding>> {
+    Ok(settings::get(db, settings::REPORT_ROUNDING)?
+        .and_then(|json| serde_json::from_str(&json).ok()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_to_increment() {
+        let rounding = |mode| Rounding { minutes: 15, mode };
+        let nearest = rounding(RoundingMode::Nearest);
+        assert_eq!(nearest.apply(488), 495);
+        assert_eq!(nearest.apply(487), 480);
+        assert_eq!(nearest.apply(0), 0);
+        assert_eq!(rounding(RoundingMode::Up).apply(481), 495);
+        assert_eq!(rounding(RoundingMode::Up).apply(480), 480);
+        assert_eq!(rounding(RoundingMode::Down).apply(494), 480);
+    }
+
+    #[test]
+    fn sql_matches_apply() {
+        let db = Connection::open_in_memory().unwrap();
+        for mode in [RoundingMode::Nearest, RoundingMode::Up, RoundingMode::Down] {
+            let rounding = Rounding { minutes: 30, mode };
+            for minutes in [0, 14, 15, 29, 30, 31, 44, 46] {
+                let rounded: i64 = db
+                    .query_row(
+                        &format!("SELECT {}", rounding.sql("?1")),
+                        [minutes],
+                        |row| row.get(0),
+                    )
+                    .unwrap();
+                assert_eq!(rounded, rounding.apply(minutes), "{:?} {}", mode, minutes);
+            }
+        }
+    }
+}
@@ -0,0 +1,391 @@
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+use crate::bluetooth::{self, BluetoothDevice};
+use crate::detector::{link_is_up, Presence, PresenceDetector};
+use crate::error::Result;
+use crate::network::{self, Cidr, InterfaceAddress};
+use crate::pattern::SsidPattern;
+use crate::settings::{DEFAULT_PROBE_TIMEOUT_MS, MAX_PROBE_TIMEOUT_MS};
+use crate::wifi::{self, WifiConnection};
+
+/// A named definition of presence, e.g. "CorpNet at HQ" as "SSID matches
+/// CorpNet AND gateway MAC is X".
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Rule {
+    /// Recorded against the sessions the rule matched.
+    pub name: String,
+    pub label: Option<String>,
+    pub condition: Condition,
+}
+
+/// Something about the machine's network that can be checked, stored as
+/// JSON tagged by `type`, e.g. `{"type": "ssid", "pattern": "CorpNet"}`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Condition {
+    /// Connected to WiFi whose SSID matches; see `SsidPattern`.
+    Ssid {
+        pattern: String,
+    },
+    /// Connected to WiFi through an access point whose MAC starts with
+    /// `prefix`.
+    Bssid {
+        prefix: String,
+    },
+    /// The default gateway has this MAC address.
+    GatewayMac {
+        mac: String,
+    },
+    /// The default gateway has this IP address.
+    Gateway {
+        address: String,
+    },
+    /// One of the machine's addresses is in this CIDR block.
+    Subnet {
+        cidr: String,
+    },
+    /// This interface has an active link.
+    Interface {
+        name: String,
+    },
+    /// "host:port" accepts a TCP connection within the timeout.
+    Reachable {
+        address: String,
+        #[serde(default = "default_timeout_ms")]
+        timeout_ms: u32,
+    },
+    /// A Bluetooth device with this address or name is connected or nearby.
+    Bluetooth {
+        device: String,
+    },
+    All {
+        conditions: Vec<Condition>,
+    },
+    Any {
+        conditions: Vec<Condition>,
+    },
+}
+
+fn default_timeout_ms() -> u32 {
+    DEFAULT_PROBE_TIMEOUT_MS
+}
+
+/// A condition parsed and ready to evaluate.
+#[derive(Clone, Debug)]
+enum Check {
+    Ssid(SsidPattern),
+    Bssid(String),
+    GatewayMac(String),
+    Gateway(IpAddr),
+    Subnet(Cidr),
+    Interface(String),
+    Reachable(String, Duration),
+    Bluetooth(String),
+    All(Vec<Check>),
+    Any(Vec<Check>),
+}
+
+impl Condition {
+    fn compile(&self) -> Result<Check, String> {
+        Ok(match self {
+            Condition::Ssid { pattern } => Check::Ssid(SsidPattern::parse(pattern)?),
+            Condition::Bssid { prefix } => Check::Bssid(wifi::normalize_mac(prefix)),
+            Condition::GatewayMac { mac } => {
+                network::validate_mac(mac)?;
+                Check::GatewayMac(wifi::normalize_mac(mac))
+            }
+            Condition::Gateway { address } => Check::Gateway(
+                address
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Invalid gateway address '{}'", address))?,
+            ),
+            Condition::Subnet { cidr } => Check::Subnet(Cidr::parse(cidr)?),
+            Condition::Interface { name } if name.trim().is_empty() => {
+                return Err("Interface name is empty".to_string())
+            }
+            Condition::Interface { name } => Check::Interface(name.trim().to_string()),
+            Condition::Reachable {
+                address,
+                timeout_ms,
+            } => {
+                network::parse_host_port(address)?;
+                if !(1..=MAX_PROBE_TIMEOUT_MS).contains(timeout_ms) {
+                    return Err(format!("Invalid probe timeout {}ms", timeout_ms));
+                }
+                Check::Reachable(
+                    address.trim().to_string(),
+                    Duration::from_millis((*timeout_ms).into()),
+                )
+            }
+            Condition::Bluetooth { device } if device.trim().is_empty() => {
+                return Err("Bluetooth device is empty".to_string())
+            }
+            Condition::Bluetooth { device } => Check::Bluetooth(device.trim().to_string()),
+            Condition::All { conditions } | Condition::Any { conditions }
+                if conditions.is_empty() =>
+            {
+                return Err("A combined condition needs at least one condition".to_string())
+            }
+            Condition::All { conditions } => Check::All(
+                conditions
+                    .iter()
+                    .map(Condition::compile)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Condition::Any { conditions } => Check::Any(
+                conditions
+                    .iter()
+                    .map(Condition::compile)
+                    .collect::<Result<_, _>>()?,
+            ),
+        })
+    }
+}
+
+/// Checks `rules` can be evaluated, describing the first problem found.
+pub fn validate(rules: &[Rule]) -> Result<(), String> {
+    RulesDetector::new(rules).map(|_| ())
+}
+
+/// Network state looked up during one poll, kept so conditions shared
+/// between rules don't repeat the lookup.
+#[derive(Default)]
+struct Observations {
+    wifi: Option<Option<WifiConnection>>,
+    gateway: Option<Option<IpAddr>>,
+    gateway_mac: Option<Option<String>>,
+    addresses: Option<Vec<InterfaceAddress>>,
+    bluetooth: Option<Vec<BluetoothDevice>>,
+    /// The SSID of the WiFi network a condition matched.
+    matched_ssid: Option<String>,
+}
+
+impl Observations {
+    fn wifi(&mut self) -> Result<Option<&WifiConnection>> {
+        if self.wifi.is_none() {
+            self.wifi = Some(wifi::get_current_wifi()?);
+        }
+        Ok(self.wifi.as_ref().and_then(Option::as_ref))
+    }
+
+    fn gateway(&mut self) -> Result<Option<IpAddr>> {
+        if self.gateway.is_none() {
+            self.gateway = Some(network::default_gateway()?.map(|gateway| gateway.ip));
+        }
+        Ok(self.gateway.flatten())
+    }
+
+    fn gateway_mac(&mut self) -> Result<Option<&str>> {
+        if self.gateway_mac.is_none() {
+            let mac = match self.gateway()? {
+                Some(ip) => network::neighbor_mac(ip)?,
+                None => None,
+            };
+            self.gateway_mac = Some(mac);
+        }
+        Ok(self.gateway_mac.as_ref().and_then(Option::as_deref))
+    }
+
+    fn addresses(&mut self) -> Result<&[InterfaceAddress]> {
+        if self.addresses.is_none() {
+            self.addresses = Some(network::interface_addresses()?);
+        }
+        Ok(self.addresses.as_deref().unwrap_or_default())
+    }
+
+    fn bluetooth(&mut self) -> Result<&[BluetoothDevice]> {
+        if self.bluetooth.is_none() {
+            self.bluetooth = Some(bluetooth::nearby_devices()?);
+        }
+        Ok(self.bluetooth.as_deref().unwrap_or_default())
+    }
+}
+
+impl Check {
+    /// Whether the condition holds. A lookup that fails, e.g. WiFi with the
+    /// adapter switched off, counts as not holding so that other branches
+    /// of an `Any` still get a chance.
+    fn holds(&self, observations: &mut Observations) -> bool {
+        self.evaluate(observations).unwrap_or_else(|e| {
+            warn!("Couldn't evaluate condition: {}", e);
+            false
+        })
+    }
+
+    fn evaluate(&self, observations: &mut Observations) -> Result<bool> {
+        Ok(match self {
+            Check::Ssid(pattern) => {
+                let ssid = observations
+                    .wifi()?
+                    .map(|connection| connection.ssid.clone())
+                    .filter(|ssid| pattern.matches(ssid));
+                let matched = ssid.is_some();
+                if matched {
+                    observations.matched_ssid = ssid;
+                }
+                matched
+            }
+            Check::Bssid(prefix) => observations
+                .wifi()?
+                .and_then(|connection| connection.bssid.as_deref())
+                .is_some_and(|bssid| bssid.starts_with(prefix.as_str())),
+            Check::GatewayMac(mac) => observations.gateway_mac()? == Some(mac.as_str()),
+            Check::Gateway(address) => observations.gateway()? == Some(*address),
+            Check::Subnet(cidr) => observations
+                .addresses()?
+                .iter()
+                .any(|address| cidr.contains(address.ip)),
+            Check::Interface(name) => link_is_up(name)?,
+            Check::Reachable(address, timeout) => network::can_connect(address, *timeout),
+            Check::Bluetooth(device) => observations
+                .bluetooth()?
+                .iter()
+                .any(|nearby| nearby.matches(device)),
+            Check::All(checks) => checks.iter().all(|check| check.holds(observations)),
+            Check::Any(checks) => checks.iter().any(|check| check.holds(observations)),
+        })
+    }
+}
+
+struct CompiledRule {
+    name: String,
+    label: Option<String>,
+    check: Check,
+}
+
+/// Present while any of the rules holds, reporting the first that does.
+pub struct RulesDetector {
+    rules: Vec<CompiledRule>,
+}
+
+impl RulesDetector {
+    pub fn new(rules: &[Rule]) -> Result<Self, String> {
+        let mut compiled: Vec<CompiledRule> = Vec::new();
+        for rule in rules {
+            let name = rule.name.trim();
+            if name.is_empty() {
+                return Err("Every rule needs a name".to_string());
+            }
+            if compiled.iter().any(|other| other.name == name) {
+                return Err(format!("There's more than one rule named '{}'", name));
+            }
+            let check = rule
+                .condition
+                .compile()
+                .map_err(|e| format!("Rule '{}': {}", name, e))?;
+            compiled.push(CompiledRule {
+                name: name.to_string(),
+                label: rule.label.clone(),
+                check,
+            });
+        }
+        Ok(Self { rules: compiled })
+    }
+}
+
+impl PresenceDetector for RulesDetector {
+    fn name(&self) -> &'static str {
+        "rules"
+    }
+
+    fn detect(&self) -> Result<Option<Presence>> {
+        let mut observations = Observations::default();
+        for rule in &self.rules {
+            observations.matched_ssid = None;
+            if rule.check.holds(&mut observations) {
+                debug!("Rule '{}' matched", rule.name);
+                return Ok(Some(Presence {
+                    ssid: observations.matched_ssid,
+                    label: rule.label.clone(),
+                    vpn: false,
+                    rule: Some(rule.name.clone()),
+                    idle: false,
+                    active: None,
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(condition: serde_json::Value) -> Rule {
+        serde_json::from_value(serde_json::json!({
+            "name": "HQ",
+            "label": null,
+            "condition": condition,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_nested_conditions() {
+        let rule = rule(serde_json::json!({
+            "type": "any",
+            "conditions": [
+                {"type": "ssid", "pattern": "eduroam"},
+                {
+                    "type": "all",
+                    "conditions": [
+                        {"type": "gateway-mac", "mac": "AA-BB-CC-DD-EE-FF"},
+                        {"type": "reachable", "address": "printer.local:631"},
+                    ],
+                },
+            ],
+        }));
+
+        let Condition::Any { conditions } = &rule.condition else {
+            panic!("expected any, got {:?}", rule.condition);
+        };
+        assert_eq!(
+            conditions[1],
+            Condition::All {
+                conditions: vec![
+                    Condition::GatewayMac {
+                        mac: "AA-BB-CC-DD-EE-FF".to_string()
+                    },
+                    Condition::Reachable {
+                        address: "printer.local:631".to_string(),
+                        timeout_ms: DEFAULT_PROBE_TIMEOUT_MS,
+                    },
+                ],
+            }
+        );
+        assert!(validate(&[rule]).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        let empty = rule(serde_json::json!({"type": "all", "conditions": []}));
+        assert!(validate(&[empty]).is_err());
+
+        let bad_mac = rule(serde_json::json!({"type": "gateway-mac", "mac": "router"}));
+        assert!(validate(&[bad_mac]).is_err());
+
+        let ssid = rule(serde_json::json!({"type": "ssid", "pattern": "CorpNet"}));
+        assert!(validate(&[ssid.clone(), ssid]).is_err());
+    }
+
+    #[test]
+    fn reachable_host_matches() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let rules = [rule(serde_json::json!({
+            "type": "all",
+            "conditions": [{
+                "type": "reachable",
+                "address": listener.local_addr().unwrap().to_string(),
+                "timeout_ms": 1000,
+            }],
+        }))];
+
+        let presence = RulesDetector::new(&rules).unwrap().detect().unwrap();
+        assert_eq!(presence.and_then(|p| p.rule).as_deref(), Some("HQ"));
+    }
+}
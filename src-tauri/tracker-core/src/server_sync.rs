@@ -0,0 +1,364 @@
+//! Syncs sessions between any number of devices through a small REST server
+//! that only ever sees them encrypted.
+//!
+//! Devices are paired by sharing a secret, as a pairing code. The server is
+//! told an account and a token derived from it; the key that encrypts each
+//! record is derived from it too and never leaves the devices. The server
+//! needs two endpoints, both authorized with `Authorization: Bearer <token>`:
+//!
+//! - `POST <server>/accounts/<account>/records` with
+//!   `{"records": [{"id", "updated_at", "data"}]}` keeps each record unless
+//!   it already has a later `updated_at` for that id, giving it the
+//!   account's next sequence number. The first token used claims the
+//!   account.
+//! - `GET <server>/accounts/<account>/records?after=<seq>` returns
+//!   `{"records": [{"id", "updated_at", "data", "seq"}]}`, the records kept
+//!   since `seq` in order, as many as it likes at a time.
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use chrono::Utc;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::error::Result;
+use crate::folder_sync::{self, Record, SyncStats};
+use crate::{audit, keychain, settings};
+
+/// Keychain account holding the secret shared by paired devices.
+pub const SECRET_ACCOUNT: &str = "sync_server";
+const SECRET_LENGTH: usize = 32;
+const NONCE_LENGTH: usize = 12;
+/// Records sent per request.
+const BATCH: usize = 500;
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// A configured server and the credentials derived from the pairing secret.
+pub struct Server {
+    url: String,
+    account: String,
+    token: String,
+    cipher: ChaCha20Poly1305,
+}
+
+impl Server {
+    fn new(url: &str, secret: &[u8]) -> Self {
+        let derive = |purpose: &str| Sha256::digest([purpose.as_bytes(), secret].concat());
+        Server {
+            url: url.trim().trim_end_matches('/').to_string(),
+            account: format!("{:x}", derive("account")),
+            token: format!("{:x}", derive("token")),
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&derive("key"))),
+        }
+    }
+
+    fn records_url(&self) -> String {
+        format!("{}/accounts/{}/records", self.url, self.account)
+    }
+
+    fn authorization(&self) -> String {
+        format!("Bearer {}", self.token)
+    }
+
+    /// `record` from `device`, encrypted with a random nonce and encoded as
+    /// base64.
+    fn seal(&self, device: &str, record: &Record) -> Result<String> {
+        let plaintext = serde_json::to_vec(&json!({ "device": device, "record": record }))
+            .map_err(|e| e.to_string())?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|_| "Couldn't encrypt a sync record")?;
+        Ok(STANDARD.encode([nonce.as_slice(), ciphertext.as_slice()].concat()))
+    }
+
+    /// The device and record sealed in `envelope`, or `None` if it wasn't
+    /// sealed with this key or doesn't match the id and time it was sent
+    /// under.
+    fn open(&self, envelope: &Envelope) -> Option<(String, Record)> {
+        let data = STANDARD.decode(&envelope.data).ok()?;
+        if data.len() < NONCE_LENGTH {
+            return None;
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .ok()?;
+        let sealed: Sealed = serde_json::from_slice(&plaintext).ok()?;
+        (sealed.record.id == envelope.id && sealed.record.updated_at == envelope.updated_at)
+            .then_some((sealed.device, sealed.record))
+    }
+}
+
+#[derive(Deserialize)]
+struct Sealed {
+    device: String,
+    record: Record,
+}
+
+/// A record as the server sees it: the id and time are in the clear so it
+/// can keep the latest of each, the rest is encrypted.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    id: String,
+    updated_at: i64,
+    data: String,
+    #[serde(default, skip_serializing)]
+    seq: i64,
+}
+
+#[derive(Deserialize)]
+struct Page {
+    records: Vec<Envelope>,
+}
+
+/// This device's changes waiting to be sent.
+pub struct Outgoing {
+    device: String,
+    records: Vec<Record>,
+}
+
+impl Outgoing {
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+/// Other devices' records received from the server.
+pub struct Incoming {
+    records: Vec<(String, Record)>,
+    cursor: i64,
+}
+
+/// The configured server, or `None` if there isn't one or this device isn't
+/// paired.
+pub fn load(db: &Connection) -> Result<Option<Server>> {
+    let Some(url) = settings::get(db, settings::SYNC_SERVER)?.filter(|url| !url.is_empty()) else {
+        return Ok(None);
+    };
+    let Some(secret) = keychain::get(SECRET_ACCOUNT)? else {
+        return Ok(None);
+    };
+    Ok(Some(Server::new(&url, &decode_code(&secret)?)))
+}
+
+fn decode_code(code: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(code.trim())
+        .ok()
+        .filter(|secret| secret.len() == SECRET_LENGTH)
+        .ok_or_else(|| "Invalid pairing code".into())
+}
+
+/// The code to enter on another device to pair it with this one, making up
+/// the secret if this is the first device.
+pub fn pairing_code(db: &Connection) -> Result<String> {
+    if let Some(secret) = keychain::get(SECRET_ACCOUNT)? {
+        return Ok(secret);
+    }
+    let code = URL_SAFE_NO_PAD.encode(ChaCha20Poly1305::generate_key(&mut OsRng));
+    keychain::set(SECRET_ACCOUNT, &code)?;
+    forget_server(db)?;
+    Ok(code)
+}
+
+/// Pairs this device using the code from another, or unpairs it if `code`
+/// is empty. Everything is sent and received again on the next sync, in
+/// case the new secret is for a different account.
+pub fn pair(db: &Connection, code: &str) -> Result<()> {
+    if code.trim().is_empty() {
+        return keychain::delete(SECRET_ACCOUNT);
+    }
+    decode_code(code)?;
+    keychain::set(SECRET_ACCOUNT, code.trim())?;
+    forget_server(db)
+}
+
+fn forget_server(db: &Connection) -> Result<()> {
+    folder_sync::reshare(db, &folder_sync::device_id(db)?)?;
+    settings::set(db, settings::SYNC_SERVER_CURSOR, "0")?;
+    Ok(())
+}
+
+/// This device's sessions that have changed since they were last sent.
+pub fn outgoing(db: &Connection) -> Result<Outgoing> {
+    let device = folder_sync::device_id(db)?;
+    let records = folder_sync::changes(db, &device, Utc::now().timestamp_millis())?;
+    Ok(Outgoing { device, records })
+}
+
+/// Sends `outgoing` to the server, a batch at a time.
+pub fn push(server: &Server, outgoing: &Outgoing) -> Result<()> {
+    for batch in outgoing.records.chunks(BATCH) {
+        let envelopes = batch
+            .iter()
+            .map(|record| {
+                Ok(Envelope {
+                    id: record.id.clone(),
+                    updated_at: record.updated_at,
+                    data: server.seal(&outgoing.device, record)?,
+                    seq: 0,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        ureq::post(&server.records_url())
+            .set("Authorization", &server.authorization())
+            .timeout(TIMEOUT)
+            .send_json(json!({ "records": envelopes }))
+            .map_err(|e| format!("The sync server rejected {} records: {}", batch.len(), e))?;
+    }
+    Ok(())
+}
+
+/// Records that `outgoing` has been sent.
+pub fn mark_pushed(db: &Connection, outgoing: &Outgoing) -> Result<()> {
+    folder_sync::mark_shared(db, &outgoing.device, &outgoing.records)
+}
+
+/// How far through the server's records this device has read.
+pub fn cursor(db: &Connection) -> Result<i64> {
+    Ok(settings::get_f64(db, settings::SYNC_SERVER_CURSOR, 0.0)? as i64)
+}
+
+/// Every record the server has kept since `after`. Records that can't be
+/// decrypted, e.g. from a device paired under another secret, are skipped.
+/// Fails rather than loop forever if a page doesn't move the cursor on.
+pub fn pull(server: &Server, after: i64) -> Result<Incoming> {
+    let mut incoming = Incoming {
+        records: Vec::new(),
+        cursor: after,
+    };
+    loop {
+        let page: Page = ureq::get(&server.records_url())
+            .query("after", &incoming.cursor.to_string())
+            .set("Authorization", &server.authorization())
+            .timeout(TIMEOUT)
+            .call()
+            .map_err(|e| format!("Couldn't fetch records from the sync server: {}", e))?
+            .into_json()?;
+        if page.records.is_empty() {
+            return Ok(incoming);
+        }
+        let after = incoming.cursor;
+        for envelope in page.records {
+            incoming.cursor = incoming.cursor.max(envelope.seq);
+            match server.open(&envelope) {
+                Some(record) => incoming.records.push(record),
+                None => warn!("Skipping unreadable sync record {}", envelope.id),
+            }
+        }
+        // Asking again would get the same page back forever
+        if incoming.cursor == after {
+            return Err(format!(
+                "The sync server sent records it had already sent, after {}",
+                after
+            )
+            .into());
+        }
+    }
+}
+
+/// Applies the other devices' records in `incoming`, the latest version of
+/// each session winning, and moves the cursor past them. Returns how many
+/// were applied.
+pub fn merge(db: &Connection, incoming: Incoming) -> Result<usize> {
+    let device = folder_sync::device_id(db)?;
+    let tx = db.unchecked_transaction()?;
+    audit::set_source(&tx, audit::SOURCE_SYNC)?;
+    let mut applied = 0;
+    for (other, record) in &incoming.records {
+        if *other != device {
+            folder_sync::apply(&tx, other, record)?;
+            applied += 1;
+        }
+    }
+    settings::set(
+        &tx,
+        settings::SYNC_SERVER_CURSOR,
+        &incoming.cursor.to_string(),
+    )?;
+    audit::clear_source(&tx)?;
+    tx.commit()?;
+    Ok(applied)
+}
+
+/// Sends this device's changes and merges in the other devices', holding
+/// `db` throughout. The app does the same in steps, to talk to the server
+/// without holding the database.
+pub fn sync(db: &Connection, server: &Server) -> Result<SyncStats> {
+    let outgoing = outgoing(db)?;
+    if !outgoing.is_empty() {
+        push(server, &outgoing)?;
+        mark_pushed(db, &outgoing)?;
+    }
+    let incoming = pull(server, cursor(db)?)?;
+    Ok(SyncStats {
+        written: outgoing.len(),
+        read: merge(db, incoming)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sessions::{self, Session};
+
+    fn record(id: &str) -> Record {
+        Record {
+            id: id.to_string(),
+            updated_at: 1_709_542_800_000,
+            session: Some(Session {
+                id: 7,
+                date: "2024-03-04".to_string(),
+                start: "09:00".to_string(),
+                end: "12:00".to_string(),
+                ssid: None,
+                label: None,
+                source: sessions::SOURCE_MANUAL.to_string(),
+                start_at: None,
+                end_at: None,
+                rule: None,
+                idle_seconds: 0,
+                active_seconds: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn only_paired_devices_can_open_records() {
+        let secret = [7; SECRET_LENGTH];
+        let (laptop, desktop) = (
+            Server::new("https://sync.example.com/", &secret),
+            Server::new("https://sync.example.com", &secret),
+        );
+        assert_eq!(laptop.records_url(), desktop.records_url());
+        assert_ne!(laptop.account, laptop.token);
+
+        let mut envelope = Envelope {
+            id: "a".to_string(),
+            updated_at: 1_709_542_800_000,
+            data: laptop.seal("laptop", &record("a")).unwrap(),
+            seq: 1,
+        };
+        let (device, opened) = desktop.open(&envelope).unwrap();
+        assert_eq!((device.as_str(), opened.id.as_str()), ("laptop", "a"));
+        assert!(Server::new("https://sync.example.com", &[8; SECRET_LENGTH])
+            .open(&envelope)
+            .is_none());
+        // The server can't pass one record off as another
+        envelope.id = "b".to_string();
+        assert!(desktop.open(&envelope).is_none());
+    }
+}
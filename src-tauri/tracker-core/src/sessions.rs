@@ -0,0 +1,532 @@
+use chrono::{DateTime, Duration, Local, NaiveDate};
+use rusqlite::{params, Connection, OptionalExtension, Result, Row};
+use serde::{Deserialize, Serialize};
+
+use crate::detector::Presence;
+use crate::settings;
+
+/// A continuous interval of presence within a single day.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: i64,
+    pub date: String,
+    pub start: String,
+    pub end: String,
+    pub ssid: Option<String>,
+    pub label: Option<String>,
+    /// `SOURCE_AUTO` for detected presence, `SOURCE_MANUAL` for timers and
+    /// hand-entered days.
+    #[serde(default = "default_source")]
+    pub source: String,
+    /// Start and end as UTC epoch seconds. `start` and `end` are these in
+    /// the current local time zone. Missing from older exports.
+    #[serde(default)]
+    pub start_at: Option<i64>,
+    #[serde(default)]
+    pub end_at: Option<i64>,
+    /// Name of the detection rule that matched, for rule-based sessions.
+    #[serde(default)]
+    pub rule: Option<String>,
+    /// Time within the session with no keyboard or mouse input, when the
+    /// idle policy flags rather than excludes it.
+    #[serde(default)]
+    pub idle_seconds: i64,
+    /// Time within the session with keyboard or mouse input, for focus
+    /// hours. `None` when input wasn't checked, e.g. for manual sessions.
+    #[serde(default)]
+    pub active_seconds: Option<i64>,
+}
+
+pub const SOURCE_AUTO: &str = "auto";
+pub const SOURCE_MANUAL: &str = "manual";
+
+fn default_source() -> String {
+    SOURCE_AUTO.to_string()
+}
+
+/// Columns for `Session::from_row`, with times converted to local time.
+const SESSION_COLUMNS_SQL: &str = "id, date,
+    COALESCE(strftime('%H:%M', start_at, 'unixepoch', 'localtime'), start),
+    COALESCE(strftime('%H:%M', end_at, 'unixepoch', 'localtime'), end),
+    ssid, label, source, start_at, end_at, rule, idle_seconds, active_seconds";
+
+impl Session {
+    /// Maps a row selected as `SESSION_COLUMNS_SQL`.
+    fn from_row(row: &Row) -> Result<Self> {
+        Ok(Session {
+            id: row.get(0)?,
+            date: row.get(1)?,
+            start: row.get(2)?,
+            end: row.get(3)?,
+            ssid: row.get(4)?,
+            label: row.get(5)?,
+            source: row.get(6)?,
+            start_at: row.get(7)?,
+            end_at: row.get(8)?,
+            rule: row.get(9)?,
+            idle_seconds: row.get(10)?,
+            active_seconds: row.get(11)?,
+        })
+    }
+}
+
+/// SQL expression for the UTC epoch seconds of a local `date` (YYYY-MM-DD)
+/// and `time` (HH:MM), given as SQL expressions.
+pub fn local_epoch_sql(date: &str, time: &str) -> String {
+    format!(
+        "CAST(strftime('%s', {} || ' ' || {}, 'utc') AS INTEGER)",
+        date, time
+    )
+}
+
+/// Like `local_epoch_sql` for an end time, which is on the next day when it's
+/// before `start`.
+pub fn local_end_epoch_sql(date: &str, start: &str, end: &str) -> String {
+    format!(
+        "CAST(strftime('%s', {date} || ' ' || {end},
+            CASE WHEN {end} < {start} THEN '+1 day' ELSE '+0 days' END, 'utc') AS INTEGER)",
+        date = date,
+        start = start,
+        end = end
+    )
+}
+
+/// SQL expression for the length of a row in `sessions` (aliased `s`), in
+/// whole minutes. Detected sessions shorter than the `min_session_minutes`
+/// setting count as zero, so blips like driving past the office don't add up.
+pub const MINUTES_SQL: &str = "(CASE
+    WHEN s.source = 'auto'
+        AND (s.end_at - s.start_at) / 60 < (
+            SELECT COALESCE(MAX(CAST(value AS INTEGER)), 5)
+            FROM settings WHERE key = 'min_session_minutes'
+        )
+    THEN 0
+    ELSE (s.end_at - s.start_at) / 60
+END)";
+
+/// The hour after midnight at which one tracking day ends and the next
+/// begins. Presence before it counts towards the previous day, so a late
+/// night at the office isn't split in two.
+#[derive(Clone, Copy, Debug)]
+pub struct DayBoundary {
+    pub hour: u32,
+}
+
+impl DayBoundary {
+    pub fn load(db: &Connection) -> Result<Self> {
+        let hour = settings::get_f64(
+            db,
+            settings::DAY_BOUNDARY_HOUR,
+            settings::DEFAULT_DAY_BOUNDARY_HOUR,
+        )?;
+        Ok(DayBoundary {
+            hour: (hour as u32).min(23),
+        })
+    }
+
+    /// The day `now` counts towards.
+    pub fn date(self, now: DateTime<Local>) -> NaiveDate {
+        (now - Duration::hours(self.hour as i64)).date_naive()
+    }
+
+    pub fn date_string(self, now: DateTime<Local>) -> String {
+        self.date(now).format("%Y-%m-%d").to_string()
+    }
+
+    /// SQLite time modifier that shifts a time so the boundary lands on
+    /// midnight, making HH:MM times within one day compare in order.
+    pub fn sql_shift(self) -> String {
+        format!("-{} hours", self.hour)
+    }
+
+    /// Minutes since midnight for an HH:MM time, plus a day for times after
+    /// midnight but before the boundary, so they sort after the evening.
+    pub fn minute_of_day(self, time: &str) -> Option<i64> {
+        let (hours, minutes) = time.split_once(':')?;
+        let minute = hours.parse::<i64>().ok()? * 60 + minutes.parse::<i64>().ok()?;
+        Some(if minute < self.hour as i64 * 60 {
+            minute + 24 * 60
+        } else {
+            minute
+        })
+    }
+}
+
+/// Formats a `DayBoundary::minute_of_day` as HH:MM.
+pub fn format_minute_of_day(minute: i64) -> String {
+    let minute = minute.rem_euclid(24 * 60);
+    format!("{:02}:{:02}", minute / 60, minute % 60)
+}
+
+/// What happened to the current session after a poll.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transition {
+    Started(i64),
+    Continued,
+    Ended(i64),
+    /// Presence carried on past the day boundary, so the session counting
+    /// towards the previous day ended and one for the new day started.
+    Rolled {
+        ended: i64,
+        started: i64,
+    },
+    Idle,
+}
+
+pub fn create_table(db: &Connection) -> Result<()> {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            date TEXT NOT NULL,
+            start TEXT NOT NULL,
+            end TEXT NOT NULL,
+            ssid TEXT,
+            label TEXT,
+            source TEXT NOT NULL DEFAULT 'auto',
+            start_at INTEGER,
+            end_at INTEGER,
+            rule TEXT,
+            idle_seconds INTEGER NOT NULL DEFAULT 0,
+            active_seconds INTEGER
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Tracks the open session between polls. A session starts on the first poll
+/// that detects presence, has its end pushed forward by every poll that still
+/// detects presence, and ends on the first poll that doesn't once the grace
+/// period has passed.
+#[derive(Default)]
+pub struct SessionTracker {
+    open: Option<OpenSession>,
+}
+
+struct OpenSession {
+    id: i64,
+    date: String,
+    start: String,
+    /// When presence was last detected.
+    last_seen: DateTime<Local>,
+    /// Whether the latest poll missed presence and is within the grace period.
+    in_gap: bool,
+}
+
+impl SessionTracker {
+    /// Advances the state machine. Gaps in presence shorter than `grace` are
+    /// bridged, so e.g. a walk to the printer doesn't split the session; the
+    /// session's end stays at the last detection until presence returns.
+    pub fn update(
+        &mut self,
+        db: &Connection,
+        presence: Option<&Presence>,
+        now: DateTime<Local>,
+        boundary: DayBoundary,
+        grace: Duration,
+    ) -> Result<Transition> {
+        let date = boundary.date_string(now);
+        let time = now.format("%H:%M").to_string();
+
+        let Some(presence) = presence else {
+            if let Some(open) = &mut self.open {
+                if now - open.last_seen < grace {
+                    open.in_gap = true;
+                    return Ok(Transition::Continued);
+                }
+            }
+            return Ok(match self.open.take() {
+                Some(open) => Transition::Ended(open.id),
+                None => Transition::Idle,
+            });
+        };
+
+        match &mut self.open {
+            Some(open) if open.date == date => {
+                // Idle or active since the last poll, going by this one
+                let elapsed = (now - open.last_seen).num_seconds().max(0);
+                let idle_seconds = if presence.idle { elapsed } else { 0 };
+                let active_seconds = if presence.active == Some(true) {
+                    elapsed
+                } else {
+                    0
+                };
+                db.execute(
+                    "UPDATE sessions SET end = ?1, end_at = ?2, idle_seconds = idle_seconds + ?4,
+                        active_seconds = active_seconds + ?5
+                     WHERE id = ?3",
+                    params![time, now.timestamp(), open.id, idle_seconds, active_seconds],
+                )?;
+                open.last_seen = now;
+                open.in_gap = false;
+                Ok(Transition::Continued)
+            }
+            _ => {
+                // Either nothing is open or the open session belongs to
+                // yesterday, in which case it was closed at its last poll.
+                let ended = self.open.take().map(|open| open.id);
+                db.execute(
+                    "INSERT INTO sessions
+                        (date, start, end, start_at, end_at, ssid, label, rule, active_seconds)
+                     VALUES (?1, ?2, ?2, ?3, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        date,
+                        time,
+                        now.timestamp(),
+                        presence.ssid,
+                        presence.label,
+                        presence.rule,
+                        // Left NULL, which stays NULL, when input isn't checked
+                        presence.active.map(|_| 0)
+                    ],
+                )?;
+                let id = db.last_insert_rowid();
+                self.open = Some(OpenSession {
+                    id,
+                    date,
+                    start: time,
+                    last_seen: now,
+                    in_gap: false,
+                });
+                Ok(match ended {
+                    Some(ended) => Transition::Rolled { ended, started: id },
+                    None => Transition::Started(id),
+                })
+            }
+        }
+    }
+
+    /// Start time of the open session, if there is one.
+    pub fn open_session_start(&self) -> Option<String> {
+        self.open.as_ref().map(|open| open.start.clone())
+    }
+
+    /// Id of the session still accruing time, i.e. open and not waiting out
+    /// a gap in presence.
+    pub fn live_session_id(&self) -> Option<i64> {
+        self.open
+            .as_ref()
+            .filter(|open| !open.in_gap)
+            .map(|open| open.id)
+    }
+
+    /// Ends the open session at `now` without waiting for a poll to notice,
+    /// e.g. because the machine is about to sleep. Returns the closed
+    /// session's id.
+    pub fn close(
+        &mut self,
+        db: &Connection,
+        now: DateTime<Local>,
+        boundary: DayBoundary,
+    ) -> Result<Option<i64>> {
+        let Some(open) = self.open.take() else {
+            return Ok(None);
+        };
+        // Presence wasn't seen since the session's end was last moved, so
+        // don't count the gap
+        if open.date == boundary.date_string(now) && !open.in_gap {
+            db.execute(
+                "UPDATE sessions SET end = ?1, end_at = ?2 WHERE id = ?3",
+                params![now.format("%H:%M").to_string(), now.timestamp(), open.id],
+            )?;
+        }
+        Ok(Some(open.id))
+    }
+}
+
+/// A stopwatch-style session started and stopped by hand, for days worked
+/// away from any detectable office. Runs independently of detection.
+#[derive(Default)]
+pub struct ManualTimer {
+    open: Option<OpenSession>,
+}
+
+impl ManualTimer {
+    pub fn is_running(&self) -> bool {
+        self.open.is_some()
+    }
+
+    pub fn session_id(&self) -> Option<i64> {
+        self.open.as_ref().map(|open| open.id)
+    }
+
+    /// Starts the timer, returning the new session's id, or `None` if it was
+    /// already running.
+    pub fn start(
+        &mut self,
+        db: &Connection,
+        now: DateTime<Local>,
+        boundary: DayBoundary,
+    ) -> Result<Option<i64>> {
+        if self.open.is_some() {
+            return Ok(None);
+        }
+        let date = boundary.date_string(now);
+        let time = now.format("%H:%M").to_string();
+        db.execute(
+            "INSERT INTO sessions (date, start, end, start_at, end_at, source)
+             VALUES (?1, ?2, ?2, ?3, ?3, ?4)",
+            params![date, time, now.timestamp(), SOURCE_MANUAL],
+        )?;
+        let id = db.last_insert_rowid();
+        self.open = Some(OpenSession {
+            id,
+            date,
+            start: time,
+            last_seen: now,
+            in_gap: false,
+        });
+        Ok(Some(id))
+    }
+
+    /// Moves the running session's end up to `now`, so little is lost if the
+    /// app quits without stopping the timer. Rolls over to a new session at
+    /// the day boundary.
+    pub fn extend(
+        &mut self,
+        db: &Connection,
+        now: DateTime<Local>,
+        boundary: DayBoundary,
+    ) -> Result<()> {
+        let Some(open) = &self.open else {
+            return Ok(());
+        };
+        if open.date == boundary.date_string(now) {
+            db.execute(
+                "UPDATE sessions SET end = ?1, end_at = ?2 WHERE id = ?3",
+                params![now.format("%H:%M").to_string(), now.timestamp(), open.id],
+            )?;
+        } else {
+            self.open = None;
+            self.start(db, now, boundary)?;
+        }
+        Ok(())
+    }
+
+    /// Stops the timer, returning the finished session's id, or `None` if it
+    /// wasn't running.
+    pub fn stop(
+        &mut self,
+        db: &Connection,
+        now: DateTime<Local>,
+        boundary: DayBoundary,
+    ) -> Result<Option<i64>> {
+        self.extend(db, now, boundary)?;
+        Ok(self.open.take().map(|open| open.id))
+    }
+}
+
+/// Deletes the session with `id` if it lasted less than `min_minutes`, then
+/// shrinks its day's earliest/latest to the remaining sessions, or deletes
+/// the day if none are left. Returns whether the session was discarded.
+pub fn discard_if_short(db: &Connection, id: i64, min_minutes: i64) -> Result<bool> {
+    let Some((date, minutes)) = db
+        .query_row(
+            "SELECT date, (end_at - start_at) / 60 FROM sessions WHERE id = ?1",
+            [id],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+        )
+        .optional()?
+    else {
+        return Ok(false);
+    };
+    if minutes >= min_minutes {
+        return Ok(false);
+    }
+
+    db.execute("DELETE FROM sessions WHERE id = ?1", [id])?;
+    let remaining = db.execute(
+        "UPDATE connections SET
+            (earliest, earliest_at) = (SELECT start, start_at FROM sessions WHERE date = ?1
+                ORDER BY start_at LIMIT 1),
+            (latest, latest_at) = (SELECT end, end_at FROM sessions WHERE date = ?1
+                ORDER BY end_at DESC LIMIT 1)
+         WHERE date = ?1 AND EXISTS (SELECT 1 FROM sessions WHERE date = ?1)",
+        [&date],
+    )?;
+    if remaining == 0 {
+        db.execute("DELETE FROM connections WHERE date = ?1", [&date])?;
+    }
+    Ok(true)
+}
+
+/// SQL condition limiting sessions aliased `s` to the location bound to
+/// `param`, or letting every session through when that's NULL. A session's
+/// location is its label: the name of the location whose rules matched it,
+/// or the label of the network that did.
+pub fn location_sql(param: &str) -> String {
+    format!("({0} IS NULL OR s.label = {0})", param)
+}
+
+/// Minutes of presence recorded on `date`, excluding gaps between sessions,
+/// at `location` if one is given.
+pub fn total_minutes(db: &Connection, date: &str, location: Option<&str>) -> Result<i64> {
+    db.query_row(
+        &format!(
+            "SELECT COALESCE(SUM({}), 0) FROM sessions s WHERE s.date = ?1 AND {}",
+            MINUTES_SQL,
+            location_sql("?2")
+        ),
+        params![date, location],
+        |row| row.get(0),
+    )
+}
+
+pub fn get_sessions(
+    db: &Connection,
+    date: Option<&str>,
+    location: Option<&str>,
+) -> Result<Vec<Session>> {
+    let mut stmt = db.prepare(&format!(
+        "SELECT {} FROM sessions s
+         WHERE (?1 IS NULL OR date = ?1) AND {}
+         ORDER BY date DESC, start_at ASC",
+        SESSION_COLUMNS_SQL,
+        location_sql("?2")
+    ))?;
+    let sessions = stmt.query_map([date, location], Session::from_row)?;
+
+    sessions.collect()
+}
+
+/// Every session before `date` (YYYY-MM-DD), oldest first.
+pub fn sessions_before(db: &Connection, date: &str) -> Result<Vec<Session>> {
+    let mut stmt = db.prepare(&format!(
+        "SELECT {} FROM sessions WHERE date < ?1 ORDER BY date ASC, start_at ASC",
+        SESSION_COLUMNS_SQL
+    ))?;
+    let sessions = stmt.query_map([date], Session::from_row)?;
+
+    sessions.collect()
+}
+
+/// Every session on or after `date` (YYYY-MM-DD), oldest first.
+pub fn sessions_since(db: &Connection, date: &str) -> Result<Vec<Session>> {
+    let mut stmt = db.prepare(&format!(
+        "SELECT {} FROM sessions WHERE date >= ?1 ORDER BY date ASC, start_at ASC",
+        SESSION_COLUMNS_SQL
+    ))?;
+    let sessions = stmt.query_map([date], Session::from_row)?;
+
+    sessions.collect()
+}
+
+/// The networks sessions have been recorded on, most recently used first.
+pub fn known_ssids(db: &Connection) -> Result<Vec<String>> {
+    let mut stmt = db.prepare(
+        "SELECT ssid FROM sessions WHERE ssid IS NOT NULL AND ssid != ''
+         GROUP BY ssid ORDER BY MAX(date) DESC",
+    )?;
+    let ssids = stmt.query_map([], |row| row.get(0))?;
+
+    ssids.collect()
+}
+
+pub fn get_session(db: &Connection, id: i64) -> Result<Option<Session>> {
+    db.query_row(
+        &format!("SELECT {} FROM sessions WHERE id = ?1", SESSION_COLUMNS_SQL),
+        [id],
+        Session::from_row,
+    )
+    .optional()
+}
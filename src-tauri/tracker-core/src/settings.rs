@@ -0,0 +1,1196 @@
+use rusqlite::{Connection, OptionalExtension, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::daily_notes::DailyNoteSetting;
+use crate::detector::{
+    AddressRule, AnyDetector, BluetoothDetector, BluetoothTarget, Geofence, GeofenceDetector,
+    IdleAwareDetector, IdlePolicy, NetworkAddressDetector, PresenceDetector, Probe,
+    ReachabilityDetector, TargetNetwork, VpnAwareDetector, VpnPolicy, WifiSsidDetector,
+    WiredNetwork, WiredNetworkDetector,
+};
+use crate::digest::EmailSetting;
+use crate::flexitime::WorkSchedule;
+use crate::google_calendar::GoogleCalendarSetting;
+use crate::invoices::InvoiceSetting;
+use crate::location::Coordinates;
+use crate::lunch::{self, LunchBreak};
+use crate::network::{self, Cidr};
+use crate::publishing::ReportDestination;
+use crate::rounding::Rounding;
+use crate::rules::{self, Rule, RulesDetector};
+use crate::team::TeamSetting;
+use crate::time_sync::TimeSyncSetting;
+use crate::webhooks::Webhook;
+
+/// WiFi networks that count as the office, stored as JSON.
+pub const NETWORKS: &str = "networks";
+pub const DEFAULT_NETWORK: &str = "eduroam";
+/// Wired connections that count as the office, stored as JSON.
+pub const WIRED_NETWORKS: &str = "wired_networks";
+/// Subnets and gateway addresses that count as the office, stored as JSON.
+pub const ADDRESS_RULES: &str = "address_rules";
+/// Internal hosts whose reachability counts as the office, stored as JSON.
+pub const PROBES: &str = "probes";
+pub const DEFAULT_PROBE_TIMEOUT_MS: u32 = 200;
+pub const MAX_PROBE_TIMEOUT_MS: u32 = 5000;
+/// Areas around the office that count as being there, stored as JSON.
+pub const GEOFENCES: &str = "geofences";
+pub const MIN_GEOFENCE_RADIUS_M: f64 = 10.0;
+pub const MAX_GEOFENCE_RADIUS_M: f64 = 10_000.0;
+/// Bluetooth devices whose presence counts as the office, stored as JSON.
+pub const BLUETOOTH_DEVICES: &str = "bluetooth_devices";
+/// Named combinations of conditions that count as the office, stored as
+/// JSON.
+pub const RULES: &str = "rules";
+/// Named sites, each with its own rules, stored as JSON.
+pub const LOCATIONS: &str = "locations";
+/// How to treat presence seen through a VPN, stored as JSON.
+pub const VPN_POLICY: &str = "vpn_policy";
+/// How to treat presence with no recent keyboard or mouse input, stored as
+/// JSON.
+pub const IDLE_POLICY: &str = "idle_policy";
+/// Minutes without input after which presence counts as idle.
+pub const IDLE_THRESHOLD_MINUTES: &str = "idle_threshold_minutes";
+pub const DEFAULT_IDLE_THRESHOLD_MINUTES: f64 = 15.0;
+
+/// Weekly hours target for goal progress.
+pub const WEEKLY_TARGET_HOURS: &str = "weekly_target_hours";
+pub const DEFAULT_WEEKLY_TARGET_HOURS: f64 = 40.0;
+/// Whether to show clock in/out notifications.
+pub const NOTIFICATIONS_ENABLED: &str = "notifications_enabled";
+/// Hours on-site in a day after which to remind the user to go home; 0
+/// turns the reminder off.
+pub const GO_HOME_AFTER_HOURS: &str = "go_home_after_hours";
+pub const DEFAULT_GO_HOME_AFTER_HOURS: f64 = 10.0;
+/// How long to wait before repeating the go-home reminder.
+pub const GO_HOME_SNOOZE_MINUTES: &str = "go_home_snooze_minutes";
+pub const DEFAULT_GO_HOME_SNOOZE_MINUTES: f64 = 30.0;
+/// How long presence can drop out before the open session is ended.
+pub const GRACE_PERIOD_MINUTES: &str = "grace_period_minutes";
+pub const DEFAULT_GRACE_PERIOD_MINUTES: f64 = 10.0;
+/// Detected sessions shorter than this are discarded. Also read directly by
+/// `sessions::MINUTES_SQL`, so keep the key and default in sync.
+pub const MIN_SESSION_MINUTES: &str = "min_session_minutes";
+pub const DEFAULT_MIN_SESSION_MINUTES: f64 = 5.0;
+/// Hour after midnight at which the tracking day rolls over.
+pub const DAY_BOUNDARY_HOUR: &str = "day_boundary_hour";
+pub const DEFAULT_DAY_BOUNDARY_HOUR: f64 = 4.0;
+/// Seconds between checks when network change notifications aren't
+/// available.
+pub const POLL_INTERVAL_SECONDS: &str = "poll_interval_seconds";
+pub const DEFAULT_POLL_INTERVAL_SECONDS: f64 = 30.0;
+pub const MIN_POLL_INTERVAL_SECONDS: u64 = 5;
+pub const MAX_POLL_INTERVAL_SECONDS: u64 = 10 * 60;
+/// Whether to close the open session and stop checking while the screen is
+/// locked.
+pub const PAUSE_WHEN_LOCKED: &str = "pause_when_locked";
+/// Global keyboard shortcut that shows or hides the window, as a Tauri
+/// accelerator; empty turns it off.
+pub const TOGGLE_SHORTCUT: &str = "toggle_shortcut";
+pub const DEFAULT_TOGGLE_SHORTCUT: &str = "CmdOrCtrl+Shift+T";
+/// URLs to notify when sessions start and end, stored as JSON.
+pub const WEBHOOKS: &str = "webhooks";
+/// Where to push completed sessions as time entries, stored as JSON; see
+/// `time_sync`.
+pub const TIME_SYNC: &str = "time_sync";
+/// The Markdown notes to write each day's times into, stored as JSON; see
+/// `daily_notes`.
+pub const DAILY_NOTES: &str = "daily_notes";
+/// The Google Calendar to publish office days to, stored as JSON; see
+/// `google_calendar`.
+pub const GOOGLE_CALENDAR: &str = "google_calendar";
+/// Hourly rate, currency, rounding and clients for invoices, stored as JSON;
+/// see `invoices`.
+pub const INVOICING: &str = "invoicing";
+/// Rounding applied to each day's time in summaries and exports, stored as
+/// JSON; see `rounding`. Stored times are never rounded.
+pub const REPORT_ROUNDING: &str = "report_rounding";
+/// ISO 3166-1 country whose public holidays aren't counted as missed days,
+/// e.g. "GB"; empty turns this off.
+pub const HOLIDAY_COUNTRY: &str = "holiday_country";
+/// ISO 3166-2 region for regional holidays, e.g. "GB-SCT"; empty counts only
+/// nationwide ones.
+pub const HOLIDAY_REGION: &str = "holiday_region";
+/// Contracted hours per weekday for the flexitime balance, stored as JSON;
+/// see `flexitime`.
+pub const WORK_SCHEDULE: &str = "work_schedule";
+/// When and for how long a gap between sessions counts as lunch, stored as
+/// JSON; see `lunch`. Unset uses the defaults, and `null` turns it off.
+pub const LUNCH_BREAK: &str = "lunch_break";
+/// The last day anomalies have been notified about, as YYYY-MM-DD. Kept by
+/// the app rather than set by the user.
+pub const ANOMALIES_NOTIFIED_THROUGH: &str = "anomalies_notified_through";
+/// Whether to sum up last week on Monday mornings.
+pub const WEEKLY_DIGEST_ENABLED: &str = "weekly_digest_enabled";
+/// The week the last digest covered, as the YYYY-MM-DD it started on. Kept
+/// by the app rather than set by the user.
+pub const WEEKLY_DIGEST_SENT_FOR: &str = "weekly_digest_sent_for";
+/// SMTP server and addresses to email the digest to as well, stored as JSON;
+/// see `digest`.
+pub const DIGEST_EMAIL: &str = "digest_email";
+/// How many months of history to keep before older days are archived and
+/// deleted; 0 keeps everything. See `retention`.
+pub const RETENTION_MONTHS: &str = "retention_months";
+pub const MAX_RETENTION_MONTHS: u32 = 100 * 12;
+/// Folder shared between devices, e.g. in iCloud Drive or Dropbox, to sync
+/// sessions through; empty turns this off. See `folder_sync`.
+pub const SYNC_FOLDER: &str = "sync_folder";
+/// This device's id in the sync folder. Kept by the app rather than set by
+/// the user.
+pub const SYNC_DEVICE_ID: &str = "sync_device_id";
+/// URL of a self-hosted server to sync sessions through, end-to-end
+/// encrypted with the pairing secret in the keychain; empty turns this off.
+/// See `server_sync`.
+pub const SYNC_SERVER: &str = "sync_server";
+/// How far through the sync server's records this device has read. Kept by
+/// the app rather than set by the user.
+pub const SYNC_SERVER_CURSOR: &str = "sync_server_cursor";
+/// Endpoint and name to share daily totals with the team under, stored as
+/// JSON; unset keeps team mode off. See `team`.
+pub const TEAM: &str = "team";
+/// Folder or S3-compatible bucket to publish shareable reports to, stored as
+/// JSON; see `publishing`.
+pub const REPORT_DESTINATION: &str = "report_destination";
+/// Whether showing the window and exporting need Touch ID (or the account
+/// password) on macOS, or the passcode in the keychain; see `app_lock`.
+pub const APP_LOCK_ENABLED: &str = "app_lock_enabled";
+/// Whether to set a Slack status while in the office, using the token in
+/// the keychain; see `slack`.
+pub const SLACK_STATUS_ENABLED: &str = "slack_status_enabled";
+pub const SLACK_STATUS_TEXT: &str = "slack_status_text";
+pub const DEFAULT_SLACK_STATUS_TEXT: &str = "In the office";
+/// Emoji code like ":office:".
+pub const SLACK_STATUS_EMOJI: &str = "slack_status_emoji";
+pub const DEFAULT_SLACK_STATUS_EMOJI: &str = ":office:";
+/// Slack's limit on status text.
+const MAX_SLACK_STATUS_LENGTH: usize = 100;
+/// Whether to serve reports over HTTP on localhost, for scripts and widgets.
+pub const HTTP_API_ENABLED: &str = "http_api_enabled";
+pub const HTTP_API_PORT: &str = "http_api_port";
+pub const DEFAULT_HTTP_API_PORT: f64 = 7723.0;
+/// Bearer token every HTTP API request has to carry.
+pub const HTTP_API_TOKEN: &str = "http_api_token";
+pub const MIN_HTTP_API_TOKEN_LENGTH: usize = 16;
+/// Whether the app is registered to launch at login.
+pub const AUTOSTART_ENABLED: &str = "autostart_enabled";
+
+pub fn create_table(db: &Connection) -> Result<()> {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+pub fn get(db: &Connection, key: &str) -> Result<Option<String>> {
+    db.query_row("SELECT value FROM settings WHERE key = ?1", [key], |row| {
+        row.get(0)
+    })
+    .optional()
+}
+
+pub fn set(db: &Connection, key: &str, value: &str) -> Result<()> {
+    db.execute(
+        "INSERT INTO settings (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value = ?2",
+        [key, value],
+    )?;
+    Ok(())
+}
+
+/// Reads a numeric setting, falling back to `default` when it's unset or
+/// unparseable.
+pub fn get_f64(db: &Connection, key: &str, default: f64) -> Result<f64> {
+    Ok(get(db, key)?
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default))
+}
+
+pub fn get_bool(db: &Connection, key: &str, default: bool) -> Result<bool> {
+    Ok(get(db, key)?
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default))
+}
+
+/// An office WiFi network as configured by the user.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NetworkSetting {
+    /// Exact SSID, glob or `re:` regex; see `SsidPattern`.
+    pub pattern: String,
+    pub label: Option<String>,
+    /// Access point MAC prefixes presence is limited to, e.g. "00:1a:2b";
+    /// empty accepts any access point.
+    #[serde(default)]
+    pub bssid_prefixes: Vec<String>,
+}
+
+impl NetworkSetting {
+    fn to_target(&self) -> Result<TargetNetwork, String> {
+        let prefixes: Vec<&str> = self
+            .bssid_prefixes
+            .iter()
+            .map(|prefix| prefix.trim())
+            .filter(|prefix| !prefix.is_empty())
+            .collect();
+        for prefix in &prefixes {
+            network::validate_mac_prefix(prefix)?;
+        }
+        let target = TargetNetwork::new(&self.pattern, self.label.as_deref())?;
+        Ok(target.with_bssid_prefixes(&prefixes))
+    }
+}
+
+/// Treats blank text fields from the settings window as unset.
+fn non_empty(value: &Option<String>) -> Option<String> {
+    value
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+}
+
+/// An office wired connection as configured by the user. At least one of
+/// the conditions must be set.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct WiredSetting {
+    pub gateway_mac: Option<String>,
+    pub interface: Option<String>,
+    /// CIDR block such as "10.20.0.0/16".
+    pub subnet: Option<String>,
+    pub label: Option<String>,
+}
+
+impl WiredSetting {
+    fn to_network(&self) -> Result<WiredNetwork, String> {
+        let network = WiredNetwork {
+            gateway_mac: non_empty(&self.gateway_mac),
+            interface: non_empty(&self.interface),
+            subnet: non_empty(&self.subnet)
+                .map(|subnet| Cidr::parse(&subnet))
+                .transpose()?,
+            label: self.label.clone(),
+        };
+        if network.gateway_mac.is_none() && network.interface.is_none() && network.subnet.is_none()
+        {
+            return Err("A wired network needs a gateway MAC, interface or subnet".to_string());
+        }
+        if let Some(mac) = &network.gateway_mac {
+            network::validate_mac(mac)?;
+        }
+        Ok(network)
+    }
+}
+
+/// An office network recognised by its addressing, as configured by the
+/// user. At least one of the conditions must be set.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct AddressSetting {
+    /// CIDR block such as "10.20.0.0/16".
+    pub subnet: Option<String>,
+    /// IP address of the default gateway, e.g. "10.20.0.1".
+    pub gateway: Option<String>,
+    pub label: Option<String>,
+}
+
+impl AddressSetting {
+    fn to_rule(&self) -> Result<AddressRule, String> {
+        let rule = AddressRule {
+            subnet: non_empty(&self.subnet)
+                .map(|subnet| Cidr::parse(&subnet))
+                .transpose()?,
+            gateway: non_empty(&self.gateway)
+                .map(|gateway| {
+                    gateway
+                        .parse()
+                        .map_err(|_| format!("Invalid gateway address '{}'", gateway))
+                })
+                .transpose()?,
+            label: self.label.clone(),
+        };
+        if rule.subnet.is_none() && rule.gateway.is_none() {
+            return Err("An address rule needs a subnet or gateway".to_string());
+        }
+        Ok(rule)
+    }
+}
+
+/// An internal host to probe, as configured by the user.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProbeSetting {
+    /// "host:port", e.g. "10.0.0.5:443".
+    pub address: String,
+    #[serde(default = "default_probe_timeout_ms")]
+    pub timeout_ms: u32,
+    pub label: Option<String>,
+}
+
+fn default_probe_timeout_ms() -> u32 {
+    DEFAULT_PROBE_TIMEOUT_MS
+}
+
+impl ProbeSetting {
+    fn to_probe(&self) -> Result<Probe, String> {
+        network::parse_host_port(&self.address)?;
+        if !(1..=MAX_PROBE_TIMEOUT_MS).contains(&self.timeout_ms) {
+            return Err(format!("Invalid probe timeout {}ms", self.timeout_ms));
+        }
+        Ok(Probe {
+            address: self.address.trim().to_string(),
+            timeout: Duration::from_millis(self.timeout_ms.into()),
+            label: self.label.clone(),
+        })
+    }
+}
+
+/// A Bluetooth device as configured by the user.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BluetoothSetting {
+    /// Address like "AA:BB:CC:DD:EE:FF", or the device's name.
+    pub device: String,
+    pub label: Option<String>,
+}
+
+impl BluetoothSetting {
+    fn to_target(&self) -> Result<BluetoothTarget, String> {
+        let device = self.device.trim();
+        if device.is_empty() {
+            return Err("Bluetooth device is empty".to_string());
+        }
+        Ok(BluetoothTarget {
+            device: device.to_string(),
+            label: self.label.clone(),
+        })
+    }
+}
+
+/// A site such as "HQ" or "Lab" with its own rules. Sessions they match are
+/// labelled with the location's name, which reports can filter by.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LocationSetting {
+    pub name: String,
+    pub rules: Vec<Rule>,
+}
+
+impl LocationSetting {
+    fn to_detector(&self) -> Result<RulesDetector, String> {
+        let name = self.name.trim();
+        if name.is_empty() {
+            return Err("Every location needs a name".to_string());
+        }
+        if self.rules.is_empty() {
+            return Err(format!("Location '{}' needs at least one rule", name));
+        }
+        let rules: Vec<Rule> = self
+            .rules
+            .iter()
+            .map(|rule| Rule {
+                label: Some(name.to_string()),
+                ..rule.clone()
+            })
+            .collect();
+        RulesDetector::new(&rules).map_err(|e| format!("Location '{}': {}", name, e))
+    }
+}
+
+/// A geofence as configured by the user, in degrees and metres.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GeofenceSetting {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_m: f64,
+    pub label: Option<String>,
+}
+
+impl GeofenceSetting {
+    fn to_geofence(&self) -> Result<Geofence, String> {
+        if !(-90.0..=90.0).contains(&self.latitude) || !(-180.0..=180.0).contains(&self.longitude) {
+            return Err(format!(
+                "Invalid coordinates {}, {}",
+                self.latitude, self.longitude
+            ));
+        }
+        if !(MIN_GEOFENCE_RADIUS_M..=MAX_GEOFENCE_RADIUS_M).contains(&self.radius_m) {
+            return Err(format!("Invalid geofence radius {}m", self.radius_m));
+        }
+        Ok(Geofence {
+            center: Coordinates {
+                latitude: self.latitude,
+                longitude: self.longitude,
+            },
+            radius_m: self.radius_m,
+            label: self.label.clone(),
+        })
+    }
+}
+
+/// Every user-configurable option, read from and written to the settings
+/// table as a whole so the settings window can edit them together.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    pub networks: Vec<NetworkSetting>,
+    #[serde(default)]
+    pub wired: Vec<WiredSetting>,
+    #[serde(default)]
+    pub addresses: Vec<AddressSetting>,
+    #[serde(default)]
+    pub probes: Vec<ProbeSetting>,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub locations: Vec<LocationSetting>,
+    #[serde(default)]
+    pub geofences: Vec<GeofenceSetting>,
+    #[serde(default)]
+    pub bluetooth: Vec<BluetoothSetting>,
+    #[serde(default)]
+    pub vpn_policy: VpnPolicy,
+    #[serde(default)]
+    pub idle_policy: IdlePolicy,
+    #[serde(default = "default_idle_threshold_minutes")]
+    pub idle_threshold_minutes: u32,
+    pub poll_interval_seconds: u32,
+    pub notifications_enabled: bool,
+    pub weekly_target_hours: f64,
+    pub go_home_after_hours: f64,
+    pub go_home_snooze_minutes: u32,
+    pub grace_period_minutes: u32,
+    pub min_session_minutes: u32,
+    pub day_boundary_hour: u32,
+    #[serde(default = "default_pause_when_locked")]
+    pub pause_when_locked: bool,
+    #[serde(default = "default_toggle_shortcut")]
+    pub toggle_shortcut: String,
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    #[serde(default)]
+    pub time_sync: Option<TimeSyncSetting>,
+    #[serde(default)]
+    pub google_calendar: Option<GoogleCalendarSetting>,
+    #[serde(default)]
+    pub daily_notes: Option<DailyNoteSetting>,
+    #[serde(default)]
+    pub invoicing: Option<InvoiceSetting>,
+    #[serde(default)]
+    pub report_rounding: Option<Rounding>,
+    #[serde(default)]
+    pub holiday_country: String,
+    #[serde(default)]
+    pub holiday_region: String,
+    #[serde(default)]
+    pub work_schedule: Option<WorkSchedule>,
+    #[serde(default = "default_lunch_break")]
+    pub lunch_break: Option<LunchBreak>,
+    #[serde(default = "default_weekly_digest_enabled")]
+    pub weekly_digest_enabled: bool,
+    #[serde(default)]
+    pub digest_email: Option<EmailSetting>,
+    #[serde(default)]
+    pub retention_months: u32,
+    #[serde(default)]
+    pub sync_folder: String,
+    #[serde(default)]
+    pub sync_server: String,
+    #[serde(default)]
+    pub team: Option<TeamSetting>,
+    #[serde(default)]
+    pub report_destination: Option<ReportDestination>,
+    #[serde(default)]
+    pub app_lock_enabled: bool,
+    #[serde(default)]
+    pub slack_status_enabled: bool,
+    #[serde(default = "default_slack_status_text")]
+    pub slack_status_text: String,
+    #[serde(default = "default_slack_status_emoji")]
+    pub slack_status_emoji: String,
+    #[serde(default)]
+    pub http_api_enabled: bool,
+    #[serde(default = "default_http_api_port")]
+    pub http_api_port: u16,
+    #[serde(default)]
+    pub http_api_token: String,
+    pub autostart_enabled: bool,
+}
+
+fn default_idle_threshold_minutes() -> u32 {
+    DEFAULT_IDLE_THRESHOLD_MINUTES as u32
+}
+
+fn default_lunch_break() -> Option<LunchBreak> {
+    Some(LunchBreak::default())
+}
+
+fn default_weekly_digest_enabled() -> bool {
+    true
+}
+
+fn default_pause_when_locked() -> bool {
+    true
+}
+
+fn default_toggle_shortcut() -> String {
+    DEFAULT_TOGGLE_SHORTCUT.to_string()
+}
+
+fn default_slack_status_text() -> String {
+    DEFAULT_SLACK_STATUS_TEXT.to_string()
+}
+
+fn default_slack_status_emoji() -> String {
+    DEFAULT_SLACK_STATUS_EMOJI.to_string()
+}
+
+fn default_http_api_port() -> u16 {
+    DEFAULT_HTTP_API_PORT as u16
+}
+
+impl Settings {
+    pub fn load(db: &Connection) -> Result<Self> {
+        let networks = get(db, NETWORKS)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_else(|| {
+                vec![NetworkSetting {
+                    pattern: DEFAULT_NETWORK.to_string(),
+                    label: None,
+                    bssid_prefixes: Vec::new(),
+                }]
+            });
+        let wired = get(db, WIRED_NETWORKS)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let addresses = get(db, ADDRESS_RULES)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let probes = get(db, PROBES)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let rules = get(db, RULES)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let locations = get(db, LOCATIONS)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let geofences = get(db, GEOFENCES)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let bluetooth = get(db, BLUETOOTH_DEVICES)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let vpn_policy = get(db, VPN_POLICY)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let idle_policy = get(db, IDLE_POLICY)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let webhooks = get(db, WEBHOOKS)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let time_sync = get(db, TIME_SYNC)?.and_then(|json| serde_json::from_str(&json).ok());
+        let google_calendar =
+            get(db, GOOGLE_CALENDAR)?.and_then(|json| serde_json::from_str(&json).ok());
+        let daily_notes = get(db, DAILY_NOTES)?.and_then(|json| serde_json::from_str(&json).ok());
+        let invoicing = get(db, INVOICING)?.and_then(|json| serde_json::from_str(&json).ok());
+        let report_rounding =
+            get(db, REPORT_ROUNDING)?.and_then(|json| serde_json::from_str(&json).ok());
+        let work_schedule =
+            get(db, WORK_SCHEDULE)?.and_then(|json| serde_json::from_str(&json).ok());
+        let lunch_break = lunch::load(db)?;
+        let digest_email = get(db, DIGEST_EMAIL)?.and_then(|json| serde_json::from_str(&json).ok());
+        let team = get(db, TEAM)?.and_then(|json| serde_json::from_str(&json).ok());
+        let report_destination =
+            get(db, REPORT_DESTINATION)?.and_then(|json| serde_json::from_str(&json).ok());
+        Ok(Self {
+            networks,
+            wired,
+            addresses,
+            probes,
+            rules,
+            locations,
+            geofences,
+            bluetooth,
+            vpn_policy,
+            idle_policy,
+            idle_threshold_minutes: get_f64(
+                db,
+                IDLE_THRESHOLD_MINUTES,
+                DEFAULT_IDLE_THRESHOLD_MINUTES,
+            )? as u32,
+            poll_interval_seconds: get_f64(
+                db,
+                POLL_INTERVAL_SECONDS,
+                DEFAULT_POLL_INTERVAL_SECONDS,
+            )? as u32,
+            notifications_enabled: get_bool(db, NOTIFICATIONS_ENABLED, true)?,
+            weekly_target_hours: get_f64(db, WEEKLY_TARGET_HOURS, DEFAULT_WEEKLY_TARGET_HOURS)?,
+            go_home_after_hours: get_f64(db, GO_HOME_AFTER_HOURS, DEFAULT_GO_HOME_AFTER_HOURS)?,
+            go_home_snooze_minutes: get_f64(
+                db,
+                GO_HOME_SNOOZE_MINUTES,
+                DEFAULT_GO_HOME_SNOOZE_MINUTES,
+            )? as u32,
+            grace_period_minutes: get_f64(db, GRACE_PERIOD_MINUTES, DEFAULT_GRACE_PERIOD_MINUTES)?
+                as u32,
+            min_session_minutes: get_f64(db, MIN_SESSION_MINUTES, DEFAULT_MIN_SESSION_MINUTES)?
+                as u32,
+            day_boundary_hour: get_f64(db, DAY_BOUNDARY_HOUR, DEFAULT_DAY_BOUNDARY_HOUR)? as u32,
+            pause_when_locked: get_bool(db, PAUSE_WHEN_LOCKED, true)?,
+            toggle_shortcut: get(db, TOGGLE_SHORTCUT)?.unwrap_or_else(default_toggle_shortcut),
+            webhooks,
+            time_sync,
+            google_calendar,
+            daily_notes,
+            invoicing,
+            report_rounding,
+            holiday_country: get(db, HOLIDAY_COUNTRY)?.unwrap_or_default(),
+            holiday_region: get(db, HOLIDAY_REGION)?.unwrap_or_default(),
+            work_schedule,
+            lunch_break,
+            weekly_digest_enabled: get_bool(db, WEEKLY_DIGEST_ENABLED, true)?,
+            digest_email,
+            retention_months: get_f64(db, RETENTION_MONTHS, 0.0)? as u32,
+            sync_folder: get(db, SYNC_FOLDER)?.unwrap_or_default(),
+            sync_server: get(db, SYNC_SERVER)?.unwrap_or_default(),
+            team,
+            report_destination,
+            app_lock_enabled: get_bool(db, APP_LOCK_ENABLED, false)?,
+            slack_status_enabled: get_bool(db, SLACK_STATUS_ENABLED, false)?,
+            slack_status_text: get(db, SLACK_STATUS_TEXT)?
+                .unwrap_or_else(default_slack_status_text),
+            slack_status_emoji: get(db, SLACK_STATUS_EMOJI)?
+                .unwrap_or_else(default_slack_status_emoji),
+            http_api_enabled: get_bool(db, HTTP_API_ENABLED, false)?,
+            http_api_port: get_f64(db, HTTP_API_PORT, DEFAULT_HTTP_API_PORT)? as u16,
+            http_api_token: get(db, HTTP_API_TOKEN)?.unwrap_or_default(),
+            autostart_enabled: get_bool(db, AUTOSTART_ENABLED, false)?,
+        })
+    }
+
+    /// The configured networks, ready to match against.
+    pub fn target_networks(&self) -> Result<Vec<TargetNetwork>, String> {
+        self.networks
+            .iter()
+            .map(NetworkSetting::to_target)
+            .collect()
+    }
+
+    /// A detector matching any of the configured rules, locations, networks,
+    /// probes, geofences and Bluetooth devices. Rules and locations come first
+    /// so that sessions they match record them. The VPN policy applies to everything a VPN could fake: not
+    /// joining the office WiFi or being at the office. The idle policy applies
+    /// to everything.
+    pub fn detector(&self) -> Result<Box<dyn PresenceDetector>, String> {
+        let mut network: Vec<Box<dyn PresenceDetector>> = Vec::new();
+        if !self.rules.is_empty() {
+            network.push(Box::new(RulesDetector::new(&self.rules)?));
+        }
+        for location in &self.locations {
+            network.push(Box::new(location.to_detector()?));
+        }
+        if !self.wired.is_empty() {
+            let wired = self
+                .wired
+                .iter()
+                .map(WiredSetting::to_network)
+                .collect::<Result<_, _>>()?;
+            network.push(Box::new(WiredNetworkDetector::new(wired)));
+        }
+        if !self.addresses.is_empty() {
+            let rules = self
+                .addresses
+                .iter()
+                .map(AddressSetting::to_rule)
+                .collect::<Result<_, _>>()?;
+            network.push(Box::new(NetworkAddressDetector::new(rules)));
+        }
+        if !self.probes.is_empty() {
+            let probes = self
+                .probes
+                .iter()
+                .map(ProbeSetting::to_probe)
+                .collect::<Result<_, _>>()?;
+            network.push(Box::new(ReachabilityDetector::new(probes)));
+        }
+
+        let mut detectors: Vec<Box<dyn PresenceDetector>> = Vec::new();
+        if !network.is_empty() {
+            detectors.push(Box::new(VpnAwareDetector::new(
+                any_of(network),
+                self.vpn_policy,
+            )));
+        }
+        if !self.networks.is_empty() {
+            detectors.push(Box::new(WifiSsidDetector::new(self.target_networks()?)));
+        }
+        if !self.geofences.is_empty() {
+            let fences = self
+                .geofences
+                .iter()
+                .map(GeofenceSetting::to_geofence)
+                .collect::<Result<_, _>>()?;
+            detectors.push(Box::new(GeofenceDetector::new(fences)));
+        }
+        if !self.bluetooth.is_empty() {
+            let targets = self
+                .bluetooth
+                .iter()
+                .map(BluetoothSetting::to_target)
+                .collect::<Result<_, _>>()?;
+            detectors.push(Box::new(BluetoothDetector::new(targets)));
+        }
+        // Wrapped even with the idle policy off, for focus hours
+        Ok(Box::new(IdleAwareDetector::new(
+            any_of(detectors),
+            self.idle_policy,
+            Duration::from_secs(u64::from(self.idle_threshold_minutes) * 60),
+        )))
+    }
+
+    /// Whether `previous` detected presence differently, i.e. the detector
+    /// needs rebuilding.
+    pub fn detection_differs(&self, previous: &Settings) -> bool {
+        self.networks != previous.networks
+            || self.wired != previous.wired
+            || self.addresses != previous.addresses
+            || self.probes != previous.probes
+            || self.rules != previous.rules
+            || self.locations != previous.locations
+            || self.geofences != previous.geofences
+            || self.bluetooth != previous.bluetooth
+            || self.vpn_policy != previous.vpn_policy
+            || self.idle_policy != previous.idle_policy
+            || self.idle_threshold_minutes != previous.idle_threshold_minutes
+    }
+
+    /// Checks every field, describing the first problem found.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.networks.is_empty()
+            && self.wired.is_empty()
+            && self.addresses.is_empty()
+            && self.probes.is_empty()
+            && self.rules.is_empty()
+            && self.locations.is_empty()
+            && self.geofences.is_empty()
+            && self.bluetooth.is_empty()
+        {
+            return Err("Add at least one office network".to_string());
+        }
+        for network in &self.networks {
+            network.to_target()?;
+        }
+        for wired in &self.wired {
+            wired.to_network()?;
+        }
+        for address in &self.addresses {
+            address.to_rule()?;
+        }
+        for probe in &self.probes {
+            probe.to_probe()?;
+        }
+        rules::validate(&self.rules)?;
+        for (i, location) in self.locations.iter().enumerate() {
+            location.to_detector()?;
+            let name = location.name.trim();
+            if self.locations[..i]
+                .iter()
+                .any(|other| other.name.trim() == name)
+            {
+                return Err(format!("There's more than one location named '{}'", name));
+            }
+        }
+        for geofence in &self.geofences {
+            geofence.to_geofence()?;
+        }
+        for device in &self.bluetooth {
+            device.to_target()?;
+        }
+        if !(1..=24 * 60).contains(&self.idle_threshold_minutes) {
+            return Err(format!(
+                "Invalid idle threshold {} minutes",
+                self.idle_threshold_minutes
+            ));
+        }
+        let poll_interval = u64::from(self.poll_interval_seconds);
+        if !(MIN_POLL_INTERVAL_SECONDS..=MAX_POLL_INTERVAL_SECONDS).contains(&poll_interval) {
+            return Err(format!(
+                "Invalid poll interval {} seconds",
+                self.poll_interval_seconds
+            ));
+        }
+        if !(self.weekly_target_hours > 0.0 && self.weekly_target_hours <= 24.0 * 7.0) {
+            return Err(format!(
+                "Invalid weekly target {}h",
+                self.weekly_target_hours
+            ));
+        }
+        if !(0.0..=24.0).contains(&self.go_home_after_hours) {
+            return Err(format!(
+                "Invalid reminder threshold {}h",
+                self.go_home_after_hours
+            ));
+        }
+        if self.go_home_snooze_minutes == 0 {
+            return Err("Snooze must be at least a minute".to_string());
+        }
+        if self.grace_period_minutes > 24 * 60 {
+            return Err(format!(
+                "Invalid grace period {} minutes",
+                self.grace_period_minutes
+            ));
+        }
+        if self.min_session_minutes > 24 * 60 {
+            return Err(format!(
+                "Invalid minimum session length {} minutes",
+                self.min_session_minutes
+            ));
+        }
+        if self.day_boundary_hour > 23 {
+            return Err(format!(
+                "Invalid day boundary hour {}",
+                self.day_boundary_hour
+            ));
+        }
+        for webhook in &self.webhooks {
+            webhook.validate()?;
+        }
+        if let Some(time_sync) = &self.time_sync {
+            time_sync.validate()?;
+        }
+        if let Some(calendar) = &self.google_calendar {
+            calendar.validate()?;
+        }
+        if let Some(daily_notes) = &self.daily_notes {
+            daily_notes.validate()?;
+        }
+        if let Some(invoicing) = &self.invoicing {
+            invoicing.validate()?;
+        }
+        if let Some(rounding) = &self.report_rounding {
+            rounding.validate()?;
+        }
+        if let Some(schedule) = &self.work_schedule {
+            schedule.validate()?;
+        }
+        if let Some(lunch) = &self.lunch_break {
+            lunch.validate()?;
+        }
+        if let Some(email) = &self.digest_email {
+            email.validate()?;
+        }
+        if let Some(team) = &self.team {
+            team.validate()?;
+        }
+        if let Some(destination) = &self.report_destination {
+            destination.validate()?;
+        }
+        let sync_folder = self.sync_folder.trim();
+        if !sync_folder.is_empty() && !Path::new(sync_folder).is_absolute() {
+            return Err(format!(
+                "Invalid sync folder '{}', expected a full path",
+                sync_folder
+            ));
+        }
+        let sync_server = self.sync_server.trim();
+        if !sync_server.is_empty() {
+            if !sync_server.starts_with("https://") && !sync_server.starts_with("http://") {
+                return Err(format!("Invalid sync server URL '{}'", sync_server));
+            }
+            // Both would share sessions under the same ids
+            if !sync_folder.is_empty() {
+                return Err("Sync through a folder or a server, not both".to_string());
+            }
+        }
+        if self.retention_months > MAX_RETENTION_MONTHS {
+            return Err(format!(
+                "Invalid history age {} months",
+                self.retention_months
+            ));
+        }
+        let country = self.holiday_country.trim();
+        if !country.is_empty()
+            && !(country.len() == 2 && country.chars().all(|c| c.is_ascii_uppercase()))
+        {
+            return Err(format!(
+                "Invalid holiday country '{}', expected a code like GB",
+                country
+            ));
+        }
+        let region = self.holiday_region.trim();
+        if !region.is_empty()
+            && (country.is_empty() || !region.starts_with(&format!("{}-", country)))
+        {
+            return Err(format!(
+                "Invalid holiday region '{}', expected one in the holiday country like GB-SCT",
+                region
+            ));
+        }
+        if self.slack_status_text.chars().count() > MAX_SLACK_STATUS_LENGTH {
+            return Err(format!(
+                "The Slack status can be at most {} characters",
+                MAX_SLACK_STATUS_LENGTH
+            ));
+        }
+        let emoji = self.slack_status_emoji.trim();
+        if !emoji.is_empty() && !(emoji.len() > 2 && emoji.starts_with(':') && emoji.ends_with(':'))
+        {
+            return Err(format!(
+                "Invalid Slack emoji '{}', expected a code like :office:",
+                emoji
+            ));
+        }
+        if self.http_api_enabled {
+            if self.http_api_port < 1024 {
+                return Err(format!(
+                    "Invalid HTTP API port {}, use 1024 or above",
+                    self.http_api_port
+                ));
+            }
+            if self.http_api_token.trim().len() < MIN_HTTP_API_TOKEN_LENGTH {
+                return Err(format!(
+                    "The HTTP API token needs at least {} characters",
+                    MIN_HTTP_API_TOKEN_LENGTH
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes every field in one transaction.
+    pub fn save(&self, db: &Connection) -> Result<()> {
+        let tx = db.unchecked_transaction()?;
+        let networks = serde_json::to_string(&self.networks)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, NETWORKS, &networks)?;
+        let wired = serde_json::to_string(&self.wired)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, WIRED_NETWORKS, &wired)?;
+        let addresses = serde_json::to_string(&self.addresses)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, ADDRESS_RULES, &addresses)?;
+        let probes = serde_json::to_string(&self.probes)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, PROBES, &probes)?;
+        let rules = serde_json::to_string(&self.rules)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, RULES, &rules)?;
+        let locations = serde_json::to_string(&self.locations)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, LOCATIONS, &locations)?;
+        let geofences = serde_json::to_string(&self.geofences)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, GEOFENCES, &geofences)?;
+        let bluetooth = serde_json::to_string(&self.bluetooth)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, BLUETOOTH_DEVICES, &bluetooth)?;
+        let vpn_policy = serde_json::to_string(&self.vpn_policy)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, VPN_POLICY, &vpn_policy)?;
+        let idle_policy = serde_json::to_string(&self.idle_policy)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, IDLE_POLICY, &idle_policy)?;
+        set(
+            &tx,
+            IDLE_THRESHOLD_MINUTES,
+            &self.idle_threshold_minutes.to_string(),
+        )?;
+        set(
+            &tx,
+            POLL_INTERVAL_SECONDS,
+            &self.poll_interval_seconds.to_string(),
+        )?;
+        set(
+            &tx,
+            NOTIFICATIONS_ENABLED,
+            &self.notifications_enabled.to_string(),
+        )?;
+        set(
+            &tx,
+            WEEKLY_TARGET_HOURS,
+            &self.weekly_target_hours.to_string(),
+        )?;
+        set(
+            &tx,
+            GO_HOME_AFTER_HOURS,
+            &self.go_home_after_hours.to_string(),
+        )?;
+        set(
+            &tx,
+            GO_HOME_SNOOZE_MINUTES,
+            &self.go_home_snooze_minutes.to_string(),
+        )?;
+        set(
+            &tx,
+            GRACE_PERIOD_MINUTES,
+            &self.grace_period_minutes.to_string(),
+        )?;
+        set(
+            &tx,
+            MIN_SESSION_MINUTES,
+            &self.min_session_minutes.to_string(),
+        )?;
+        set(&tx, DAY_BOUNDARY_HOUR, &self.day_boundary_hour.to_string())?;
+        set(&tx, PAUSE_WHEN_LOCKED, &self.pause_when_locked.to_string())?;
+        set(&tx, TOGGLE_SHORTCUT, self.toggle_shortcut.trim())?;
+        let webhooks = serde_json::to_string(&self.webhooks)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, WEBHOOKS, &webhooks)?;
+        let time_sync = serde_json::to_string(&self.time_sync)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, TIME_SYNC, &time_sync)?;
+        let google_calendar = serde_json::to_string(&self.google_calendar)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, GOOGLE_CALENDAR, &google_calendar)?;
+        let daily_notes = serde_json::to_string(&self.daily_notes)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, DAILY_NOTES, &daily_notes)?;
+        let invoicing = serde_json::to_string(&self.invoicing)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, INVOICING, &invoicing)?;
+        let report_rounding = serde_json::to_string(&self.report_rounding)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, REPORT_ROUNDING, &report_rounding)?;
+        let work_schedule = serde_json::to_string(&self.work_schedule)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, WORK_SCHEDULE, &work_schedule)?;
+        let lunch_break = serde_json::to_string(&self.lunch_break)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, LUNCH_BREAK, &lunch_break)?;
+        set(
+            &tx,
+            WEEKLY_DIGEST_ENABLED,
+            &self.weekly_digest_enabled.to_string(),
+        )?;
+        let digest_email = serde_json::to_string(&self.digest_email)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, DIGEST_EMAIL, &digest_email)?;
+        set(&tx, RETENTION_MONTHS, &self.retention_months.to_string())?;
+        set(&tx, SYNC_FOLDER, self.sync_folder.trim())?;
+        set(&tx, SYNC_SERVER, self.sync_server.trim())?;
+        let team = serde_json::to_string(&self.team)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, TEAM, &team)?;
+        let report_destination = serde_json::to_string(&self.report_destination)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        set(&tx, REPORT_DESTINATION, &report_destination)?;
+        set(&tx, APP_LOCK_ENABLED, &self.app_lock_enabled.to_string())?;
+        set(
+            &tx,
+            SLACK_STATUS_ENABLED,
+            &self.slack_status_enabled.to_string(),
+        )?;
+        set(&tx, HOLIDAY_COUNTRY, self.holiday_country.trim())?;
+        set(&tx, HOLIDAY_REGION, self.holiday_region.trim())?;
+        set(&tx, SLACK_STATUS_TEXT, self.slack_status_text.trim())?;
+        set(&tx, SLACK_STATUS_EMOJI, self.slack_status_emoji.trim())?;
+        set(&tx, HTTP_API_ENABLED, &self.http_api_enabled.to_string())?;
+        set(&tx, HTTP_API_PORT, &self.http_api_port.to_string())?;
+        set(&tx, HTTP_API_TOKEN, self.http_api_token.trim())?;
+        set(&tx, AUTOSTART_ENABLED, &self.autostart_enabled.to_string())?;
+        tx.commit()
+    }
+}
+
+/// Avoids wrapping a lone detector.
+fn any_of(mut detectors: Vec<Box<dyn PresenceDetector>>) -> Box<dyn PresenceDetector> {
+    if detectors.len() == 1 {
+        detectors.remove(0)
+    } else {
+        Box::new(AnyDetector::new(detectors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn defaults() -> Settings {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        Settings::load(&db).unwrap()
+    }
+
+    #[test]
+    fn saves_and_loads_every_field() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        let mut settings = Settings::load(&db).unwrap();
+        assert_eq!(settings.validate(), Ok(()));
+
+        settings.networks = vec![NetworkSetting {
+            pattern: "Corp*".to_string(),
+            label: Some("HQ".to_string()),
+            bssid_prefixes: vec!["00:1a:2b".to_string()],
+        }];
+        settings.wired = vec![WiredSetting {
+            subnet: Some("10.20.0.0/16".to_string()),
+            ..WiredSetting::default()
+        }];
+        settings.webhooks = vec![Webhook {
+            url: "https://example.com/hook".to_string(),
+            template: String::new(),
+        }];
+        settings.weekly_target_hours = 37.5;
+        settings.day_boundary_hour = 3;
+        settings.lunch_break = None;
+        settings.sync_server = "https://sync.example.com".to_string();
+        settings.slack_status_enabled = true;
+        assert_eq!(settings.validate(), Ok(()));
+        settings.save(&db).unwrap();
+
+        assert_eq!(Settings::load(&db).unwrap(), settings);
+    }
+
+    #[test]
+    fn rejects_invalid_networks_and_urls() {
+        let invalid = [
+            Settings {
+                networks: vec![NetworkSetting {
+                    pattern: "re:(".to_string(),
+                    label: None,
+                    bssid_prefixes: Vec::new(),
+                }],
+                ..defaults()
+            },
+            Settings {
+                networks: vec![NetworkSetting {
+                    pattern: "CorpNet".to_string(),
+                    label: None,
+                    bssid_prefixes: vec!["00:zz".to_string()],
+                }],
+                ..defaults()
+            },
+            Settings {
+                wired: vec![WiredSetting {
+                    subnet: Some("10.20.0.0/33".to_string()),
+                    ..WiredSetting::default()
+                }],
+                ..defaults()
+            },
+            Settings {
+                wired: vec![WiredSetting {
+                    gateway_mac: Some("00:1a:2b:3c:4d".to_string()),
+                    ..WiredSetting::default()
+                }],
+                ..defaults()
+            },
+            Settings {
+                webhooks: vec![Webhook {
+                    url: "example.com/hook".to_string(),
+                    template: String::new(),
+                }],
+                ..defaults()
+            },
+            Settings {
+                sync_server: "ftp://sync.example.com".to_string(),
+                ..defaults()
+            },
+        ];
+        for settings in invalid {
+            assert!(settings.validate().is_err(), "{:?}", settings);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use serde_json::json;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+use tracing::error;
+
+use crate::error::Result;
+use crate::pool::ReadPool;
+use crate::{keychain, settings};
+
+/// Keychain account holding the Slack user token (`xoxp-...`), which needs
+/// the `users.profile:write` scope.
+pub const TOKEN_ACCOUNT: &str = "slack";
+const PROFILE_SET_URL: &str = "https://slack.com/api/users.profile.set";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The `users.profile.set` body for a status, or clearing it if `text` and
+/// `emoji` are empty.
+fn profile(text: &str, emoji: &str) -> serde_json::Value {
+    json!({
+        "profile": {
+            "status_text": text,
+            "status_emoji": emoji,
+            "status_expiration": 0,
+        }
+    })
+}
+
+/// Updates the Slack status from a thread of its own, one request at a
+/// time, so Slack being slow can't hold up tracking and an older status
+/// can't land after a newer one. Settings and the token are read on that
+/// thread too, never under the caller's lock on the database.
+pub struct StatusUpdater {
+    updates: Sender<bool>,
+}
+
+impl StatusUpdater {
+    /// Starts the thread, which reads settings through `readers`.
+    pub fn spawn(readers: ReadPool) -> Self {
+        let (updates, pending) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(in_office) = pending.recv() {
+                // Only the last of any that queued up behind a slow request
+                // is still true
+                let in_office = pending.try_iter().last().unwrap_or(in_office);
+                if let Err(e) = update_status(&readers, in_office) {
+                    error!("Error updating Slack status: {}", e);
+                }
+            }
+        });
+        Self { updates }
+    }
+
+    /// Sets the Slack status from settings when `in_office`, or clears it,
+    /// if the integration is turned on.
+    pub fn update(&self, in_office: bool) {
+        // Only fails if the thread panicked, which it will have logged
+        let _ = self.updates.send(in_office);
+    }
+}
+
+fn update_status(readers: &ReadPool, in_office: bool) -> Result<()> {
+    let body = {
+        let db = readers.get()?;
+        if !settings::get_bool(&db, settings::SLACK_STATUS_ENABLED, false)? {
+            return Ok(());
+        }
+        if in_office {
+            let text = settings::get(&db, settings::SLACK_STATUS_TEXT)?
+                .unwrap_or_else(|| settings::DEFAULT_SLACK_STATUS_TEXT.to_string());
+            let emoji = settings::get(&db, settings::SLACK_STATUS_EMOJI)?
+                .unwrap_or_else(|| settings::DEFAULT_SLACK_STATUS_EMOJI.to_string());
+            profile(&text, &emoji)
+        } else {
+            profile("", "")
+        }
+    };
+    let token = keychain::get(TOKEN_ACCOUNT)?
+        .ok_or("Slack status is on but there's no Slack token in the keychain")?;
+    set_profile(&token, &body)
+}
+
+fn set_profile(token: &str, body: &serde_json::Value) -> Result<()> {
+    let response: serde_json::Value = ureq::post(PROFILE_SET_URL)
+        .timeout(TIMEOUT)
+        .set("Authorization", &format!("Bearer {}", token))
+        .send_json(body)
+        .map_err(|e| e.to_string())?
+        .into_json()?;
+    // Slack reports failures in the body with a 200
+    if response["ok"] != true {
+        return Err(format!(
+            "Slack said {}",
+            response["error"].as_str().unwrap_or("the request failed")
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clearing_sends_empty_status() {
+        let body = profile("", "");
+        assert_eq!(body["profile"]["status_text"], "");
+        assert_eq!(body["profile"]["status_emoji"], "");
+        let body = profile("In the office", ":office:");
+        assert_eq!(body["profile"]["status_text"], "In the office");
+        assert_eq!(body["profile"]["status_emoji"], ":office:");
+    }
+}
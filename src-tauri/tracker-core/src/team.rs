@@ -0,0 +1,233 @@
+use chrono::{Duration, NaiveDate};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::Duration as Timeout;
+
+use crate::error::Result;
+use crate::reports;
+use crate::{keychain, settings};
+
+/// Keychain account holding the bearer token for the team endpoint, if it
+/// needs one.
+pub const TOKEN_ACCOUNT: &str = "team";
+/// Days published each time, ending today, so edits to recent days carry over.
+const PUBLISH_DAYS: i64 = 7;
+const TIMEOUT: Timeout = Timeout::from_secs(10);
+
+/// Where to share daily totals with the rest of the team. Only the name and
+/// each day's total minutes are sent: no times, networks or locations.
+///
+/// The endpoint takes `POST` with `{"member", "days": [{"date",
+/// "total_minutes"}]}`, replacing that member's totals for those days, and
+/// answers `GET ?from=&to=` with `{"days": [{"member", "date",
+/// "total_minutes"}]}` for everyone.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TeamSetting {
+    pub endpoint: String,
+    /// What the rest of the team sees this device's totals as.
+    pub member: String,
+}
+
+impl TeamSetting {
+    pub fn validate(&self) -> Result<(), String> {
+        let endpoint = self.endpoint.trim();
+        if !endpoint.starts_with("https://") && !endpoint.starts_with("http://") {
+            return Err(format!("Invalid team endpoint URL '{}'", endpoint));
+        }
+        if self.member.trim().is_empty() {
+            return Err("Team mode needs a name to share totals under".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// One member's total for a day, as the endpoint returns it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MemberDay {
+    pub member: String,
+    pub date: String,
+    pub total_minutes: i64,
+}
+
+#[derive(Serialize)]
+pub struct MemberSummary {
+    pub member: String,
+    pub total_minutes: i64,
+    /// Days with any time in the office.
+    pub office_days: i64,
+    pub days: Vec<reports::DayMinutes>,
+}
+
+/// Who was in between `from` and `to`, longest total first.
+#[derive(Serialize)]
+pub struct TeamSummary {
+    pub from: String,
+    pub to: String,
+    pub members: Vec<MemberSummary>,
+}
+
+/// The configured team, if team mode is on.
+pub fn load(db: &Connection) -> Result<Option<TeamSetting>> {
+    Ok(settings::get(db, settings::TEAM)?.and_then(|json| serde_json::from_str(&json).ok()))
+}
+
+/// This device's totals for every day of the last week, ending `today`, to
+/// publish. Days with nothing recorded are sent as 0, so a day deleted here
+/// doesn't keep its old total on the endpoint.
+pub fn recent_totals(db: &Connection, today: NaiveDate) -> Result<Vec<reports::DayMinutes>> {
+    let from = today - Duration::days(PUBLISH_DAYS - 1);
+    let recorded: BTreeMap<String, i64> =
+        reports::day_totals(db, &from.to_string(), &today.to_string(), None)?
+            .into_iter()
+            .map(|day| (day.date, day.total_minutes))
+            .collect();
+    Ok(from
+        .iter_days()
+        .take(PUBLISH_DAYS as usize)
+        .map(|date| {
+            let date = date.to_string();
+            reports::DayMinutes {
+                total_minutes: recorded.get(&date).copied().unwrap_or(0),
+                date,
+            }
+        })
+        .collect())
+}
+
+fn authorize(request: ureq::Request) -> Result<ureq::Request> {
+    Ok(match keychain::get(TOKEN_ACCOUNT)? {
+        Some(token) => request.set("Authorization", &format!("Bearer {}", token)),
+        None => request,
+    })
+}
+
+/// Publishes `days` under the configured name.
+pub fn publish(config: &TeamSetting, days: &[reports::DayMinutes]) -> Result<()> {
+    authorize(ureq::post(config.endpoint.trim()))?
+        .timeout(TIMEOUT)
+        .send_json(json!({ "member": config.member.trim(), "days": days }))
+        .map_err(|e| format!("Couldn't publish to the team endpoint: {}", e))?;
+    Ok(())
+}
+
+/// Everyone's totals between `from` and `to` inclusive.
+pub fn fetch(config: &TeamSetting, from: &str, to: &str) -> Result<TeamSummary> {
+    #[derive(Deserialize)]
+    struct Response {
+        days: Vec<MemberDay>,
+    }
+    let response: Response = authorize(ureq::get(config.endpoint.trim()))?
+        .query("from", from)
+        .query("to", to)
+        .timeout(TIMEOUT)
+        .call()
+        .map_err(|e| format!("Couldn't fetch the team's totals: {}", e))?
+        .into_json()?;
+    Ok(summarize(from, to, response.days))
+}
+
+/// Groups `days` by member, leaving out any outside `from` to `to` in case
+/// the endpoint ignores the range.
+fn summarize(from: &str, to: &str, days: Vec<MemberDay>) -> TeamSummary {
+    let mut by_member: BTreeMap<String, Vec<reports::DayMinutes>> = BTreeMap::new();
+    for day in days {
+        if day.date.as_str() < from || day.date.as_str() > to {
+            continue;
+        }
+        by_member
+            .entry(day.member)
+            .or_default()
+            .push(reports::DayMinutes {
+                date: day.date,
+                total_minutes: day.total_minutes,
+            });
+    }
+    let mut members: Vec<MemberSummary> = by_member
+        .into_iter()
+        .map(|(member, mut days)| {
+            days.sort_by(|a, b| a.date.cmp(&b.date));
+            MemberSummary {
+                member,
+                total_minutes: days.iter().map(|day| day.total_minutes).sum(),
+                office_days: days.iter().filter(|day| day.total_minutes > 0).count() as i64,
+                days,
+            }
+        })
+        .collect();
+    members.sort_by(|a, b| b.total_minutes.cmp(&a.total_minutes));
+    TeamSummary {
+        from: from.to_string(),
+        to: to.to_string(),
+        members,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{edits, migrations};
+
+    fn day(member: &str, date: &str, total_minutes: i64) -> MemberDay {
+        MemberDay {
+            member: member.to_string(),
+            date: date.to_string(),
+            total_minutes,
+        }
+    }
+
+    #[test]
+    fn summary_ranks_members_by_total() {
+        let summary = summarize(
+            "2024-03-04",
+            "2024-03-10",
+            vec![
+                day("Ada", "2024-03-05", 240),
+                day("Grace", "2024-03-04", 480),
+                day("Ada", "2024-03-04", 300),
+                day("Grace", "2024-03-06", 0),
+                day("Grace", "2024-03-11", 600),
+            ],
+        );
+        let members: Vec<(&str, i64, i64)> = summary
+            .members
+            .iter()
+            .map(|member| {
+                (
+                    member.member.as_str(),
+                    member.total_minutes,
+                    member.office_days,
+                )
+            })
+            .collect();
+        assert_eq!(members, vec![("Ada", 540, 2), ("Grace", 480, 1)]);
+        assert_eq!(summary.members[0].days[0].date, "2024-03-04");
+    }
+
+    #[test]
+    fn recent_totals_include_empty_days() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        edits::upsert_day(&mut db, "2024-03-06", "09:00", "17:00").unwrap();
+
+        let today = NaiveDate::from_ymd_opt(2024, 3, 10).unwrap();
+        let days = recent_totals(&db, today).unwrap();
+        let totals: Vec<(&str, i64)> = days
+            .iter()
+            .map(|day| (day.date.as_str(), day.total_minutes))
+            .collect();
+        assert_eq!(
+            totals,
+            vec![
+                ("2024-03-04", 0),
+                ("2024-03-05", 0),
+                ("2024-03-06", 480),
+                ("2024-03-07", 0),
+                ("2024-03-08", 0),
+                ("2024-03-09", 0),
+                ("2024-03-10", 0),
+            ]
+        );
+    }
+}
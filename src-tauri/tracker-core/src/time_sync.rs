@@ -0,0 +1,362 @@
+use chrono::{SecondsFormat, TimeZone, Utc};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+
+use crate::error::Result;
+use crate::sessions::{self, Session};
+use crate::settings;
+
+const TOGGL_API: &str = "https://api.track.toggl.com/api/v9";
+const CLOCKIFY_API: &str = "https://api.clockify.me/api/v1";
+const TIMEOUT: Duration = Duration::from_secs(15);
+/// Shows in Toggl as the app the entries came from.
+const CREATED_WITH: &str = "Foundership Time Tracker";
+
+/// Which time tracking service to push sessions to.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Provider {
+    Toggl,
+    Clockify,
+}
+
+impl Provider {
+    fn name(self) -> &'static str {
+        match self {
+            Provider::Toggl => "toggl",
+            Provider::Clockify => "clockify",
+        }
+    }
+}
+
+/// Sends sessions at `location` to `project_id`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProjectMapping {
+    pub location: String,
+    pub project_id: String,
+}
+
+/// Pushing completed sessions to Toggl Track or Clockify as time entries,
+/// as configured by the user.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TimeSyncSetting {
+    pub provider: Provider,
+    pub api_key: String,
+    pub workspace_id: String,
+    /// Sessions from before this date (YYYY-MM-DD) are left alone, so turning
+    /// this on doesn't upload years of history.
+    pub since: String,
+    /// Project for sessions at a location without a mapping, if any.
+    #[serde(default)]
+    pub default_project_id: Option<String>,
+    #[serde(default)]
+    pub projects: Vec<ProjectMapping>,
+    /// Used when the session has no location.
+    #[serde(default = "default_description")]
+    pub description: String,
+}
+
+fn default_description() -> String {
+    "Office".to_string()
+}
+
+impl TimeSyncSetting {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.api_key.trim().is_empty() {
+            return Err("Time sync needs an API key".to_string());
+        }
+        if self.workspace_id.trim().is_empty() {
+            return Err("Time sync needs a workspace id".to_string());
+        }
+        chrono::NaiveDate::parse_from_str(&self.since, "%Y-%m-%d")
+            .map_err(|_| format!("Invalid date '{}', expected YYYY-MM-DD", self.since))?;
+        // Toggl's ids are numbers, Clockify's are strings
+        if self.provider == Provider::Toggl {
+            let ids = std::iter::once(&self.workspace_id)
+                .chain(&self.default_project_id)
+                .chain(self.projects.iter().map(|mapping| &mapping.project_id));
+            for id in ids {
+                toggl_id(id)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn project_for(&self, session: &Session) -> Option<&str> {
+        let mapped = session.label.as_deref().and_then(|label| {
+            self.projects
+                .iter()
+                .find(|mapping| mapping.location.trim() == label)
+        });
+        match mapped {
+            Some(mapping) => Some(mapping.project_id.trim()),
+            None => self.default_project_id.as_deref().map(str::trim),
+        }
+    }
+
+    /// The time entry body for `session`, which must have both timestamps.
+    fn entry(&self, session: &Session, start_at: i64, end_at: i64) -> Result<serde_json::Value> {
+        let description = session
+            .label
+            .clone()
+            .unwrap_or_else(|| self.description.clone());
+        let start = rfc3339(start_at)?;
+        let end = rfc3339(end_at)?;
+        let project = self.project_for(session);
+        Ok(match self.provider {
+            Provider::Toggl => {
+                let project_id = project.map(toggl_id).transpose()?;
+                json!({
+                    "created_with": CREATED_WITH,
+                    "description": description,
+                    "workspace_id": toggl_id(&self.workspace_id)?,
+                    "project_id": project_id,
+                    "start": start,
+                    "stop": end,
+                    "duration": end_at - start_at,
+                })
+            }
+            Provider::Clockify => json!({
+                "description": description,
+                "projectId": project,
+                "start": start,
+                "end": end,
+            }),
+        })
+    }
+}
+
+fn toggl_id(id: &str) -> Result<i64, String> {
+    id.trim()
+        .parse()
+        .map_err(|_| format!("Invalid Toggl id '{}'", id))
+}
+
+fn rfc3339(epoch: i64) -> Result<String> {
+    Ok(Utc
+        .timestamp_opt(epoch, 0)
+        .single()
+        .ok_or_else(|| format!("Invalid timestamp {}", epoch))?
+        .to_rfc3339_opts(SecondsFormat::Secs, true))
+}
+
+/// Remembers which sessions have been pushed, and as which entry, so each
+/// one is only sent once.
+pub fn create_table(db: &Connection) -> rusqlite::Result<()> {
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS synced_sessions (
+            session_id INTEGER PRIMARY KEY,
+            provider TEXT NOT NULL,
+            entry_id TEXT NOT NULL,
+            synced_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+/// The configured sync, if it's turned on.
+pub fn load(db: &Connection) -> Result<Option<TimeSyncSetting>> {
+    Ok(settings::get(db, settings::TIME_SYNC)?.and_then(|json| serde_json::from_str(&json).ok()))
+}
+
+/// Completed sessions since `config.since` that haven't been pushed yet,
+/// leaving out `live` ones that are still running.
+pub fn pending(db: &Connection, config: &TimeSyncSetting, live: &[i64]) -> Result<Vec<Session>> {
+    let mut stmt = db.prepare("SELECT 1 FROM synced_sessions WHERE session_id = ?1")?;
+    let mut pending = Vec::new();
+    for session in sessions::sessions_since(db, &config.since)? {
+        if live.contains(&session.id) || session.start_at.is_none() || session.end_at.is_none() {
+            continue;
+        }
+        if !stmt.exists([session.id])? {
+            pending.push(session);
+        }
+    }
+    Ok(pending)
+}
+
+/// Creates a time entry for `session`, returning its id.
+pub fn push(config: &TimeSyncSetting, session: &Session) -> Result<String> {
+    let (Some(start_at), Some(end_at)) = (session.start_at, session.end_at) else {
+        return Err(format!("Session {} has no timestamps", session.id).into());
+    };
+    let body = config.entry(session, start_at, end_at)?;
+    let workspace = config.workspace_id.trim();
+    let request = match config.provider {
+        Provider::Toggl => ureq::post(&format!(
+            "{}/workspaces/{}/time_entries",
+            TOGGL_API, workspace
+        ))
+        .set(
+            "Authorization",
+            &format!("Basic {}", basic_auth(config.api_key.trim(), "api_token")),
+        ),
+        Provider::Clockify => ureq::post(&format!(
+            "{}/workspaces/{}/time-entries",
+            CLOCKIFY_API, workspace
+        ))
+        .set("X-Api-Key", config.api_key.trim()),
+    };
+    let response: serde_json::Value = request
+        .timeout(TIMEOUT)
+        .send_json(body)
+        .map_err(|e| {
+            format!(
+                "{} rejected session {}: {}",
+                config.provider.name(),
+                session.id,
+                e
+            )
+        })?
+        .into_json()?;
+    match &response["id"] {
+        serde_json::Value::Number(id) => Ok(id.to_string()),
+        serde_json::Value::String(id) => Ok(id.clone()),
+        _ => Err(format!("{} didn't return an entry id", config.provider.name()).into()),
+    }
+}
+
+/// Records that `session_id` was pushed as `entry_id`.
+pub fn record_synced(
+    db: &Connection,
+    config: &TimeSyncSetting,
+    session_id: i64,
+    entry_id: &str,
+) -> Result<()> {
+    db.execute(
+        "INSERT OR REPLACE INTO synced_sessions (session_id, provider, entry_id, synced_at)
+         VALUES (?1, ?2, ?3, strftime('%s', 'now'))",
+        params![session_id, config.provider.name(), entry_id],
+    )?;
+    Ok(())
+}
+
+/// Pushes every pending session, stopping at the first failure so the rest
+/// are tried again next time. Returns how many were pushed.
+pub fn sync(db: &Connection, live: &[i64]) -> Result<usize> {
+    let Some(config) = load(db)? else {
+        return Ok(0);
+    };
+    let pending = pending(db, &config, live)?;
+    for session in &pending {
+        let entry_id = push(&config, session)?;
+        record_synced(db, &config, session.id, &entry_id)?;
+    }
+    Ok(pending.len())
+}
+
+/// `user:password` in base64, for HTTP basic auth.
+fn basic_auth(user: &str, password: &str) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let input = format!("{}:{}", user, password);
+    let mut encoded = String::new();
+    for chunk in input.as_bytes().chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (u32::from(bytes[0]) << 16) | (u32::from(bytes[1]) << 8) | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn config(provider: Provider) -> TimeSyncSetting {
+        TimeSyncSetting {
+            provider,
+            api_key: "key".to_string(),
+            workspace_id: "42".to_string(),
+            since: "2024-03-01".to_string(),
+            default_project_id: Some("7".to_string()),
+            projects: vec![ProjectMapping {
+                location: "Lab".to_string(),
+                project_id: "8".to_string(),
+            }],
+            description: default_description(),
+        }
+    }
+
+    fn insert_session(db: &Connection, date: &str, start_at: i64, label: Option<&str>) -> i64 {
+        db.execute(
+            "INSERT INTO sessions (date, start, end, label, start_at, end_at)
+             VALUES (?1, '09:00', '10:00', ?2, ?3, ?4)",
+            params![date, label, start_at, start_at + 3600],
+        )
+        .unwrap();
+        db.last_insert_rowid()
+    }
+
+    #[test]
+    fn pending_skips_synced_live_and_old_sessions() {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        let config = config(Provider::Clockify);
+        insert_session(&db, "2024-02-28", 1_709_100_000, None);
+        let synced = insert_session(&db, "2024-03-04", 1_709_542_800, None);
+        let pending = insert_session(&db, "2024-03-05", 1_709_629_200, None);
+        let live = insert_session(&db, "2024-03-06", 1_709_715_600, None);
+        record_synced(&db, &config, synced, "abc").unwrap();
+
+        let ids: Vec<i64> = super::pending(&db, &config, &[live])
+            .unwrap()
+            .iter()
+            .map(|session| session.id)
+            .collect();
+        assert_eq!(ids, vec![pending]);
+    }
+
+    #[test]
+    fn entries_use_the_location_project() {
+        let session = Session {
+            id: 1,
+            date: "2024-03-04".to_string(),
+            start: "09:00".to_string(),
+            end: "10:00".to_string(),
+            ssid: None,
+            label: Some("Lab".to_string()),
+            source: sessions::SOURCE_AUTO.to_string(),
+            start_at: Some(1_709_542_800),
+            end_at: Some(1_709_546_400),
+            rule: None,
+            idle_seconds: 0,
+            active_seconds: None,
+        };
+        let toggl = config(Provider::Toggl)
+            .entry(&session, 1_709_542_800, 1_709_546_400)
+            .unwrap();
+        assert_eq!(toggl["project_id"], 8);
+        assert_eq!(toggl["workspace_id"], 42);
+        assert_eq!(toggl["start"], "2024-03-04T09:00:00Z");
+        assert_eq!(toggl["duration"], 3600);
+
+        let mut elsewhere = session.clone();
+        elsewhere.label = None;
+        let clockify = config(Provider::Clockify)
+            .entry(&elsewhere, 1_709_542_800, 1_709_546_400)
+            .unwrap();
+        assert_eq!(clockify["projectId"], "7");
+        assert_eq!(clockify["description"], "Office");
+        assert_eq!(clockify["end"], "2024-03-04T10:00:00Z");
+    }
+
+    #[test]
+    fn basic_auth_is_base64() {
+        assert_eq!(basic_auth("key", "api_token"), "a2V5OmFwaV90b2tlbg==");
+        assert_eq!(basic_auth("ab", "c"), "YWI6Yw==");
+    }
+}
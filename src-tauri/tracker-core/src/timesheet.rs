@@ -0,0 +1,218 @@
+use chrono::{Datelike, Duration, NaiveDate};
+use minijinja::{AutoEscape, Environment};
+use printpdf::{BuiltinFont, Mm, PdfDocument};
+use rusqlite::Connection;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::connections::{self, LogQuery};
+use crate::error::Result;
+use crate::reports::format_minutes;
+
+pub const DEFAULT_HTML_TEMPLATE: &str = include_str!("../templates/timesheet.html");
+/// Laid out line by line in a fixed-width font for PDFs.
+pub const DEFAULT_TEXT_TEMPLATE: &str = include_str!("../templates/timesheet.txt");
+
+const PAGE_WIDTH: Mm = Mm(210.0);
+const PAGE_HEIGHT: Mm = Mm(297.0);
+const MARGIN: f32 = 20.0;
+const FONT_SIZE: f32 = 10.0;
+const LINE_HEIGHT: f32 = 5.0;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TimesheetDay {
+    pub date: String,
+    /// e.g. "Mon".
+    pub weekday: String,
+    pub earliest: String,
+    pub latest: String,
+    pub total_minutes: i64,
+    pub note: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TimesheetWeek {
+    /// The Monday the week starts on, even if the range starts later.
+    pub week_start: String,
+    pub days: Vec<TimesheetDay>,
+    pub total_minutes: i64,
+}
+
+/// Every recorded day in a range, grouped into weeks, which is what the
+/// templates are rendered with.
+#[derive(Clone, Debug, Serialize)]
+pub struct Timesheet {
+    pub from: String,
+    pub to: String,
+    pub location: Option<String>,
+    pub weeks: Vec<TimesheetWeek>,
+    pub total_minutes: i64,
+}
+
+/// The days from `from` to `to` inclusive, optionally at just one location.
+pub fn timesheet(
+    db: &Connection,
+    from: NaiveDate,
+    to: NaiveDate,
+    location: Option<&str>,
+) -> Result<Timesheet> {
+    let (from, to) = (from.to_string(), to.to_string());
+    let query = LogQuery {
+        from: Some(&from),
+        to: Some(&to),
+        location,
+        ..Default::default()
+    };
+    let mut log = connections::get_connection_log(db, &query)?;
+    log.sort_by(|a, b| a.date.cmp(&b.date));
+
+    let mut weeks: Vec<TimesheetWeek> = Vec::new();
+    for day in log {
+        let date = NaiveDate::parse_from_str(&day.date, "%Y-%m-%d")
+            .map_err(|_| format!("Invalid date '{}'", day.date))?;
+        let week_start =
+            (date - Duration::days(date.weekday().num_days_from_monday() as i64)).to_string();
+        if weeks.last().map(|week| &week.week_start) != Some(&week_start) {
+            weeks.push(TimesheetWeek {
+                week_start,
+                days: Vec::new(),
+                total_minutes: 0,
+            });
+        }
+        let week = weeks.last_mut().expect("a week was just pushed");
+        week.total_minutes += day.total_minutes;
+        week.days.push(TimesheetDay {
+            weekday: date.format("%a").to_string(),
+            date: day.date,
+            earliest: day.earliest,
+            latest: day.latest,
+            total_minutes: day.total_minutes,
+            note: day.note,
+        });
+    }
+    Ok(Timesheet {
+        total_minutes: weeks.iter().map(|week| week.total_minutes).sum(),
+        from,
+        to,
+        location: location.map(str::to_string),
+        weeks,
+    })
+}
+
+/// Renders `context`, e.g. a `Timesheet`, with a MiniJinja `template`, which
+/// gets a `duration` filter for minutes, escaping values if it's `html`.
+pub fn render(context: &impl Serialize, template: &str, html: bool) -> Result<String> {
+    let mut env = Environment::new();
+    env.add_filter("duration", |minutes: i64| format_minutes(minutes));
+    env.set_auto_escape_callback(move |_| {
+        if html {
+            AutoEscape::Html
+        } else {
+            AutoEscape::None
+        }
+    });
+    env.render_str(template, context)
+        .map_err(|e| format!("Couldn't render the template: {}", e).into())
+}
+
+/// Writes the timesheet for `from` to `to` to `path`, as a PDF if it ends in
+/// `.pdf` and HTML otherwise, using `template` or the default for the format.
+pub fn generate(
+    db: &Connection,
+    from: NaiveDate,
+    to: NaiveDate,
+    location: Option<&str>,
+    template: Option<&str>,
+    path: &Path,
+) -> Result<()> {
+    if from > to {
+        return Err(format!("{} is after {}", from, to).into());
+    }
+    let timesheet = timesheet(db, from, to, location)?;
+    let pdf = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("pdf"));
+    if pdf {
+        let text = render(&timesheet, template.unwrap_or(DEFAULT_TEXT_TEMPLATE), false)?;
+        write_pdf(&text, &format!("Timesheet {} to {}", from, to), path)
+    } else {
+        let html = render(&timesheet, template.unwrap_or(DEFAULT_HTML_TEMPLATE), true)?;
+        Ok(fs::write(path, html)?)
+    }
+}
+
+/// Lays `text` out on A4 pages, a line at a time, in Courier.
+pub(crate) fn write_pdf(text: &str, title: &str, path: &Path) -> Result<()> {
+    let (doc, page, layer) = PdfDocument::new(title, PAGE_WIDTH, PAGE_HEIGHT, "Text");
+    let font = doc
+        .add_builtin_font(BuiltinFont::Courier)
+        .map_err(|e| e.to_string())?;
+    let mut layer = doc.get_page(page).get_layer(layer);
+    let mut y = PAGE_HEIGHT.0 - MARGIN;
+    for line in text.lines() {
+        if y < MARGIN {
+            let (page, new_layer) = doc.add_page(PAGE_WIDTH, PAGE_HEIGHT, "Text");
+            layer = doc.get_page(page).get_layer(new_layer);
+            y = PAGE_HEIGHT.0 - MARGIN;
+        }
+        layer.use_text(line, FONT_SIZE, Mm(MARGIN), Mm(y), &font);
+        y -= LINE_HEIGHT;
+    }
+    doc.save(&mut BufWriter::new(File::create(path)?))
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migrations;
+
+    fn db() -> Connection {
+        let mut db = Connection::open_in_memory().unwrap();
+        migrations::run(&mut db).unwrap();
+        for (date, note) in [
+            ("2024-03-01", None),
+            ("2024-03-04", Some("<b>late</b>")),
+            ("2024-03-05", None),
+        ] {
+            db.execute(
+                "INSERT INTO connections (date, earliest, latest, note) VALUES (?1, '09:00', '17:00', ?2)",
+                rusqlite::params![date, note],
+            )
+            .unwrap();
+        }
+        db
+    }
+
+    fn date(date: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn days_are_grouped_into_weeks() {
+        let timesheet = timesheet(&db(), date("2024-03-01"), date("2024-03-31"), None).unwrap();
+        let weeks: Vec<(&str, usize)> = timesheet
+            .weeks
+            .iter()
+            .map(|week| (week.week_start.as_str(), week.days.len()))
+            .collect();
+        assert_eq!(weeks, vec![("2024-02-26", 1), ("2024-03-04", 2)]);
+        assert_eq!(timesheet.weeks[1].days[0].weekday, "Mon");
+    }
+
+    #[test]
+    fn html_is_escaped_and_has_signature_line() {
+        let timesheet = timesheet(&db(), date("2024-03-04"), date("2024-03-10"), None).unwrap();
+        let html = render(&timesheet, DEFAULT_HTML_TEMPLATE, true).unwrap();
+        assert!(html.contains("&lt;b&gt;late&lt;/b&gt;"));
+        assert!(html.contains("Signature:"));
+        assert!(html.contains("Week of 2024-03-04"));
+
+        let text = render(&timesheet, DEFAULT_TEXT_TEMPLATE, false).unwrap();
+        assert!(text.contains("Mon 2024-03-04    09:00    17:00"));
+        assert!(text.contains("<b>late</b>"));
+    }
+}
@@ -0,0 +1,425 @@
+use chrono::{DateTime, Duration, Local};
+use rusqlite::Connection;
+
+use crate::connections::{insert_connection, MANUAL_PRESENCE};
+use crate::day_types::{self, DayType};
+use crate::detector::Presence;
+use crate::error::Result;
+use crate::heartbeat;
+use crate::sessions::{self, DayBoundary, ManualTimer, SessionTracker, Transition};
+use crate::settings;
+
+/// What one presence check changed, for the caller to report on.
+#[derive(Debug)]
+pub struct CheckOutcome {
+    pub presence: Option<Presence>,
+    pub transition: Transition,
+    /// Whether the session that just ended, including one ended by rolling
+    /// over to a new day, was too short to keep and was deleted.
+    pub discarded: bool,
+    /// The tracking day the check counted towards.
+    pub date: String,
+}
+
+/// Records a presence check made at `now`: widens the day's earliest/latest,
+/// tags the day as working from home if presence came over a VPN, keeps a
+/// running manual timer going, and advances the session state machine,
+/// discarding sessions that end up too short. Leaves a heartbeat for
+/// `heartbeat::close_dangling` in case the app never gets to end the
+/// sessions still running.
+pub fn record_check(
+    db: &Connection,
+    presence: Option<Presence>,
+    sessions: &mut SessionTracker,
+    manual: &mut ManualTimer,
+    now: DateTime<Local>,
+) -> Result<CheckOutcome> {
+    let boundary = DayBoundary::load(db)?;
+    if let Some(presence) = &presence {
+        insert_connection(db, presence, now, boundary)?;
+        if presence.vpn {
+            day_types::tag_if_untagged(db, &boundary.date_string(now), DayType::Wfh)?;
+        }
+    }
+    if manual.is_running() {
+        manual.extend(db, now, boundary)?;
+        insert_connection(db, &MANUAL_PRESENCE, now, boundary)?;
+    }
+
+    // A running manual timer already covers this time, so don't count
+    // detected presence twice
+    let detected = if manual.is_running() {
+        None
+    } else {
+        presence.as_ref()
+    };
+    let grace_minutes = settings::get_f64(
+        db,
+        settings::GRACE_PERIOD_MINUTES,
+        settings::DEFAULT_GRACE_PERIOD_MINUTES,
+    )?;
+    let transition = sessions.update(
+        db,
+        detected,
+        now,
+        boundary,
+        Duration::seconds((grace_minutes * 60.0) as i64),
+    )?;
+
+    let mut discarded = false;
+    if let Transition::Ended(id) | Transition::Rolled { ended: id, .. } = transition {
+        let min_minutes = settings::get_f64(
+            db,
+            settings::MIN_SESSION_MINUTES,
+            settings::DEFAULT_MIN_SESSION_MINUTES,
+        )?;
+        discarded = sessions::discard_if_short(db, id, min_minutes as i64)?;
+    }
+    heartbeat::record(db, now, sessions.live_session_id(), manual.session_id())?;
+
+    Ok(CheckOutcome {
+        presence,
+        transition,
+        discarded,
+        date: boundary.date_string(now),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock};
+    use crate::connections;
+    use crate::detector::{MockDetector, PresenceDetector};
+    use crate::migrations;
+    use chrono::TimeZone;
+
+    struct Harness {
+        db: Connection,
+        clock: MockClock,
+        detector: MockDetector,
+        sessions: SessionTracker,
+        manual: ManualTimer,
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let mut db = Connection::open_in_memory().unwrap();
+            migrations::run(&mut db).unwrap();
+            Self {
+                db,
+                clock: MockClock::new(Local.with_ymd_and_hms(2024, 3, 4, 9, 0, 0).unwrap()),
+                detector: MockDetector::new(false),
+                sessions: SessionTracker::default(),
+                manual: ManualTimer::default(),
+            }
+        }
+
+        /// Moves the clock on by `minutes` and checks with the detector
+        /// reporting `present`.
+        fn check_after(&mut self, minutes: i64, present: bool) -> CheckOutcome {
+            self.clock.advance(Duration::minutes(minutes));
+            self.detector.set_present(present);
+            record_check(
+                &self.db,
+                self.detector.detect().unwrap(),
+                &mut self.sessions,
+                &mut self.manual,
+                self.clock.now(),
+            )
+            .unwrap()
+        }
+
+        fn day(&self, date: &str) -> connections::ConnectionLog {
+            connections::get_day(&self.db, date).unwrap().unwrap()
+        }
+    }
+
+    #[test]
+    fn insert_connection_keeps_earliest_and_latest() {
+        let harness = Harness::new();
+        let boundary = DayBoundary::load(&harness.db).unwrap();
+        let at = |hour, minute| Local.with_ymd_and_hms(2024, 3, 4, hour, minute, 0).unwrap();
+        for now in [at(12, 0), at(9, 30), at(17, 45), at(11, 0)] {
+            insert_connection(&harness.db, &MANUAL_PRESENCE, now, boundary).unwrap();
+        }
+
+        let day = harness.day("2024-03-04");
+        assert_eq!(
+            (day.earliest.as_str(), day.latest.as_str()),
+            ("09:30", "17:45")
+        );
+    }
+
+    #[test]
+    fn session_runs_from_first_to_last_detection() {
+        let mut harness = Harness::new();
+        let started = harness.check_after(0, true);
+        assert!(matches!(started.transition, Transition::Started(_)));
+        for _ in 0..4 {
+            assert_eq!(
+                harness.check_after(30, true).transition,
+                Transition::Continued
+            );
+        }
+        // Absent within the grace period
+        assert_eq!(
+            harness.check_after(5, false).transition,
+            Transition::Continued
+        );
+        let ended = harness.check_after(30, false);
+        assert!(matches!(ended.transition, Transition::Ended(_)));
+        assert!(!ended.discarded);
+
+        let day = harness.day("2024-03-04");
+        assert_eq!(
+            (day.earliest.as_str(), day.latest.as_str()),
+            ("09:00", "11:00")
+        );
+        assert_eq!(day.total_minutes, 120);
+    }
+
+    #[test]
+    fn short_sessions_are_discarded() {
+        let mut harness = Harness::new();
+        harness.check_after(0, true);
+        harness.check_after(2, true);
+        let ended = harness.check_after(30, false);
+
+        assert!(ended.discarded);
+        assert!(connections::get_day(&harness.db, "2024-03-04")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn manual_timer_suppresses_detected_sessions() {
+        let mut harness = Harness::new();
+        let boundary = DayBoundary::load(&harness.db).unwrap();
+        harness
+            .manual
+            .start(&harness.db, harness.clock.now(), boundary)
+            .unwrap();
+
+        let outcome = harness.check_after(30, true);
+
+        assert_eq!(outcome.transition, Transition::Idle);
+        assert_eq!(harness.day("2024-03-04").total_minutes, 30);
+    }
+
+    #[test]
+    fn late_night_counts_towards_previous_day() {
+        let mut harness = Harness::new();
+        harness
+            .clock
+            .set(Local.with_ymd_and_hms(2024, 3, 4, 23, 0, 0).unwrap());
+        harness.check_after(0, true);
+        let outcome = harness.check_after(2 * 60, true);
+
+        assert_eq!(outcome.date, "2024-03-04");
+        assert_eq!(harness.day("2024-03-04").latest, "01:00");
+    }
+
+    #[test]
+    fn sessions_roll_over_at_the_day_boundary() {
+        let mut harness = Harness::new();
+        harness
+            .clock
+            .set(Local.with_ymd_and_hms(2024, 3, 4, 23, 0, 0).unwrap());
+        let Transition::Started(first) = harness.check_after(0, true).transition else {
+            panic!("expected a session to start");
+        };
+        for _ in 0..4 {
+            assert_eq!(
+                harness.check_after(60, true).transition,
+                Transition::Continued
+            );
+        }
+        // 04:00, the default boundary
+        let rolled = harness.check_after(60, true);
+        let Transition::Rolled { ended, started } = rolled.transition else {
+            panic!(
+                "expected the session to roll over, got {:?}",
+                rolled.transition
+            );
+        };
+        assert_eq!(ended, first);
+        assert!(!rolled.discarded);
+        assert_eq!(rolled.date, "2024-03-05");
+
+        let ended = sessions::get_session(&harness.db, ended).unwrap().unwrap();
+        assert_eq!(
+            (ended.date.as_str(), ended.end.as_str()),
+            ("2024-03-04", "03:00")
+        );
+        let started = sessions::get_session(&harness.db, started)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (started.date.as_str(), started.start.as_str()),
+            ("2024-03-05", "04:00")
+        );
+    }
+
+    #[test]
+    fn vpn_presence_counts_as_working_from_home() {
+        let mut harness = Harness::new();
+        let presence = Presence {
+            ssid: None,
+            label: None,
+            vpn: true,
+            rule: None,
+            idle: false,
+            active: None,
+        };
+        let now = harness.clock.now();
+        record_check(
+            &harness.db,
+            Some(presence),
+            &mut harness.sessions,
+            &mut harness.manual,
+            now,
+        )
+        .unwrap();
+
+        let tags = day_types::get_day_types(&harness.db, "2024-03-04", "2024-03-04").unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].day_type, DayType::Wfh);
+    }
+
+    #[test]
+    fn matched_rule_is_recorded_on_session() {
+        let mut harness = Harness::new();
+        let presence = Presence {
+            ssid: Some("CorpNet".to_string()),
+            label: None,
+            vpn: false,
+            rule: Some("Desk".to_string()),
+            idle: false,
+            active: None,
+        };
+        let now = harness.clock.now();
+        let outcome = record_check(
+            &harness.db,
+            Some(presence),
+            &mut harness.sessions,
+            &mut harness.manual,
+            now,
+        )
+        .unwrap();
+
+        let Transition::Started(id) = outcome.transition else {
+            panic!("expected a session to start, got {:?}", outcome.transition);
+        };
+        let session = sessions::get_session(&harness.db, id).unwrap().unwrap();
+        assert_eq!(session.rule.as_deref(), Some("Desk"));
+    }
+
+    #[test]
+    fn reports_filter_by_location() {
+        let mut harness = Harness::new();
+        let boundary = DayBoundary::load(&harness.db).unwrap();
+        for (location, minutes) in [("HQ", 60), ("Lab", 30)] {
+            let presence = Presence {
+                ssid: None,
+                label: Some(location.to_string()),
+                vpn: false,
+                rule: None,
+                idle: false,
+                active: None,
+            };
+            for _ in 0..2 {
+                let now = harness.clock.now();
+                record_check(
+                    &harness.db,
+                    Some(presence.clone()),
+                    &mut harness.sessions,
+                    &mut harness.manual,
+                    now,
+                )
+                .unwrap();
+                harness.clock.advance(Duration::minutes(minutes));
+            }
+            let now = harness.clock.now();
+            harness.sessions.close(&harness.db, now, boundary).unwrap();
+        }
+
+        let total = |location| sessions::total_minutes(&harness.db, "2024-03-04", location);
+        assert_eq!(total(None).unwrap(), 180);
+        assert_eq!(total(Some("Lab")).unwrap(), 60);
+        let days = crate::reports::day_totals(&harness.db, "2024-03-04", "2024-03-04", Some("Lab"))
+            .unwrap();
+        assert_eq!(days[0].earliest, "11:00");
+        let query = connections::LogQuery {
+            location: Some("Co-working"),
+            ..Default::default()
+        };
+        assert!(connections::get_connection_log(&harness.db, &query)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn idle_time_is_flagged_and_active_time_counted_on_session() {
+        let mut harness = Harness::new();
+        let check = |harness: &mut Harness, idle| {
+            let presence = Presence {
+                ssid: None,
+                label: None,
+                vpn: false,
+                rule: None,
+                idle,
+                active: Some(!idle),
+            };
+            let now = harness.clock.now();
+            let outcome = record_check(
+                &harness.db,
+                Some(presence),
+                &mut harness.sessions,
+                &mut harness.manual,
+                now,
+            )
+            .unwrap();
+            harness.clock.advance(Duration::minutes(20));
+            outcome
+        };
+        let Transition::Started(id) = check(&mut harness, false).transition else {
+            panic!("expected a session to start");
+        };
+        check(&mut harness, true);
+        check(&mut harness, true);
+        check(&mut harness, false);
+
+        let session = sessions::get_session(&harness.db, id).unwrap().unwrap();
+        assert_eq!(session.idle_seconds, 40 * 60);
+        assert_eq!(session.active_seconds, Some(20 * 60));
+        assert_eq!(harness.day("2024-03-04").total_minutes, 60);
+        let days =
+            crate::reports::day_totals(&harness.db, "2024-03-04", "2024-03-04", None).unwrap();
+        assert_eq!(days[0].active_minutes, Some(20));
+    }
+
+    #[test]
+    fn dangling_session_is_closed_at_last_heartbeat() {
+        let mut harness = Harness::new();
+        let boundary = DayBoundary::load(&harness.db).unwrap();
+        let id = harness
+            .manual
+            .start(&harness.db, harness.clock.now(), boundary)
+            .unwrap()
+            .unwrap();
+        harness.check_after(30, false);
+        harness.check_after(30, false);
+
+        // As if the app crashed before the timer's end was last saved
+        harness
+            .db
+            .execute("UPDATE sessions SET end_at = start_at WHERE id = ?1", [id])
+            .unwrap();
+        assert_eq!(heartbeat::close_dangling(&harness.db).unwrap(), vec![id]);
+
+        let session = sessions::get_session(&harness.db, id).unwrap().unwrap();
+        assert_eq!(session.end, "10:00");
+        assert!(heartbeat::close_dangling(&harness.db).unwrap().is_empty());
+    }
+}
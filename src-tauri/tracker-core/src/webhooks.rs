@@ -0,0 +1,227 @@
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::error::Result;
+use crate::sessions::{self, Session};
+use crate::settings;
+
+/// Used when a webhook's template is left empty.
+pub const DEFAULT_TEMPLATE: &str = r#"{"event": "{{event}}", "session_id": {{session_id}}, "date": "{{date}}", "start": "{{start}}", "end": "{{end}}", "minutes": {{minutes}}, "location": "{{location}}"}"#;
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Tries before giving up on a delivery, waiting twice as long after each.
+const MAX_ATTEMPTS: u32 = 5;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// What a webhook is fired for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WebhookEvent {
+    ClockIn,
+    ClockOut,
+}
+
+impl WebhookEvent {
+    pub fn name(self) -> &'static str {
+        match self {
+            WebhookEvent::ClockIn => "clock_in",
+            WebhookEvent::ClockOut => "clock_out",
+        }
+    }
+}
+
+/// A URL to POST JSON to when a session starts or ends, e.g. a Slack
+/// incoming webhook, an n8n workflow or a Home Assistant automation.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    /// JSON body with `{{event}}`, `{{session_id}}`, `{{date}}`, `{{start}}`,
+    /// `{{end}}`, `{{minutes}}` and `{{location}}` placeholders, filled in
+    /// escaped for use inside JSON strings. `DEFAULT_TEMPLATE` if empty.
+    #[serde(default)]
+    pub template: String,
+}
+
+impl Webhook {
+    pub fn validate(&self) -> Result<(), String> {
+        let url = self.url.trim();
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(format!("Invalid webhook URL '{}'", url));
+        }
+        let sample = Session {
+            id: 1,
+            date: "2024-03-04".to_string(),
+            start: "09:00".to_string(),
+            end: "17:30".to_string(),
+            ssid: Some("CorpNet".to_string()),
+            label: Some("HQ \"main\"".to_string()),
+            source: sessions::SOURCE_AUTO.to_string(),
+            start_at: Some(0),
+            end_at: Some(8 * 60 * 60 + 30 * 60),
+            rule: None,
+            idle_seconds: 0,
+            active_seconds: None,
+        };
+        serde_json::from_str::<serde_json::Value>(&self.render(WebhookEvent::ClockOut, &sample))
+            .map_err(|e| format!("The template for {} isn't valid JSON: {}", url, e))?;
+        Ok(())
+    }
+
+    /// The body to send for `event` on `session`.
+    pub fn render(&self, event: WebhookEvent, session: &Session) -> String {
+        let template = match self.template.trim() {
+            "" => DEFAULT_TEMPLATE,
+            template => template,
+        };
+        let minutes = match (session.start_at, session.end_at) {
+            (Some(start), Some(end)) => (end - start).max(0) / 60,
+            _ => 0,
+        };
+        [
+            ("event", event.name().to_string()),
+            ("session_id", session.id.to_string()),
+            ("date", session.date.clone()),
+            ("start", session.start.clone()),
+            ("end", session.end.clone()),
+            ("minutes", minutes.to_string()),
+            ("location", session.label.clone().unwrap_or_default()),
+        ]
+        .iter()
+        .fold(template.to_string(), |body, (name, value)| {
+            body.replace(&format!("{{{{{}}}}}", name), &escape(value))
+        })
+    }
+}
+
+/// `value` as the inside of a JSON string.
+fn escape(value: &str) -> String {
+    let quoted = serde_json::Value::from(value).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+/// How long to wait before try number `attempt` (from 1) is retried.
+fn retry_delay(attempt: u32) -> Duration {
+    FIRST_RETRY_DELAY * 2u32.pow(attempt - 1)
+}
+
+/// Sends `event` for `session` to every configured webhook, each on a thread
+/// of its own so a slow endpoint can't hold up tracking.
+pub fn fire(db: &Connection, event: WebhookEvent, session: &Session) -> Result<()> {
+    let webhooks: Vec<Webhook> = settings::get(db, settings::WEBHOOKS)?
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
+    for webhook in webhooks {
+        let body = webhook.render(event, session);
+        let url = webhook.url.trim().to_string();
+        thread::spawn(move || deliver(&url, &body));
+    }
+    Ok(())
+}
+
+/// Like `fire`, for the session with `id` if it still exists.
+pub fn fire_for_session(db: &Connection, event: WebhookEvent, id: i64) -> Result<()> {
+    match sessions::get_session(db, id)? {
+        Some(session) => fire(db, event, &session),
+        None => Ok(()),
+    }
+}
+
+/// POSTs `body`, retrying with backoff on network errors, rate limiting and
+/// server errors but not on other rejections, which won't go away.
+fn deliver(url: &str, body: &str) {
+    for attempt in 1..=MAX_ATTEMPTS {
+        let result = ureq::post(url)
+            .timeout(TIMEOUT)
+            .set("Content-Type", "application/json")
+            .send_string(body);
+        let error = match result {
+            Ok(_) => return,
+            Err(ureq::Error::Status(status, _)) if status != 429 && status < 500 => {
+                warn!("Webhook {} rejected the request with {}", url, status);
+                return;
+            }
+            Err(e) => e,
+        };
+        if attempt == MAX_ATTEMPTS {
+            warn!(
+                "Giving up on webhook {} after {} tries: {}",
+                url, attempt, error
+            );
+            return;
+        }
+        let delay = retry_delay(attempt);
+        warn!("Webhook {} failed, retrying in {:?}: {}", url, delay, error);
+        thread::sleep(delay);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session {
+            id: 7,
+            date: "2024-03-04".to_string(),
+            start: "09:12".to_string(),
+            end: "17:42".to_string(),
+            ssid: Some("CorpNet".to_string()),
+            label: Some("HQ".to_string()),
+            source: sessions::SOURCE_AUTO.to_string(),
+            start_at: Some(1_000),
+            end_at: Some(1_000 + 510 * 60),
+            rule: None,
+            idle_seconds: 0,
+            active_seconds: None,
+        }
+    }
+
+    #[test]
+    fn default_template_renders_session() {
+        let webhook = Webhook {
+            url: "https://example.com/hook".to_string(),
+            template: String::new(),
+        };
+        let body: serde_json::Value =
+            serde_json::from_str(&webhook.render(WebhookEvent::ClockOut, &session())).unwrap();
+        assert_eq!(body["event"], "clock_out");
+        assert_eq!(body["session_id"], 7);
+        assert_eq!(body["start"], "09:12");
+        assert_eq!(body["minutes"], 510);
+        assert_eq!(body["location"], "HQ");
+    }
+
+    #[test]
+    fn values_are_escaped_for_json_strings() {
+        let webhook = Webhook {
+            url: "https://example.com/hook".to_string(),
+            template: r#"{"text": "Arrived at {{location}}"}"#.to_string(),
+        };
+        let mut session = session();
+        session.label = Some("The \"Lab\"".to_string());
+        let body: serde_json::Value =
+            serde_json::from_str(&webhook.render(WebhookEvent::ClockIn, &session)).unwrap();
+        assert_eq!(body["text"], "Arrived at The \"Lab\"");
+    }
+
+    #[test]
+    fn invalid_templates_and_urls_are_rejected() {
+        let mut webhook = Webhook {
+            url: "https://example.com/hook".to_string(),
+            template: r#"{"text": {{location}}}"#.to_string(),
+        };
+        assert!(webhook.validate().is_err());
+        webhook.template = String::new();
+        assert!(webhook.validate().is_ok());
+        webhook.url = "example.com".to_string();
+        assert!(webhook.validate().is_err());
+    }
+
+    #[test]
+    fn retries_back_off() {
+        assert_eq!(retry_delay(1), Duration::from_secs(2));
+        assert_eq!(retry_delay(2), Duration::from_secs(4));
+        assert_eq!(retry_delay(4), Duration::from_secs(16));
+    }
+}
@@ -0,0 +1,556 @@
+use serde::Serialize;
+use std::process::Command;
+
+use crate::error::Result;
+use crate::process::{CommandExt, COMMAND_TIMEOUT};
+
+/// The WiFi network the machine is currently connected to.
+#[derive(Clone, Debug)]
+pub struct WifiConnection {
+    pub ssid: String,
+    /// MAC address of the access point, lowercase and colon-separated. `None`
+    /// when the platform backend can't report it.
+    pub bssid: Option<String>,
+}
+
+/// Returns the WiFi network the machine is currently connected to, or `None`
+/// if it isn't connected or the SSID couldn't be determined. Fails only if
+/// the system tool needed to find out couldn't be run.
+pub fn get_current_wifi() -> Result<Option<WifiConnection>> {
+    platform::get_current_wifi()
+}
+
+/// A network to offer when picking the office's, e.g. during onboarding.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct WifiNetwork {
+    pub ssid: String,
+    /// Strength of the strongest access point seen, 0 to 100. `None` when
+    /// it's out of range or the platform backend can't report it.
+    pub signal: Option<u8>,
+    pub in_range: bool,
+    pub connected: bool,
+    /// Whether presence has been recorded on it before.
+    pub known: bool,
+}
+
+/// Networks in range, then any of the `known` SSIDs that aren't, with the
+/// one the machine is connected to first and the rest strongest first.
+/// Scanning can take a few seconds, so call it off the main thread. Fails
+/// only if the system tool needed to scan couldn't be run.
+pub fn list_networks(known: &[String]) -> Result<Vec<WifiNetwork>> {
+    let scanned = platform::scan_networks()?;
+    let current = get_current_wifi().ok().flatten();
+    Ok(merge_networks(
+        scanned,
+        current.as_ref().map(|connection| connection.ssid.as_str()),
+        known,
+    ))
+}
+
+/// Combines scan results, which list each access point separately, with
+/// the current network, which scans sometimes leave out, and `known`.
+fn merge_networks(
+    scanned: Vec<(String, Option<u8>)>,
+    current: Option<&str>,
+    known: &[String],
+) -> Vec<WifiNetwork> {
+    let mut networks: Vec<WifiNetwork> = Vec::new();
+    let in_range = scanned
+        .into_iter()
+        .map(|(ssid, signal)| (ssid, signal, true))
+        .chain(current.map(|ssid| (ssid.to_string(), None, true)))
+        .chain(known.iter().map(|ssid| (ssid.clone(), None, false)));
+    for (ssid, signal, in_range) in in_range {
+        let ssid = ssid.trim();
+        if ssid.is_empty() {
+            continue;
+        }
+        match networks.iter_mut().find(|network| network.ssid == ssid) {
+            Some(network) => {
+                network.signal = network.signal.max(signal);
+                network.in_range |= in_range;
+            }
+            None => networks.push(WifiNetwork {
+                ssid: ssid.to_string(),
+                signal,
+                in_range,
+                connected: false,
+                known: false,
+            }),
+        }
+    }
+    for network in &mut networks {
+        network.connected = current == Some(network.ssid.as_str());
+        network.known = known.contains(&network.ssid);
+    }
+    // Stable, so networks out of range keep the order of `known`
+    networks.sort_by_key(|network| {
+        (
+            !network.connected,
+            !network.in_range,
+            std::cmp::Reverse(network.signal),
+        )
+    });
+    networks
+}
+
+/// Normalises a MAC address (or prefix) to lowercase colon-separated form so
+/// `AA-BB-CC` and `aa:bb:cc` compare equal.
+pub fn normalize_mac(mac: &str) -> String {
+    mac.trim().to_lowercase().replace('-', ":")
+}
+
+/// Prepares SSID detection at startup: prompts for any OS permissions it
+/// depends on and looks up which network interface is the WiFi adapter.
+pub fn init() {
+    #[cfg(target_os = "macos")]
+    platform::init();
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use super::*;
+    use crate::error::{Error, LockExt};
+    use objc2_core_location::CLLocationManager;
+    use objc2_core_wlan::CWWiFiClient;
+    use objc2_foundation::NSString;
+    use std::sync::Mutex;
+    use tracing::{info, warn};
+
+    /// Device name of the WiFi adapter (`en0` on most Macs, but `en1` on some
+    /// models and something else again for USB adapters).
+    static WIFI_DEVICE: Mutex<Option<String>> = Mutex::new(None);
+
+    pub fn init() {
+        request_permissions();
+        match wifi_device() {
+            Ok(Some(device)) => info!("Using WiFi device {}", device),
+            Ok(None) => {}
+            Err(e) => warn!("Couldn't look up the WiFi device: {}", e),
+        }
+    }
+
+    /// Since Sonoma, CoreWLAN only reveals the SSID to apps with location
+    /// access, so ask for it up front. Must be called on the main thread.
+    fn request_permissions() {
+        unsafe {
+            let manager = CLLocationManager::new();
+            manager.requestWhenInUseAuthorization();
+            // The prompt is dismissed if the manager is deallocated, and the
+            // authorization applies app-wide, so keep it alive for the process.
+            std::mem::forget(manager);
+        }
+    }
+
+    pub fn get_current_wifi() -> Result<Option<WifiConnection>> {
+        if let Some(connection) = corewlan_wifi() {
+            return Ok(Some(connection));
+        }
+        Ok(networksetup_ssid()?.map(|ssid| WifiConnection { ssid, bssid: None }))
+    }
+
+    fn corewlan_wifi() -> Option<WifiConnection> {
+        unsafe {
+            let client = CWWiFiClient::sharedWiFiClient();
+            let interface = client.interface()?;
+            // nil when disconnected or when location access hasn't been granted
+            let ssid = interface.ssid()?;
+            Some(WifiConnection {
+                ssid: ssid.to_string(),
+                bssid: interface
+                    .bssid()
+                    .map(|bssid| normalize_mac(&bssid.to_string())),
+            })
+        }
+    }
+
+    /// Asks CoreWLAN to scan, which like reading the SSID needs location
+    /// access. Signal strength is estimated from RSSI, -100 dBm being 0 and
+    /// -50 dBm or better 100.
+    pub fn scan_networks() -> Result<Vec<(String, Option<u8>)>> {
+        unsafe {
+            let client = CWWiFiClient::sharedWiFiClient();
+            let Some(interface) = client.interface() else {
+                return Ok(Vec::new());
+            };
+            let networks = interface
+                .scanForNetworksWithName_error(None::<&NSString>)
+                .map_err(|e| {
+                    Error::Message(format!("WiFi scan failed: {}", e.localizedDescription()))
+                })?;
+            Ok(networks
+                .iter()
+                .filter_map(|network| {
+                    let ssid = network.ssid()?.to_string();
+                    let signal = (2 * (network.rssiValue() + 100)).clamp(0, 100) as u8;
+                    Some((ssid, Some(signal)))
+                })
+                .collect())
+        }
+    }
+
+    fn networksetup_ssid() -> Result<Option<String>> {
+        let Some(device) = wifi_device()? else {
+            return Ok(None);
+        };
+        let output = Command::new("networksetup")
+            .args(&["-getairportnetwork", &device])
+            .output_within(COMMAND_TIMEOUT)
+            .map_err(Error::command("networksetup"))?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if output.status.success() && !stdout.contains("is not a Wi-Fi interface") {
+            // The output format is typically: "Current Wi-Fi Network: SSID_NAME"
+            Ok(stdout.split(": ").nth(1).map(|s| s.trim().to_string()))
+        } else {
+            warn!(
+                "Error executing networksetup command on {}: {}{}",
+                device,
+                stdout.trim(),
+                String::from_utf8_lossy(&output.stderr)
+            );
+            // The adapter may have been unplugged or renamed; look it up
+            // again on the next poll.
+            *WIFI_DEVICE.lock_unpoisoned() = None;
+            Ok(None)
+        }
+    }
+
+    fn wifi_device() -> Result<Option<String>> {
+        let mut cached = WIFI_DEVICE.lock_unpoisoned();
+        if cached.is_none() {
+            *cached = detect_wifi_device()?;
+        }
+        Ok(cached.clone())
+    }
+
+    fn detect_wifi_device() -> Result<Option<String>> {
+        let output = Command::new("networksetup")
+            .arg("-listallhardwareports")
+            .output_within(COMMAND_TIMEOUT)
+            .map_err(Error::command("networksetup"))?;
+
+        if !output.status.success() {
+            warn!(
+                "Error listing hardware ports: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            return Ok(None);
+        }
+
+        let device = parse_hardware_ports(&String::from_utf8_lossy(&output.stdout));
+        if device.is_none() {
+            warn!("No WiFi hardware port found");
+        }
+        Ok(device)
+    }
+
+    /// Finds the WiFi device in `networksetup -listallhardwareports` output,
+    /// which lists each port as:
+    ///
+    /// ```text
+    /// Hardware Port: Wi-Fi
+    /// Device: en0
+    /// Ethernet Address: aa:bb:cc:dd:ee:ff
+    /// ```
+    ///
+    /// Older macOS versions call the port "AirPort".
+    fn parse_hardware_ports(output: &str) -> Option<String> {
+        let mut is_wifi = false;
+        for line in output.lines() {
+            if let Some(port) = line.strip_prefix("Hardware Port: ") {
+                is_wifi = matches!(port.trim(), "Wi-Fi" | "AirPort");
+            } else if let Some(device) = line.strip_prefix("Device: ") {
+                if is_wifi {
+                    return Some(device.trim().to_string());
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(target_os = "windows")]
+mod platform {
+    use super::*;
+    use crate::error::Error;
+    use tracing::warn;
+
+    pub fn get_current_wifi() -> Result<Option<WifiConnection>> {
+        let output = Command::new("netsh")
+            .args(&["wlan", "show", "interfaces"])
+            .output_within(COMMAND_TIMEOUT)
+            .map_err(Error::command("netsh"))?;
+
+        if output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            Ok(parse_netsh_interfaces(&stdout))
+        } else {
+            warn!(
+                "Error executing netsh command: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            Ok(None)
+        }
+    }
+
+    pub fn scan_networks() -> Result<Vec<(String, Option<u8>)>> {
+        let output = Command::new("netsh")
+            .args(&["wlan", "show", "networks", "mode=bssid"])
+            .output_within(COMMAND_TIMEOUT)
+            .map_err(Error::command("netsh"))?;
+
+        if output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            Ok(parse_netsh_networks(&stdout))
+        } else {
+            warn!(
+                "Error executing netsh command: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            Ok(Vec::new())
+        }
+    }
+
+    /// Picks the networks out of `netsh wlan show networks mode=bssid`
+    /// output, which lists each with its access points as:
+    ///
+    /// ```text
+    /// SSID 1 : SSID_NAME
+    ///     Network type            : Infrastructure
+    ///     BSSID 1                 : aa:bb:cc:dd:ee:ff
+    ///          Signal             : 85%
+    /// ```
+    fn parse_netsh_networks(output: &str) -> Vec<(String, Option<u8>)> {
+        let mut networks: Vec<(String, Option<u8>)> = Vec::new();
+        for line in output.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+            if key.starts_with("SSID ") {
+                networks.push((value.to_string(), None));
+            } else if key == "Signal" {
+                let signal = value.trim_end_matches('%').parse().ok();
+                if let Some((_, strongest)) = networks.last_mut() {
+                    *strongest = (*strongest).max(signal);
+                }
+            }
+        }
+        networks
+    }
+
+    /// Picks the SSID and BSSID out of `netsh wlan show interfaces` output,
+    /// which looks like:
+    ///
+    /// ```text
+    ///     State                  : connected
+    ///     SSID                   : SSID_NAME
+    ///     BSSID                  : aa:bb:cc:dd:ee:ff
+    /// ```
+    ///
+    /// Keys are matched exactly so `SSID` doesn't pick up the `BSSID` line.
+    fn parse_netsh_interfaces(output: &str) -> Option<WifiConnection> {
+        let field = |name: &str| {
+            output.lines().find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                let value = value.trim();
+                (key.trim() == name && !value.is_empty()).then(|| value.to_string())
+            })
+        };
+
+        Some(WifiConnection {
+            ssid: field("SSID")?,
+            bssid: field("BSSID").map(|bssid| normalize_mac(&bssid)),
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use super::*;
+    use crate::error::Error;
+    use std::io::ErrorKind;
+    use tracing::warn;
+
+    /// Tries NetworkManager first and falls back to `iwgetid` for systems that
+    /// manage WiFi with wpa_supplicant/iwd directly. A tool that's installed
+    /// but times out or can't be run fails the check rather than falling back.
+    pub fn get_current_wifi() -> Result<Option<WifiConnection>> {
+        match nmcli_wifi()? {
+            Some(connection) => Ok(Some(connection)),
+            None => iwgetid_wifi(),
+        }
+    }
+
+    /// `None` if NetworkManager isn't installed or running, or isn't
+    /// connected to a network.
+    fn nmcli_wifi() -> Result<Option<WifiConnection>> {
+        let output = match Command::new("nmcli")
+            .args(&["-t", "-f", "active,ssid,bssid", "dev", "wifi"])
+            .output_within(COMMAND_TIMEOUT)
+        {
+            Ok(output) => output,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::command("nmcli")(e)),
+        };
+
+        if output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            Ok(parse_nmcli_wifi(&stdout))
+        } else {
+            warn!(
+                "Error executing nmcli command: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            Ok(None)
+        }
+    }
+
+    /// Picks the active network out of `nmcli -t -f active,ssid,bssid dev wifi`
+    /// output, which lists every visible network as
+    /// `yes:SSID_NAME:AA\:BB\:CC\:DD\:EE\:FF` or `no:...`.
+    fn parse_nmcli_wifi(output: &str) -> Option<WifiConnection> {
+        output.lines().find_map(|line| {
+            let fields = split_terse(line);
+            match fields.as_slice() {
+                [active, ssid, bssid] if active == "yes" && !ssid.is_empty() => {
+                    Some(WifiConnection {
+                        ssid: ssid.clone(),
+                        bssid: (!bssid.is_empty()).then(|| normalize_mac(bssid)),
+                    })
+                }
+                _ => None,
+            }
+        })
+    }
+
+    /// Lists what NetworkManager can see; without it, scanning needs root, so
+    /// only the current network is offered.
+    pub fn scan_networks() -> Result<Vec<(String, Option<u8>)>> {
+        let output = match Command::new("nmcli")
+            .args(&["-t", "-f", "ssid,signal", "dev", "wifi", "list"])
+            .output_within(COMMAND_TIMEOUT)
+        {
+            Ok(output) => output,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Error::command("nmcli")(e)),
+        };
+
+        if !output.status.success() {
+            warn!(
+                "Error executing nmcli command: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+            return Ok(Vec::new());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| match split_terse(line).as_slice() {
+                [ssid, signal] => Some((ssid.clone(), signal.parse().ok())),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Splits a terse-mode nmcli line on `:`, honouring the `\:` escapes nmcli
+    /// uses for literal colons inside values.
+    fn split_terse(line: &str) -> Vec<String> {
+        let mut fields = vec![String::new()];
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => fields.last_mut().unwrap().extend(chars.next()),
+                ':' => fields.push(String::new()),
+                c => fields.last_mut().unwrap().push(c),
+            }
+        }
+        fields
+    }
+
+    fn iwgetid_wifi() -> Result<Option<WifiConnection>> {
+        let Some(ssid) = iwgetid(&["-r"])? else {
+            return Ok(None);
+        };
+        Ok(Some(WifiConnection {
+            ssid,
+            bssid: iwgetid(&["-a", "-r"])?.map(|bssid| normalize_mac(&bssid)),
+        }))
+    }
+
+    /// `None` if there's no connection, or no iwgetid either, in which case
+    /// nothing here manages WiFi for us to ask.
+    fn iwgetid(args: &[&str]) -> Result<Option<String>> {
+        let output = match Command::new("iwgetid")
+            .args(args)
+            .output_within(COMMAND_TIMEOUT)
+        {
+            Ok(output) => output,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::command("iwgetid")(e)),
+        };
+
+        // iwgetid exits with a non-zero status when there is no connection
+        if output.status.success() {
+            let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            Ok((!value.is_empty()).then_some(value))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
+mod platform {
+    use super::*;
+    use crate::error::Error;
+
+    pub fn get_current_wifi() -> Result<Option<WifiConnection>> {
+        Err(Error::Message(
+            "WiFi SSID detection is not supported on this platform".to_string(),
+        ))
+    }
+
+    pub fn scan_networks() -> Result<Vec<(String, Option<u8>)>> {
+        Err(Error::Message(
+            "WiFi scanning is not supported on this platform".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_access_points_and_known_networks() {
+        let scanned = vec![
+            ("Guest".to_string(), Some(40)),
+            ("CorpNet".to_string(), Some(60)),
+            ("CorpNet".to_string(), Some(90)),
+            ("".to_string(), Some(99)),
+        ];
+        let known = vec!["Home".to_string(), "CorpNet".to_string()];
+        let networks = merge_networks(scanned, Some("Guest"), &known);
+        let summary: Vec<(&str, Option<u8>, bool, bool, bool)> = networks
+            .iter()
+            .map(|network| {
+                (
+                    network.ssid.as_str(),
+                    network.signal,
+                    network.in_range,
+                    network.connected,
+                    network.known,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("Guest", Some(40), true, true, false),
+                ("CorpNet", Some(90), true, false, true),
+                ("Home", None, false, false, true),
+            ]
+        );
+    }
+}
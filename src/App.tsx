@@ -1,5 +1,6 @@
 import { useState, useEffect } from 'react';
 import { invoke } from '@tauri-apps/api/tauri';
+import { listen } from '@tauri-apps/api/event';
 import { format } from 'date-fns';
 import './App.css';
 
@@ -24,9 +25,11 @@ const App = () => {
     };
 
     fetchConnections();
-    const interval = setInterval(fetchConnections, 30000); // Update every 30s
+    const unlisten = listen('day-updated', fetchConnections);
 
-    return () => clearInterval(interval);
+    return () => {
+      unlisten.then((fn) => fn());
+    };
   }, []);
 
   const formatDate = (date: string) => {